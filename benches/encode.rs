@@ -0,0 +1,112 @@
+//! Times tile encoding against the original pixel-at-a-time packer. The
+//! two are checked to agree by the unit tests in `src/tile.rs`.
+//!
+//! Run with `cargo bench`.
+
+use gbtile::{encode_tile, DecodedImage, Rgb};
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WIDTH: u32 = 2048;
+const HEIGHT: u32 = 2048;
+const RUNS: u32 = 5;
+
+const SHADES: [Rgb; 4] = [
+    Rgb {
+        r: 240,
+        g: 240,
+        b: 240,
+    },
+    Rgb {
+        r: 144,
+        g: 144,
+        b: 144,
+    },
+    Rgb {
+        r: 96,
+        g: 96,
+        b: 96,
+    },
+    Rgb { r: 0, g: 0, b: 0 },
+];
+
+/// A large image with a pseudo-random mix of all four shades.
+fn background() -> DecodedImage {
+    let mut state: u32 = 0x1234_5678;
+    let image_data = (0..WIDTH * HEIGHT)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Some(SHADES[(state % 4) as usize])
+        })
+        .collect();
+    DecodedImage {
+        input_filename: "background.png".to_string(),
+        width: WIDTH,
+        height: HEIGHT,
+        image_data,
+        color_numbers: SHADES
+            .iter()
+            .enumerate()
+            .map(|(i, rgb)| (*rgb, i as u8))
+            .collect(),
+        transparent_color: None,
+        palette_register: 0xE4,
+        cgb_palettes: None,
+        frames: 1,
+        tilemaps: Vec::new(),
+        warnings: Vec::new(),
+    }
+}
+
+/// The encoder as it was before packing 8 pixels at a time.
+fn reference_encode(decoded_image: &DecodedImage) -> Vec<u8> {
+    let color_numbers: &BTreeMap<Rgb, u8> = &decoded_image.color_numbers;
+    let mut tile_data = Vec::new();
+    for row in 0..decoded_image.height / 8 {
+        for column in 0..decoded_image.width / 8 {
+            for tile_row in 0..8 {
+                let mut low_byte = 0;
+                let mut high_byte = 0;
+                for tile_column in 0..8 {
+                    let pixel_index = (column * 8 + tile_column)
+                        + ((decoded_image.width * tile_row) + (row * 8 * decoded_image.width));
+                    let pixel = decoded_image.image_data[pixel_index as usize];
+                    let color = color_numbers[&pixel.unwrap()];
+                    low_byte |= (color & 0x01) << (7 - tile_column);
+                    high_byte |= ((color >> 1) & 0x01) << (7 - tile_column);
+                }
+                tile_data.push(low_byte);
+                tile_data.push(high_byte);
+            }
+        }
+    }
+    tile_data
+}
+
+/// The best time of several runs, each on a freshly generated image.
+fn time<F: FnMut(DecodedImage) -> Vec<u8>>(mut run: F) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let image = background();
+        let start = Instant::now();
+        black_box(run(image));
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main() {
+    let reference_time = time(|image| reference_encode(&image));
+    let packed_time = time(|image| encode_tile(image).tile_data);
+
+    println!("{}x{} background, best of {} runs:", WIDTH, HEIGHT, RUNS);
+    println!("  pixel at a time: {:?}", reference_time);
+    println!("  8 pixels at once: {:?}", packed_time);
+    println!(
+        "  speedup: {:.2}x",
+        reference_time.as_secs_f64() / packed_time.as_secs_f64()
+    );
+}
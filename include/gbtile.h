@@ -0,0 +1,40 @@
+#ifndef GBTILE_H
+#define GBTILE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define GBTILE_OK 0
+#define GBTILE_INVALID_ARGUMENT 1
+#define GBTILE_CONVERSION_FAILED 2
+
+/* Bytes allocated by gbtile. Release with gbtile_buffer_free. */
+typedef struct {
+    uint8_t *data;
+    size_t len;
+} GbtileBuffer;
+
+/* Convert an in-memory image into generated source text. `name` is used for
+ * the generated symbol, and `output_type` is a format name like "gbdk" or
+ * "rgbds". Returns GBTILE_OK and fills `out` on success. */
+int gbtile_convert(const uint8_t *input, size_t input_len, const char *name,
+                   const char *output_type, GbtileBuffer *out);
+
+/* Convert an in-memory image into raw 2bpp tile data, 16 bytes per tile. */
+int gbtile_encode(const uint8_t *input, size_t input_len, const char *name,
+                  GbtileBuffer *out);
+
+void gbtile_buffer_free(GbtileBuffer *buffer);
+
+/* The message for the last failed call on this thread, or NULL. */
+const char *gbtile_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
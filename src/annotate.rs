@@ -0,0 +1,105 @@
+use crate::image::{off_palette_pixels, stray_pixels, DecodeOptions, ImageReadError, Rgb, Rgba};
+use crate::source::SourceImage;
+use std::io;
+
+/// The color offending pixels are drawn in.
+const HIGHLIGHT: Rgb = Rgb {
+    r: 0xff,
+    g: 0x00,
+    b: 0xff,
+};
+
+/// The color the 8x8 tile grid is mixed with.
+const GRID: Rgb = Rgb {
+    r: 0x40,
+    g: 0x40,
+    b: 0x40,
+};
+
+/// Draw `source_image` as a PNG, with the pixels `err` is about in
+/// magenta and the 8x8 tile grid over it, so the problem can be seen next
+/// to the source. Transparent pixels are drawn over white.
+pub(crate) fn error_image(
+    source_image: &SourceImage,
+    options: &DecodeOptions,
+    err: &ImageReadError,
+) -> io::Result<Vec<u8>> {
+    let (width, height) = (source_image.width, source_image.height);
+    let frame_height = height / source_image.frames.max(1);
+    let offending: Vec<bool> = match err {
+        ImageReadError::TooManyColors { .. } => stray_pixels(source_image, options),
+        ImageReadError::OffPalette { .. } => off_palette_pixels(source_image, options),
+        // The tile that didn't fit in a palette.
+        ImageReadError::TooManyPalettes { x, y, .. }
+        | ImageReadError::PaletteMaskFull { x, y, .. } => (0..source_image.pixels.len() as u32)
+            .map(|i| (i % width) / 8 == x / 8 && (i / width) / 8 == y / 8)
+            .collect(),
+        ImageReadError::ImageTooSmall { .. } | ImageReadError::NoGridCells { .. } => {
+            vec![true; source_image.pixels.len()]
+        }
+        // Rows past the last full tile of each frame.
+        ImageReadError::FrameHeight { height } => (0..source_image.pixels.len() as u32)
+            .map(|i| (i / width) % height >= height / 8 * 8)
+            .collect(),
+        // Columns and rows past the last full tile of each frame.
+        ImageReadError::NotTileAligned { .. } => (0..source_image.pixels.len() as u32)
+            .map(|i| {
+                let frame_height = frame_height.max(1);
+                i % width >= width / 8 * 8 || (i / width) % frame_height >= frame_height / 8 * 8
+            })
+            .collect(),
+        // The part of the rectangle that's in the image.
+        ImageReadError::RectOutsideImage { rect, .. } => (0..source_image.pixels.len() as u32)
+            .map(|i| {
+                let (x, y) = (i % width, (i / width) % frame_height.max(1));
+                x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height
+            })
+            .collect(),
+        _ => vec![false; source_image.pixels.len()],
+    };
+    let mut rgb_data = Vec::with_capacity(source_image.pixels.len() * 3);
+    for (i, (pixel, offending)) in source_image.pixels.iter().zip(offending).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let color = if offending {
+            HIGHLIGHT
+        } else if x % 8 == 0 || y % 8 == 0 {
+            mix(over_white(*pixel), GRID)
+        } else {
+            over_white(*pixel)
+        };
+        rgb_data.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb_data))
+        .map_err(io::Error::other)?;
+    Ok(png_data)
+}
+
+/// The color of `pixel` drawn over white.
+fn over_white(pixel: Rgba) -> Rgb {
+    let channel = |value: u8| {
+        let alpha = u32::from(pixel.a);
+        ((u32::from(value) * alpha + 255 * (255 - alpha)) / 255) as u8
+    };
+    Rgb {
+        r: channel(pixel.r),
+        g: channel(pixel.g),
+        b: channel(pixel.b),
+    }
+}
+
+/// Half of `a` and half of `b`.
+fn mix(a: Rgb, b: Rgb) -> Rgb {
+    let channel = |a: u8, b: u8| ((u16::from(a) + u16::from(b)) / 2) as u8;
+    Rgb {
+        r: channel(a.r, b.r),
+        g: channel(a.g, b.g),
+        b: channel(a.b, b.b),
+    }
+}
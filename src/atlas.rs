@@ -0,0 +1,211 @@
+use crate::image::{detect_source, DecodeOptions, DecodedImage, ImageReadError, Rgba};
+use crate::source::{SourceImage, SourceRegistry};
+use serde_json::Value;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// A sprite packed into a TexturePacker sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasSprite {
+    /// The sprite's file name, without its extension.
+    pub name: String,
+    /// Where the sprite is in the sheet. For rotated sprites, these are the
+    /// unrotated width and height.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Whether the sprite was turned 90 degrees clockwise to pack it.
+    pub rotated: bool,
+    /// Where the packed pixels go in the sprite's original size, for sprites
+    /// with their transparent edges trimmed off.
+    pub offset_x: u32,
+    pub offset_y: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+}
+
+/// A TexturePacker JSON atlas, in either the hash or array layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Atlas {
+    /// The sheet image, relative to the atlas.
+    pub image: String,
+    /// The packed sprites. The hash layout lists them by name, and the
+    /// array layout in the order they're stored.
+    pub sprites: Vec<AtlasSprite>,
+}
+
+impl Atlas {
+    pub fn parse(data: &[u8]) -> Result<Atlas, ImageReadError> {
+        let atlas: Value = serde_json::from_slice(data)
+            .map_err(|err| invalid(&format!("atlas isn't valid JSON: {}", err)))?;
+        let image = atlas["meta"]["image"]
+            .as_str()
+            .ok_or_else(|| invalid("atlas doesn't name its sheet image"))?
+            .to_string();
+        let frames: Vec<(&str, &Value)> = match &atlas["frames"] {
+            Value::Object(frames) => frames
+                .iter()
+                .map(|(name, frame)| (name.as_str(), frame))
+                .collect(),
+            Value::Array(frames) => frames
+                .iter()
+                .map(|frame| (frame["filename"].as_str().unwrap_or_default(), frame))
+                .collect(),
+            _ => return Err(invalid("atlas has no frames")),
+        };
+        let sprites = frames
+            .into_iter()
+            .map(|(name, frame)| sprite(name, frame))
+            .collect::<Result<Vec<AtlasSprite>, ImageReadError>>()?;
+        Ok(Atlas { image, sprites })
+    }
+
+    /// Where the sheet image is, for the atlas at `path`.
+    pub fn sheet_path(&self, path: &str) -> PathBuf {
+        Path::new(path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&self.image)
+    }
+
+    /// Cut `sprite` out of the decoded sheet, turning it back upright and
+    /// restoring any trimmed edges as transparent pixels.
+    pub fn cut(
+        &self,
+        sheet: &SourceImage,
+        sprite: &AtlasSprite,
+    ) -> Result<SourceImage, ImageReadError> {
+        let (packed_width, packed_height) = if sprite.rotated {
+            (sprite.height, sprite.width)
+        } else {
+            (sprite.width, sprite.height)
+        };
+        if sprite.x + packed_width > sheet.width || sprite.y + packed_height > sheet.height {
+            return Err(invalid(&format!(
+                "sprite '{}' is outside the sheet",
+                sprite.name
+            )));
+        }
+        if sprite.offset_x + sprite.width > sprite.source_width
+            || sprite.offset_y + sprite.height > sprite.source_height
+        {
+            return Err(invalid(&format!(
+                "sprite '{}' is larger than its source size",
+                sprite.name
+            )));
+        }
+        let packed = sheet.crop(sprite.x, sprite.y, packed_width, packed_height);
+
+        let transparent = Rgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        let mut pixels =
+            vec![transparent; sprite.source_width as usize * sprite.source_height as usize];
+        for y in 0..sprite.height {
+            for x in 0..sprite.width {
+                // A clockwise turn put the sprite's left column along the top.
+                let (packed_x, packed_y) = if sprite.rotated {
+                    (packed_width - 1 - y, x)
+                } else {
+                    (x, y)
+                };
+                let pixel = packed.pixels[(packed_y * packed_width + packed_x) as usize];
+                let index = (sprite.offset_y + y) * sprite.source_width + sprite.offset_x + x;
+                pixels[index as usize] = pixel;
+            }
+        }
+        Ok(SourceImage {
+            width: sprite.source_width,
+            height: sprite.source_height,
+            pixels,
+            palette: packed.palette,
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+/// Read the TexturePacker atlas at `path` and its sheet image, detecting
+/// the sheet's format with `sources`. Each sprite is decoded on its own, so
+/// each gets its own color numbers.
+pub fn decode_atlas(
+    sources: &SourceRegistry,
+    options: &DecodeOptions,
+    path: &str,
+) -> Result<Vec<(AtlasSprite, DecodedImage)>, ImageReadError> {
+    let atlas = Atlas::parse(&fs::read(path)?)?;
+    let image = atlas.sheet_path(path);
+    let image_name = image.to_string_lossy();
+    debug!(
+        "Atlas {} packs {} sprite(s) into {}",
+        path,
+        atlas.sprites.len(),
+        image_name
+    );
+    let mut reader = BufReader::new(File::open(&image)?);
+    let sheet = detect_source(sources, &image_name, &mut reader)?.decode(&mut reader)?;
+    atlas
+        .sprites
+        .iter()
+        .map(|sprite| {
+            let source_image = atlas.cut(&sheet, sprite)?;
+            let input_filename = format!("{} ({})", path, sprite.name);
+            let decoded_image = DecodedImage::from_source(&input_filename, source_image, options)?;
+            Ok((sprite.clone(), decoded_image))
+        })
+        .collect()
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "TexturePacker",
+        message: message.to_string(),
+    }
+}
+
+fn sprite(name: &str, frame: &Value) -> Result<AtlasSprite, ImageReadError> {
+    let number = |value: &Value| {
+        value.as_u64().map(|number| number as u32).ok_or_else(|| {
+            invalid(&format!(
+                "sprite '{}' is missing its position or size",
+                name
+            ))
+        })
+    };
+    let rect = &frame["frame"];
+    let (width, height) = (number(&rect["w"])?, number(&rect["h"])?);
+    let trimmed = frame["trimmed"].as_bool().unwrap_or(false);
+    let (offset_x, offset_y, source_width, source_height) = if trimmed {
+        (
+            number(&frame["spriteSourceSize"]["x"])?,
+            number(&frame["spriteSourceSize"]["y"])?,
+            number(&frame["sourceSize"]["w"])?,
+            number(&frame["sourceSize"]["h"])?,
+        )
+    } else {
+        (0, 0, width, height)
+    };
+    let name = match name.rfind('.') {
+        Some(dot) if !name[dot..].contains('/') => &name[..dot],
+        _ => name,
+    };
+    Ok(AtlasSprite {
+        name: name.to_string(),
+        x: number(&rect["x"])?,
+        y: number(&rect["y"])?,
+        width,
+        height,
+        rotated: frame["rotated"].as_bool().unwrap_or(false),
+        offset_x,
+        offset_y,
+        source_width,
+        source_height,
+    })
+}
@@ -0,0 +1,128 @@
+//! Helpers for converting assets from a Cargo build script.
+//!
+//! ```no_run
+//! // build.rs
+//! use gbtile::OutputType;
+//!
+//! fn main() {
+//!     gbtile::build::Builder::new("assets")
+//!         .output_type(OutputType::Rgbds)
+//!         .run()
+//!         .expect("Could not convert tiles");
+//! }
+//! ```
+//!
+//! Every image under the asset directory is converted into a file of the
+//! same relative path under `OUT_DIR`, with the extension of the output
+//! format. Cargo is told to re-run the build script when any asset changes,
+//! and outputs are only rewritten when their contents differ, so unchanged
+//! tiles don't trigger downstream rebuilds.
+
+use crate::image::{ColorNumber, ImageReadError};
+use crate::source::SourceRegistry;
+use crate::{Converter, Error, OutputFormat, OutputType};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Builder {
+    asset_dir: PathBuf,
+    out_dir: Option<PathBuf>,
+    converter: Converter,
+}
+
+impl Builder {
+    pub fn new<P: AsRef<Path>>(asset_dir: P) -> Self {
+        Builder {
+            asset_dir: asset_dir.as_ref().to_path_buf(),
+            out_dir: None,
+            converter: Converter::new(),
+        }
+    }
+
+    /// Write generated files here instead of `OUT_DIR`.
+    pub fn out_dir<P: AsRef<Path>>(mut self, out_dir: P) -> Self {
+        self.out_dir = Some(out_dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn output_type(mut self, output_type: OutputType) -> Self {
+        self.converter = self.converter.output_type(output_type);
+        self
+    }
+
+    pub fn output_format<F: OutputFormat + 'static>(mut self, output_format: F) -> Self {
+        self.converter = self.converter.output_format(output_format);
+        self
+    }
+
+    /// Give fully transparent pixels this color number. Defaults to 0.
+    pub fn transparent_color(mut self, color: ColorNumber) -> Self {
+        self.converter = self.converter.transparent_color(color);
+        self
+    }
+
+    /// Convert every image, returning the paths of the generated files.
+    pub fn run(&self) -> Result<Vec<PathBuf>, Error> {
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is not set")),
+        };
+        println!("cargo:rerun-if-changed={}", self.asset_dir.display());
+
+        let images = find_images(&self.asset_dir).map_err(|err| Error::Read {
+            path: self.asset_dir.display().to_string(),
+            source: ImageReadError::Io(err),
+        })?;
+        let mut generated = Vec::new();
+        for image in images {
+            println!("cargo:rerun-if-changed={}", image.display());
+            let relative = image.strip_prefix(&self.asset_dir).unwrap();
+            let output = out_dir
+                .join(relative)
+                .with_extension(self.converter.file_extension());
+            let contents = self.converter.convert_file(&image.to_string_lossy())?;
+            write_if_changed(&output, &contents).map_err(|source| Error::Write {
+                path: output.display().to_string(),
+                source,
+            })?;
+            generated.push(output);
+        }
+        Ok(generated)
+    }
+}
+
+/// Recursively find every file under `dir` with an extension the
+/// built-in image sources search input directories for, as in
+/// [`ImageSource::discovered_extensions`](crate::source::ImageSource::discovered_extensions),
+/// in a stable order.
+pub fn find_images(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let sources = SourceRegistry::default();
+    let mut images = Vec::new();
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            images.extend(find_images(&path)?);
+        } else if sources.discovers_extension(&path) {
+            images.push(path);
+        }
+    }
+    Ok(images)
+}
+
+fn write_if_changed(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if fs::read(path)
+        .map(|existing| existing == contents)
+        .unwrap_or(false)
+    {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)
+}
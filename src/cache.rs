@@ -0,0 +1,123 @@
+//! A record of which outputs are up to date, so unchanged inputs can be
+//! skipped instead of being decoded, encoded and written again.
+//!
+//! Each output is stored with a key hashing its inputs' contents and the
+//! options it was generated with, along with a hash of the output itself so
+//! outputs that were edited or deleted since are regenerated.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "gbtile-cache 1";
+
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Entry {
+    key: u64,
+    output_hash: u64,
+}
+
+impl Cache {
+    /// Load the cache at `path`. A missing or unreadable cache is empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .filter(|contents| contents.lines().next() == Some(HEADER))
+            .map(|contents| contents.lines().skip(1).filter_map(parse_entry).collect())
+            .unwrap_or_default();
+        Cache { path, entries }
+    }
+
+    /// The key for an output generated from `inputs` with `options`, which
+    /// should describe everything else that affects the output.
+    pub fn key(inputs: &[String], options: &str) -> io::Result<u64> {
+        let mut hasher = Fnv1a::new();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write(options.as_bytes());
+        for input in inputs {
+            hasher.write(input.as_bytes());
+            hasher.write(&fs::read(input)?);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Whether `output` was generated with `key` and hasn't changed since.
+    pub fn is_up_to_date(&self, output: &str, key: u64) -> bool {
+        let entry = match self.entries.get(output) {
+            Some(entry) if entry.key == key => entry,
+            _ => return false,
+        };
+        match fs::read(output) {
+            Ok(contents) => hash(&contents) == entry.output_hash,
+            Err(_) => false,
+        }
+    }
+
+    /// Record that `output` was just generated with `key`.
+    pub fn insert(&mut self, output: &str, key: u64) -> io::Result<()> {
+        let output_hash = hash(&fs::read(output)?);
+        self.entries
+            .insert(output.to_string(), Entry { key, output_hash });
+        Ok(())
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut contents = format!("{}\n", HEADER);
+        for (output, entry) in &self.entries {
+            contents.push_str(&format!(
+                "{:016x} {:016x} {}\n",
+                entry.key, entry.output_hash, output
+            ));
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, contents)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(String, Entry)> {
+    let mut fields = line.splitn(3, ' ');
+    let key = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let output_hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let output = fields.next()?.to_string();
+    Some((output, Entry { key, output_hash }))
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is guaranteed not to
+/// change between Rust versions, so the cache stays valid across upgrades.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Hash `bytes`, prefixed with their length so that writing "ab" then
+    /// "c" differs from writing "a" then "bc".
+    fn write(&mut self, bytes: &[u8]) {
+        let len = (bytes.len() as u64).to_le_bytes();
+        for &byte in len.iter().chain(bytes) {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,190 @@
+use crate::image::{
+    brightness_color_numbers, ColorUse, DecodeOptions, ImageReadError, Rgb, GB_MAX_COLOR_COUNT,
+};
+use crate::sgb::SGB_PALETTE_COUNT;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How many background palettes the Game Boy Color holds.
+pub const CGB_PALETTE_COUNT: usize = 8;
+
+/// Game Boy Color palettes shared out between an image's tiles, so the
+/// image can use more than four colors as long as each tile uses at most
+/// four of them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CgbPalettes {
+    /// The color numbers of each palette's opaque colors.
+    pub color_numbers: Vec<BTreeMap<Rgb, u8>>,
+    /// The palette each tile uses, left to right and top to bottom.
+    pub tile_palettes: Vec<u8>,
+}
+
+/// Share out up to [`CGB_PALETTE_COUNT`] palettes between the 8x8 tiles of
+/// `image_data`, `width` pixels wide. Each palette holds four colors, less
+/// one if `transparent_color` is kept, numbered by brightness like a single
+/// palette's.
+///
+/// For the Super Game Boy, there are only [`SGB_PALETTE_COUNT`], and color
+/// 0 is shared between them. Unless transparent pixels take color 0, the
+/// image's lightest color goes in every palette to be it.
+///
+/// Tiles `palette_mask` marks go in the palette it gives them first. Then
+/// the other tiles, those with the most colors first, each go into the
+/// palette they add the fewest colors to. `color_use` gives the source
+/// color and position of a pixel, for errors.
+pub(crate) fn assign_palettes(
+    image_data: &[Option<Rgb>],
+    width: u32,
+    transparent_color: Option<u8>,
+    palette_mask: Option<&[Option<u8>]>,
+    options: &DecodeOptions,
+    color_use: impl Fn(usize) -> ColorUse,
+) -> Result<CgbPalettes, ImageReadError> {
+    let palette_count = match options.sgb {
+        true => SGB_PALETTE_COUNT,
+        false => CGB_PALETTE_COUNT,
+    };
+    let shared = match options.sgb && transparent_color != Some(0) {
+        true => image_data
+            .iter()
+            .flatten()
+            .max_by(|a, b| options.luminance.compare(options.gamma, **a, **b))
+            .copied(),
+        false => None,
+    };
+    let capacity =
+        GB_MAX_COLOR_COUNT - transparent_color.is_some() as usize - shared.is_some() as usize;
+    let width = width as usize;
+    let (columns, rows) = (width / 8, image_data.len() / width.max(1) / 8);
+    let mut tile_colors: Vec<BTreeSet<Rgb>> = Vec::with_capacity(columns * rows);
+    // The first pixel of each tile, and of each color past the capacity.
+    let mut tile_starts = Vec::with_capacity(columns * rows);
+    let mut tile_marks = Vec::with_capacity(columns * rows);
+    let mut stray = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let mut colors = BTreeSet::new();
+            let mut marks = BTreeSet::new();
+            for y in 0..8 {
+                let start = (row * 8 + y) * width + column * 8;
+                for (i, pixel) in image_data[start..start + 8].iter().enumerate() {
+                    if let Some(rgb) = pixel.filter(|rgb| Some(*rgb) != shared) {
+                        if colors.insert(rgb) && colors.len() > capacity {
+                            stray.push(color_use(start + i));
+                        }
+                    }
+                }
+                if let Some(palette_mask) = palette_mask {
+                    marks.extend(&palette_mask[start..start + 8]);
+                }
+            }
+            let start = row * 8 * width + column * 8;
+            if marks.len() > 1 {
+                let first = color_use(start);
+                return Err(ImageReadError::Invalid {
+                    format: "palette mask",
+                    message: format!(
+                        "tile row {}, column {} isn't covered by a single color",
+                        first.y / 8,
+                        first.x / 8
+                    ),
+                });
+            }
+            tile_colors.push(colors);
+            tile_starts.push(start);
+            tile_marks.push(marks.pop_first().flatten());
+        }
+    }
+    if !stray.is_empty() {
+        return Err(ImageReadError::too_many_colors(stray, options.sprite));
+    }
+
+    let mut order: Vec<usize> = (0..tile_colors.len()).collect();
+    order.sort_by_key(|tile| Reverse(tile_colors[*tile].len()));
+    let marked = tile_marks
+        .iter()
+        .flatten()
+        .max()
+        .map_or(0, |palette| *palette as usize + 1);
+    if marked > palette_count {
+        return Err(ImageReadError::Invalid {
+            format: "palette mask",
+            message: format!(
+                "it marks {} palettes, but there are only {}",
+                marked, palette_count
+            ),
+        });
+    }
+    let mut palettes: Vec<BTreeSet<Rgb>> = vec![BTreeSet::new(); marked];
+    let mut tile_palettes = vec![0; tile_colors.len()];
+    for &tile in &order {
+        let Some(palette) = tile_marks[tile] else {
+            continue;
+        };
+        let colors = &tile_colors[tile];
+        let palette_colors = &mut palettes[palette as usize];
+        palette_colors.extend(colors);
+        if palette_colors.len() > capacity {
+            let first = color_use(tile_starts[tile]);
+            return Err(ImageReadError::PaletteMaskFull {
+                x: first.x,
+                y: first.y,
+                colors: colors.iter().chain(shared.as_ref()).copied().collect(),
+                palette,
+                total: palette_colors.len() + shared.is_some() as usize,
+            });
+        }
+        tile_palettes[tile] = palette;
+    }
+    for tile in order {
+        if tile_marks[tile].is_some() {
+            continue;
+        }
+        let colors = &tile_colors[tile];
+        let best = palettes
+            .iter()
+            .enumerate()
+            .map(|(palette, palette_colors)| {
+                let added = colors.difference(palette_colors).count();
+                (palette, palette_colors.len() + added, added)
+            })
+            .filter(|(_, len, _)| *len <= capacity)
+            .min_by_key(|(_, _, added)| *added);
+        let palette = match best {
+            Some((palette, _, _)) => palette,
+            None if palettes.len() < palette_count => {
+                palettes.push(BTreeSet::new());
+                palettes.len() - 1
+            }
+            None => {
+                let first = color_use(tile_starts[tile]);
+                let total: BTreeSet<&Rgb> = tile_colors
+                    .iter()
+                    .flatten()
+                    .chain(shared.as_ref())
+                    .collect();
+                return Err(ImageReadError::TooManyPalettes {
+                    x: first.x,
+                    y: first.y,
+                    colors: colors.iter().chain(shared.as_ref()).copied().collect(),
+                    total: total.len(),
+                    palettes: palette_count,
+                });
+            }
+        };
+        palettes[palette].extend(colors);
+        tile_palettes[tile] = palette as u8;
+    }
+    for palette in &mut palettes {
+        palette.extend(shared);
+    }
+    debug!("Palettes are: {:?}", palettes);
+
+    Ok(CgbPalettes {
+        color_numbers: palettes
+            .iter()
+            .map(|colors| brightness_color_numbers(colors, transparent_color, options))
+            .collect(),
+        tile_palettes,
+    })
+}
@@ -0,0 +1,342 @@
+//! Project config files describing many conversions, usually `gbtile.toml`:
+//!
+//! ```toml
+//! [[job]]
+//! input = "sprites/player.png"
+//! output = "gen/player.asm"
+//! type = "rgbds"
+//!
+//! [[job]]
+//! input = "backgrounds/title.png"
+//! output = "gen/title.h"
+//! ```
+//!
+//! `input` may also be a list of images, which are all written into the one
+//! output file.
+//!
+//! `regions` names a [region manifest](crate::regions), converting each
+//! region of a single input into its own tile block.
+//!
+//! `palette-mask` names an image the same size as the inputs marking the
+//! palette each tile takes when palettes are shared out, as in
+//! [`SourceImage::with_palette_mask`](crate::SourceImage::with_palette_mask).
+//!
+//! Relative paths are resolved against the directory holding the config file.
+//!
+//! A `[defaults]` table sets options for flags that weren't given on the
+//! command line or through `GBTILE_*` environment variables:
+//!
+//! ```toml
+//! [defaults]
+//! type = "rgbds"
+//! prefix = "gfx_"
+//! transparent-color = 0
+//! sprite = false
+//! cgb = false
+//! cgb-color-correction = true
+//! obp = 1
+//! dmg-compatible = true
+//! order = "column"
+//! metatile = "16x16"
+//! base-index = 96
+//! addressing = "8800"
+//! bpp = 1
+//! font = true
+//! first-char = 32
+//! trim-blank = true
+//! shared-tileset = true
+//! split-banks = "rom"
+//! delta-frames = true
+//! metasprites = true
+//! sgb = false
+//! alpha-threshold = 128
+//! grid = "16x16"
+//! margin = 1
+//! spacing = 2
+//! frames = "16x16"
+//! pad = 0
+//! truncate = false
+//! palette = "#0f380f,#306230,#8bac0f,#9bbc0f"
+//! strict-palette = true
+//! map = "ff0000=3,00ff00=0"
+//! luminance = "rec709"
+//! gamma = "linear"
+//! color-tolerance = 32
+//! auto-contrast = true
+//! posterize = 4
+//! quantize = true
+//! dither = "floyd-steinberg"
+//! ```
+//!
+//! `palette-file` can name a GIMP or JASC palette file to use instead of
+//! `palette`, and `palette-preset` a built-in palette like `"dmg-green"`.
+
+use crate::{
+    parse_color_map, parse_palette, read_palette_file, Addressing, Bpp, ColorNumber, Dither, Error,
+    Gamma, Grid, Luminance, MetatileSize, ObjectPalette, PalettePreset, Rgb, SplitBanks, TileOrder,
+    GB_MAX_COLOR_COUNT,
+};
+use serde::{Deserialize, Deserializer};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The config file name looked for by [`Config::discover`].
+pub const CONFIG_FILE_NAME: &str = "gbtile.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "job", default)]
+    pub jobs: Vec<Job>,
+    #[serde(default)]
+    pub defaults: Defaults,
+}
+
+/// Project-wide defaults for command line options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    /// The output format name, for jobs that don't set one.
+    #[serde(rename = "type")]
+    pub output_type: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    /// The color number of fully transparent pixels.
+    #[serde(rename = "transparent-color", default, deserialize_with = "number")]
+    pub transparent_color: Option<ColorNumber>,
+    /// Whether to follow the sprite color convention.
+    pub sprite: Option<bool>,
+    /// Whether to convert in Game Boy Color mode.
+    pub cgb: Option<bool>,
+    /// Whether to convert in Super Game Boy mode.
+    pub sgb: Option<bool>,
+    /// Whether to correct Game Boy Color palettes for the screen.
+    #[serde(rename = "cgb-color-correction")]
+    pub cgb_color_correction: Option<bool>,
+    /// The object palette sprites use, 0 or 1.
+    #[serde(default, deserialize_with = "number")]
+    pub obp: Option<ObjectPalette>,
+    /// Whether to number colors by their original Game Boy shade.
+    #[serde(rename = "dmg-compatible")]
+    pub dmg_compatible: Option<bool>,
+    /// The order tiles are written in.
+    #[serde(default, deserialize_with = "parsed")]
+    pub order: Option<TileOrder>,
+    /// The size of the metatiles to group tiles into.
+    #[serde(default, deserialize_with = "parsed")]
+    pub metatile: Option<MetatileSize>,
+    /// The VRAM tile number maps count tiles from.
+    #[serde(rename = "base-index")]
+    pub base_index: Option<u8>,
+    /// How the background reads the tile numbers maps draw with.
+    #[serde(default, deserialize_with = "parsed")]
+    pub addressing: Option<Addressing>,
+    /// The bits per pixel tiles are written with, 1 or 2.
+    #[serde(default, deserialize_with = "number")]
+    pub bpp: Option<Bpp>,
+    /// Whether to convert images as fonts.
+    pub font: Option<bool>,
+    /// The character the first glyph of a font draws.
+    #[serde(rename = "first-char")]
+    pub first_char: Option<u8>,
+    /// Whether to leave out tiles that are entirely color 0.
+    #[serde(rename = "trim-blank")]
+    pub trim_blank: Option<bool>,
+    /// Whether the inputs of a job share one tileset.
+    #[serde(rename = "shared-tileset")]
+    pub shared_tileset: Option<bool>,
+    /// The banks tile data is split to fit, `vram` or `rom`.
+    #[serde(default, rename = "split-banks", deserialize_with = "parsed")]
+    pub split_banks: Option<SplitBanks>,
+    /// Whether animations are written as the changes between frames.
+    #[serde(rename = "delta-frames")]
+    pub delta_frames: Option<bool>,
+    /// Whether to make a metasprite of each animation frame.
+    pub metasprites: Option<bool>,
+    /// The alpha below which pixels are transparent.
+    #[serde(rename = "alpha-threshold")]
+    pub alpha_threshold: Option<u8>,
+    /// The size of the cells sprite sheets are cut into.
+    #[serde(default, deserialize_with = "parsed")]
+    pub grid: Option<Grid>,
+    /// The pixels around a sheet before its first cell.
+    pub margin: Option<u32>,
+    /// The pixels between a sheet's cells.
+    pub spacing: Option<u32>,
+    /// The size of the animation frames sheets are cut into.
+    #[serde(default, deserialize_with = "parsed")]
+    pub frames: Option<Grid>,
+    /// The color number images are padded to whole tiles with.
+    #[serde(default, deserialize_with = "number")]
+    pub pad: Option<ColorNumber>,
+    /// Whether to drop the edge pixels of images that aren't whole tiles
+    /// instead of failing.
+    pub truncate: Option<bool>,
+    /// The colors of color numbers 0 to 3, listed darkest first as in
+    /// [`parse_palette`].
+    #[serde(default, deserialize_with = "palette")]
+    pub palette: Option<[Rgb; GB_MAX_COLOR_COUNT]>,
+    /// A palette file giving [`palette`](Defaults::palette) instead, read
+    /// when the config is loaded.
+    #[serde(rename = "palette-file")]
+    pub palette_file: Option<String>,
+    /// A built-in palette giving [`palette`](Defaults::palette) instead.
+    #[serde(rename = "palette-preset", default, deserialize_with = "parsed")]
+    pub palette_preset: Option<PalettePreset>,
+    /// Source colors pinned to color numbers, as in [`parse_color_map`].
+    #[serde(rename = "map", default, deserialize_with = "color_map")]
+    pub color_map: Option<[Option<Rgb>; GB_MAX_COLOR_COUNT]>,
+    /// Whether colors must exactly match the palette's.
+    #[serde(rename = "strict-palette")]
+    pub strict_palette: Option<bool>,
+    /// How colors are ordered from lightest to darkest.
+    #[serde(default, deserialize_with = "parsed")]
+    pub luminance: Option<Luminance>,
+    /// How colors' channels are read when comparing them.
+    #[serde(default, deserialize_with = "parsed")]
+    pub gamma: Option<Gamma>,
+    /// How many values of each channel are rounded together.
+    #[serde(rename = "color-tolerance")]
+    pub color_tolerance: Option<u8>,
+    /// Whether images with too many colors are reduced to four.
+    pub quantize: Option<bool>,
+    /// Whether to stretch images' contrast before reading colors.
+    #[serde(rename = "auto-contrast")]
+    pub auto_contrast: Option<bool>,
+    /// How many levels to round each channel to before reading colors.
+    pub posterize: Option<u8>,
+    /// How quantized images' colors are spread over their pixels.
+    #[serde(default, deserialize_with = "parsed")]
+    pub dither: Option<Dither>,
+}
+
+/// A single image to convert.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    #[serde(deserialize_with = "one_or_many")]
+    pub input: Vec<String>,
+    pub output: String,
+    /// The output format name. Defaults to `gbdk`.
+    #[serde(rename = "type")]
+    pub output_type: Option<String>,
+    /// The generated symbol name. Defaults to the input file stem, and can
+    /// only be set for a single input.
+    pub name: Option<String>,
+    /// A [region manifest](crate::regions), converting each region of the
+    /// input into its own tile block. Only for a single input.
+    pub regions: Option<String>,
+    /// An image marking the palette each tile of the inputs takes when
+    /// palettes are shared out.
+    #[serde(rename = "palette-mask")]
+    pub palette_mask: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, Error> {
+        let config_error = |message: String| Error::Config {
+            path: path.to_string(),
+            message,
+        };
+        let contents = fs::read_to_string(path).map_err(|err| config_error(err.to_string()))?;
+        let mut config: Config =
+            toml::from_str(&contents).map_err(|err| config_error(err.to_string()))?;
+
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        for job in &mut config.jobs {
+            for input in &mut job.input {
+                *input = base.join(&*input).to_string_lossy().into_owned();
+            }
+            job.output = base.join(&job.output).to_string_lossy().into_owned();
+            if let Some(regions) = &mut job.regions {
+                *regions = base.join(&*regions).to_string_lossy().into_owned();
+            }
+            if let Some(palette_mask) = &mut job.palette_mask {
+                *palette_mask = base.join(&*palette_mask).to_string_lossy().into_owned();
+            }
+        }
+        if let Some(palette_file) = &mut config.defaults.palette_file {
+            if config.defaults.palette.is_some() {
+                return Err(config_error(
+                    "give either a palette or a palette file, not both".to_string(),
+                ));
+            }
+            *palette_file = base.join(&*palette_file).to_string_lossy().into_owned();
+            config.defaults.palette = Some(read_palette_file(palette_file)?);
+        }
+        if let Some(preset) = config.defaults.palette_preset {
+            if config.defaults.palette.is_some() {
+                return Err(config_error(
+                    "give either a palette, a palette file or a palette preset, not more than one"
+                        .to_string(),
+                ));
+            }
+            config.defaults.palette = Some(preset.colors());
+        }
+        Ok(config)
+    }
+
+    /// The nearest [`CONFIG_FILE_NAME`] in `dir` or any of its parents.
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .find(|path| path.is_file())
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(
+        untagged,
+        expecting = "expected an image path or a list of image paths"
+    )]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(input) => vec![input],
+        OneOrMany::Many(inputs) => inputs,
+    })
+}
+
+fn palette<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[Rgb; GB_MAX_COLOR_COUNT]>, D::Error> {
+    let palette = String::deserialize(deserializer)?;
+    parse_palette(&palette)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn color_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[Option<Rgb>; GB_MAX_COLOR_COUNT]>, D::Error> {
+    let color_map = String::deserialize(deserializer)?;
+    parse_color_map(&color_map)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// A number checked like the matching command line option.
+fn number<'de, D: Deserializer<'de>, T: TryFrom<u8, Error = String>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    let value = u8::deserialize(deserializer)?;
+    T::try_from(value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// A value parsed from a string, like the matching command line option.
+fn parsed<'de, D: Deserializer<'de>, T: FromStr>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T::Err: Display,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,49 @@
+use crate::tile::EncodedTile;
+use crate::vram::TILE_BYTES;
+use std::collections::HashMap;
+
+/// An animation written as the tiles that change from one frame to the
+/// next, so only those are kept in ROM and copied to VRAM each frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameDeltas {
+    /// The tiles copied by the patches, each distinct tile once, encoded as
+    /// in [`EncodedTile::tile_data`].
+    pub tile_data: Vec<u8>,
+    /// The patches drawing each frame over the frame before it, or over the
+    /// last frame for frame 0, so the animation loops: the number of each
+    /// tile that changes, counted from the frame's first tile, and the
+    /// number of the tile in `tile_data` to copy over it.
+    pub patches: Vec<Vec<(usize, usize)>>,
+}
+
+/// Find the tiles of each of `encoded_tile`'s animation frames that differ
+/// from the frame before's, as in [`FrameDeltas`]. Tiles that change to the
+/// same pixels share their data.
+pub fn delta_frames(encoded_tile: &EncodedTile) -> FrameDeltas {
+    let frames: Vec<&[u8]> = encoded_tile.frame_data().collect();
+    let mut deltas = FrameDeltas {
+        tile_data: Vec::new(),
+        patches: Vec::with_capacity(frames.len()),
+    };
+    // Each distinct changed tile's data, and its number in `tile_data`.
+    let mut numbers: HashMap<&[u8], usize> = HashMap::new();
+    for (frame, frame_data) in frames.iter().enumerate() {
+        let previous = frames[(frame + frames.len() - 1) % frames.len()];
+        let patches = frame_data
+            .chunks(TILE_BYTES)
+            .zip(previous.chunks(TILE_BYTES))
+            .enumerate()
+            .filter(|(_, (tile, previous))| tile != previous)
+            .map(|(tile, (data, _))| {
+                let next = numbers.len();
+                let number = *numbers.entry(data).or_insert(next);
+                if number == next {
+                    deltas.tile_data.extend_from_slice(data);
+                }
+                (tile, number)
+            })
+            .collect();
+        deltas.patches.push(patches);
+    }
+    deltas
+}
@@ -0,0 +1,79 @@
+#[cfg(feature = "json")]
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A machine readable error or warning about a particular input.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable, kebab-case identifier for the kind of problem, e.g. `too-many-colors`.
+    pub code: &'static str,
+    pub file: String,
+    pub message: String,
+    /// The pixel coordinates the problem was found at, if it has one.
+    pub location: Option<(u32, u32)>,
+}
+
+impl Diagnostic {
+    pub fn warning(code: &'static str, file: &str, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code,
+            file: file.to_string(),
+            message,
+            location: None,
+        }
+    }
+
+    /// Where the problem is, compiler style: `file` or `file:x,y`.
+    pub fn position(&self) -> String {
+        match self.location {
+            Some((x, y)) => format!("{}:{},{}", self.file, x, y),
+            None => self.file.clone(),
+        }
+    }
+
+    /// Encode as a single line JSON object.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        let mut object = Map::new();
+        object.insert("severity".to_string(), json!(self.severity.as_str()));
+        object.insert("code".to_string(), json!(self.code));
+        object.insert("file".to_string(), json!(self.file));
+        object.insert("message".to_string(), json!(self.message));
+        if let Some((x, y)) = self.location {
+            object.insert("x".to_string(), json!(x));
+            object.insert("y".to_string(), json!(y));
+        }
+        Value::Object(object).to_string()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// Formats like a compiler message, e.g.
+    /// `error: image.png:12,40: pixel uses a 5th color, ...`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.severity.as_str(),
+            self.position(),
+            self.message
+        )
+    }
+}
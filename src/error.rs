@@ -0,0 +1,86 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::image::ImageReadError;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// A conversion failure, along with the file that caused it.
+#[derive(Debug)]
+pub enum Error {
+    /// The input image could not be read, or isn't usable as Game Boy tiles.
+    Read {
+        path: String,
+        source: ImageReadError,
+    },
+    /// The generated output could not be written.
+    Write { path: String, source: io::Error },
+    /// A project config file could not be read or parsed.
+    Config { path: String, message: String },
+    /// An input argument didn't name any usable images.
+    Input { path: String, message: String },
+}
+
+impl Error {
+    /// The file the error refers to.
+    pub fn path(&self) -> &str {
+        match self {
+            Error::Read { path, .. } => path,
+            Error::Write { path, .. } => path,
+            Error::Config { path, .. } => path,
+            Error::Input { path, .. } => path,
+        }
+    }
+
+    /// A stable identifier for this kind of error, used in diagnostics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Read { source, .. } => source.code(),
+            Error::Write { .. } => "write",
+            Error::Config { .. } => "config",
+            Error::Input { .. } => "input",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (message, location) = match self {
+            Error::Read { source, .. } => (source.to_string(), source.location()),
+            Error::Write { source, .. } => (source.to_string(), None),
+            Error::Config { message, .. } => (message.clone(), None),
+            Error::Input { message, .. } => (message.clone(), None),
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            code: self.code(),
+            file: self.path().to_string(),
+            message,
+            location,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read { path, source } => {
+                write!(f, "could not convert '{}': {}", path, source)?;
+                if let Some((x, y)) = source.location() {
+                    write!(f, " (at pixel {},{})", x, y)?;
+                }
+                Ok(())
+            }
+            Error::Write { path, source } => write!(f, "could not write '{}': {}", path, source),
+            Error::Config { path, message } => write!(f, "invalid config '{}': {}", path, message),
+            Error::Input { path, message } => write!(f, "invalid input '{}': {}", path, message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Read { source, .. } => Some(source),
+            Error::Write { source, .. } => Some(source),
+            Error::Config { .. } | Error::Input { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! A C interface for embedding gbtile in non-Rust tools.
+//!
+//! Build with `cargo build --release --features ffi` and link against the
+//! resulting `libgbtile` shared library. Declarations are in `include/gbtile.h`.
+
+use crate::{Converter, FormatRegistry};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+pub const GBTILE_OK: c_int = 0;
+pub const GBTILE_INVALID_ARGUMENT: c_int = 1;
+pub const GBTILE_CONVERSION_FAILED: c_int = 2;
+
+/// Bytes allocated by gbtile. Release with [`gbtile_buffer_free`].
+#[repr(C)]
+pub struct GbtileBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+fn to_buffer(bytes: Vec<u8>) -> GbtileBuffer {
+    let bytes = bytes.into_boxed_slice();
+    let len = bytes.len();
+    GbtileBuffer {
+        data: Box::into_raw(bytes) as *mut u8,
+        len,
+    }
+}
+
+unsafe fn read_str<'a>(value: *const c_char, argument: &str) -> Result<&'a str, c_int> {
+    if value.is_null() {
+        set_last_error(format!("{} must not be NULL", argument));
+        return Err(GBTILE_INVALID_ARGUMENT);
+    }
+    CStr::from_ptr(value).to_str().map_err(|_| {
+        set_last_error(format!("{} is not valid UTF-8", argument));
+        GBTILE_INVALID_ARGUMENT
+    })
+}
+
+/// Run `f`, turning a panic into a failed conversion, since unwinding into
+/// C is undefined behavior.
+fn catch_panic<F: FnOnce() -> Result<(), c_int>>(f: F) -> Result<(), c_int> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        set_last_error(format!("conversion panicked: {}", message));
+        Err(GBTILE_CONVERSION_FAILED)
+    })
+}
+
+unsafe fn convert(
+    input: *const u8,
+    input_len: usize,
+    name: *const c_char,
+    output_type: Option<*const c_char>,
+    out: *mut GbtileBuffer,
+) -> Result<(), c_int> {
+    if input.is_null() || out.is_null() {
+        set_last_error("input and out must not be NULL".to_string());
+        return Err(GBTILE_INVALID_ARGUMENT);
+    }
+    let image = slice::from_raw_parts(input, input_len);
+    let name = read_str(name, "name")?;
+    let encoded_tile = Converter::new().encode_bytes(name, image).map_err(|err| {
+        set_last_error(err.to_string());
+        GBTILE_CONVERSION_FAILED
+    })?;
+    let bytes = match output_type {
+        Some(output_type) => {
+            let output_type = read_str(output_type, "output_type")?;
+            let formats = FormatRegistry::default();
+            let output_format = formats.get(output_type).ok_or_else(|| {
+                set_last_error(format!("unknown output type: {}", output_type));
+                GBTILE_INVALID_ARGUMENT
+            })?;
+            output_format.render(&encoded_tile)
+        }
+        None => encoded_tile.tile_data,
+    };
+    *out = to_buffer(bytes);
+    Ok(())
+}
+
+/// Convert an in-memory image into generated source text.
+///
+/// `name` is used for the generated symbol and `output_type` is a format
+/// name such as `"gbdk"` or `"rgbds"`. On success, `out` receives a buffer
+/// that must be released with [`gbtile_buffer_free`].
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes, `name` and
+/// `output_type` must be NUL terminated strings and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn gbtile_convert(
+    input: *const u8,
+    input_len: usize,
+    name: *const c_char,
+    output_type: *const c_char,
+    out: *mut GbtileBuffer,
+) -> c_int {
+    match catch_panic(|| convert(input, input_len, name, Some(output_type), out)) {
+        Ok(()) => GBTILE_OK,
+        Err(code) => code,
+    }
+}
+
+/// Convert an in-memory image into raw 2bpp tile data, 16 bytes per tile.
+///
+/// # Safety
+///
+/// Same requirements as [`gbtile_convert`].
+#[no_mangle]
+pub unsafe extern "C" fn gbtile_encode(
+    input: *const u8,
+    input_len: usize,
+    name: *const c_char,
+    out: *mut GbtileBuffer,
+) -> c_int {
+    match catch_panic(|| convert(input, input_len, name, None, out)) {
+        Ok(()) => GBTILE_OK,
+        Err(code) => code,
+    }
+}
+
+/// Release a buffer returned by gbtile. Safe to call on an empty buffer.
+///
+/// # Safety
+///
+/// `buffer` must have been filled in by gbtile and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn gbtile_buffer_free(buffer: *mut GbtileBuffer) {
+    if buffer.is_null() || (*buffer).data.is_null() {
+        return;
+    }
+    let bytes = slice::from_raw_parts_mut((*buffer).data, (*buffer).len);
+    drop(Box::from_raw(bytes as *mut [u8]));
+    (*buffer).data = ptr::null_mut();
+    (*buffer).len = 0;
+}
+
+/// The message for the last failed call on this thread, or NULL. The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn gbtile_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_PNG: &[u8] = include_bytes!("../img/ascii.png");
+    const EMPTY: &[u8] = b"\0";
+    const GBDK: &[u8] = b"gbdk\0";
+
+    #[test]
+    fn empty_names_fail_without_panicking() {
+        let mut out = GbtileBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        let code = unsafe {
+            gbtile_convert(
+                ASCII_PNG.as_ptr(),
+                ASCII_PNG.len(),
+                EMPTY.as_ptr() as *const c_char,
+                GBDK.as_ptr() as *const c_char,
+                &mut out,
+            )
+        };
+        assert_eq!(code, GBTILE_CONVERSION_FAILED);
+        assert!(out.data.is_null());
+        assert!(!gbtile_last_error().is_null());
+        let code = unsafe {
+            gbtile_encode(
+                ASCII_PNG.as_ptr(),
+                ASCII_PNG.len(),
+                EMPTY.as_ptr() as *const c_char,
+                &mut out,
+            )
+        };
+        assert_eq!(code, GBTILE_CONVERSION_FAILED);
+    }
+
+    #[test]
+    fn panics_fail_the_conversion() {
+        let result = catch_panic(|| panic!("tile out of range"));
+        assert_eq!(result, Err(GBTILE_CONVERSION_FAILED));
+        let message = unsafe { CStr::from_ptr(gbtile_last_error()) };
+        assert!(message.to_str().unwrap().contains("tile out of range"));
+    }
+}
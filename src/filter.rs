@@ -0,0 +1,67 @@
+use crate::image::{DecodeOptions, Rgba};
+use std::borrow::Cow;
+
+/// Apply the filters `options` asks for to `pixels` before their colors are
+/// read: stretching their contrast, then posterizing them. Transparent
+/// pixels are left alone.
+pub(crate) fn filter_pixels<'a>(pixels: &'a [Rgba], options: &DecodeOptions) -> Cow<'a, [Rgba]> {
+    if !options.auto_contrast && options.posterize.is_none() {
+        return Cow::Borrowed(pixels);
+    }
+    let mut pixels = pixels.to_vec();
+    if options.auto_contrast {
+        auto_contrast(&mut pixels, options.alpha_threshold);
+    }
+    if let Some(levels) = options.posterize {
+        for pixel in pixels.iter_mut() {
+            *pixel = posterize(*pixel, levels);
+        }
+    }
+    Cow::Owned(pixels)
+}
+
+/// Stretch the channels of `pixels` so the lowest value of any opaque
+/// pixel's channels becomes 0 and the highest 255. Every channel is
+/// stretched alike, so hues stay as they were.
+fn auto_contrast(pixels: &mut [Rgba], alpha_threshold: u8) {
+    let values = pixels
+        .iter()
+        .filter(|pixel| !pixel.is_below(alpha_threshold))
+        .flat_map(|pixel| [pixel.r, pixel.g, pixel.b]);
+    let (low, high) = values.fold((u8::MAX, u8::MIN), |(low, high), value| {
+        (low.min(value), high.max(value))
+    });
+    if low >= high {
+        return;
+    }
+    debug!("Stretching contrast from {} to {}", low, high);
+    let stretch = |value: u8| {
+        let value = u32::from(value.clamp(low, high) - low);
+        let range = u32::from(high - low);
+        ((value * 255 + range / 2) / range) as u8
+    };
+    for pixel in pixels
+        .iter_mut()
+        .filter(|pixel| !pixel.is_below(alpha_threshold))
+    {
+        pixel.r = stretch(pixel.r);
+        pixel.g = stretch(pixel.g);
+        pixel.b = stretch(pixel.b);
+    }
+}
+
+/// Round each of `pixel`'s channels to the nearest of `levels` evenly spaced
+/// values from 0 to 255.
+pub(crate) fn posterize(pixel: Rgba, levels: u8) -> Rgba {
+    let steps = u32::from(levels.max(2) - 1);
+    let round = |value: u8| {
+        let step = (u32::from(value) * steps + 127) / 255;
+        ((step * 255 + steps / 2) / steps) as u8
+    };
+    Rgba {
+        r: round(pixel.r),
+        g: round(pixel.g),
+        b: round(pixel.b),
+        a: pixel.a,
+    }
+}
@@ -0,0 +1,449 @@
+use super::{char_constant, map_name, metatile_names, variable_name, OutputFormat};
+use crate::delta::FrameDeltas;
+use crate::image::GB_MAX_COLOR_COUNT;
+use crate::metasprite::metasprite_pivot;
+use crate::tile::EncodedTile;
+use crate::vram::TILE_BYTES;
+
+/// C `unsigned char` arrays for GBDK.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Gbdk;
+
+impl OutputFormat for Gbdk {
+    fn name(&self) -> &'static str {
+        "gbdk"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "h"
+    }
+
+    fn render(&self, encoded_tile: &EncodedTile) -> Vec<u8> {
+        write_tile_gbdk(&variable_name(encoded_tile), encoded_tile).into_bytes()
+    }
+
+    fn comment(&self, text: &str) -> Option<String> {
+        Some(format!("// {}", text))
+    }
+
+    fn declared_symbols(&self, contents: &str) -> Vec<String> {
+        contents
+            .lines()
+            .filter_map(|line| {
+                line.strip_prefix("unsigned char ")
+                    .or_else(|| line.strip_prefix("unsigned int "))
+                    .or_else(|| line.strip_prefix("metasprite_t "))
+                    .or_else(|| line.strip_prefix("#define "))
+            })
+            .filter_map(|declaration| {
+                declaration
+                    .trim_start_matches('*')
+                    .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .next()
+            })
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Format tile data as a C `unsigned char` array for GBDK, in 1bpp for
+/// `set_bkg_1bpp_data` when [`EncodedTile::bpp`] is 1.
+///
+/// Animations get an array per frame instead, named `<name>_frame_<n>`,
+/// followed by `<NAME>_FRAME_COUNT` and `<NAME>_FRAME_TILES` defines, a
+/// `<NAME>_FRAME_<n>_TILE` define giving the offset of each frame's first
+/// tile from the first frame's, and a `<name>_frames` table pointing at
+/// each frame. Animations written as the changes between frames, as in
+/// [`EncodedTile::frame_deltas`], get the first frame, the tiles that
+/// change and the patches copying them instead. Tile data split into banks,
+/// as in [`EncodedTile::bank_tiles`], gets an array per bank.
+///
+/// A `<NAME>_PALETTE` define follows, giving the suggested BGP or OBP
+/// register value, as in [`EncodedTile::palette_register`], then in 1bpp a
+/// `<NAME>_BPP` define of 1, so `gbtile decode` reads the tiles back as
+/// 1bpp. Sprites given an object palette also get `<NAME>_OBP0` or
+/// `<NAME>_OBP1`, the same value, and `<NAME>_OAM_FLAGS`, the flags for
+/// `set_sprite_prop` selecting that palette, as in
+/// [`EncodedTile::oam_flags`]. Fonts get a `<NAME>_FIRST_CHAR` define, and
+/// a define for each character giving the tile drawing it, named as in
+/// [`char_constant`], then a `<name>_widths` array of each glyph's width in
+/// pixels, as in [`EncodedTile::glyph_widths`], for variable-width text.
+/// In CGB mode, a `<name>_cgb_palette` array of `unsigned int` RGB555
+/// words follows it, a line of four per palette, ready for
+/// `set_bkg_palette` or `set_sprite_palette`. Images sharing out several
+/// palettes also get a `<name>_tile_palettes` array giving each tile's
+/// palette number.
+///
+/// In SGB mode, the Super Game Boy's commands follow, as arrays of
+/// packets ready for `sgb_transfer`: `<name>_sgb_pal01` and
+/// `<name>_sgb_pal23` setting the palettes, `<name>_sgb_attr_blk` giving
+/// each tile's palette when the palettes fit in one ATTR_BLK command, and
+/// `<name>_sgb_attr_file`, the same as an attribute file for ATTR_TRN.
+///
+/// Each map drawn with the tiles also gets an array of tile numbers, named
+/// as in [`map_name`], with `<MAP>_WIDTH` and `<MAP>_HEIGHT` defines giving
+/// its size in tiles. In CGB mode, each map is followed by a
+/// `<map>_attributes` array for VRAM bank 1, as in
+/// [`EncodedTile::cgb_attributes`].
+///
+/// Tiles grouped into metatiles get a `<name>_metatiles` array of each
+/// metatile's tile numbers, one after another, named as in
+/// [`metatile_names`], with `<NAME>_METATILES_WIDTH` and
+/// `<NAME>_METATILES_HEIGHT` defines giving a metatile's size in tiles and
+/// `<NAME>_METATILES_COUNT` how many there are, then a
+/// `<name>_metatile_map` array of metatile numbers with `_WIDTH` and
+/// `_HEIGHT` defines giving its size in metatiles. In CGB mode, the
+/// metatiles' attributes follow in `<name>_metatiles_attributes`. Tile
+/// numbers in maps and metatiles are as the background reads them, as in
+/// [`EncodedTile::map_tiles`].
+///
+/// Tiles made into metasprites, as in [`EncodedTile::metasprites`], end
+/// with a GBDK-2020 `metasprite_t` array for each frame and a table
+/// pointing at each, ready for `move_metasprite`.
+pub fn write_tile_gbdk(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let mut formatted = tiles(variable_name, encoded_tile);
+    formatted.push_str(&format!(
+        "\n#define {}_PALETTE {:#04X}\n",
+        variable_name.to_uppercase(),
+        encoded_tile.palette_register
+    ));
+    if encoded_tile.bpp == 1 {
+        formatted.push_str(&format!("#define {}_BPP 1\n", variable_name.to_uppercase()));
+    }
+    if let (Some(obp), Some(oam_flags)) = (encoded_tile.obp, encoded_tile.oam_flags()) {
+        let constant = variable_name.to_uppercase();
+        formatted.push_str(&format!(
+            "#define {}_OBP{} {:#04X}\n#define {}_OAM_FLAGS {:#04X}\n",
+            constant, obp, encoded_tile.palette_register, constant, oam_flags
+        ));
+    }
+    if let Some(first_char) = encoded_tile.first_char {
+        formatted.push_str(&format!(
+            "#define {}_FIRST_CHAR {}\n",
+            variable_name.to_uppercase(),
+            first_char
+        ));
+        for (code, tile) in encoded_tile.char_tiles() {
+            formatted.push_str(&format!(
+                "#define {} {:#04X}\n",
+                char_constant(variable_name, code),
+                tile
+            ));
+        }
+        formatted.push_str(&array(
+            &format!("{}_widths", variable_name),
+            &encoded_tile.glyph_widths(),
+        ));
+    }
+    if encoded_tile.cgb {
+        let palettes: Vec<String> = encoded_tile
+            .cgb_palette()
+            .chunks(GB_MAX_COLOR_COUNT)
+            .map(|palette| {
+                let words: Vec<String> = palette
+                    .iter()
+                    .map(|word| format!("{:#06X}", word))
+                    .collect();
+                format!("    {}", words.join(","))
+            })
+            .collect();
+        formatted.push_str(&format!(
+            "unsigned int {}_cgb_palette[] = {{\n{}\n}};\n",
+            variable_name,
+            palettes.join(",\n")
+        ));
+        if let Some(cgb_palettes) = &encoded_tile.cgb_palettes {
+            formatted.push_str(&array(
+                &format!("{}_tile_palettes", variable_name),
+                &cgb_palettes.tile_palettes,
+            ));
+        }
+    }
+    if encoded_tile.sgb {
+        let [pal01, pal23] = encoded_tile.sgb_palette_packets();
+        formatted.push_str(&array(&format!("{}_sgb_pal01", variable_name), &pal01));
+        formatted.push_str(&array(&format!("{}_sgb_pal23", variable_name), &pal23));
+        if let Some(attr_blk) = encoded_tile.sgb_attribute_blocks() {
+            formatted.push_str(&array(
+                &format!("{}_sgb_attr_blk", variable_name),
+                &attr_blk,
+            ));
+        }
+        formatted.push_str(&array(
+            &format!("{}_sgb_attr_file", variable_name),
+            &encoded_tile.sgb_attribute_file(),
+        ));
+    }
+    for tilemap in &encoded_tile.tilemaps {
+        let map_name = map_name(variable_name, tilemap);
+        let constant = map_name.to_uppercase();
+        formatted.push_str(&format!(
+            "\n#define {}_WIDTH {}\n#define {}_HEIGHT {}\n",
+            constant, tilemap.width, constant, tilemap.height
+        ));
+        formatted.push_str(&array(&map_name, &encoded_tile.map_tiles(&tilemap.tiles)));
+        if encoded_tile.cgb {
+            formatted.push_str(&array(
+                &format!("{}_attributes", map_name),
+                &encoded_tile.cgb_attributes(tilemap),
+            ));
+        }
+    }
+    for metatiles in &encoded_tile.metatiles {
+        let (metatiles_name, map_name) = metatile_names(variable_name, metatiles);
+        let constant = metatiles_name.to_uppercase();
+        formatted.push_str(&format!(
+            "\n#define {}_WIDTH {}\n#define {}_HEIGHT {}\n#define {}_COUNT {}\n",
+            constant,
+            metatiles.columns,
+            constant,
+            metatiles.rows,
+            constant,
+            metatiles.count()
+        ));
+        formatted.push_str(&array(
+            &metatiles_name,
+            &encoded_tile.map_tiles(&metatiles.definitions),
+        ));
+        if encoded_tile.cgb {
+            formatted.push_str(&array(
+                &format!("{}_attributes", metatiles_name),
+                &metatiles.attributes,
+            ));
+        }
+        let constant = map_name.to_uppercase();
+        formatted.push_str(&format!(
+            "#define {}_WIDTH {}\n#define {}_HEIGHT {}\n",
+            constant, metatiles.width, constant, metatiles.height
+        ));
+        formatted.push_str(&array(&map_name, &metatiles.map));
+    }
+    if !encoded_tile.metasprites.is_empty() {
+        formatted.push_str(&metasprites(variable_name, encoded_tile));
+    }
+    formatted
+}
+
+fn tiles(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let tile_data = encoded_tile.rendered_data();
+    if let Some(bank_bytes) = encoded_tile.bank_bytes() {
+        return banks(variable_name, encoded_tile, &tile_data, bank_bytes);
+    }
+    if encoded_tile.frames <= 1 {
+        return array(variable_name, &tile_data);
+    }
+    if let (Some(frame_deltas), Some(delta_data)) = (
+        &encoded_tile.frame_deltas,
+        encoded_tile.rendered_delta_data(),
+    ) {
+        return deltas(variable_name, encoded_tile, frame_deltas, &delta_data);
+    }
+    let frame_names: Vec<String> = (0..encoded_tile.frames)
+        .map(|frame| format!("{}_frame_{}", variable_name, frame))
+        .collect();
+    let mut formatted = String::new();
+    let frame_len = tile_data.len() / encoded_tile.frames as usize;
+    for (frame_name, frame_data) in frame_names.iter().zip(tile_data.chunks(frame_len.max(1))) {
+        formatted.push_str(&array(frame_name, frame_data));
+        formatted.push('\n');
+    }
+    let constant = variable_name.to_uppercase();
+    let frame_tiles = encoded_tile.tile_data.len() / TILE_BYTES / encoded_tile.frames as usize;
+    formatted.push_str(&format!(
+        "#define {}_FRAME_COUNT {}\n#define {}_FRAME_TILES {}\n",
+        constant, encoded_tile.frames, constant, frame_tiles
+    ));
+    for frame in 0..encoded_tile.frames as usize {
+        formatted.push_str(&format!(
+            "#define {}_FRAME_{}_TILE {}\n",
+            constant,
+            frame,
+            frame * frame_tiles
+        ));
+    }
+    formatted.push_str(&format!(
+        "unsigned char *{}_frames[] = {{{}}};\n",
+        variable_name,
+        frame_names.join(", ")
+    ));
+    formatted
+}
+
+/// The first frame of an animation, then the tiles that change from each
+/// frame to the next and the patches copying them, as in
+/// [`EncodedTile::frame_deltas`].
+///
+/// The first frame gets a `<name>_frame_0` array, followed by a
+/// `<NAME>_DELTA_TILES` define and a `<name>_delta_tiles` array of the
+/// tiles that change. The patches drawing each frame over the one before
+/// follow in a `<name>_patches` array of `unsigned int` pairs: the number
+/// of the tile to replace, counted from the frame's first tile, and the
+/// offset in bytes of the tile to copy over it in `<name>_delta_tiles`.
+/// `<name>_patch_counts` gives the number of patches for each frame, and
+/// `<name>_frame_patches` points at each frame's first patch. Frame 0's
+/// patches draw it over the last frame, so the animation loops.
+fn deltas(
+    variable_name: &str,
+    encoded_tile: &EncodedTile,
+    frame_deltas: &FrameDeltas,
+    delta_data: &[u8],
+) -> String {
+    let constant = variable_name.to_uppercase();
+    let tile_bytes = encoded_tile.rendered_tile_bytes();
+    let tile_data = encoded_tile.rendered_data();
+    let frame_len = tile_data.len() / encoded_tile.frames as usize;
+    let mut formatted = array(
+        &format!("{}_frame_0", variable_name),
+        &tile_data[..frame_len],
+    );
+    formatted.push_str(&format!(
+        "\n#define {}_FRAME_COUNT {}\n#define {}_FRAME_TILES {}\n#define {}_DELTA_TILES {}\n",
+        constant,
+        encoded_tile.frames,
+        constant,
+        frame_len / tile_bytes.max(1),
+        constant,
+        delta_data.len() / tile_bytes.max(1)
+    ));
+    formatted.push_str(&array(
+        &format!("{}_delta_tiles", variable_name),
+        delta_data,
+    ));
+    let patches: Vec<u16> = frame_deltas
+        .patches
+        .iter()
+        .flatten()
+        .flat_map(|(tile, delta_tile)| [*tile as u16, (delta_tile * tile_bytes) as u16])
+        .collect();
+    formatted.push_str(&words(&format!("{}_patches", variable_name), &patches));
+    let counts: Vec<u16> = frame_deltas
+        .patches
+        .iter()
+        .map(|patches| patches.len() as u16)
+        .collect();
+    formatted.push_str(&words(&format!("{}_patch_counts", variable_name), &counts));
+    let starts: Vec<String> = frame_deltas
+        .patches
+        .iter()
+        .scan(0, |start, patches| {
+            let frame_start = format!("{}_patches + {}", variable_name, *start);
+            *start += patches.len() * 2;
+            Some(frame_start)
+        })
+        .collect();
+    formatted.push_str(&format!(
+        "unsigned int *{}_frame_patches[] = {{{}}};\n",
+        variable_name,
+        starts.join(", ")
+    ));
+    formatted
+}
+
+/// Tile data split into banks, as in [`EncodedTile::bank_tiles`]: an
+/// array per bank, named `<name>_bank_<n>`, each followed by a
+/// `<NAME>_BANK_<n>_TILES` define, then a `<NAME>_BANK_COUNT` define.
+fn banks(
+    variable_name: &str,
+    encoded_tile: &EncodedTile,
+    tile_data: &[u8],
+    bank_bytes: usize,
+) -> String {
+    let constant = variable_name.to_uppercase();
+    let tile_bytes = bank_bytes / encoded_tile.bank_tiles.unwrap_or(1);
+    let mut formatted = String::new();
+    let banks = tile_data.chunks(bank_bytes.max(1));
+    let bank_count = banks.len();
+    for (bank, bank_data) in banks.enumerate() {
+        formatted.push_str(&array(
+            &format!("{}_bank_{}", variable_name, bank),
+            bank_data,
+        ));
+        formatted.push_str(&format!(
+            "#define {}_BANK_{}_TILES {}\n\n",
+            constant,
+            bank,
+            bank_data.len() / tile_bytes.max(1)
+        ));
+    }
+    formatted.push_str(&format!("#define {}_BANK_COUNT {}\n", constant, bank_count));
+    formatted
+}
+
+/// A `metasprite_t` array for each metasprite, as GBDK-2020's
+/// `move_metasprite` takes, and a table pointing at each.
+///
+/// `<NAME>_PIVOT_X` and `<NAME>_PIVOT_Y` defines come first, giving the
+/// pivot the sprites are placed by, as in [`metasprite_pivot`], then the
+/// array for each frame, named `<name>_metasprite_<n>`, and the
+/// `<name>_metasprites` table. Each
+/// sprite is placed from the one before, as `METASPR_ITEM` expects, and
+/// draws the tile numbered from the base tile `move_metasprite` is given.
+fn metasprites(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let constant = variable_name.to_uppercase();
+    let (pivot_x, pivot_y) = metasprite_pivot(encoded_tile);
+    let mut formatted = format!(
+        "\n#define {}_PIVOT_X {}\n#define {}_PIVOT_Y {}\n#include <gb/metasprites.h>\n",
+        constant, pivot_x, constant, pivot_y
+    );
+    let names: Vec<String> = (0..encoded_tile.metasprites.len())
+        .map(|metasprite| format!("{}_metasprite_{}", variable_name, metasprite))
+        .collect();
+    for (name, metasprite) in names.iter().zip(&encoded_tile.metasprites) {
+        let mut items = Vec::with_capacity(metasprite.len() + 1);
+        // Each sprite is placed from the one before, the first from the pivot.
+        let (mut y, mut x) = (0, 0);
+        for item in metasprite {
+            items.push(format!(
+                "    METASPR_ITEM({}, {}, {}, {:#04X})",
+                item.y - y,
+                item.x - x,
+                item.tile,
+                item.props
+            ));
+            (y, x) = (item.y, item.x);
+        }
+        items.push("    METASPR_TERM".to_string());
+        formatted.push_str(&format!(
+            "metasprite_t {}[] = {{\n{}\n}};\n",
+            name,
+            items.join(",\n")
+        ));
+    }
+    formatted.push_str(&format!(
+        "metasprite_t *{}_metasprites[] = {{{}}};\n",
+        variable_name,
+        names.join(", ")
+    ));
+    formatted
+}
+
+/// A C `unsigned int` array of `words`, eight to a line.
+fn words(variable_name: &str, words: &[u16]) -> String {
+    let lines: Vec<String> = words
+        .chunks(8)
+        .map(|line| {
+            let formatted_words: Vec<String> =
+                line.iter().map(|word| format!("{:#06X}", word)).collect();
+            format!("    {}", formatted_words.join(","))
+        })
+        .collect();
+    format!(
+        "unsigned int {}[] = {{\n{}\n}};\n",
+        variable_name,
+        lines.join(",\n")
+    )
+}
+
+fn array(variable_name: &str, tile_data: &[u8]) -> String {
+    let preamble = format!("unsigned char {}[] = {{", variable_name);
+    let mut body = Vec::new();
+    for line in tile_data.chunks(16) {
+        let mut formatted_bytes = Vec::new();
+        for byte in line {
+            formatted_bytes.push(format!("{:#04X}", byte));
+        }
+        body.push(format!("    {}", formatted_bytes.join(",")));
+    }
+
+    format!("{}\n{}\n}};\n", preamble, body.join(",\n"))
+}
@@ -0,0 +1,309 @@
+use crate::metatile::Metatiles;
+use crate::tile::{EncodedTile, Tilemap};
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+mod gbdk;
+mod parse;
+mod raw;
+mod rgbds;
+
+pub use self::gbdk::{write_tile_gbdk, Gbdk};
+pub use self::parse::{parse_generated, GeneratedTiles};
+pub use self::raw::Raw;
+pub use self::rgbds::{write_charmap_rgbds, write_tile_rgbds, Rgbds};
+
+/// A target that encoded tiles can be rendered into.
+///
+/// Implement this to add a new output target, then add it to a
+/// [`FormatRegistry`] so it can be looked up by name.
+pub trait OutputFormat: fmt::Debug + Send + Sync {
+    /// The short name used to select this format, e.g. `gbdk`.
+    fn name(&self) -> &'static str;
+
+    /// The conventional extension for generated files, without the dot.
+    fn file_extension(&self) -> &'static str;
+
+    /// Render the tile data into the bytes of the generated file.
+    fn render(&self, encoded_tile: &EncodedTile) -> Vec<u8>;
+
+    /// Format `text` as a single line comment, if this format has comments.
+    fn comment(&self, _text: &str) -> Option<String> {
+        None
+    }
+
+    /// Written between tiles rendered into the same file. Defaults to a
+    /// newline, leaving a blank line after each tile's rendering.
+    fn separator(&self) -> &'static [u8] {
+        b"\n"
+    }
+
+    /// Render several tiles into one generated file. The default starts
+    /// with a [`GENERATED_MARKER`] comment, then renders each tile on its
+    /// own and puts the [`separator`](OutputFormat::separator) between them.
+    fn render_all(&self, encoded_tiles: &[EncodedTile]) -> Vec<u8> {
+        let mut rendered = Vec::new();
+        if let Some(marker) = self.comment(GENERATED_MARKER) {
+            rendered.extend(format!("{}\n\n", marker).into_bytes());
+        }
+        for (i, encoded_tile) in encoded_tiles.iter().enumerate() {
+            if i > 0 {
+                rendered.extend_from_slice(self.separator());
+            }
+            rendered.extend(self.render(encoded_tile));
+        }
+        rendered
+    }
+
+    /// The symbols declared in a file generated in this format, used to
+    /// avoid defining a symbol twice when appending. The default finds none.
+    fn declared_symbols(&self, _contents: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Written as a comment on the first line of generated files, so they can
+/// be told apart from hand-written ones.
+pub const GENERATED_MARKER: &str = "Generated by gbtile. Do not edit.";
+
+/// Whether `contents` starts with a [`GENERATED_MARKER`] comment.
+pub fn is_generated(contents: &[u8]) -> bool {
+    let first_line = contents.split(|&byte| byte == b'\n').next().unwrap_or(&[]);
+    String::from_utf8_lossy(first_line).contains(GENERATED_MARKER)
+}
+
+/// The output formats built into gbtile.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum OutputType {
+    #[default]
+    Gbdk,
+    Rgbds,
+    /// Raw 2bpp tile data.
+    Raw,
+}
+
+/// A set of output formats, looked up by their [`OutputFormat::name`].
+#[derive(Debug)]
+pub struct FormatRegistry {
+    formats: Vec<Box<dyn OutputFormat>>,
+}
+
+impl Default for FormatRegistry {
+    /// A registry holding the built-in GBDK, RGBDS and raw 2bpp formats.
+    fn default() -> Self {
+        let mut registry = FormatRegistry::empty();
+        registry.register(Box::new(Gbdk));
+        registry.register(Box::new(Rgbds));
+        registry.register(Box::new(Raw));
+        registry
+    }
+}
+
+impl FormatRegistry {
+    pub fn empty() -> Self {
+        FormatRegistry {
+            formats: Vec::new(),
+        }
+    }
+
+    /// Add a format, replacing any existing format with the same name.
+    pub fn register(&mut self, format: Box<dyn OutputFormat>) {
+        self.formats
+            .retain(|existing| existing.name() != format.name());
+        self.formats.push(format);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn OutputFormat> {
+        self.formats
+            .iter()
+            .find(|format| format.name() == name)
+            .map(|format| format.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.formats.iter().map(|format| format.name()).collect()
+    }
+}
+
+/// The symbol name generated for a tile: its explicit name if it has one,
+/// otherwise its input file stem, made into a valid identifier, or `tiles`
+/// if the input has no file name.
+pub fn variable_name(encoded_tile: &EncodedTile) -> String {
+    match &encoded_tile.name {
+        Some(name) => sanitize_identifier(name),
+        None => sanitize_identifier(
+            &file_stem(&encoded_tile.input_filename).unwrap_or_else(|| DEFAULT_SYMBOL.to_string()),
+        ),
+    }
+}
+
+/// The symbol generated for a map drawn with the tiles named
+/// `variable_name`: `<name>_map`, or `<name>_<map name>_map` for named maps.
+pub fn map_name(variable_name: &str, tilemap: &Tilemap) -> String {
+    match &tilemap.name {
+        Some(name) => sanitize_identifier(&format!("{}_{}_map", variable_name, name)),
+        None => format!("{}_map", variable_name),
+    }
+}
+
+/// The symbols generated for metatiles grouped from a map drawn with the
+/// tiles named `variable_name`: `<name>_metatiles` for their definitions
+/// and `<name>_metatile_map` for the map, with the map's name after
+/// `<name>` for named maps.
+pub fn metatile_names(variable_name: &str, metatiles: &Metatiles) -> (String, String) {
+    let base = match &metatiles.name {
+        Some(name) => sanitize_identifier(&format!("{}_{}", variable_name, name)),
+        None => variable_name.to_string(),
+    };
+    (
+        format!("{}_metatiles", base),
+        format!("{}_metatile_map", base),
+    )
+}
+
+/// The constant generated for character `code` of the font named
+/// `variable_name`: `<NAME>_CHAR_<c>` for letters and digits, and
+/// `<NAME>_CHAR_0x<code>` in hex for any other character, such as
+/// `<NAME>_CHAR_0x20` for space.
+pub fn char_constant(variable_name: &str, code: u8) -> String {
+    let constant = variable_name.to_uppercase();
+    match code.is_ascii_alphanumeric() {
+        true => format!("{}_CHAR_{}", constant, code as char),
+        false => format!("{}_CHAR_0x{:02X}", constant, code),
+    }
+}
+
+/// The symbol name used for tiles whose input has no file name to take
+/// one from.
+const DEFAULT_SYMBOL: &str = "tiles";
+
+/// The file name of `input_filename` without its extension, or `None` if
+/// it has no file name, like `""` or `".."`.
+pub(crate) fn file_stem(input_filename: &str) -> Option<String> {
+    Path::new(input_filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+}
+
+/// Turn `name` into an identifier that's valid in both C and RGBDS, by
+/// replacing each run of other characters with an underscore and adding a
+/// leading underscore if it would start with a digit. For example
+/// `8x8-enemy (final)` becomes `_8x8_enemy_final`.
+pub fn sanitize_identifier(name: &str) -> String {
+    let mut identifier = String::with_capacity(name.len() + 1);
+    let mut separate = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            if separate && c != '_' {
+                identifier.push('_');
+            }
+            identifier.push(c);
+            separate = false;
+        } else {
+            separate = !identifier.is_empty();
+        }
+    }
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+/// Render tile data and write it to `out_file`, replacing any existing contents.
+pub fn write_tile(
+    encoded_tile: &EncodedTile,
+    out_file: &str,
+    output_format: &dyn OutputFormat,
+) -> Result<(), io::Error> {
+    write_output(out_file, &output_format.render(encoded_tile))
+}
+
+/// Render several tiles into one file at `out_file`, replacing any existing contents.
+pub fn write_tiles(
+    encoded_tiles: &[EncodedTile],
+    out_file: &str,
+    output_format: &dyn OutputFormat,
+) -> Result<(), io::Error> {
+    write_output(out_file, &output_format.render_all(encoded_tiles))
+}
+
+/// Convert `\r\n` and lone `\r` line endings to `\n`, so rendered output
+/// is byte-identical regardless of platform or format implementation.
+pub fn normalize_line_endings(rendered: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(rendered.len());
+    let mut bytes = rendered.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' {
+            if bytes.peek() == Some(&&b'\n') {
+                bytes.next();
+            }
+            normalized.push(b'\n');
+        } else {
+            normalized.push(byte);
+        }
+    }
+    normalized
+}
+
+/// Write already rendered output to `out_file`, replacing any existing contents.
+pub fn write_output(out_file: &str, formatted_result: &[u8]) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(out_file)?;
+    file.write_all(formatted_result)?;
+    Ok(())
+}
+
+/// Add rendered output to the end of `out_file`, creating it if needed.
+/// Nothing is written if the output declares a symbol the file already has.
+/// The output's [`GENERATED_MARKER`] comment is left out when the file
+/// already has contents.
+pub fn append_output(
+    out_file: &str,
+    formatted_result: &[u8],
+    output_format: &dyn OutputFormat,
+) -> Result<(), io::Error> {
+    let existing = match fs::read(out_file) {
+        Ok(existing) => String::from_utf8_lossy(&existing).into_owned(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let declared = output_format.declared_symbols(&existing);
+    let duplicate = output_format
+        .declared_symbols(&String::from_utf8_lossy(formatted_result))
+        .into_iter()
+        .find(|symbol| declared.contains(symbol));
+    if let Some(symbol) = duplicate {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("symbol '{}' is already defined", symbol),
+        ));
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(out_file)?;
+    if existing.is_empty() {
+        file.write_all(formatted_result)?;
+        return Ok(());
+    }
+    let mut formatted_result = formatted_result;
+    if is_generated(formatted_result) {
+        let marker_end = formatted_result
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .map_or(formatted_result.len(), |position| position + 2);
+        formatted_result = &formatted_result[marker_end..];
+    }
+    file.write_all(output_format.separator())?;
+    file.write_all(formatted_result)?;
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeSet;
+
+/// Tile data read back from a generated file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratedTiles {
+    /// The array or label the data was declared with.
+    pub name: String,
+    pub tile_data: Vec<u8>,
+    /// The bits per pixel the data is in: 1 when a `<NAME>_BPP` constant
+    /// says so, otherwise 2.
+    pub bpp: u8,
+}
+
+impl GeneratedTiles {
+    /// Whether this is the tile data declared as `symbol`, or one of the
+    /// `<symbol>_frame_<n>` frames of an animation declared as `symbol`.
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        match self.name.strip_prefix(symbol) {
+            Some("") => true,
+            Some(rest) => rest.strip_prefix("_frame_").is_some_and(|frame| {
+                !frame.is_empty() && frame.chars().all(|c| c.is_ascii_digit())
+            }),
+            None => false,
+        }
+    }
+}
+
+/// The endings of symbols generated next to tiles that don't hold tiles.
+const NOT_TILES: [&str; 6] = [
+    "_tile_palettes",
+    "_widths",
+    "_sgb_pal01",
+    "_sgb_pal23",
+    "_sgb_attr_blk",
+    "_sgb_attr_file",
+];
+
+/// Read the tile data back out of a file generated in the GBDK or RGBDS
+/// format, in the order it's declared.
+///
+/// GBDK arrays of `0x` bytes and RGBDS labels followed by `db` lines of `$`
+/// bytes are both recognized. Maps, which have `<MAP>_WIDTH` constants,
+/// and their `<map>_attributes`, tables of pointers, Game Boy Color
+/// palettes, which are `unsigned int` arrays or `dw` lines, the
+/// `<name>_tile_palettes` numbers that go with them, fonts' `<name>_widths`
+/// and Super Game Boy commands are left out. Tiles written in 1bpp are
+/// recognized by their `<NAME>_BPP` constant, which also covers the
+/// symbols declared after them, such as an animation's frames.
+pub fn parse_generated(contents: &str) -> Vec<GeneratedTiles> {
+    let constants: Vec<(&str, &str)> = contents
+        .lines()
+        .filter_map(|line| {
+            let definition = line
+                .strip_prefix("#define ")
+                .or_else(|| line.strip_prefix("DEF "))?;
+            let mut words = definition.split_whitespace();
+            Some((words.next()?, words.next_back()?))
+        })
+        .collect();
+    let maps: BTreeSet<String> = constants
+        .iter()
+        .filter_map(|(constant, _)| constant.strip_suffix("_WIDTH"))
+        .map(str::to_string)
+        .collect();
+    let bpps: Vec<(&str, u8)> = constants
+        .iter()
+        .filter_map(|(constant, value)| Some((constant.strip_suffix("_BPP")?, value.parse().ok()?)))
+        .collect();
+    let bpp = |name: &str| {
+        let name = name.to_uppercase();
+        bpps.iter()
+            .filter(|(symbol, _)| {
+                name.strip_prefix(symbol)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+            })
+            .max_by_key(|(symbol, _)| symbol.len())
+            .map_or(2, |(_, bpp)| *bpp)
+    };
+
+    let mut declared: Vec<GeneratedTiles> = Vec::new();
+    let mut in_declaration = false;
+    for line in contents.lines() {
+        if let Some(declaration) = line.strip_prefix("unsigned char ") {
+            // Pointer tables start with `*` and aren't tile data.
+            let name = declaration
+                .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .next()
+                .unwrap_or_default();
+            in_declaration = !name.is_empty();
+            if in_declaration {
+                declared.push(GeneratedTiles {
+                    name: name.to_string(),
+                    tile_data: Vec::new(),
+                    bpp: bpp(name),
+                });
+            }
+        } else if let Some(label) = label(line) {
+            // Data after an `_end` label belongs to no symbol.
+            in_declaration = !label.ends_with("_end");
+            if in_declaration {
+                declared.push(GeneratedTiles {
+                    name: label.to_string(),
+                    tile_data: Vec::new(),
+                    bpp: bpp(label),
+                });
+            }
+        } else if in_declaration && !line.trim_start().starts_with("dw ") {
+            let tiles = declared.last_mut().unwrap();
+            let data = line.trim_start();
+            let data = data.strip_prefix("db ").unwrap_or(data);
+            tiles.tile_data.extend(data.split(',').filter_map(|byte| {
+                let byte = byte.trim();
+                let hex = byte
+                    .strip_prefix("0x")
+                    .or_else(|| byte.strip_prefix("0X"))
+                    .or_else(|| byte.strip_prefix('$'))?;
+                u8::from_str_radix(hex, 16).ok()
+            }));
+            if line.contains("};") {
+                in_declaration = false;
+            }
+        }
+    }
+    declared
+        .into_iter()
+        .filter(|tiles| {
+            !tiles.tile_data.is_empty()
+                && !maps.contains(&tiles.name.to_uppercase())
+                && !tiles
+                    .name
+                    .strip_suffix("_attributes")
+                    .is_some_and(|map| maps.contains(&map.to_uppercase()))
+                && !NOT_TILES.iter().any(|suffix| tiles.name.ends_with(suffix))
+        })
+        .collect()
+}
+
+/// The RGBDS label declared on `line`, if any: a symbol in the first
+/// column followed by a colon.
+fn label(line: &str) -> Option<&str> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    line.trim_end()
+        .strip_suffix(':')
+        .filter(|label| label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
@@ -0,0 +1,28 @@
+use super::OutputFormat;
+use crate::tile::EncodedTile;
+
+/// Raw 2bpp tile data, 16 bytes per tile, or 8 in 1bpp, for tools and
+/// build steps that take binary assets, such as RGBDS's `INCBIN`.
+///
+/// Only the tiles are written: symbols, animation tables and maps have
+/// nowhere to go.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Raw;
+
+impl OutputFormat for Raw {
+    fn name(&self) -> &'static str {
+        "2bpp"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "2bpp"
+    }
+
+    fn render(&self, encoded_tile: &EncodedTile) -> Vec<u8> {
+        encoded_tile.rendered_data().into_owned()
+    }
+
+    fn separator(&self) -> &'static [u8] {
+        b""
+    }
+}
@@ -0,0 +1,555 @@
+use super::{
+    char_constant, map_name, metatile_names, variable_name, OutputFormat, GENERATED_MARKER,
+};
+use crate::delta::FrameDeltas;
+use crate::image::GB_MAX_COLOR_COUNT;
+use crate::metasprite::metasprite_pivot;
+use crate::tile::EncodedTile;
+use crate::vram::TILE_BYTES;
+
+/// An exported ROM0 section of `db` lines for RGBDS.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Rgbds;
+
+impl OutputFormat for Rgbds {
+    fn name(&self) -> &'static str {
+        "rgbds"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "asm"
+    }
+
+    fn render(&self, encoded_tile: &EncodedTile) -> Vec<u8> {
+        write_tile_rgbds(&variable_name(encoded_tile), encoded_tile).into_bytes()
+    }
+
+    fn comment(&self, text: &str) -> Option<String> {
+        Some(format!("; {}", text))
+    }
+
+    fn declared_symbols(&self, contents: &str) -> Vec<String> {
+        // Labels are the only lines starting in the first column that end
+        // with a colon.
+        let labels = contents
+            .lines()
+            .filter(|line| !line.starts_with(char::is_whitespace))
+            .filter_map(|line| line.trim_end().strip_suffix(':'));
+        let constants = contents
+            .lines()
+            .filter_map(|line| line.strip_prefix("DEF "))
+            .filter_map(|definition| definition.split_whitespace().next());
+        labels.chain(constants).map(str::to_string).collect()
+    }
+}
+
+/// Format tile data as an exported ROM0 section of `db` lines for RGBDS,
+/// in 1bpp when [`EncodedTile::bpp`] is 1.
+///
+/// Animations also get a `<name>_frame_<n>` label at the start of each
+/// frame, `<NAME>_FRAME_COUNT` and `<NAME>_FRAME_TILES` constants, a
+/// `<NAME>_FRAME_<n>_TILE` constant giving the offset of each frame's
+/// first tile from the first frame's, and a `<name>_frames` table of `dw`
+/// pointers to each frame. Animations written as the changes between
+/// frames, as in [`EncodedTile::frame_deltas`], get the first frame, the
+/// tiles that change and the patches copying them instead. Tile data split
+/// into banks, as in [`EncodedTile::bank_tiles`], is written in a ROMX
+/// section per bank, with the rest of the output in ROM0.
+///
+/// A `<NAME>_PALETTE` constant follows, giving the suggested BGP or OBP
+/// register value, as in [`EncodedTile::palette_register`], then in 1bpp a
+/// `<NAME>_BPP` constant of 1, so `gbtile decode` reads the tiles back as
+/// 1bpp. Sprites given an object palette also get `<NAME>_OBP0` or
+/// `<NAME>_OBP1`, the same value for rOBP0 or rOBP1, and
+/// `<NAME>_OAM_FLAGS`, the OAM attribute flags selecting that palette, as
+/// in [`EncodedTile::oam_flags`]. Fonts get a `<NAME>_FIRST_CHAR` constant,
+/// and a constant for each character giving the tile drawing it, named as
+/// in [`char_constant`], then a `<name>_widths` label of each glyph's
+/// width in pixels, as in [`EncodedTile::glyph_widths`], for variable-width
+/// text.
+///
+/// In CGB mode, a `<name>_cgb_palette` label of `dw` RGB555 words, a line
+/// of four per palette and ending at `<name>_cgb_palette_end`, follows,
+/// ready to copy through BCPD or OCPD. Images sharing out several palettes
+/// also get a `<name>_tile_palettes` label of each tile's palette number,
+/// ending at `<name>_tile_palettes_end`.
+///
+/// In SGB mode, the Super Game Boy's commands follow, each as a label of
+/// packets ending at `<label>_end`: `<name>_sgb_pal01` and
+/// `<name>_sgb_pal23` setting the palettes, `<name>_sgb_attr_blk` giving
+/// each tile's palette when the palettes fit in one ATTR_BLK command, and
+/// `<name>_sgb_attr_file`, the same as an attribute file for ATTR_TRN.
+///
+/// Each map drawn with the tiles also gets a label of tile numbers, named
+/// as in [`map_name`] and ending at `<map>_end`, with `<MAP>_WIDTH` and
+/// `<MAP>_HEIGHT` constants giving its size in tiles. In CGB mode, each map
+/// is followed by a `<map>_attributes` label for VRAM bank 1, as in
+/// [`EncodedTile::cgb_attributes`], ending at `<map>_attributes_end`.
+///
+/// Tiles grouped into metatiles get a `<name>_metatiles` label of each
+/// metatile's tile numbers, one after another, named as in
+/// [`metatile_names`] and ending at `<name>_metatiles_end`, with
+/// `<NAME>_METATILES_WIDTH` and `<NAME>_METATILES_HEIGHT` constants giving
+/// a metatile's size in tiles and `<NAME>_METATILES_COUNT` how many there
+/// are, then a `<name>_metatile_map` label of metatile numbers with
+/// `_WIDTH` and `_HEIGHT` constants giving its size in metatiles. In CGB
+/// mode, the metatiles' attributes follow at `<name>_metatiles_attributes`.
+/// Tile numbers in maps and metatiles are as the background reads them, as
+/// in [`EncodedTile::map_tiles`].
+///
+/// Tiles made into metasprites, as in [`EncodedTile::metasprites`], end
+/// with a label of GBDK-2020 `metasprite_t` entries for each frame and a
+/// table of `dw` pointers to each.
+pub fn write_tile_rgbds(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let mut formatted = tiles(variable_name, encoded_tile);
+    let constant = variable_name.to_uppercase();
+    formatted.push_str(&format!(
+        "\nDEF {}_PALETTE EQU ${:02x}\nEXPORT {}_PALETTE\n",
+        constant, encoded_tile.palette_register, constant
+    ));
+    if encoded_tile.bpp == 1 {
+        formatted.push_str(&format!(
+            "DEF {}_BPP EQU 1\nEXPORT {}_BPP\n",
+            constant, constant
+        ));
+    }
+    if let (Some(obp), Some(oam_flags)) = (encoded_tile.obp, encoded_tile.oam_flags()) {
+        formatted.push_str(&format!(
+            "DEF {}_OBP{} EQU ${:02x}\nDEF {}_OAM_FLAGS EQU ${:02x}\nEXPORT {}_OBP{}, {}_OAM_FLAGS\n",
+            constant,
+            obp,
+            encoded_tile.palette_register,
+            constant,
+            oam_flags,
+            constant,
+            obp,
+            constant
+        ));
+    }
+    if let Some(first_char) = encoded_tile.first_char {
+        formatted.push_str(&format!(
+            "DEF {}_FIRST_CHAR EQU {}\nEXPORT {}_FIRST_CHAR\n",
+            constant, first_char, constant
+        ));
+        let mut chars = Vec::new();
+        for (code, tile) in encoded_tile.char_tiles() {
+            let char_constant = char_constant(variable_name, code);
+            formatted.push_str(&format!("DEF {} EQU ${:02x}\n", char_constant, tile));
+            chars.push(char_constant);
+        }
+        for exported in chars.chunks(8) {
+            formatted.push_str(&format!("EXPORT {}\n", exported.join(", ")));
+        }
+        formatted.push_str(&block(
+            &format!("{}_widths", variable_name),
+            &encoded_tile.glyph_widths(),
+        ));
+    }
+    if encoded_tile.cgb {
+        let palettes: Vec<String> = encoded_tile
+            .cgb_palette()
+            .chunks(GB_MAX_COLOR_COUNT)
+            .map(|palette| {
+                let words: Vec<String> = palette
+                    .iter()
+                    .map(|word| format!("${:04x}", word))
+                    .collect();
+                format!("    dw {}", words.join(","))
+            })
+            .collect();
+        formatted.push_str(&format!(
+            "\nEXPORT {}_cgb_palette, {}_cgb_palette_end\n\n{}_cgb_palette:\n{}\n{}_cgb_palette_end:\n",
+            variable_name,
+            variable_name,
+            variable_name,
+            palettes.join("\n"),
+            variable_name
+        ));
+        if let Some(cgb_palettes) = &encoded_tile.cgb_palettes {
+            formatted.push_str(&format!(
+                "\nEXPORT {}_tile_palettes, {}_tile_palettes_end\n\n{}_tile_palettes:\n{}\n{}_tile_palettes_end:\n",
+                variable_name,
+                variable_name,
+                variable_name,
+                db_lines(&cgb_palettes.tile_palettes),
+                variable_name
+            ));
+        }
+    }
+    if encoded_tile.sgb {
+        let [pal01, pal23] = encoded_tile.sgb_palette_packets();
+        formatted.push_str(&block(&format!("{}_sgb_pal01", variable_name), &pal01));
+        formatted.push_str(&block(&format!("{}_sgb_pal23", variable_name), &pal23));
+        if let Some(attr_blk) = encoded_tile.sgb_attribute_blocks() {
+            formatted.push_str(&block(
+                &format!("{}_sgb_attr_blk", variable_name),
+                &attr_blk,
+            ));
+        }
+        formatted.push_str(&block(
+            &format!("{}_sgb_attr_file", variable_name),
+            &encoded_tile.sgb_attribute_file(),
+        ));
+    }
+    for tilemap in &encoded_tile.tilemaps {
+        let map_name = map_name(variable_name, tilemap);
+        let constant = map_name.to_uppercase();
+        formatted.push_str(&format!(
+            "\nDEF {}_WIDTH EQU {}\nDEF {}_HEIGHT EQU {}\nEXPORT {}_WIDTH, {}_HEIGHT, {}, {}_end\n\n{}:\n{}\n{}_end:\n",
+            constant,
+            tilemap.width,
+            constant,
+            tilemap.height,
+            constant,
+            constant,
+            map_name,
+            map_name,
+            map_name,
+            db_lines(&encoded_tile.map_tiles(&tilemap.tiles)),
+            map_name
+        ));
+        if encoded_tile.cgb {
+            formatted.push_str(&format!(
+                "\nEXPORT {}_attributes, {}_attributes_end\n\n{}_attributes:\n{}\n{}_attributes_end:\n",
+                map_name,
+                map_name,
+                map_name,
+                db_lines(&encoded_tile.cgb_attributes(tilemap)),
+                map_name
+            ));
+        }
+    }
+    for metatiles in &encoded_tile.metatiles {
+        let (metatiles_name, map_name) = metatile_names(variable_name, metatiles);
+        let constant = metatiles_name.to_uppercase();
+        formatted.push_str(&format!(
+            "\nDEF {}_WIDTH EQU {}\nDEF {}_HEIGHT EQU {}\nDEF {}_COUNT EQU {}\nEXPORT {}_WIDTH, {}_HEIGHT, {}_COUNT\n",
+            constant,
+            metatiles.columns,
+            constant,
+            metatiles.rows,
+            constant,
+            metatiles.count(),
+            constant,
+            constant,
+            constant
+        ));
+        formatted.push_str(&block(
+            &metatiles_name,
+            &encoded_tile.map_tiles(&metatiles.definitions),
+        ));
+        if encoded_tile.cgb {
+            formatted.push_str(&block(
+                &format!("{}_attributes", metatiles_name),
+                &metatiles.attributes,
+            ));
+        }
+        let constant = map_name.to_uppercase();
+        formatted.push_str(&format!(
+            "\nDEF {}_WIDTH EQU {}\nDEF {}_HEIGHT EQU {}\nEXPORT {}_WIDTH, {}_HEIGHT\n",
+            constant, metatiles.width, constant, metatiles.height, constant, constant
+        ));
+        formatted.push_str(&block(&map_name, &metatiles.map));
+    }
+    if !encoded_tile.metasprites.is_empty() {
+        formatted.push_str(&metasprites(variable_name, encoded_tile));
+    }
+    formatted
+}
+
+/// An RGBDS include of `CHARMAP` definitions mapping each character the
+/// fonts among `encoded_tiles` draw to the tile drawing it, as in
+/// [`EncodedTile::char_tiles`], so strings in assembly compile straight to
+/// the font's tile numbers. Control characters and codes past `~` have no
+/// way to be written in a string, so they're left out. `None` if none of
+/// the tiles are fonts.
+pub fn write_charmap_rgbds(encoded_tiles: &[EncodedTile]) -> Option<String> {
+    let fonts: Vec<&EncodedTile> = encoded_tiles
+        .iter()
+        .filter(|encoded_tile| encoded_tile.first_char.is_some())
+        .collect();
+    if fonts.is_empty() {
+        return None;
+    }
+    let mut formatted = format!("; {}\n", GENERATED_MARKER);
+    for encoded_tile in fonts {
+        formatted.push_str(&format!("\n; {}\n", variable_name(encoded_tile)));
+        for (code, tile) in encoded_tile.char_tiles() {
+            let string = match code {
+                b'"' | b'\\' | b'{' | b'}' => format!("\\{}", code as char),
+                b' '..=b'~' => (code as char).to_string(),
+                _ => continue,
+            };
+            formatted.push_str(&format!("CHARMAP \"{}\", ${:02x}\n", string, tile));
+        }
+    }
+    Some(formatted)
+}
+
+fn tiles(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let end_symbol = format!("{}_end", variable_name);
+    let tile_data = encoded_tile.rendered_data();
+    if let Some(bank_bytes) = encoded_tile.bank_bytes() {
+        return banks(variable_name, encoded_tile, &tile_data, bank_bytes);
+    }
+    if encoded_tile.frames <= 1 {
+        let preamble = format!(
+            "SECTION \"Tiles for '{}'\", ROM0\n\nEXPORT {}, {}\n\n{}:",
+            variable_name, variable_name, end_symbol, variable_name
+        );
+        return format!("{}\n{}\n{}:\n", preamble, db_lines(&tile_data), end_symbol);
+    }
+    if let (Some(frame_deltas), Some(delta_data)) = (
+        &encoded_tile.frame_deltas,
+        encoded_tile.rendered_delta_data(),
+    ) {
+        return deltas(variable_name, encoded_tile, frame_deltas, &delta_data);
+    }
+
+    let frame_labels: Vec<String> = (0..encoded_tile.frames)
+        .map(|frame| format!("{}_frame_{}", variable_name, frame))
+        .collect();
+    let constant = variable_name.to_uppercase();
+    let mut formatted = format!(
+        "SECTION \"Tiles for '{}'\", ROM0\n\nEXPORT {}, {}, {}_frames\nEXPORT {}\n",
+        variable_name,
+        variable_name,
+        end_symbol,
+        variable_name,
+        frame_labels.join(", ")
+    );
+    let frame_tiles = encoded_tile.tile_data.len() / TILE_BYTES / encoded_tile.frames as usize;
+    formatted.push_str(&format!(
+        "DEF {}_FRAME_COUNT EQU {}\nDEF {}_FRAME_TILES EQU {}\nEXPORT {}_FRAME_COUNT, {}_FRAME_TILES\n",
+        constant, encoded_tile.frames, constant, frame_tiles, constant, constant
+    ));
+    let frame_constants: Vec<String> = (0..encoded_tile.frames)
+        .map(|frame| format!("{}_FRAME_{}_TILE", constant, frame))
+        .collect();
+    for (frame, frame_constant) in frame_constants.iter().enumerate() {
+        formatted.push_str(&format!(
+            "DEF {} EQU {}\n",
+            frame_constant,
+            frame * frame_tiles
+        ));
+    }
+    for exported in frame_constants.chunks(8) {
+        formatted.push_str(&format!("EXPORT {}\n", exported.join(", ")));
+    }
+    formatted.push_str(&format!("\n{}:\n", variable_name));
+    let frame_len = tile_data.len() / encoded_tile.frames as usize;
+    for (frame_label, frame_data) in frame_labels.iter().zip(tile_data.chunks(frame_len.max(1))) {
+        formatted.push_str(&format!("{}:\n{}\n", frame_label, db_lines(frame_data)));
+    }
+    formatted.push_str(&format!(
+        "{}:\n\n{}_frames:\n    dw {}\n",
+        end_symbol,
+        variable_name,
+        frame_labels.join(", ")
+    ));
+    formatted
+}
+
+/// The first frame of an animation, then the tiles that change from each
+/// frame to the next and the patches copying them, as in
+/// [`EncodedTile::frame_deltas`].
+///
+/// The first frame gets a `<name>_frame_0` label, followed by a
+/// `<NAME>_DELTA_TILES` constant and a `<name>_delta_tiles` label of the
+/// tiles that change. The patches drawing each frame over the one before
+/// follow at `<name>_patches`, with a `<name>_frame_<n>_patches` label at
+/// each frame's, as `dw` pairs: the number of the tile to replace, counted
+/// from the frame's first tile, and the offset in bytes of the tile to
+/// copy over it from `<name>_delta_tiles`. `<name>_patch_counts` gives the
+/// number of patches for each frame, and `<name>_frame_patches` is a table
+/// of `dw` pointers to each frame's. Frame 0's patches draw it over the
+/// last frame, so the animation loops.
+fn deltas(
+    variable_name: &str,
+    encoded_tile: &EncodedTile,
+    frame_deltas: &FrameDeltas,
+    delta_data: &[u8],
+) -> String {
+    let constant = variable_name.to_uppercase();
+    let tile_bytes = encoded_tile.rendered_tile_bytes();
+    let tile_data = encoded_tile.rendered_data();
+    let frame_len = tile_data.len() / encoded_tile.frames as usize;
+    let mut formatted = format!(
+        "SECTION \"Tiles for '{}'\", ROM0\n\nDEF {}_FRAME_COUNT EQU {}\nDEF {}_FRAME_TILES EQU {}\nDEF {}_DELTA_TILES EQU {}\nEXPORT {}_FRAME_COUNT, {}_FRAME_TILES, {}_DELTA_TILES\n",
+        variable_name,
+        constant,
+        encoded_tile.frames,
+        constant,
+        frame_len / tile_bytes.max(1),
+        constant,
+        delta_data.len() / tile_bytes.max(1),
+        constant,
+        constant,
+        constant
+    );
+    formatted.push_str(&block(
+        &format!("{}_frame_0", variable_name),
+        &tile_data[..frame_len],
+    ));
+    formatted.push_str(&block(
+        &format!("{}_delta_tiles", variable_name),
+        delta_data,
+    ));
+    let frame_labels: Vec<String> = (0..frame_deltas.patches.len())
+        .map(|frame| format!("{}_frame_{}_patches", variable_name, frame))
+        .collect();
+    formatted.push_str(&format!(
+        "\nEXPORT {}_patches, {}_patches_end, {}_patch_counts, {}_frame_patches\n",
+        variable_name, variable_name, variable_name, variable_name
+    ));
+    for exported in frame_labels.chunks(8) {
+        formatted.push_str(&format!("EXPORT {}\n", exported.join(", ")));
+    }
+    formatted.push_str(&format!("\n{}_patches:\n", variable_name));
+    for (frame_label, patches) in frame_labels.iter().zip(&frame_deltas.patches) {
+        let patches: Vec<u16> = patches
+            .iter()
+            .flat_map(|(tile, delta_tile)| [*tile as u16, (delta_tile * tile_bytes) as u16])
+            .collect();
+        formatted.push_str(&format!("{}:\n", frame_label));
+        if !patches.is_empty() {
+            formatted.push_str(&format!("{}\n", dw_lines(&patches)));
+        }
+    }
+    let counts: Vec<u16> = frame_deltas
+        .patches
+        .iter()
+        .map(|patches| patches.len() as u16)
+        .collect();
+    formatted.push_str(&format!(
+        "{}_patches_end:\n\n{}_patch_counts:\n{}\n\n{}_frame_patches:\n    dw {}\n",
+        variable_name,
+        variable_name,
+        dw_lines(&counts),
+        variable_name,
+        frame_labels.join(", ")
+    ));
+    formatted
+}
+
+/// A label of `db` entries for each metasprite, laid out as GBDK-2020's
+/// `metasprite_t`, and a table of `dw` pointers to each.
+///
+/// `<NAME>_PIVOT_X` and `<NAME>_PIVOT_Y` constants come first, giving the
+/// pivot the sprites are placed by, as in [`metasprite_pivot`], then a
+/// `<name>_metasprite_<n>` label for each frame and the
+/// `<name>_metasprites` table. Each sprite is four `db` bytes: its Y and X
+/// offsets from the sprite before, or the pivot for the first, the tile it
+/// draws and its OAM flags. A Y offset of -128 ends each metasprite.
+fn metasprites(variable_name: &str, encoded_tile: &EncodedTile) -> String {
+    let constant = variable_name.to_uppercase();
+    let (pivot_x, pivot_y) = metasprite_pivot(encoded_tile);
+    let labels: Vec<String> = (0..encoded_tile.metasprites.len())
+        .map(|metasprite| format!("{}_metasprite_{}", variable_name, metasprite))
+        .collect();
+    let mut formatted = format!(
+        "\nDEF {}_PIVOT_X EQU {}\nDEF {}_PIVOT_Y EQU {}\nEXPORT {}_PIVOT_X, {}_PIVOT_Y, {}_metasprites\n",
+        constant, pivot_x, constant, pivot_y, constant, constant, variable_name
+    );
+    for exported in labels.chunks(8) {
+        formatted.push_str(&format!("EXPORT {}\n", exported.join(", ")));
+    }
+    for (label, metasprite) in labels.iter().zip(&encoded_tile.metasprites) {
+        formatted.push_str(&format!("\n{}:\n", label));
+        // Each sprite is placed from the one before, the first from the pivot.
+        let (mut y, mut x) = (0, 0);
+        for item in metasprite {
+            formatted.push_str(&format!(
+                "    db {}, {}, {}, {}\n",
+                item.y - y,
+                item.x - x,
+                item.tile,
+                item.props
+            ));
+            (y, x) = (item.y, item.x);
+        }
+        formatted.push_str("    db -128\n");
+    }
+    formatted.push_str(&format!(
+        "\n{}_metasprites:\n    dw {}\n",
+        variable_name,
+        labels.join(", ")
+    ));
+    formatted
+}
+
+/// Tile data split into a ROMX section per bank, so the linker can place
+/// each in a bank of its own, followed by the ROM0 section the rest of the
+/// output goes in.
+///
+/// Each bank is labeled `<name>_bank_<n>` and ends at
+/// `<name>_bank_<n>_end`, with a `<NAME>_BANK_<n>_TILES` constant, then a
+/// `<NAME>_BANK_COUNT` constant follows the last.
+fn banks(
+    variable_name: &str,
+    encoded_tile: &EncodedTile,
+    tile_data: &[u8],
+    bank_bytes: usize,
+) -> String {
+    let constant = variable_name.to_uppercase();
+    let tile_bytes = bank_bytes / encoded_tile.bank_tiles.unwrap_or(1);
+    let mut formatted = String::new();
+    let banks = tile_data.chunks(bank_bytes.max(1));
+    let bank_count = banks.len();
+    for (bank, bank_data) in banks.enumerate() {
+        let label = format!("{}_bank_{}", variable_name, bank);
+        formatted.push_str(&format!(
+            "SECTION \"Tiles for '{}', bank {}\", ROMX\n\nDEF {}_BANK_{}_TILES EQU {}\nEXPORT {}_BANK_{}_TILES\n{}\n",
+            variable_name,
+            bank,
+            constant,
+            bank,
+            bank_data.len() / tile_bytes.max(1),
+            constant,
+            bank,
+            block(&label, bank_data)
+        ));
+    }
+    formatted.push_str(&format!(
+        "SECTION \"Tiles for '{}'\", ROM0\n\nDEF {}_BANK_COUNT EQU {}\nEXPORT {}_BANK_COUNT\n",
+        variable_name, constant, bank_count, constant
+    ));
+    formatted
+}
+
+/// An exported label of `db` lines, ending at `<label>_end`.
+fn block(label: &str, data: &[u8]) -> String {
+    format!(
+        "\nEXPORT {}, {}_end\n\n{}:\n{}\n{}_end:\n",
+        label,
+        label,
+        label,
+        db_lines(data),
+        label
+    )
+}
+
+/// `dw` lines of `words`, eight to a line.
+fn dw_lines(words: &[u16]) -> String {
+    let lines: Vec<String> = words
+        .chunks(8)
+        .map(|line| {
+            let formatted_words: Vec<String> =
+                line.iter().map(|word| format!("${:04x}", word)).collect();
+            format!("    dw {}", formatted_words.join(","))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn db_lines(tile_data: &[u8]) -> String {
+    let mut body = Vec::new();
+    for line in tile_data.chunks(16) {
+        let mut formatted_bytes = Vec::new();
+        for byte in line {
+            formatted_bytes.push(format!("${:02x}", byte));
+        }
+        body.push(format!("    db {}", formatted_bytes.join(",")));
+    }
+    body.join(",\n")
+}
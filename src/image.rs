@@ -0,0 +1,1867 @@
+use crate::cgb::{assign_palettes, CgbPalettes};
+use crate::diagnostic::Diagnostic;
+use crate::filter::filter_pixels;
+use crate::quantize::quantize;
+use crate::source::{ImageSource, SourceImage, SourceRegistry, MAGIC_LEN};
+use crate::tile::Tilemap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The maximum number of distinct shades a Game Boy tile can use.
+pub const GB_MAX_COLOR_COUNT: usize = 4;
+
+/// A tile color number, 0 to 3.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ColorNumber(u8);
+
+impl From<ColorNumber> for u8 {
+    fn from(color: ColorNumber) -> u8 {
+        color.0
+    }
+}
+
+impl TryFrom<u8> for ColorNumber {
+    type Error = String;
+
+    fn try_from(color: u8) -> Result<ColorNumber, String> {
+        match color {
+            0..=3 => Ok(ColorNumber(color)),
+            _ => Err(format!("'{}' isn't a color number, use 0 to 3", color)),
+        }
+    }
+}
+
+/// How many values of each channel [`Rgb::round`] snaps together.
+pub const DEFAULT_COLOR_TOLERANCE: u8 = 48;
+
+/// An 8-bit per channel color, as read from the source image.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash, Ord, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// The inverse of the Game Boy Color screen's channel mixing, in hundredths:
+/// the screen shows `(26r + 4g + 2b) / 32` red, `(24g + 8b) / 32` green and
+/// `(6r + 4g + 22b) / 32` blue for the red, green and blue written.
+const CGB_SCREEN_CORRECTION: [[i32; 3]; 3] = [[124, -20, -4], [12, 140, -52], [-36, -20, 156]];
+
+impl Rgb {
+    /// Snap each channel down to a coarse bucket, so slightly different
+    /// shades in the source image collapse into the same color.
+    pub fn round(&self) -> Rgb {
+        self.round_to(DEFAULT_COLOR_TOLERANCE)
+    }
+
+    /// The Game Boy Color's 15-bit form of this color, with 5 bits each of
+    /// red, green and blue from the lowest bits up, as written to BCPD and
+    /// OCPD.
+    pub fn to_rgb555(&self) -> u16 {
+        let channel = |value: u8| (u16::from(value) * 31 + 127) / 255;
+        channel(self.r) | (channel(self.g) << 5) | (channel(self.b) << 10)
+    }
+
+    /// The color to write to a Game Boy Color palette so it looks like this
+    /// one on the console's screen, which mixes some of each channel into
+    /// the others and washes colors out. Undoes the mixing emulators model
+    /// the screen with, as far as it can without leaving 0 to 255.
+    pub fn cgb_color_corrected(&self) -> Rgb {
+        let channels = [self.r, self.g, self.b].map(i32::from);
+        let correct = |weights: [i32; 3]| {
+            let sum: i32 = weights.iter().zip(channels).map(|(w, c)| w * c).sum();
+            (sum + 50).div_euclid(100).clamp(0, 255) as u8
+        };
+        let [r, g, b] = CGB_SCREEN_CORRECTION.map(correct);
+        Rgb { r, g, b }
+    }
+
+    /// Like [`Rgb::round`], but with buckets `tolerance` values wide. A
+    /// tolerance of 1 keeps every color.
+    pub fn round_to(&self, tolerance: u8) -> Rgb {
+        let tolerance = tolerance.max(1);
+        Rgb {
+            r: (self.r / tolerance) * tolerance,
+            g: (self.g / tolerance) * tolerance,
+            b: (self.b / tolerance) * tolerance,
+        }
+    }
+}
+
+/// An 8-bit per channel color with alpha, as read from the source image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    /// 0 for fully transparent, up to 255 for opaque.
+    pub a: u8,
+}
+
+impl Rgba {
+    /// The color, ignoring alpha.
+    pub fn rgb(&self) -> Rgb {
+        Rgb {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.a == 0
+    }
+
+    /// Whether the pixel counts as transparent when pixels less opaque
+    /// than `alpha_threshold` do.
+    pub fn is_below(&self, alpha_threshold: u8) -> bool {
+        self.a < alpha_threshold
+    }
+}
+
+impl fmt::Display for Rgb {
+    /// Formats as a hex color, e.g. `#ff00ff`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses a hex color, e.g. `#ff00ff`, with or without the `#`.
+    fn from_str(s: &str) -> Result<Rgb, String> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Rgb { r, g, b }),
+            _ => Err(format!("'{}' isn't a hex color like '#9bbc0f'", s)),
+        }
+    }
+}
+
+/// How the brightness of colors is compared, to number them from lightest
+/// to darkest.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Luminance {
+    /// Compare the red channels, then the green, then the blue, as gbtile
+    /// always has. Fine for grays, but can order saturated colors
+    /// unexpectedly.
+    #[default]
+    Rgb,
+    /// Weigh the channels by how bright they look, as in Rec. 709: 0.2126
+    /// red, 0.7152 green and 0.0722 blue, so saturated blues don't count
+    /// as bright as mid grays.
+    Rec709,
+}
+
+impl Luminance {
+    /// Order `a` and `b` from darkest to lightest, reading their channels
+    /// with `gamma`.
+    pub fn compare(self, gamma: Gamma, a: Rgb, b: Rgb) -> Ordering {
+        match self {
+            Luminance::Rgb => a.cmp(&b),
+            Luminance::Rec709 => self
+                .lightness(gamma, a)
+                .total_cmp(&self.lightness(gamma, b))
+                .then(a.cmp(&b)),
+        }
+    }
+
+    /// How light `rgb` is, from 0 for black to 1 for white, reading its
+    /// channels with `gamma`. `Rgb` weighs each channel the same.
+    pub fn lightness(self, gamma: Gamma, rgb: Rgb) -> f32 {
+        let (r, g, b) = (
+            gamma.intensity(rgb.r),
+            gamma.intensity(rgb.g),
+            gamma.intensity(rgb.b),
+        );
+        match self {
+            Luminance::Rgb => (r + g + b) / 3.0,
+            Luminance::Rec709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+        }
+    }
+}
+
+impl FromStr for Luminance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Luminance, String> {
+        match s {
+            "rgb" => Ok(Luminance::Rgb),
+            "rec709" => Ok(Luminance::Rec709),
+            _ => Err(format!("'{}' isn't a luminance, use 'rgb' or 'rec709'", s)),
+        }
+    }
+}
+
+/// How the channel values of colors are read when comparing them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Gamma {
+    /// Use the values as stored, so each step counts the same, as gbtile
+    /// always has.
+    #[default]
+    Srgb,
+    /// Decode the values from sRGB into linear light first, so mid-tones
+    /// weigh as much light as they really give off.
+    Linear,
+}
+
+impl Gamma {
+    /// The intensity of a channel value, from 0 to 1.
+    pub fn intensity(self, channel: u8) -> f32 {
+        let value = f32::from(channel) / 255.0;
+        match self {
+            Gamma::Srgb => value,
+            Gamma::Linear if value <= 0.04045 => value / 12.92,
+            Gamma::Linear => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    }
+}
+
+impl FromStr for Gamma {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Gamma, String> {
+        match s {
+            "srgb" => Ok(Gamma::Srgb),
+            "linear" => Ok(Gamma::Linear),
+            _ => Err(format!("'{}' isn't a gamma, use 'srgb' or 'linear'", s)),
+        }
+    }
+}
+
+/// How the colors of images reduced to four by quantizing are spread over
+/// their pixels.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Each pixel takes the nearest color, leaving flat bands where a
+    /// gradient was.
+    #[default]
+    None,
+    /// Spread each pixel's difference from its nearest color onto its
+    /// neighbors, so gradients turn into a mix of the colors either side.
+    FloydSteinberg,
+    /// Nudge each pixel lighter or darker by a repeating 2x2 pattern
+    /// before picking its nearest color. Ordered patterns repeat from tile
+    /// to tile, so they deduplicate and compress far better than error
+    /// diffusion.
+    Bayer2,
+    /// Like [`Dither::Bayer2`], with a finer 4x4 pattern.
+    Bayer4,
+    /// Like [`Dither::Bayer2`], with an 8x8 pattern, one per tile.
+    Bayer8,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dither, String> {
+        match s {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            "bayer2" => Ok(Dither::Bayer2),
+            "bayer4" => Ok(Dither::Bayer4),
+            "bayer8" => Ok(Dither::Bayer8),
+            _ => Err(format!(
+                "'{}' isn't a dither, use 'none', 'floyd-steinberg', 'bayer2', 'bayer4' or 'bayer8'",
+                s
+            )),
+        }
+    }
+}
+
+/// Options for turning a source image's pixels into color numbers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeOptions {
+    /// The color number given to fully transparent pixels. Defaults to 0,
+    /// the color sprites treat as transparent.
+    pub transparent_color: u8,
+    /// Only convert this rectangle of each frame, instead of the whole
+    /// image. It's widened to the 8x8 tile grid if it isn't on it.
+    pub rect: Option<Rect>,
+    /// Cut the cells of a sprite sheet out of their gutters and put them
+    /// side by side, in the same rows and columns, before anything else.
+    pub grid: Option<Grid>,
+    /// Cut the image into animation frames of this size, read left to
+    /// right and top to bottom, after `grid`. Each frame of an image that's
+    /// already animated is cut up in turn.
+    pub frame_size: Option<Grid>,
+    /// Extend images, or each animation frame, that aren't a whole number
+    /// of 8x8 tiles to the next tile boundary with pixels of this color
+    /// number, instead of dropping the edge pixels past the last full tile.
+    /// The image must use the color, or it must be the transparent color.
+    pub pad: Option<u8>,
+    /// Drop the edge pixels past the last full tile of images that aren't
+    /// a whole number of 8x8 tiles, with a warning, instead of failing with
+    /// [`ImageReadError::NotTileAligned`].
+    pub truncate: bool,
+    /// The colors of color numbers 0 to 3. When given, each of the image's
+    /// colors is matched exactly, without rounding, to the nearest of them
+    /// and takes its number, instead of colors being numbered by
+    /// brightness. Colors are counted after they're matched, so shades in
+    /// between, like anti-aliasing, don't take colors of their own.
+    pub palette: Option<[Rgb; GB_MAX_COLOR_COUNT]>,
+    /// The source color pinned to each color number, as given by
+    /// [`parse_color_map`](crate::parse_color_map). The image's other colors
+    /// are numbered by brightness around them. A pinned color also matches
+    /// the color it rounds to. Ignored when there's a `palette`.
+    pub color_map: [Option<Rgb>; GB_MAX_COLOR_COUNT],
+    /// How colors are ordered from lightest to darkest, when they're
+    /// numbered by brightness.
+    pub luminance: Luminance,
+    /// How channel values are read when comparing colors' brightness and
+    /// matching them to `palette`.
+    pub gamma: Gamma,
+    /// How many values of each channel are rounded together, so nearby
+    /// colors merge before they're counted. Defaults to
+    /// [`DEFAULT_COLOR_TOLERANCE`].
+    pub color_tolerance: u8,
+    /// Stretch the image's contrast before reading its colors, so the
+    /// darkest channel value becomes 0 and the lightest 255, spreading
+    /// washed-out images over all four shades. Streaming is skipped, since
+    /// every pixel has to be seen first.
+    pub auto_contrast: bool,
+    /// Round each channel to this many evenly spaced levels, 2 to 255,
+    /// before reading colors, after stretching the contrast.
+    pub posterize: Option<u8>,
+    /// Reduce images with too many colors to the best four by median cut,
+    /// instead of failing with [`ImageReadError::TooManyColors`].
+    pub quantize: bool,
+    /// How quantized images' colors are spread over their pixels.
+    pub dither: Dither,
+    /// Pixels with less alpha than this are transparent, and the rest are
+    /// opaque, so semi-transparent anti-aliasing doesn't add colors.
+    /// Defaults to 1, so only fully transparent pixels are.
+    pub alpha_threshold: u8,
+    /// Follow the sprite convention: transparent pixels are color 0, and
+    /// opaque pixels only use colors 1 to 3, whether or not the image has
+    /// transparent pixels. Overrides `transparent_color`.
+    pub sprite: bool,
+    /// Fail with [`ImageReadError::OffPalette`] if an opaque color isn't
+    /// exactly one of `palette`'s, instead of taking the nearest. Only
+    /// checked when there's a palette.
+    pub strict_palette: bool,
+    /// Keep opaque colors exactly as they are in the source instead of
+    /// rounding them, so they can be written out as a Game Boy Color
+    /// palette. Without a `palette`, images with more than four colors
+    /// are shared out between several palettes, as in [`CgbPalettes`].
+    pub cgb: bool,
+    /// Like `cgb`, but for the Super Game Boy's 4 palettes, which all share
+    /// color 0.
+    pub sgb: bool,
+    /// Number colors by the original Game Boy shade their lightness is
+    /// nearest, instead of just by their order, so Game Boy Color tiles
+    /// look right in grayscale on the original Game Boy too, through the
+    /// usual palette register. Only used when colors are numbered by
+    /// brightness.
+    pub dmg_compatible: bool,
+}
+
+impl DecodeOptions {
+    /// The colors opaque pixels may use with a strict palette, or `None`
+    /// if any may.
+    pub(crate) fn strict_colors(&self) -> Option<&[Rgb]> {
+        match (&self.palette, self.strict_palette) {
+            (Some(palette), true) => Some(&palette[self.sprite as usize..]),
+            _ => None,
+        }
+    }
+
+    /// The color number kept for transparent pixels, if an image with or
+    /// without them keeps one.
+    pub(crate) fn transparent_color(&self, has_transparent: bool) -> Option<u8> {
+        match (has_transparent, self.sprite) {
+            (_, true) => Some(0),
+            (true, false) => Some(self.transparent_color),
+            (false, false) => None,
+        }
+    }
+
+    /// Whether an image with or without transparent pixels has one color
+    /// number fewer for its opaque colors. With a palette, opaque colors
+    /// take the palette's numbers, and transparent pixels share theirs
+    /// with the palette color of the same number.
+    pub(crate) fn reserves_transparent(&self, has_transparent: bool) -> bool {
+        self.palette.is_none() && self.transparent_color(has_transparent).is_some()
+    }
+
+    /// Whether opaque colors are kept exactly and shared out between
+    /// several palettes, for the Game Boy Color or Super Game Boy.
+    pub(crate) fn shares_palettes(&self) -> bool {
+        self.cgb || self.sgb
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            transparent_color: 0,
+            rect: None,
+            grid: None,
+            frame_size: None,
+            pad: None,
+            truncate: false,
+            palette: None,
+            color_map: [None; GB_MAX_COLOR_COUNT],
+            luminance: Luminance::default(),
+            gamma: Gamma::default(),
+            color_tolerance: DEFAULT_COLOR_TOLERANCE,
+            auto_contrast: false,
+            posterize: None,
+            quantize: false,
+            dither: Dither::default(),
+            alpha_threshold: 1,
+            sprite: false,
+            strict_palette: false,
+            cgb: false,
+            sgb: false,
+            dmg_compatible: false,
+        }
+    }
+}
+
+/// A rectangle of an image, in pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The smallest rectangle on the 8x8 tile grid that covers this one.
+    pub fn snap_to_tiles(&self) -> Rect {
+        let (x, y) = (self.x / 8 * 8, self.y / 8 * 8);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).div_ceil(8) * 8 - x,
+            height: (self.y + self.height).div_ceil(8) * 8 - y,
+        }
+    }
+}
+
+impl fmt::Display for Rect {
+    /// Formats as `x,y,width,height`, e.g. `0,16,32,8`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    /// Parses `x,y,width,height`, e.g. `0,16,32,8`.
+    fn from_str(s: &str) -> Result<Rect, String> {
+        let numbers = s
+            .split(',')
+            .map(|number| number.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>();
+        match numbers.as_deref() {
+            Ok(&[x, y, width, height]) if width > 0 && height > 0 => Ok(Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            Ok(&[_, _, _, _]) => Err(format!("'{}' is empty, its width and height can't be 0", s)),
+            _ => Err(format!("'{}' isn't a rectangle like '0,16,32,8'", s)),
+        }
+    }
+}
+
+/// The cells of a sprite sheet laid out on a grid, with gutters around
+/// and between them that aren't part of any cell.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Grid {
+    /// The size of each cell in pixels, a whole number of 8x8 tiles.
+    pub width: u32,
+    pub height: u32,
+    /// The pixels before the first cell, left of it and above it.
+    pub margin: u32,
+    /// The pixels between neighbouring cells.
+    pub spacing: u32,
+}
+
+impl Grid {
+    /// How many whole cells fit across and down an image `width` by
+    /// `height` pixels.
+    pub fn cells(&self, width: u32, height: u32) -> (u32, u32) {
+        let fit = |size: u32, cell: u32| {
+            (size + self.spacing).saturating_sub(self.margin) / (cell + self.spacing)
+        };
+        (fit(width, self.width), fit(height, self.height))
+    }
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    /// Parses the cell size as `widthxheight`, e.g. `16x16`, with no
+    /// margin or spacing.
+    fn from_str(s: &str) -> Result<Grid, String> {
+        let size = s
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        match size {
+            Some((width, height))
+                if width > 0 && height > 0 && width % 8 == 0 && height % 8 == 0 =>
+            {
+                Ok(Grid {
+                    width,
+                    height,
+                    margin: 0,
+                    spacing: 0,
+                })
+            }
+            Some(_) => Err(format!("'{}' isn't a whole number of 8x8 tiles", s)),
+            None => Err(format!("'{}' isn't a size like '16x16'", s)),
+        }
+    }
+}
+
+/// A source image reduced to at most four colors, with each color
+/// assigned a Game Boy color number.
+pub struct DecodedImage {
+    pub input_filename: String,
+    pub width: u32,
+    pub height: u32,
+    /// Each pixel's color after rounding, or `None` for fully transparent
+    /// pixels.
+    pub image_data: Vec<Option<Rgb>>,
+    /// The color numbers of the opaque colors.
+    pub color_numbers: BTreeMap<Rgb, u8>,
+    /// The color number of transparent pixels, if the image has any.
+    pub transparent_color: Option<u8>,
+    /// The suggested BGP or OBP register value, as in
+    /// [`EncodedTile::palette_register`](crate::EncodedTile::palette_register).
+    pub palette_register: u8,
+    /// The Game Boy Color palettes shared out between the image's tiles,
+    /// when it has more colors than one palette holds. `color_numbers`
+    /// then holds the first palette's.
+    pub cgb_palettes: Option<CgbPalettes>,
+    /// How many animation frames are stacked in the image, as in
+    /// [`SourceImage::frames`].
+    pub frames: u32,
+    /// The maps drawn with the image's tiles, as in
+    /// [`SourceImage::tilemaps`].
+    pub tilemaps: Vec<Tilemap>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl DecodedImage {
+    /// Round the colors of a source image and assign each a color number.
+    /// Fully transparent pixels count as one more color, and all take the
+    /// transparent color number from `options`.
+    pub fn from_source(
+        input_filename: &str,
+        mut source_image: SourceImage,
+        options: &DecodeOptions,
+    ) -> Result<DecodedImage, ImageReadError> {
+        if let Some(grid) = options.grid {
+            source_image = slice_grid(source_image, grid)?;
+        }
+        if let Some(frame_size) = options.frame_size {
+            source_image = slice_frames(source_image, frame_size)?;
+        }
+        if let Some(pad) = options.pad {
+            source_image = pad_to_tiles(source_image, pad, options)?;
+        }
+        if let Some(rect) = options.rect {
+            return DecodedImage::from_source_rect(input_filename, &source_image, rect, options);
+        }
+        check_dimensions(source_image.width, source_image.height)?;
+        check_frames(source_image.height, source_image.frames)?;
+        DecodedImage::from_pixels(input_filename, source_image, options, None, Vec::new())
+    }
+
+    /// Like [`DecodedImage::from_source`] with `rect` as the rectangle to
+    /// convert, but borrowing the source image, so several rectangles can
+    /// be cut from an image decoded once.
+    pub fn from_source_rect(
+        input_filename: &str,
+        source_image: &SourceImage,
+        rect: Rect,
+        options: &DecodeOptions,
+    ) -> Result<DecodedImage, ImageReadError> {
+        check_dimensions(source_image.width, source_image.height)?;
+        check_frames(source_image.height, source_image.frames)?;
+        let frame_height = source_image.height / source_image.frames.max(1);
+        let (rect, warning) = tile_rect(input_filename, rect, source_image.width, frame_height)?;
+        DecodedImage::from_pixels(
+            input_filename,
+            crop_frames(source_image, rect, frame_height),
+            options,
+            Some((rect, frame_height)),
+            warning.into_iter().collect(),
+        )
+    }
+
+    /// Number the colors of a source image already cut down to the part
+    /// being converted. `rect` is where that part is in each frame of the
+    /// original image, and how high those frames are, for errors.
+    fn from_pixels(
+        input_filename: &str,
+        source_image: SourceImage,
+        options: &DecodeOptions,
+        rect: Option<(Rect, u32)>,
+        mut warnings: Vec<Diagnostic>,
+    ) -> Result<DecodedImage, ImageReadError> {
+        warnings.extend(check_tile_aligned(
+            input_filename,
+            source_image.width,
+            source_image.height,
+            source_image.frames,
+            options,
+        )?);
+        let palette = source_image.palette.as_deref();
+        let color_uses = |indices: Vec<usize>| -> Vec<ColorUse> {
+            indices
+                .into_iter()
+                .map(|i| {
+                    let (x, y) = source_position(i, source_image.width, rect);
+                    ColorUse {
+                        color: source_image.pixels[i].rgb(),
+                        x,
+                        y,
+                    }
+                })
+                .collect()
+        };
+        if let Some(strict_colors) = options.strict_colors() {
+            let off = off_palette(&source_image, strict_colors, options);
+            if !off.is_empty() {
+                return Err(ImageReadError::off_palette(color_uses(off)));
+            }
+        }
+        let image_data = pixel_colors(&source_image, options);
+        let (unique_colors, mut has_transparent, stray) = scan_colors(&image_data, options);
+        let shares_palettes = options.shares_palettes() && options.palette.is_none();
+        // A palette mask shares out palettes even between tiles that would
+        // fit in one.
+        let palette_mask = source_image
+            .palette_mask
+            .as_deref()
+            .filter(|_| shares_palettes);
+        let mut cgb_palettes = None;
+        if !stray.is_empty() || palette_mask.is_some() {
+            debug!(
+                "Unique colors are: {:?}, transparent: {}, stray: {:?}",
+                unique_colors, has_transparent, stray
+            );
+            if !shares_palettes {
+                return Err(ImageReadError::too_many_colors(
+                    color_uses(stray),
+                    options.sprite,
+                ));
+            }
+            // Transparency may have been left over with the stray colors.
+            has_transparent = image_data.contains(&None);
+            cgb_palettes = Some(assign_palettes(
+                &image_data,
+                source_image.width,
+                options.transparent_color(has_transparent),
+                palette_mask,
+                options,
+                |i| color_uses(vec![i]).remove(0),
+            )?);
+        }
+        let transparent_color = options.transparent_color(has_transparent);
+        let color_numbers = match &cgb_palettes {
+            Some(cgb_palettes) => cgb_palettes.color_numbers[0].clone(),
+            None => number_colors(&unique_colors, palette, transparent_color, options),
+        };
+        debug!(
+            "Color numbers are: {:?}, transparent: {:?}",
+            color_numbers, transparent_color
+        );
+
+        Ok(DecodedImage {
+            input_filename: input_filename.to_string(),
+            width: source_image.width,
+            height: source_image.height,
+            image_data,
+            palette_register: palette_register(&color_numbers, options),
+            cgb_palettes,
+            color_numbers,
+            transparent_color,
+            frames: source_image.frames,
+            tilemaps: source_image.tilemaps,
+            warnings,
+        })
+    }
+
+    /// The Game Boy color number (0-3) for a pixel in this image.
+    pub fn lookup_color(&self, pixel: Option<Rgb>) -> u8 {
+        match pixel {
+            Some(rgb) => self.color_numbers[&rgb],
+            None => self.transparent_color.unwrap(),
+        }
+    }
+}
+
+/// A color an image uses, and the first pixel using it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorUse {
+    /// The color in the source image, before rounding.
+    pub color: Rgb,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl ColorUse {
+    /// The row and column of the 8x8 tile holding the pixel.
+    pub fn tile(&self) -> (u32, u32) {
+        (self.y / 8, self.x / 8)
+    }
+}
+
+/// How many of the colors that don't fit are listed in
+/// [`ImageReadError::TooManyColors`] and [`ImageReadError::OffPalette`]
+/// messages.
+const LISTED_STRAY_COLORS: usize = 8;
+
+#[derive(Debug)]
+pub enum ImageReadError {
+    Png(png::DecodingError),
+    Io(io::Error),
+    UnsupportedColorType(png::ColorType),
+    /// The input is malformed, or uses a feature of its format that isn't
+    /// supported.
+    Invalid {
+        /// The format's name, e.g. `GIF`.
+        format: &'static str,
+        message: String,
+    },
+    UnknownFormat,
+    /// A fifth color was found, first appearing at pixel (`x`, `y`).
+    TooManyColors {
+        x: u32,
+        y: u32,
+        /// The color of that pixel in the source image, before rounding.
+        color: Rgb,
+        /// Every other color that didn't fit, in the order they first
+        /// appear.
+        more: Vec<ColorUse>,
+        /// Whether the image was read as a sprite, whose color 0 is always
+        /// transparent, leaving three colors for opaque pixels.
+        sprite: bool,
+    },
+    /// With a strict palette, a color that isn't exactly one of the
+    /// palette's was found, first appearing at pixel (`x`, `y`).
+    OffPalette {
+        x: u32,
+        y: u32,
+        color: Rgb,
+        /// Every other color that isn't in the palette, in the order they
+        /// first appear.
+        more: Vec<ColorUse>,
+    },
+    /// With Game Boy Color palettes, the tile at pixel (`x`, `y`) doesn't
+    /// fit in any of the palettes left by the tiles placed before it.
+    TooManyPalettes {
+        x: u32,
+        y: u32,
+        /// The tile's colors.
+        colors: Vec<Rgb>,
+        /// How many colors the whole image uses.
+        total: usize,
+        /// How many palettes there were to share out.
+        palettes: usize,
+    },
+    /// The tile at pixel (`x`, `y`) doesn't fit in the palette a palette
+    /// mask marks it for, alongside the other tiles marked for it.
+    PaletteMaskFull {
+        x: u32,
+        y: u32,
+        /// The tile's colors.
+        colors: Vec<Rgb>,
+        palette: u8,
+        /// How many colors the palette's tiles use, with this one's.
+        total: usize,
+    },
+    ImageTooSmall {
+        width: u32,
+        height: u32,
+    },
+    /// The frames of an animation aren't a whole number of tiles high, so
+    /// they can't be cut into tiles separately.
+    FrameHeight {
+        height: u32,
+    },
+    /// The rectangle to convert, after widening it to the tile grid,
+    /// reaches past the edge of a `width` by `height` frame.
+    RectOutsideImage {
+        rect: Rect,
+        width: u32,
+        height: u32,
+    },
+    /// The image, or each of its animation frames, isn't a whole number of
+    /// 8x8 tiles wide and high.
+    NotTileAligned {
+        width: u32,
+        height: u32,
+        frames: u32,
+    },
+    /// Not a single cell of the grid fits in a `width` by `height` frame.
+    NoGridCells {
+        grid: Grid,
+        width: u32,
+        height: u32,
+    },
+    /// The image was to be padded to whole tiles with a color number that
+    /// none of its colors take.
+    PadColor {
+        color: u8,
+    },
+    /// 1bpp tiles were asked for, but the tiles draw with these colors
+    /// besides color 0, more than the one other 1bpp has.
+    OneBppColors {
+        colors: Vec<u8>,
+    },
+    /// A font of `glyphs` tiles, starting at character `first_char`, runs
+    /// past character 255.
+    FontPastLastChar {
+        glyphs: usize,
+        first_char: u8,
+    },
+    /// Tile `tile`, numbered from `base_index`, would be numbered past the
+    /// 256 a tile map can hold.
+    TileNumberPastLast {
+        tile: usize,
+        base_index: u8,
+    },
+}
+
+impl fmt::Display for ImageReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageReadError::Png(err) => write!(f, "invalid PNG: {}", err),
+            ImageReadError::Io(err) => write!(f, "{}", err),
+            ImageReadError::UnsupportedColorType(color_type) => {
+                write!(f, "unsupported PNG color type: {:?}", color_type)
+            }
+            ImageReadError::Invalid { format, message } => {
+                write!(f, "invalid {}: {}", format, message)
+            }
+            ImageReadError::UnknownFormat => write!(f, "unrecognized image format"),
+            ImageReadError::TooManyColors {
+                x,
+                y,
+                color,
+                more,
+                sprite,
+            } => {
+                let first = ColorUse {
+                    color: *color,
+                    x: *x,
+                    y: *y,
+                };
+                let (row, column) = first.tile();
+                if *sprite {
+                    write!(
+                        f,
+                        "pixel uses a {}th opaque color, {}, in tile row {}, column {}, but sprites can only use {} besides transparent color 0 after rounding",
+                        GB_MAX_COLOR_COUNT, color, row, column, GB_MAX_COLOR_COUNT - 1
+                    )?;
+                } else {
+                    write!(
+                        f,
+                        "pixel uses a 5th color, {}, in tile row {}, column {}, but tiles can only use {} after rounding",
+                        color, row, column, GB_MAX_COLOR_COUNT
+                    )?;
+                }
+                write_more_colors(f, more)
+            }
+            ImageReadError::OffPalette { x, y, color, more } => {
+                let (row, column) = (y / 8, x / 8);
+                write!(
+                    f,
+                    "pixel uses {}, in tile row {}, column {}, which isn't one of the palette's colors",
+                    color, row, column
+                )?;
+                write_more_colors(f, more)
+            }
+            ImageReadError::TooManyPalettes {
+                x,
+                y,
+                colors,
+                total,
+                palettes,
+            } => {
+                let colors: Vec<String> = colors.iter().map(Rgb::to_string).collect();
+                write!(
+                    f,
+                    "tile row {}, column {}, using {}, doesn't fit in any of the {} palettes alongside the other tiles, which use {} colors in all",
+                    y / 8,
+                    x / 8,
+                    colors.join(", "),
+                    palettes,
+                    total
+                )
+            }
+            ImageReadError::PaletteMaskFull {
+                x,
+                y,
+                colors,
+                palette,
+                total,
+            } => {
+                let colors: Vec<String> = colors.iter().map(Rgb::to_string).collect();
+                write!(
+                    f,
+                    "tile row {}, column {}, using {}, doesn't fit in palette {}, which the palette mask marks it for, since that palette's tiles use {} colors",
+                    y / 8,
+                    x / 8,
+                    colors.join(", "),
+                    palette,
+                    total
+                )
+            }
+            ImageReadError::ImageTooSmall { width, height } => write!(
+                f,
+                "image is {}x{} pixels, but must be at least one 8x8 tile",
+                width, height
+            ),
+            ImageReadError::FrameHeight { height } => write!(
+                f,
+                "animation frames are {} pixels high, but must be a multiple of 8",
+                height
+            ),
+            ImageReadError::RectOutsideImage {
+                rect,
+                width,
+                height,
+            } => write!(
+                f,
+                "rectangle {} reaches past the edge of the {}x{} pixel image",
+                rect, width, height
+            ),
+            ImageReadError::NotTileAligned {
+                width,
+                height,
+                frames,
+            } => {
+                let frame_height = height / (*frames).max(1);
+                write!(
+                    f,
+                    "{} {}x{} pixels, but must be a whole number of 8x8 tiles; the nearest such size is {}x{}",
+                    if *frames > 1 { "frames are" } else { "image is" },
+                    width,
+                    frame_height,
+                    nearest_tile_size(*width),
+                    nearest_tile_size(frame_height)
+                )
+            }
+            ImageReadError::NoGridCells {
+                grid,
+                width,
+                height,
+            } => write!(
+                f,
+                "image is {}x{} pixels, too small for a single {}x{} cell of the grid",
+                width, height, grid.width, grid.height
+            ),
+            ImageReadError::PadColor { color } => write!(
+                f,
+                "can't pad the image to whole tiles with color {}, none of its colors are numbered {}",
+                color, color
+            ),
+            ImageReadError::OneBppColors { colors } => {
+                let colors: Vec<String> = colors.iter().map(u8::to_string).collect();
+                write!(
+                    f,
+                    "1bpp tiles can only draw with color 0 and one other, but these use colors {}",
+                    colors.join(", ")
+                )
+            }
+            ImageReadError::FontPastLastChar { glyphs, first_char } => write!(
+                f,
+                "the font's {} glyphs from character {} run past character 255",
+                glyphs, first_char
+            ),
+            ImageReadError::TileNumberPastLast { tile, base_index } => write!(
+                f,
+                "tile {} would be tile {} from base index {}, past the 256 a map can number",
+                tile,
+                tile + *base_index as usize,
+                base_index
+            ),
+        }
+    }
+}
+
+/// List the first few of `more`, the other colors that didn't fit, after
+/// an error message.
+fn write_more_colors(f: &mut fmt::Formatter, more: &[ColorUse]) -> fmt::Result {
+    for stray in more.iter().take(LISTED_STRAY_COLORS) {
+        let (row, column) = stray.tile();
+        write!(
+            f,
+            "; {} first at {},{} in tile row {}, column {}",
+            stray.color, stray.x, stray.y, row, column
+        )?;
+    }
+    if more.len() > LISTED_STRAY_COLORS {
+        write!(f, "; and {} more colors", more.len() - LISTED_STRAY_COLORS)?;
+    }
+    Ok(())
+}
+
+impl ImageReadError {
+    /// The error for an image with the colors of `stray` left over after
+    /// its first four, or a sprite's first three opaque colors, pointing at
+    /// the first of them.
+    pub(crate) fn too_many_colors(mut stray: Vec<ColorUse>, sprite: bool) -> ImageReadError {
+        let first = stray.remove(0);
+        ImageReadError::TooManyColors {
+            x: first.x,
+            y: first.y,
+            color: first.color,
+            more: stray,
+            sprite,
+        }
+    }
+
+    /// The error for an image using the colors of `stray`, which aren't in
+    /// a strict palette, pointing at the first of them.
+    pub(crate) fn off_palette(mut stray: Vec<ColorUse>) -> ImageReadError {
+        let first = stray.remove(0);
+        ImageReadError::OffPalette {
+            x: first.x,
+            y: first.y,
+            color: first.color,
+            more: stray,
+        }
+    }
+
+    /// A stable identifier for this kind of error, used in diagnostics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ImageReadError::Png(_) => "png",
+            ImageReadError::Io(_) => "io",
+            ImageReadError::UnsupportedColorType(_) => "unsupported-color-type",
+            ImageReadError::Invalid { .. } => "invalid-image",
+            ImageReadError::UnknownFormat => "unknown-format",
+            ImageReadError::TooManyColors { .. } => "too-many-colors",
+            ImageReadError::OffPalette { .. } => "off-palette",
+            ImageReadError::TooManyPalettes { .. } => "too-many-palettes",
+            ImageReadError::PaletteMaskFull { .. } => "palette-mask-full",
+            ImageReadError::ImageTooSmall { .. } => "image-too-small",
+            ImageReadError::FrameHeight { .. } => "frame-height",
+            ImageReadError::RectOutsideImage { .. } => "rect-outside-image",
+            ImageReadError::NotTileAligned { .. } => "not-tile-aligned",
+            ImageReadError::NoGridCells { .. } => "no-grid-cells",
+            ImageReadError::PadColor { .. } => "pad-color",
+            ImageReadError::OneBppColors { .. } => "one-bpp-colors",
+            ImageReadError::FontPastLastChar { .. } => "font-past-last-char",
+            ImageReadError::TileNumberPastLast { .. } => "tile-number-past-last",
+        }
+    }
+
+    /// The pixel coordinates the error was found at, if it has one.
+    pub fn location(&self) -> Option<(u32, u32)> {
+        match self {
+            ImageReadError::TooManyColors { x, y, .. }
+            | ImageReadError::OffPalette { x, y, .. }
+            | ImageReadError::TooManyPalettes { x, y, .. }
+            | ImageReadError::PaletteMaskFull { x, y, .. } => Some((*x, *y)),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for ImageReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ImageReadError::Png(err) => Some(err),
+            ImageReadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageReadError {
+    fn from(err: io::Error) -> Self {
+        ImageReadError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for ImageReadError {
+    fn from(err: png::DecodingError) -> Self {
+        ImageReadError::Png(err)
+    }
+}
+
+/// Fail if an image is too small to hold a single tile.
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<(), ImageReadError> {
+    if width < 8 || height < 8 {
+        return Err(ImageReadError::ImageTooSmall { width, height });
+    }
+    Ok(())
+}
+
+/// The cells of `grid` in each frame of `source_image`, cut out of their
+/// gutters and put side by side in the same rows and columns. Pixels past
+/// the last whole cell are dropped.
+fn slice_grid(source_image: SourceImage, grid: Grid) -> Result<SourceImage, ImageReadError> {
+    let frames = source_image.frames.max(1);
+    let frame_height = source_image.height / frames;
+    let (columns, rows) = grid.cells(source_image.width, frame_height);
+    if columns == 0 || rows == 0 {
+        return Err(ImageReadError::NoGridCells {
+            grid,
+            width: source_image.width,
+            height: frame_height,
+        });
+    }
+    debug!(
+        "Slicing {} columns and {} rows of {}x{} cells",
+        columns, rows, grid.width, grid.height
+    );
+    let cell_start = |cell: u32, size: u32| grid.margin + cell * (size + grid.spacing);
+    let (width, height) = (columns * grid.width, rows * grid.height);
+    let mut pixels = Vec::with_capacity((width * height * frames) as usize);
+    let mut palette_mask = source_image.palette_mask.as_ref().map(|_| Vec::new());
+    for frame in 0..frames {
+        for row in 0..rows {
+            let cells: Vec<SourceImage> = (0..columns)
+                .map(|column| {
+                    source_image.crop(
+                        cell_start(column, grid.width),
+                        frame * frame_height + cell_start(row, grid.height),
+                        grid.width,
+                        grid.height,
+                    )
+                })
+                .collect();
+            for y in 0..grid.height as usize {
+                let line = y * grid.width as usize..(y + 1) * grid.width as usize;
+                for cell in &cells {
+                    pixels.extend_from_slice(&cell.pixels[line.clone()]);
+                    if let (Some(palette_mask), Some(cell_mask)) =
+                        (&mut palette_mask, &cell.palette_mask)
+                    {
+                        palette_mask.extend_from_slice(&cell_mask[line.clone()]);
+                    }
+                }
+            }
+        }
+    }
+    Ok(SourceImage {
+        width,
+        height: height * frames,
+        pixels,
+        palette: source_image.palette,
+        frames: source_image.frames,
+        tilemaps: Vec::new(),
+        palette_mask,
+    })
+}
+
+/// The cells of `frame_size` in each frame of `source_image`, left to right
+/// and top to bottom, stacked as the frames of an animation. Pixels past
+/// the last whole cell are dropped.
+fn slice_frames(
+    source_image: SourceImage,
+    frame_size: Grid,
+) -> Result<SourceImage, ImageReadError> {
+    let frames = source_image.frames.max(1);
+    let frame_height = source_image.height / frames;
+    let (columns, rows) = frame_size.cells(source_image.width, frame_height);
+    if columns == 0 || rows == 0 {
+        return Err(ImageReadError::NoGridCells {
+            grid: frame_size,
+            width: source_image.width,
+            height: frame_height,
+        });
+    }
+    debug!(
+        "Cutting {} frames of {}x{} pixels from each frame",
+        columns * rows,
+        frame_size.width,
+        frame_size.height
+    );
+    let cell_start = |cell: u32, size: u32| frame_size.margin + cell * (size + frame_size.spacing);
+    let cells: Vec<SourceImage> = (0..frames)
+        .flat_map(|frame| (0..rows).map(move |row| (frame, row)))
+        .flat_map(|(frame, row)| (0..columns).map(move |column| (frame, row, column)))
+        .map(|(frame, row, column)| {
+            source_image.crop(
+                cell_start(column, frame_size.width),
+                frame * frame_height + cell_start(row, frame_size.height),
+                frame_size.width,
+                frame_size.height,
+            )
+        })
+        .collect();
+    let palette_mask = source_image.palette_mask.as_ref().map(|_| {
+        cells
+            .iter()
+            .flat_map(|cell| cell.palette_mask.iter().flatten().copied())
+            .collect()
+    });
+    Ok(SourceImage {
+        width: frame_size.width,
+        height: frame_size.height * cells.len() as u32,
+        pixels: cells
+            .iter()
+            .flat_map(|cell| cell.pixels.iter().copied())
+            .collect(),
+        palette: source_image.palette,
+        frames: cells.len() as u32,
+        tilemaps: Vec::new(),
+        palette_mask,
+    })
+}
+
+/// `source_image` with each frame extended to whole 8x8 tiles with pixels
+/// of the color numbered `pad`, copied from the first pixel taking it, so
+/// they're numbered the same. Fails if no pixel takes it, unless it's the
+/// transparent color.
+fn pad_to_tiles(
+    source_image: SourceImage,
+    pad: u8,
+    options: &DecodeOptions,
+) -> Result<SourceImage, ImageReadError> {
+    let frame_height = source_image.height / source_image.frames.max(1);
+    if source_image.width.is_multiple_of(8) && frame_height.is_multiple_of(8) {
+        return Ok(source_image);
+    }
+    let image_data = pixel_colors(&source_image, options);
+    let (unique_colors, has_transparent, _) = scan_colors(&image_data, options);
+    let transparent_color = options.transparent_color(has_transparent);
+    let color_numbers = number_colors(
+        &unique_colors,
+        source_image.palette.as_deref(),
+        transparent_color,
+        options,
+    );
+    let numbered = |pixel: &Option<Rgb>| match pixel {
+        Some(rgb) => color_numbers.get(rgb) == Some(&pad),
+        None => transparent_color == Some(pad),
+    };
+    let fill = match image_data.iter().position(numbered) {
+        Some(i) => source_image.pixels[i],
+        // Sprites keep color 0 for transparent pixels even without any.
+        None if transparent_color == Some(pad) => Rgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        },
+        None => return Err(ImageReadError::PadColor { color: pad }),
+    };
+    debug!(
+        "Padding {}x{} pixel frames to whole tiles with {:?}",
+        source_image.width, frame_height, fill
+    );
+    Ok(source_image.pad_to_tiles(fill))
+}
+
+/// Fail if the frames stacked in an image of `height` pixels aren't each a
+/// whole number of tiles high.
+pub(crate) fn check_frames(height: u32, frames: u32) -> Result<(), ImageReadError> {
+    let frame_height = height / frames.max(1);
+    if frames > 1 && !frame_height.is_multiple_of(8) {
+        return Err(ImageReadError::FrameHeight {
+            height: frame_height,
+        });
+    }
+    Ok(())
+}
+
+/// Each pixel's color after filtering and rounding, or after quantizing if
+/// `options` asks for it, or `None` for transparent pixels.
+fn pixel_colors(source_image: &SourceImage, options: &DecodeOptions) -> Vec<Option<Rgb>> {
+    // A palette was chosen by the artist, so its colors are kept exactly
+    // instead of being rounded.
+    let exact =
+        source_image.palette.is_some() || options.palette.is_some() || options.shares_palettes();
+    let pixels = filter_pixels(&source_image.pixels, options);
+    let mut image_data: Vec<Option<Rgb>> = pixels
+        .iter()
+        .map(|pixel| {
+            trace!("Original RGBA is: {:?}", pixel);
+            match pixel {
+                pixel if pixel.is_below(options.alpha_threshold) => None,
+                pixel if exact => Some(palette_color(pixel.rgb(), options)),
+                pixel => Some(pixel.rgb().round_to(options.color_tolerance)),
+            }
+        })
+        .collect();
+    if options.quantize {
+        quantize(&mut image_data, &pixels, source_image.width, options);
+    }
+    image_data
+}
+
+/// The opaque colors of `image_data` that fit in a tile, whether it has
+/// transparent pixels that fit too, and the index of the first pixel of
+/// each color left over. Colors past the fourth are left out and
+/// listed, so every one can be fixed at once.
+fn scan_colors(
+    image_data: &[Option<Rgb>],
+    options: &DecodeOptions,
+) -> (BTreeSet<Rgb>, bool, Vec<usize>) {
+    let mut unique_colors = BTreeSet::new();
+    let mut has_transparent = false;
+    let mut stray_colors = BTreeSet::new();
+    let mut stray = Vec::new();
+    for (i, color) in image_data.iter().enumerate() {
+        if stray_colors.contains(color) {
+            continue;
+        }
+        let added = match color {
+            Some(color) => unique_colors.insert(*color),
+            None => !std::mem::replace(&mut has_transparent, true),
+        };
+        let reserved = options.reserves_transparent(has_transparent);
+        if added && unique_colors.len() + reserved as usize > GB_MAX_COLOR_COUNT {
+            match color {
+                Some(color) => unique_colors.remove(color),
+                None => std::mem::replace(&mut has_transparent, false),
+            };
+            stray_colors.insert(*color);
+            stray.push(i);
+        }
+    }
+    (unique_colors, has_transparent, stray)
+}
+
+/// The index of the first pixel of each opaque color of `source_image`
+/// that isn't exactly one of `strict_colors`.
+fn off_palette(
+    source_image: &SourceImage,
+    strict_colors: &[Rgb],
+    options: &DecodeOptions,
+) -> Vec<usize> {
+    let mut seen = BTreeSet::new();
+    source_image
+        .pixels
+        .iter()
+        .enumerate()
+        .filter(|(_, pixel)| {
+            !pixel.is_below(options.alpha_threshold) && !strict_colors.contains(&pixel.rgb())
+        })
+        .filter(|(_, pixel)| seen.insert(pixel.rgb()))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Which pixels of `source_image` are opaque and not exactly one of the
+/// strict palette's colors in `options`, as a row by row mask.
+pub(crate) fn off_palette_pixels(source_image: &SourceImage, options: &DecodeOptions) -> Vec<bool> {
+    let strict_colors = options.strict_colors().unwrap_or(&[]);
+    source_image
+        .pixels
+        .iter()
+        .map(|pixel| {
+            !pixel.is_below(options.alpha_threshold) && !strict_colors.contains(&pixel.rgb())
+        })
+        .collect()
+}
+
+/// Where pixel `i` of an image `width` pixels wide is in the whole source
+/// image, when the image is `rect` of each frame as in
+/// [`DecodedImage::from_pixels`].
+fn source_position(i: usize, width: u32, rect: Option<(Rect, u32)>) -> (u32, u32) {
+    let i = i as u32;
+    let (x, y) = (i % width, i / width);
+    match rect {
+        Some((rect, frame_height)) => (
+            rect.x + x,
+            y / rect.height * frame_height + rect.y + y % rect.height,
+        ),
+        None => (x, y),
+    }
+}
+
+/// The tile-aligned rectangle of `options` in each frame of `source_image`
+/// and how high the frames are, with the rectangle cut out of every frame,
+/// or `None` if there's no rectangle or it doesn't fit.
+fn crop_to_rect(
+    source_image: &SourceImage,
+    options: &DecodeOptions,
+) -> (Option<(Rect, u32)>, Option<SourceImage>) {
+    let frame_height = source_image.height / source_image.frames.max(1);
+    let rect = options
+        .rect
+        .as_ref()
+        .map(Rect::snap_to_tiles)
+        .filter(|rect| {
+            rect.x + rect.width <= source_image.width && rect.y + rect.height <= frame_height
+        })
+        .map(|rect| (rect, frame_height));
+    let cropped = rect.map(|(rect, frame_height)| crop_frames(source_image, rect, frame_height));
+    (rect, cropped)
+}
+
+/// One of an image's colors, as counted by [`count_colors`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorCount {
+    /// The color after filtering and rounding, or `None` for transparent
+    /// pixels.
+    pub color: Option<Rgb>,
+    /// How many pixels use it.
+    pub pixels: usize,
+}
+
+/// Count the pixels of each of `source_image`'s colors as they're read
+/// when it's decoded with `options`, most used first, whether or not they
+/// fit in a tile. Only the rectangle of `options` is counted, if it has
+/// one.
+pub fn count_colors(source_image: &SourceImage, options: &DecodeOptions) -> Vec<ColorCount> {
+    let (_, cropped) = crop_to_rect(source_image, options);
+    let image = cropped.as_ref().unwrap_or(source_image);
+    let mut counts: BTreeMap<Option<Rgb>, usize> = BTreeMap::new();
+    for color in pixel_colors(image, options) {
+        *counts.entry(color).or_insert(0) += 1;
+    }
+    let mut counts: Vec<ColorCount> = counts
+        .into_iter()
+        .map(|(color, pixels)| ColorCount { color, pixels })
+        .collect();
+    counts.sort_by_key(|count| std::cmp::Reverse(count.pixels));
+    counts
+}
+
+/// Which pixels of `source_image` use a color that doesn't fit in a tile,
+/// as a row by row mask of the whole image. The most used colors are the
+/// ones that fit, so the mask picks out the stray pixels to fix. Only the
+/// rectangle of `options` is checked, if it has one.
+pub(crate) fn stray_pixels(source_image: &SourceImage, options: &DecodeOptions) -> Vec<bool> {
+    let mut mask = vec![false; source_image.pixels.len()];
+    let (rect, cropped) = crop_to_rect(source_image, options);
+    let image = cropped.as_ref().unwrap_or(source_image);
+    let image_data = pixel_colors(image, options);
+    let mut counts = BTreeMap::new();
+    for color in image_data.iter().flatten() {
+        *counts.entry(*color).or_insert(0) += 1;
+    }
+    let has_transparent = image_data.contains(&None);
+    let fits = GB_MAX_COLOR_COUNT - options.reserves_transparent(has_transparent) as usize;
+    let mut by_use: Vec<(Rgb, usize)> = counts.into_iter().collect();
+    by_use.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let kept: BTreeSet<Rgb> = by_use.iter().take(fits).map(|(rgb, _)| *rgb).collect();
+    for (i, color) in image_data.iter().enumerate() {
+        if color.is_some_and(|color| !kept.contains(&color)) {
+            let (x, y) = source_position(i, image.width, rect);
+            mask[(y * source_image.width + x) as usize] = true;
+        }
+    }
+    mask
+}
+
+/// The tile-aligned rectangle to convert of each `width` by `height`
+/// frame, with a warning if `rect` had to be widened to the tile grid.
+pub(crate) fn tile_rect(
+    input_filename: &str,
+    rect: Rect,
+    width: u32,
+    height: u32,
+) -> Result<(Rect, Option<Diagnostic>), ImageReadError> {
+    let snapped = rect.snap_to_tiles();
+    if snapped.x + snapped.width > width || snapped.y + snapped.height > height {
+        return Err(ImageReadError::RectOutsideImage {
+            rect: snapped,
+            width,
+            height,
+        });
+    }
+    let warning = if snapped != rect {
+        Some(Diagnostic::warning(
+            "rect-snapped",
+            input_filename,
+            format!(
+                "rectangle {} isn't on the 8x8 tile grid, so {} is converted instead",
+                rect, snapped
+            ),
+        ))
+    } else {
+        None
+    };
+    Ok((snapped, warning))
+}
+
+/// The same `rect` of each `frame_height` pixel high frame of an image,
+/// stacked as the frames of a new image. Maps are left out, since their
+/// tile numbers count the whole image's tiles.
+fn crop_frames(source_image: &SourceImage, rect: Rect, frame_height: u32) -> SourceImage {
+    let mut pixels = Vec::with_capacity((rect.width * rect.height * source_image.frames) as usize);
+    let mut palette_mask = source_image.palette_mask.as_ref().map(|_| Vec::new());
+    for frame in 0..source_image.frames.max(1) {
+        let y = frame * frame_height + rect.y;
+        let cropped = source_image.crop(rect.x, y, rect.width, rect.height);
+        pixels.extend(cropped.pixels);
+        if let (Some(palette_mask), Some(cropped)) = (&mut palette_mask, cropped.palette_mask) {
+            palette_mask.extend(cropped);
+        }
+    }
+    SourceImage {
+        width: rect.width,
+        height: rect.height * source_image.frames.max(1),
+        pixels,
+        palette: source_image.palette.clone(),
+        frames: source_image.frames,
+        tilemaps: Vec::new(),
+        palette_mask,
+    }
+}
+
+/// Fail with [`ImageReadError::NotTileAligned`] if an image's frames aren't
+/// a whole number of 8x8 tiles, or if `options` allow truncating it, warn
+/// that the edge pixels past the last full tile are dropped.
+pub(crate) fn check_tile_aligned(
+    input_filename: &str,
+    width: u32,
+    height: u32,
+    frames: u32,
+    options: &DecodeOptions,
+) -> Result<Vec<Diagnostic>, ImageReadError> {
+    let frame_height = height / frames.max(1);
+    if width.is_multiple_of(8) && frame_height.is_multiple_of(8) {
+        return Ok(Vec::new());
+    }
+    if !options.truncate {
+        return Err(ImageReadError::NotTileAligned {
+            width,
+            height,
+            frames,
+        });
+    }
+    Ok(vec![Diagnostic::warning(
+        "dimensions-truncated",
+        input_filename,
+        format!(
+            "image is {}x{} pixels, edge pixels past the last full 8x8 tile are dropped",
+            width, height
+        ),
+    )])
+}
+
+/// The multiple of 8 nearest `size`, rounding halves up, and at least 8.
+fn nearest_tile_size(size: u32) -> u32 {
+    ((size + 4) / 8 * 8).max(8)
+}
+
+/// Number the opaque colors of an image, leaving `transparent_color` for
+/// its transparent pixels if it has any.
+///
+/// With a palette in `options`, the colors given for each color number,
+/// colors take the number of the nearest of them, even if that's the
+/// transparent color's, which its transparent pixels then share. Sprites
+/// only take colors 1 to 3.
+///
+/// With a `palette`, colors take their palette index. If a color isn't in
+/// the palette or its index is the transparent color's, the palette order
+/// can't be kept and colors are numbered as for any other image.
+pub(crate) fn number_colors(
+    unique_colors: &BTreeSet<Rgb>,
+    palette: Option<&[Rgb]>,
+    transparent_color: Option<u8>,
+    options: &DecodeOptions,
+) -> BTreeMap<Rgb, u8> {
+    if let Some(color_palette) = &options.palette {
+        let first = options.sprite as usize;
+        return unique_colors
+            .iter()
+            .map(|rgb| {
+                let color = nearest_color(&color_palette[first..], *rgb, options.gamma);
+                (*rgb, color + first as u8)
+            })
+            .collect();
+    }
+    if options.color_map.iter().any(Option::is_some) {
+        return mapped_color_numbers(unique_colors, transparent_color, options);
+    }
+    if let Some(palette) = palette {
+        let mut color_numbers = palette_color_numbers(palette);
+        let usable = unique_colors
+            .iter()
+            .all(|rgb| match color_numbers.get(rgb) {
+                Some(color) => Some(*color) != transparent_color,
+                None => false,
+            });
+        if usable {
+            if let Some(transparent_color) = transparent_color {
+                color_numbers.retain(|_, color| *color != transparent_color);
+            }
+            return color_numbers;
+        }
+        debug!("Palette order clashes with the transparent color, numbering colors by color");
+    }
+    brightness_color_numbers(unique_colors, transparent_color, options)
+}
+
+/// Number `unique_colors` from lightest to darkest, by their shades if
+/// `options` asks for colors compatible with the original Game Boy, or
+/// else in order.
+pub(crate) fn brightness_color_numbers(
+    unique_colors: &BTreeSet<Rgb>,
+    transparent_color: Option<u8>,
+    options: &DecodeOptions,
+) -> BTreeMap<Rgb, u8> {
+    match options.dmg_compatible {
+        true => shade_color_numbers(
+            unique_colors,
+            transparent_color,
+            options.luminance,
+            options.gamma,
+        ),
+        false => rgbs_to_color_number(
+            unique_colors,
+            transparent_color,
+            options.luminance,
+            options.gamma,
+        ),
+    }
+}
+
+/// Number `unique_colors` by brightness like [`rgbs_to_color_number`], apart
+/// from the colors `options.color_map` pins to a number. Pins to the
+/// transparent color's number are left out.
+fn mapped_color_numbers(
+    unique_colors: &BTreeSet<Rgb>,
+    transparent_color: Option<u8>,
+    options: &DecodeOptions,
+) -> BTreeMap<Rgb, u8> {
+    let mut color_numbers = BTreeMap::new();
+    for (color, pinned) in options.color_map.iter().enumerate() {
+        let pinned = match pinned {
+            Some(pinned) if Some(color as u8) != transparent_color => *pinned,
+            _ => continue,
+        };
+        let rounded = pinned.round_to(options.color_tolerance);
+        let found = unique_colors
+            .get(&pinned)
+            .or_else(|| unique_colors.get(&rounded));
+        if let Some(rgb) = found {
+            color_numbers.insert(*rgb, color as u8);
+        }
+    }
+    let numbers: Vec<u8> = (0..GB_MAX_COLOR_COUNT as u8)
+        .filter(|color| Some(*color) != transparent_color)
+        .filter(|color| !color_numbers.values().any(|pinned| pinned == color))
+        .collect();
+    let mut colors: Vec<Rgb> = unique_colors
+        .iter()
+        .filter(|rgb| !color_numbers.contains_key(rgb))
+        .cloned()
+        .collect();
+    colors.sort_by(|a, b| options.luminance.compare(options.gamma, *b, *a));
+    color_numbers.extend(colors.into_iter().zip(numbers));
+    color_numbers
+}
+
+/// The BGP or OBP register value showing each color number of
+/// `color_numbers` in the shade of its color, the lightest colors in the
+/// lightest shades. Color numbers given by a declared palette already are,
+/// so they keep their own shade, as do unused and transparent numbers.
+/// Color `n`'s shade is held in bits `2n` and `2n + 1`.
+pub(crate) fn palette_register(color_numbers: &BTreeMap<Rgb, u8>, options: &DecodeOptions) -> u8 {
+    let mut shades = [0, 1, 2, 3];
+    if options.palette.is_none() {
+        let mut numbers: Vec<u8> = color_numbers.values().copied().collect();
+        numbers.sort();
+        let mut colors: Vec<Rgb> = color_numbers.keys().copied().collect();
+        colors.sort_by(|a, b| options.luminance.compare(options.gamma, *b, *a));
+        for (rgb, shade) in colors.iter().zip(numbers) {
+            shades[color_numbers[rgb] as usize] = shade;
+        }
+    }
+    shades
+        .iter()
+        .enumerate()
+        .fold(0, |register, (color, shade)| {
+            register | (shade << (2 * color))
+        })
+}
+
+/// The color of `options.palette` nearest `rgb`, leaving out color 0 for
+/// sprites, or `rgb` itself without a palette.
+pub(crate) fn palette_color(rgb: Rgb, options: &DecodeOptions) -> Rgb {
+    match &options.palette {
+        Some(color_palette) => {
+            let color_palette = &color_palette[options.sprite as usize..];
+            color_palette[nearest_color(color_palette, rgb, options.gamma) as usize]
+        }
+        None => rgb,
+    }
+}
+
+/// The number of the color in `color_palette` closest to `rgb`, with
+/// channels read with `gamma`, or the lowest of them if several are as
+/// close.
+pub(crate) fn nearest_color(color_palette: &[Rgb], rgb: Rgb, gamma: Gamma) -> u8 {
+    let distance = |color: &Rgb| {
+        let channel = |a: u8, b: u8| (gamma.intensity(a) - gamma.intensity(b)).powi(2);
+        channel(color.r, rgb.r) + channel(color.g, rgb.g) + channel(color.b, rgb.b)
+    };
+    (0..color_palette.len())
+        .min_by(|&a, &b| distance(&color_palette[a]).total_cmp(&distance(&color_palette[b])))
+        .unwrap() as u8
+}
+
+/// Number colors from lightest to darkest, as compared by `luminance` with
+/// channels read with `gamma`, skipping `transparent_color`.
+pub(crate) fn rgbs_to_color_number(
+    unique_colors: &BTreeSet<Rgb>,
+    transparent_color: Option<u8>,
+    luminance: Luminance,
+    gamma: Gamma,
+) -> BTreeMap<Rgb, u8> {
+    let numbers = (0..GB_MAX_COLOR_COUNT as u8).filter(|color| Some(*color) != transparent_color);
+    let mut colors: Vec<Rgb> = unique_colors.iter().cloned().collect();
+    colors.sort_by(|a, b| luminance.compare(gamma, *b, *a));
+    colors.into_iter().zip(numbers).collect()
+}
+
+/// Number `unique_colors` by the original Game Boy shade nearest their
+/// lightness, 0 for white to 3 for black, skipping `transparent_color`.
+/// Colors sharing a shade, or darker colors pushed past one, take the next
+/// numbers along, keeping the colors in brightness order, so two colors
+/// never share a number while there are enough to go around.
+pub(crate) fn shade_color_numbers(
+    unique_colors: &BTreeSet<Rgb>,
+    transparent_color: Option<u8>,
+    luminance: Luminance,
+    gamma: Gamma,
+) -> BTreeMap<Rgb, u8> {
+    let numbers: Vec<u8> = (0..GB_MAX_COLOR_COUNT as u8)
+        .filter(|color| Some(*color) != transparent_color)
+        .collect();
+    let mut colors: Vec<Rgb> = unique_colors.iter().cloned().collect();
+    colors.sort_by(|a, b| luminance.compare(gamma, *b, *a));
+    let mut next = 0;
+    let mut color_numbers = BTreeMap::new();
+    for (i, rgb) in colors.iter().enumerate() {
+        let darkness = 1.0 - luminance.lightness(gamma, *rgb);
+        let shade = (darkness * (GB_MAX_COLOR_COUNT - 1) as f32).round() as u8;
+        let wanted = numbers
+            .iter()
+            .position(|color| *color >= shade)
+            .unwrap_or(numbers.len() - 1);
+        // Leave enough numbers for the darker colors still to come.
+        let last = numbers.len().saturating_sub(colors.len() - i);
+        let index = wanted.max(next).min(last);
+        color_numbers.insert(*rgb, numbers[index]);
+        next = index + 1;
+    }
+    color_numbers
+}
+
+/// The colors of `color_numbers`, indexed by color number. The entry for
+/// `transparent_color`, if given, is black.
+pub(crate) fn colors_by_number(
+    color_numbers: &BTreeMap<Rgb, u8>,
+    transparent_color: Option<u8>,
+) -> Vec<Rgb> {
+    let count = color_numbers
+        .values()
+        .chain(&transparent_color)
+        .max()
+        .map_or(0, |color| *color as usize + 1);
+    let mut colors = vec![Rgb { r: 0, g: 0, b: 0 }; count];
+    for (rgb, color) in color_numbers {
+        colors[*color as usize] = *rgb;
+    }
+    colors
+}
+
+/// Number each palette color by its index. When a color appears more than
+/// once, its first index is used.
+pub(crate) fn palette_color_numbers(palette: &[Rgb]) -> BTreeMap<Rgb, u8> {
+    let mut color_numbers = BTreeMap::new();
+    for (i, rgb) in palette.iter().enumerate() {
+        color_numbers.entry(*rgb).or_insert(i as u8);
+    }
+    color_numbers
+}
+
+/// Decode the image at `image_input`, detecting its format with the
+/// built-in sources. Fails if it uses more than [`GB_MAX_COLOR_COUNT`]
+/// colors after rounding.
+pub fn decode_image(image_input: &str) -> Result<DecodedImage, ImageReadError> {
+    decode_image_with(
+        &SourceRegistry::default(),
+        &DecodeOptions::default(),
+        image_input,
+    )
+}
+
+/// Like [`decode_image`], but detecting the format from `sources` and
+/// decoding with `options`.
+pub fn decode_image_with(
+    sources: &SourceRegistry,
+    options: &DecodeOptions,
+    image_input: &str,
+) -> Result<DecodedImage, ImageReadError> {
+    decode_reader(sources, options, image_input, File::open(image_input)?)
+}
+
+/// Decode an image from any reader. `image_input` names the image for
+/// format detection by extension, diagnostics and the generated symbol.
+pub fn decode_reader<R: Read>(
+    sources: &SourceRegistry,
+    options: &DecodeOptions,
+    image_input: &str,
+    reader: R,
+) -> Result<DecodedImage, ImageReadError> {
+    let source_image = decode_source(sources, image_input, reader)?;
+    DecodedImage::from_source(image_input, source_image, options)
+}
+
+/// Read an image's pixels from any reader, detecting its format like
+/// [`decode_reader`], without reducing its colors.
+pub fn decode_source<R: Read>(
+    sources: &SourceRegistry,
+    image_input: &str,
+    reader: R,
+) -> Result<SourceImage, ImageReadError> {
+    let mut reader = BufReader::new(reader);
+    let source = detect_source(sources, image_input, &mut reader)?;
+    source.decode(&mut reader)
+}
+
+/// The other files that decoding the image in `reader` reads, as in
+/// [`ImageSource::referenced_files`], detecting its format like
+/// [`decode_reader`].
+pub fn referenced_files<R: Read>(
+    sources: &SourceRegistry,
+    image_input: &str,
+    reader: R,
+) -> Result<Vec<PathBuf>, ImageReadError> {
+    let mut reader = BufReader::new(reader);
+    let source = detect_source(sources, image_input, &mut reader)?;
+    source.referenced_files(&mut reader)
+}
+
+/// Pick the source for an input by peeking at its first bytes, without
+/// consuming them.
+pub(crate) fn detect_source<'a, R: BufRead>(
+    sources: &'a SourceRegistry,
+    image_input: &str,
+    reader: &mut R,
+) -> Result<&'a dyn ImageSource, ImageReadError> {
+    let header = reader.fill_buf()?;
+    let header = &header[..header.len().min(MAGIC_LEN)];
+    let source = sources
+        .detect(Path::new(image_input), header)
+        .ok_or(ImageReadError::UnknownFormat)?;
+    debug!("Decoding {} as {}", image_input, source.name());
+    Ok(source)
+}
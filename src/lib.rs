@@ -0,0 +1,890 @@
+//! Convert images into Game Boy 2bpp tiles, formatted as GBDK C arrays
+//! or RGBDS assembly.
+//!
+//! The [`Converter`] builder covers the common case of turning one image
+//! into formatted source:
+//!
+//! ```no_run
+//! use gbtile::{Converter, OutputType};
+//!
+//! let source = Converter::new()
+//!     .output_type(OutputType::Rgbds)
+//!     .convert_file("ascii.png")
+//!     .expect("Could not convert image");
+//! ```
+//!
+//! The individual stages ([`decode_image`], [`encode_tile`] and
+//! [`OutputFormat::render`]) are also public for pipelines that need to inspect or
+//! adjust the data in between.
+
+#[macro_use]
+mod logging;
+
+mod annotate;
+#[cfg(feature = "json")]
+mod atlas;
+pub mod build;
+pub mod cache;
+mod cgb;
+#[cfg(feature = "config")]
+pub mod config;
+mod delta;
+mod diagnostic;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+mod format;
+mod image;
+mod metasprite;
+mod metatile;
+mod palette;
+mod quantize;
+#[cfg(feature = "config")]
+pub mod regions;
+mod sgb;
+mod source;
+mod stream;
+mod tile;
+mod tileset;
+mod vram;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "json")]
+pub use crate::atlas::{decode_atlas, Atlas, AtlasSprite};
+pub use crate::cgb::{CgbPalettes, CGB_PALETTE_COUNT};
+pub use crate::delta::{delta_frames, FrameDeltas};
+pub use crate::diagnostic::{Diagnostic, Severity};
+pub use crate::error::Error;
+pub use crate::format::{
+    append_output, char_constant, is_generated, map_name, metatile_names, normalize_line_endings,
+    parse_generated, sanitize_identifier, variable_name, write_charmap_rgbds, write_output,
+    write_tile, write_tile_gbdk, write_tile_rgbds, write_tiles, FormatRegistry, Gbdk,
+    GeneratedTiles, OutputFormat, OutputType, Raw, Rgbds, GENERATED_MARKER,
+};
+pub use crate::image::{
+    count_colors, decode_image, decode_image_with, decode_reader, decode_source, referenced_files,
+    ColorCount, ColorNumber, ColorUse, DecodeOptions, DecodedImage, Dither, Gamma, Grid,
+    ImageReadError, Luminance, Rect, Rgb, Rgba, DEFAULT_COLOR_TOLERANCE, GB_MAX_COLOR_COUNT,
+};
+pub use crate::metasprite::{
+    build_metasprites, metasprite_pivot, MetaspriteItem, OAM_SPRITE_COUNT,
+};
+pub use crate::metatile::{group_metatiles, MetatileSize, Metatiles};
+pub use crate::palette::{
+    parse_color_map, parse_palette, parse_palette_file, read_palette_file, PalettePreset,
+};
+pub use crate::sgb::{SGB_PACKET_BYTES, SGB_PALETTE_COUNT, SGB_SCREEN_COLUMNS, SGB_SCREEN_ROWS};
+#[cfg(feature = "json")]
+pub use crate::source::LdtkSource;
+pub use crate::source::{
+    AsepriteSource, GbmSource, GbrSource, GifSource, ImageSource, JpegSource, PngSource, RawSource,
+    RowSink, SourceImage, SourceRegistry, TgaSource, WebpSource,
+};
+pub use crate::stream::{encode_streaming, StreamingEncoder};
+pub use crate::tile::{
+    encode_tile, pack_tile_row, unpack_tile_row, Addressing, Bpp, EncodedTile, ObjectPalette,
+    TileOrder, Tilemap, CGB_X_FLIP, CGB_Y_FLIP, OAM_OBP1,
+};
+pub use crate::tileset::share_tileset;
+pub use crate::vram::{SplitBanks, VramUsage, ROM_BANK_BYTES, TILE_BYTES, VRAM_BANK_BYTES};
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+/// Builder for a single image to tile conversion.
+#[derive(Clone, Debug)]
+pub struct Converter {
+    output_format: Arc<dyn OutputFormat>,
+    name: Option<String>,
+    prefix: String,
+    suffix: String,
+    decode_options: DecodeOptions,
+    sources: Arc<SourceRegistry>,
+    deterministic: bool,
+    streaming: bool,
+    cgb_color_correction: bool,
+    obp: Option<u8>,
+    palette_mask: Option<Arc<SourceImage>>,
+    order: TileOrder,
+    metatile: Option<MetatileSize>,
+    base_index: u8,
+    addressing: Addressing,
+    bpp: u8,
+    first_char: Option<u8>,
+    trim_blank: bool,
+    bank_tiles: Option<usize>,
+    delta_frames: bool,
+    metasprites: bool,
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Converter {
+            output_format: Arc::new(Gbdk),
+            name: None,
+            prefix: String::new(),
+            suffix: String::new(),
+            decode_options: DecodeOptions::default(),
+            sources: Arc::new(SourceRegistry::default()),
+            deterministic: false,
+            streaming: false,
+            cgb_color_correction: false,
+            obp: None,
+            palette_mask: None,
+            order: TileOrder::Row,
+            metatile: None,
+            base_index: 0,
+            addressing: Addressing::Mode8000,
+            bpp: 2,
+            first_char: None,
+            trim_blank: false,
+            bank_tiles: None,
+            delta_frames: false,
+            metasprites: false,
+        }
+    }
+}
+
+impl Converter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use one of the built-in output formats. Defaults to GBDK.
+    pub fn output_type(self, output_type: OutputType) -> Self {
+        match output_type {
+            OutputType::Gbdk => self.output_format(Gbdk),
+            OutputType::Rgbds => self.output_format(Rgbds),
+            OutputType::Raw => self.output_format(Raw),
+        }
+    }
+
+    /// Use a custom output format.
+    pub fn output_format<F: OutputFormat + 'static>(mut self, output_format: F) -> Self {
+        self.output_format = Arc::new(output_format);
+        self
+    }
+
+    /// Name the generated symbol, instead of deriving it from the input
+    /// file name.
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Prepend `prefix` to every generated symbol name.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Append `suffix` to every generated symbol name.
+    pub fn suffix<S: Into<String>>(mut self, suffix: S) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    /// Give fully transparent pixels this color number. Defaults to 0, the
+    /// color sprites treat as transparent.
+    pub fn transparent_color(mut self, color: ColorNumber) -> Self {
+        self.decode_options.transparent_color = color.into();
+        self
+    }
+
+    /// Follow the sprite convention, where transparent pixels are color 0
+    /// and opaque pixels only use colors 1 to 3, as OBJ palettes do on
+    /// hardware. Overrides [`Converter::transparent_color`].
+    pub fn sprite(mut self, sprite: bool) -> Self {
+        self.decode_options.sprite = sprite;
+        self
+    }
+
+    /// Draw sprites made of the tiles with object palette `obp` on the
+    /// original Game Boy, rendering the palette register as that palette's
+    /// with the OAM flags selecting it. Implies [`Converter::sprite`],
+    /// since color 0 of an object palette is always transparent.
+    pub fn obp(mut self, obp: Option<ObjectPalette>) -> Self {
+        if obp.is_some() {
+            self.decode_options.sprite = true;
+        }
+        self.obp = obp.map(u8::from);
+        self
+    }
+
+    /// Write each image's tiles in `order`, renumbering the maps drawn with
+    /// them to match. Defaults to [`TileOrder::Row`].
+    pub fn order(mut self, order: TileOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Group the tiles into metatiles of `metatile`, rendering each distinct
+    /// block once and a map of which is drawn where, as in
+    /// [`group_metatiles`].
+    pub fn metatile(mut self, metatile: Option<MetatileSize>) -> Self {
+        self.metatile = metatile;
+        self
+    }
+
+    /// Number the tiles maps and metatiles draw from `base_index`, for tiles
+    /// loaded at that VRAM tile, such as after a font. Defaults to 0.
+    pub fn base_index(mut self, base_index: u8) -> Self {
+        self.base_index = base_index;
+        self
+    }
+
+    /// Number the tiles maps and metatiles draw as the background reads them
+    /// in `addressing`, with the tiles loaded into that block from its
+    /// first tile on. Defaults to [`Addressing::Mode8000`].
+    pub fn addressing(mut self, addressing: Addressing) -> Self {
+        self.addressing = addressing;
+        self
+    }
+
+    /// Write the tiles with `bpp` bits per pixel: 2, or 1 for two-color
+    /// tiles, such as fonts, at half the size. Converting tiles drawing
+    /// with more than one color besides color 0 to 1bpp fails.
+    pub fn bpp(mut self, bpp: Bpp) -> Self {
+        self.bpp = bpp.into();
+        self
+    }
+
+    /// Convert images as fonts: grids of 8x8 glyphs, read left to right and
+    /// top to bottom whatever the [order](Converter::order), in character
+    /// order from `first_char`, rendered with constants giving the tile
+    /// drawing each character.
+    pub fn font(mut self, first_char: Option<u8>) -> Self {
+        self.first_char = first_char;
+        self
+    }
+
+    /// Leave out tiles that are entirely color 0, as in
+    /// [`EncodedTile::trim_blank`], such as the padding of sprite sheets
+    /// made a power of two in size.
+    pub fn trim_blank(mut self, trim_blank: bool) -> Self {
+        self.trim_blank = trim_blank;
+        self
+    }
+
+    /// Split the tile data into arrays or sections of at most `bank_tiles`
+    /// tiles each, to fit VRAM or ROM banks, as in
+    /// [`EncodedTile::bank_tiles`]. `None`, the default, writes it whole.
+    pub fn split_banks(mut self, bank_tiles: Option<usize>) -> Self {
+        self.bank_tiles = bank_tiles.filter(|tiles| *tiles > 0);
+        self
+    }
+
+    /// Write animations as their first frame and the tiles that change
+    /// from each frame to the next, as in [`EncodedTile::frame_deltas`],
+    /// instead of every frame whole. Still images are written as usual.
+    pub fn delta_frames(mut self, delta_frames: bool) -> Self {
+        self.delta_frames = delta_frames;
+        self
+    }
+
+    /// Make a metasprite of each animation frame, or of the image, for
+    /// GBDK-2020's `move_metasprite`, as in [`build_metasprites`].
+    /// Can't be combined with [`Converter::trim_blank`], since that
+    /// renumbers the tiles the sprites draw.
+    pub fn metasprites(mut self, metasprites: bool) -> Self {
+        self.metasprites = metasprites;
+        self
+    }
+
+    /// Treat pixels with less alpha than `alpha_threshold` as transparent,
+    /// and the rest as opaque. Defaults to 1, so only fully transparent
+    /// pixels are.
+    pub fn alpha_threshold(mut self, alpha_threshold: u8) -> Self {
+        self.decode_options.alpha_threshold = alpha_threshold;
+        self
+    }
+
+    /// Number colors by `palette`, the colors of color numbers 0 to 3,
+    /// instead of by brightness. Each color in the image takes the number
+    /// of the nearest palette color.
+    pub fn palette(mut self, palette: [Rgb; GB_MAX_COLOR_COUNT]) -> Self {
+        self.decode_options.palette = Some(palette);
+        self
+    }
+
+    /// Keep the source colors exactly and render them as a Game Boy Color
+    /// palette of RGB555 words next to the tile data, in formats that can
+    /// hold one.
+    pub fn cgb(mut self, cgb: bool) -> Self {
+        self.decode_options.cgb = cgb;
+        self
+    }
+
+    /// Correct the Game Boy Color palette's colors for the console's
+    /// washed-out screen, as in [`Rgb::cgb_color_corrected`], so they look
+    /// like the image's on hardware. Only used with [`Converter::cgb`].
+    pub fn cgb_color_correction(mut self, cgb_color_correction: bool) -> Self {
+        self.cgb_color_correction = cgb_color_correction;
+        self
+    }
+
+    /// Number each palette's colors by the original Game Boy shade nearest
+    /// their lightness, so one ROM can load the same tiles on the original
+    /// Game Boy, through the palette register, and the Game Boy Color,
+    /// through [`Converter::cgb`]'s palettes.
+    pub fn dmg_compatible(mut self, dmg_compatible: bool) -> Self {
+        self.decode_options.dmg_compatible = dmg_compatible;
+        self
+    }
+
+    /// Mark the palette each tile takes when palettes are shared out with
+    /// [`Converter::cgb`] or [`Converter::sgb`], with `mask`, an image the
+    /// same size as each input, as in [`SourceImage::with_palette_mask`].
+    /// Not used for atlases.
+    pub fn palette_mask(mut self, mask: SourceImage) -> Self {
+        self.palette_mask = Some(Arc::new(mask));
+        self
+    }
+
+    /// Keep the source colors exactly and render them as Super Game Boy
+    /// palette and attribute commands next to the tile data, in formats
+    /// that can hold them. Up to [`SGB_PALETTE_COUNT`] palettes are shared
+    /// out between the tiles, all with the same color 0.
+    pub fn sgb(mut self, sgb: bool) -> Self {
+        self.decode_options.sgb = sgb;
+        self
+    }
+
+    /// Fail if an opaque color isn't exactly one of the palette's, instead
+    /// of taking the nearest. Only checked when there's a
+    /// [`palette`](Converter::palette).
+    pub fn strict_palette(mut self, strict_palette: bool) -> Self {
+        self.decode_options.strict_palette = strict_palette;
+        self
+    }
+
+    /// Stretch the image's contrast before reading its colors, so washed-out
+    /// images spread over all four shades instead of collapsing into two.
+    pub fn auto_contrast(mut self, auto_contrast: bool) -> Self {
+        self.decode_options.auto_contrast = auto_contrast;
+        self
+    }
+
+    /// Round each channel to `levels` evenly spaced values before reading
+    /// colors, after any contrast stretching.
+    pub fn posterize(mut self, levels: Option<u8>) -> Self {
+        self.decode_options.posterize = levels;
+        self
+    }
+
+    /// Pin source colors to color numbers, as given by [`parse_color_map`],
+    /// while the image's other colors are still numbered by brightness.
+    /// Needed when two colors are as bright as each other.
+    pub fn color_map(mut self, color_map: [Option<Rgb>; GB_MAX_COLOR_COUNT]) -> Self {
+        self.decode_options.color_map = color_map;
+        self
+    }
+
+    /// Compare colors' brightness with `luminance` when numbering them
+    /// from lightest to darkest. Defaults to [`Luminance::Rgb`].
+    pub fn luminance(mut self, luminance: Luminance) -> Self {
+        self.decode_options.luminance = luminance;
+        self
+    }
+
+    /// Read colors' channels with `gamma` when comparing their brightness
+    /// or matching them to a palette. Defaults to [`Gamma::Srgb`].
+    pub fn gamma(mut self, gamma: Gamma) -> Self {
+        self.decode_options.gamma = gamma;
+        self
+    }
+
+    /// Round each channel to steps of `tolerance` values, merging nearby
+    /// colors before they're counted. Defaults to
+    /// [`DEFAULT_COLOR_TOLERANCE`]; 1 keeps every color distinct.
+    pub fn color_tolerance(mut self, tolerance: u8) -> Self {
+        self.decode_options.color_tolerance = tolerance;
+        self
+    }
+
+    /// Reduce images with more than four colors to the best four, found by
+    /// median cut, instead of failing. Quantized images aren't streamed,
+    /// since every color has to be seen first.
+    pub fn quantize(mut self, quantize: bool) -> Self {
+        self.decode_options.quantize = quantize;
+        self
+    }
+
+    /// Spread the colors of quantized images over their pixels with
+    /// `dither`. Defaults to [`Dither::None`].
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.decode_options.dither = dither;
+        self
+    }
+
+    /// Only convert `rect` of the image, or of each frame of an animation.
+    /// It's widened to the 8x8 tile grid if it isn't on it.
+    pub fn rect(mut self, rect: Rect) -> Self {
+        self.decode_options.rect = Some(rect);
+        self
+    }
+
+    /// Cut the cells of a sprite sheet laid out on `grid` out of their
+    /// gutters before tiling, as in [`DecodeOptions::grid`].
+    pub fn grid(mut self, grid: Option<Grid>) -> Self {
+        self.decode_options.grid = grid;
+        self
+    }
+
+    /// Cut images into animation frames of `frame_size`, as in
+    /// [`DecodeOptions::frame_size`].
+    pub fn frame_size(mut self, frame_size: Option<Grid>) -> Self {
+        self.decode_options.frame_size = frame_size;
+        self
+    }
+
+    /// Pad images that aren't a whole number of tiles out to the next tile
+    /// boundary with the color numbered `pad`, instead of dropping their
+    /// edge pixels. Streaming is skipped for images that need it.
+    pub fn pad(mut self, pad: Option<u8>) -> Self {
+        self.decode_options.pad = pad;
+        self
+    }
+
+    /// Drop the edge pixels of images that aren't a whole number of tiles,
+    /// with a warning, instead of failing.
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.decode_options.truncate = truncate;
+        self
+    }
+
+    /// Detect and decode input formats with `sources`, instead of the
+    /// built-in ones. Register a configured [`AsepriteSource`] here to pick
+    /// a sprite's layer or tag.
+    pub fn sources(mut self, sources: SourceRegistry) -> Self {
+        self.sources = Arc::new(sources);
+        self
+    }
+
+    /// Guarantee byte-identical output for identical inputs and options, by
+    /// normalizing line endings and leaving out anything that varies between
+    /// runs or gbtile versions.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Decode and encode images a row at a time, so very large images don't
+    /// need to be held in memory all at once. Quantized or contrast-stretched
+    /// images and Game Boy Color and Super Game Boy modes still read the
+    /// whole image, since they need every pixel.
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        self.streaming = streaming;
+        self
+    }
+
+    /// The conventional file extension for the configured output format.
+    pub fn file_extension(&self) -> &'static str {
+        self.output_format.file_extension()
+    }
+
+    /// Render already encoded tile data with the configured output format.
+    pub fn render(&self, encoded_tile: &EncodedTile) -> Vec<u8> {
+        let rendered = self.output_format.render(encoded_tile);
+        if self.deterministic {
+            normalize_line_endings(&rendered)
+        } else {
+            rendered
+        }
+    }
+
+    /// Render several encoded images into one file with the configured
+    /// output format.
+    pub fn render_all(&self, encoded_tiles: &[EncodedTile]) -> Vec<u8> {
+        let rendered = self.output_format.render_all(encoded_tiles);
+        if self.deterministic {
+            normalize_line_endings(&rendered)
+        } else {
+            rendered
+        }
+    }
+
+    /// Decode and encode the image at `input`, without formatting it.
+    pub fn encode_file(&self, input: &str) -> Result<EncodedTile, Error> {
+        let file = File::open(input).map_err(|err| Error::Read {
+            path: input.to_string(),
+            source: ImageReadError::Io(err),
+        })?;
+        self.encode_reader(input, file)
+    }
+
+    /// Decode and encode an image from any reader, such as stdin. `name` is
+    /// used for format detection by extension, diagnostics and the generated
+    /// symbol. Fails if `name` has no file name, like `""`, and no
+    /// [`name`](Converter::name) is set.
+    pub fn encode_reader<R: Read>(&self, name: &str, reader: R) -> Result<EncodedTile, Error> {
+        let symbol = match &self.name {
+            Some(symbol) => symbol.clone(),
+            None => format::file_stem(name).ok_or_else(|| Error::Input {
+                path: name.to_string(),
+                message:
+                    "there's no file name to name the generated symbol after, so it needs a name"
+                        .to_string(),
+            })?,
+        };
+        let encoded_tile = if self.streaming
+            && !self.decode_options.quantize
+            && !self.decode_options.auto_contrast
+            && self.decode_options.pad.is_none()
+            && self.decode_options.grid.is_none()
+            && self.decode_options.frame_size.is_none()
+            && !self.decode_options.shares_palettes()
+        {
+            encode_streaming(&self.sources, &self.decode_options, name, reader)
+        } else {
+            decode_source(&self.sources, name, reader)
+                .and_then(|source_image| self.masked(source_image))
+                .and_then(|source_image| {
+                    DecodedImage::from_source(name, source_image, &self.decode_options)
+                })
+                .map(encode_tile)
+        };
+        let mut encoded_tile = encoded_tile.map_err(|source| Error::Read {
+            path: name.to_string(),
+            source,
+        })?;
+        self.name_tile(name, &symbol, &mut encoded_tile);
+        self.arrange_tiles(name, &mut encoded_tile)?;
+        if self.name.is_none() && self.prefix.is_empty() && self.suffix.is_empty() {
+            encoded_tile.name = None;
+        }
+        Ok(encoded_tile)
+    }
+
+    /// Decode and encode each sprite packed into the TexturePacker atlas at
+    /// `input`, named after its file name in the atlas.
+    #[cfg(feature = "json")]
+    pub fn encode_atlas(&self, input: &str) -> Result<Vec<EncodedTile>, Error> {
+        let sprites =
+            decode_atlas(&self.sources, &self.decode_options, input).map_err(|source| {
+                Error::Read {
+                    path: input.to_string(),
+                    source,
+                }
+            })?;
+        sprites
+            .into_iter()
+            .map(|(sprite, decoded_image)| {
+                let mut encoded_tile = encode_tile(decoded_image);
+                self.name_tile(input, &sprite.name, &mut encoded_tile);
+                self.arrange_tiles(input, &mut encoded_tile)?;
+                Ok(encoded_tile)
+            })
+            .collect()
+    }
+
+    /// Decode the image at `input` once, then encode each of `regions` of
+    /// it, named after the region.
+    #[cfg(feature = "config")]
+    pub fn encode_regions(
+        &self,
+        input: &str,
+        regions: &[regions::Region],
+    ) -> Result<Vec<EncodedTile>, Error> {
+        let read_error = |source| Error::Read {
+            path: input.to_string(),
+            source,
+        };
+        let file = File::open(input).map_err(|err| read_error(ImageReadError::Io(err)))?;
+        let mut reader = std::io::BufReader::new(file);
+        let source_image = image::detect_source(&self.sources, input, &mut reader)
+            .and_then(|source| source.decode(&mut reader))
+            .and_then(|source_image| self.masked(source_image))
+            .map_err(read_error)?;
+        regions
+            .iter()
+            .map(|region| {
+                let input_filename = format!("{} ({})", input, region.name);
+                let decoded_image = DecodedImage::from_source_rect(
+                    &input_filename,
+                    &source_image,
+                    region.rect(),
+                    &self.decode_options,
+                )
+                .map_err(read_error)?;
+                let mut encoded_tile = encode_tile(decoded_image);
+                self.name_tile(input, &region.name, &mut encoded_tile);
+                self.arrange_tiles(&input_filename, &mut encoded_tile)?;
+                Ok(encoded_tile)
+            })
+            .collect()
+    }
+
+    /// Draw the image at `input` as a PNG, with the pixels `err` is about
+    /// in magenta and an 8x8 tile grid over it, for an image that couldn't
+    /// be converted because of its colors or size. The whole image is drawn,
+    /// even when only a rectangle of it is converted.
+    pub fn error_image(&self, input: &str, err: &ImageReadError) -> Result<Vec<u8>, Error> {
+        let file = File::open(input).map_err(|err| Error::Read {
+            path: input.to_string(),
+            source: ImageReadError::Io(err),
+        })?;
+        let mut reader = std::io::BufReader::new(file);
+        let source_image = image::detect_source(&self.sources, input, &mut reader)
+            .and_then(|source| source.decode(&mut reader))
+            .map_err(|source| Error::Read {
+                path: input.to_string(),
+                source,
+            })?;
+        annotate::error_image(&source_image, &self.decode_options, err).map_err(|source| {
+            Error::Write {
+                path: input.to_string(),
+                source,
+            }
+        })
+    }
+
+    /// `source_image` with the palette mask marked on it, if there is one.
+    fn masked(&self, source_image: SourceImage) -> Result<SourceImage, ImageReadError> {
+        match &self.palette_mask {
+            Some(mask) => source_image.with_palette_mask(mask),
+            None => Ok(source_image),
+        }
+    }
+
+    /// Set the palettes `encoded_tile` is written with: CGB, with or
+    /// without color correction, SGB and the object palette.
+    fn apply_output_options(&self, encoded_tile: &mut EncodedTile) {
+        encoded_tile.cgb = self.decode_options.cgb;
+        encoded_tile.cgb_color_correction = self.cgb_color_correction;
+        encoded_tile.sgb = self.decode_options.sgb;
+        encoded_tile.obp = self.obp;
+    }
+
+    /// Apply the output options to `encoded_tile`, then put its tiles in
+    /// order, group them into metatiles and leave out blank ones, if asked
+    /// to, number them from the base index in the addressing mode, check
+    /// they fit the bits per pixel, and find the changes between animation
+    /// frames and make metasprites, if asked to.
+    fn arrange_tiles(&self, input: &str, encoded_tile: &mut EncodedTile) -> Result<(), Error> {
+        self.apply_output_options(encoded_tile);
+        let input_error = |message| Error::Input {
+            path: input.to_string(),
+            message,
+        };
+        let read_error = |source| Error::Read {
+            path: input.to_string(),
+            source,
+        };
+        let order = match self.first_char {
+            Some(_) => TileOrder::Row,
+            None => self.order,
+        };
+        encoded_tile.reorder(order).map_err(input_error)?;
+        if let Some(size) = self.metatile {
+            encoded_tile.metatiles = group_metatiles(encoded_tile, size).map_err(input_error)?;
+        }
+        if self.trim_blank {
+            let trimmed = encoded_tile.trim_blank();
+            debug!("{}: left out {} blank tile(s)", input, trimmed);
+        }
+        let drawn = encoded_tile
+            .tilemaps
+            .iter()
+            .flat_map(|tilemap| &tilemap.tiles)
+            .chain(
+                encoded_tile
+                    .metatiles
+                    .iter()
+                    .flat_map(|metatiles| &metatiles.definitions),
+            );
+        let mut last = drawn.max().map(|last| *last as usize);
+        let tiles = encoded_tile.tile_data.len() / TILE_BYTES;
+        if let Some(first_char) = self.first_char {
+            if first_char as usize + tiles > 256 {
+                return Err(read_error(ImageReadError::FontPastLastChar {
+                    glyphs: tiles,
+                    first_char,
+                }));
+            }
+            last = last.max(tiles.checked_sub(1));
+        }
+        if let Some(last) = last.filter(|last| last + self.base_index as usize > 255) {
+            return Err(read_error(ImageReadError::TileNumberPastLast {
+                tile: last,
+                base_index: self.base_index,
+            }));
+        }
+        encoded_tile.base_index = self.base_index;
+        encoded_tile.addressing = self.addressing;
+        encoded_tile.first_char = self.first_char;
+        if self.bpp == 1 {
+            let colors = encoded_tile.used_colors();
+            if colors.len() > 1 {
+                return Err(read_error(ImageReadError::OneBppColors { colors }));
+            }
+        }
+        encoded_tile.bpp = self.bpp;
+        if self.bank_tiles.is_some() && encoded_tile.frames > 1 {
+            return Err(input_error(
+                "animations are already split into a section per frame, so they can't be split into banks"
+                    .to_string(),
+            ));
+        }
+        encoded_tile.bank_tiles = self.bank_tiles;
+        if self.delta_frames && encoded_tile.frames > 1 {
+            if !encoded_tile.metatiles.is_empty() {
+                return Err(input_error(
+                    "metatiles draw tiles from every frame, so the frames can't be written as changes"
+                        .to_string(),
+                ));
+            }
+            encoded_tile.frame_deltas = Some(delta_frames(encoded_tile));
+        }
+        if self.metasprites {
+            if self.trim_blank {
+                return Err(input_error(
+                    "blank tiles are already left out of metasprites, so they can't be trimmed"
+                        .to_string(),
+                ));
+            }
+            encoded_tile.metasprites =
+                build_metasprites(input, encoded_tile).map_err(input_error)?;
+        }
+        Ok(())
+    }
+
+    /// Name `encoded_tile` after `symbol` with the prefix and suffix added,
+    /// warning if that isn't a valid symbol name.
+    fn name_tile(&self, input: &str, symbol: &str, encoded_tile: &mut EncodedTile) {
+        let symbol = format!("{}{}{}", self.prefix, symbol, self.suffix);
+        let variable_name = sanitize_identifier(&symbol);
+        if variable_name != symbol {
+            encoded_tile.warnings.push(Diagnostic::warning(
+                "symbol-renamed",
+                input,
+                format!(
+                    "'{}' isn't a valid symbol name, using '{}' instead",
+                    symbol, variable_name
+                ),
+            ));
+        }
+        encoded_tile.name = Some(symbol);
+    }
+
+    /// Decode and encode an in-memory image. `name` is used for format
+    /// detection by extension, diagnostics and the generated symbol.
+    pub fn encode_bytes(&self, name: &str, image: &[u8]) -> Result<EncodedTile, Error> {
+        self.encode_reader(name, image)
+    }
+
+    /// Decode, encode and render an in-memory image.
+    pub fn convert_bytes(&self, name: &str, image: &[u8]) -> Result<Vec<u8>, Error> {
+        let encoded_tile = self.encode_bytes(name, image)?;
+        Ok(self.render(&encoded_tile))
+    }
+
+    /// Decode, encode and render the image at `input`.
+    pub fn convert_file(&self, input: &str) -> Result<Vec<u8>, Error> {
+        let encoded_tile = self.encode_file(input)?;
+        Ok(self.render(&encoded_tile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_PNG: &[u8] = include_bytes!("../img/ascii.png");
+
+    #[test]
+    fn empty_names_fail_without_a_symbol_name() {
+        for name in ["", ".."] {
+            assert!(matches!(
+                Converter::new().encode_bytes(name, ASCII_PNG),
+                Err(Error::Input { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn empty_names_convert_with_a_symbol_name() {
+        let rendered = Converter::new()
+            .name("ascii")
+            .convert_bytes("", ASCII_PNG)
+            .unwrap();
+        assert!(String::from_utf8(rendered)
+            .unwrap()
+            .contains("unsigned char ascii[]"));
+    }
+
+    #[test]
+    fn fonts_past_character_255_fail_with_a_read_error() {
+        let encoded_tile = Converter::new()
+            .font(Some(100))
+            .encode_bytes("ascii", ASCII_PNG);
+        assert!(matches!(
+            encoded_tile,
+            Err(Error::Read {
+                source: ImageReadError::FontPastLastChar {
+                    glyphs: 224,
+                    first_char: 100
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn anti_aliased_colors_take_the_nearest_palette_color() {
+        let palette = parse_palette("#000000,#404040,#808080,#ffffff").unwrap();
+        let encoded_tile = Converter::new()
+            .palette(palette)
+            .encode_bytes("ascii", ASCII_PNG)
+            .unwrap();
+        assert!(encoded_tile.colors.len() <= GB_MAX_COLOR_COUNT);
+    }
+
+    #[test]
+    fn palette_colors_share_their_number_with_transparency() {
+        // Each color of the palette, then a transparent pixel, repeated.
+        let colors: [[u8; 4]; 5] = [
+            [0x00, 0x00, 0x00, 0xff],
+            [0x55, 0x55, 0x55, 0xff],
+            [0xaa, 0xaa, 0xaa, 0xff],
+            [0xff, 0xff, 0xff, 0xff],
+            [0x00, 0x00, 0x00, 0x00],
+        ];
+        let pixels: Vec<u8> = (0..64).flat_map(|i| colors[i % 5]).collect();
+        let mut image = Vec::new();
+        let mut encoder = png::Encoder::new(&mut image, 8, 8);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&pixels))
+            .unwrap();
+        let palette = parse_palette("#000000,#555555,#aaaaaa,#ffffff").unwrap();
+        for streaming in [false, true] {
+            let encoded_tile = Converter::new()
+                .palette(palette)
+                .streaming(streaming)
+                .encode_bytes("five", &image)
+                .unwrap();
+            assert_eq!(encoded_tile.tile_data.len(), 16);
+        }
+    }
+
+    #[test]
+    fn one_bpp_output_parses_back_as_one_bpp() {
+        for output_type in [OutputType::Gbdk, OutputType::Rgbds] {
+            let rendered = Converter::new()
+                .output_type(output_type)
+                .bpp(Bpp::One)
+                .convert_bytes("ascii", ASCII_PNG)
+                .unwrap();
+            let declared = parse_generated(&String::from_utf8(rendered).unwrap());
+            assert_eq!(declared.len(), 1);
+            assert_eq!(declared[0].bpp, 1);
+            assert_eq!(declared[0].tile_data.len() % 8, 0);
+        }
+    }
+}
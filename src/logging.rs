@@ -0,0 +1,44 @@
+//! Logging macros that forward to the `log` crate when the `logging`
+//! feature is enabled, and compile to nothing otherwise.
+
+#[cfg(feature = "logging")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! info {
+    ($($arg:tt)*) => { log::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
@@ -1,329 +1,2966 @@
-use clap::{App, Arg};
-use log;
-use log::Level;
-use png::Decoder;
-use std::collections::{BTreeSet, HashMap};
-use std::fs::{File, OpenOptions};
+use clap::builder::TypedValueParser;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap_complete::Shell;
+use gbtile::build::find_images;
+use gbtile::cache::Cache;
+use gbtile::config::{Config, Defaults, Job};
+use gbtile::regions::Regions;
+use gbtile::{
+    append_output, count_colors, decode_atlas, decode_image_with, decode_reader, decode_source,
+    encode_tile, is_generated, normalize_line_endings, parse_color_map, parse_generated,
+    parse_palette, read_palette_file, referenced_files, share_tileset, unpack_tile_row,
+    variable_name, write_charmap_rgbds, write_output, Addressing, AsepriteSource, Atlas, Bpp,
+    ColorCount, ColorNumber, Converter, DecodeOptions, DecodedImage, Diagnostic, Dither,
+    EncodedTile, Error, FormatRegistry, Gamma, GbmSource, Grid, ImageReadError, LdtkSource,
+    Luminance, MetatileSize, ObjectPalette, OutputFormat, PalettePreset, Rect, Rgb, Rgbds,
+    Severity, SourceImage, SourceRegistry, SplitBanks, TileOrder, VramUsage,
+    DEFAULT_COLOR_TOLERANCE, GB_MAX_COLOR_COUNT, GENERATED_MARKER, TILE_BYTES,
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Value};
+use simple_logger::SimpleLogger;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
+use std::process;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-const GB_MAX_COLOR_COUNT: usize = 4;
+/// The name used for images read from stdin, which becomes their symbol name.
+const STDIN_NAME: &str = "stdin";
 
-#[derive(Copy, Clone, Debug)]
-enum OutputType {
-    Gbdk,
-    Rgbds,
+/// Exit statuses, so scripts can tell classes of failure apart. When several
+/// jobs fail, the first failure decides the status.
+mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    /// Invalid command line arguments, config file or input patterns.
+    pub const USAGE: i32 = 2;
+    /// A file couldn't be read or written.
+    pub const IO: i32 = 3;
+    /// An input isn't a valid image in a supported format.
+    pub const DECODE: i32 = 4;
+    /// An image uses more colors than a tile can.
+    pub const PALETTE: i32 = 5;
+    /// An image's dimensions can't be turned into tiles.
+    pub const DIMENSIONS: i32 = 6;
+    /// An output went over a VRAM budget, with `--fail-on-budget`.
+    pub const BUDGET: i32 = 7;
 }
 
-#[derive(Debug)]
+/// Whether a job's output stayed within the VRAM budget checks: the
+/// `--warn-vram` threshold and the tile slots of VRAM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Budget {
+    Within,
+    Over,
+}
+
+/// How often inputs are checked for changes in watch mode.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug)]
 struct CommandArguments {
-    pub input: String,
-    pub output: String,
-    pub output_type: OutputType,
+    pub jobs: Vec<Job>,
+    /// The output type for jobs that don't set their own.
+    pub output_type: Option<String>,
+    pub deterministic: bool,
+    pub stream: bool,
+    pub dry_run: bool,
+    /// The config file given, or found, whose `[defaults]` were used.
+    pub config: Option<String>,
+    /// The palette file the palette was read from, if any.
+    pub palette_file: Option<String>,
+    pub depfile: Option<String>,
+    pub cache: Option<String>,
+    pub report: Option<String>,
+    /// The tile slots VRAM usage is measured against, 256 or 384.
+    pub tile_slots: usize,
+    /// Warn when an output uses more than this percentage of the tile slots.
+    pub warn_vram: Option<u32>,
+    /// Exit with [`exit_code::BUDGET`] when an output goes over a VRAM
+    /// budget, instead of only warning.
+    pub fail_on_budget: bool,
+    /// The color number of fully transparent pixels.
+    pub transparent_color: ColorNumber,
+    /// The only part of each image converted.
+    pub rect: Option<Rect>,
+    /// The grid sprite sheets' cells are cut from.
+    pub grid: Option<Grid>,
+    /// The size of the animation frames images are cut into.
+    pub frame_size: Option<Grid>,
+    /// The color number images are padded to whole tiles with.
+    pub pad: Option<u8>,
+    /// Drop the edge pixels of images that aren't whole tiles instead of
+    /// failing.
+    pub truncate: bool,
+    /// The colors of color numbers 0 to 3, instead of numbering by
+    /// brightness.
+    pub palette: Option<[Rgb; GB_MAX_COLOR_COUNT]>,
+    /// The source colors pinned to each color number.
+    pub color_map: [Option<Rgb>; GB_MAX_COLOR_COUNT],
+    /// How colors' brightness is compared when numbering them.
+    pub luminance: Luminance,
+    /// How colors' channels are read when comparing them.
+    pub gamma: Gamma,
+    /// How many values of each channel are rounded together.
+    pub color_tolerance: u8,
+    /// Reduce images with too many colors to four instead of failing.
+    pub quantize: bool,
+    /// Stretch the image's contrast before reading colors.
+    pub auto_contrast: bool,
+    /// Round channels to this many levels before reading colors.
+    pub posterize: Option<u8>,
+    /// How quantized images' colors are spread over their pixels.
+    pub dither: Dither,
+    /// Pixels with less alpha than this are transparent.
+    pub alpha_threshold: u8,
+    /// Keep color 0 for transparency and number opaque colors 1 to 3.
+    pub sprite: bool,
+    /// Fail on colors that aren't exactly one of `palette`'s.
+    pub strict_palette: bool,
+    /// Keep source colors exactly and write them as a CGB palette.
+    pub cgb: bool,
+    /// Share out SGB palettes and write their commands.
+    pub sgb: bool,
+    /// Correct the CGB palette's colors for the screen.
+    pub cgb_color_correction: bool,
+    /// The object palette sprites use, which implies `sprite`.
+    pub obp: Option<ObjectPalette>,
+    /// Number colors by their original Game Boy shade.
+    pub dmg_compatible: bool,
+    /// The order tiles are written in.
+    pub order: TileOrder,
+    /// The size of the metatiles to group tiles into.
+    pub metatile: Option<MetatileSize>,
+    /// The VRAM tile number maps count tiles from.
+    pub base_index: u8,
+    /// How the background reads the tile numbers maps draw with.
+    pub addressing: Addressing,
+    /// The bits per pixel tiles are written with.
+    pub bpp: Bpp,
+    /// The character the first glyph draws, when converting fonts.
+    pub first_char: Option<u8>,
+    /// Leave out tiles that are entirely color 0.
+    pub trim_blank: bool,
+    /// Share one tileset between the inputs of a job, with a map for each.
+    pub shared_tileset: bool,
+    /// The banks tile data is split to fit.
+    pub split_banks: Option<SplitBanks>,
+    /// Write animations as the tiles that change between frames.
+    pub delta_frames: bool,
+    /// Make a metasprite of each animation frame.
+    pub metasprites: bool,
+    /// Write `<input>.errors.png` for images that fail because of their
+    /// colors or size.
+    pub error_images: bool,
+    /// The only layer drawn from Aseprite files.
+    pub layer: Option<String>,
+    /// The tag whose frames are read from Aseprite files.
+    pub tag: Option<String>,
+    pub append: bool,
+    pub force: bool,
+    pub prefix: String,
+    pub suffix: String,
+    pub diagnostics: DiagnosticsFormat,
+    /// How progress through the jobs is shown, or `None` to pick
+    /// automatically.
+    pub progress: Option<ProgressStyle>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DiagnosticsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ProgressStyle {
+    /// A progress bar, redrawn in place on the last line of the terminal.
+    Bar,
+    /// One line per finished job, for logs and CI.
+    Plain,
+    None,
+}
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// The progress bar currently drawn on the last line of stderr, if any.
+static STATUS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Run `f` with the progress bar temporarily erased, so anything it
+/// writes to stderr doesn't get mixed into the bar.
+fn without_status_line<T>(f: impl FnOnce() -> T) -> T {
+    let status_line = STATUS_LINE.lock().unwrap();
+    if let Some(line) = &*status_line {
+        erase(line);
+    }
+    let result = f();
+    if let Some(line) = &*status_line {
+        eprint!("{}", line);
+    }
+    result
+}
+
+fn erase(line: &str) {
+    eprint!("\r{}\r", " ".repeat(line.chars().count()));
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash, Ord, Eq)]
-struct RGB {
-    r: u8,
-    g: u8,
-    b: u8,
+/// The level logged to stderr, chosen with -v, -q and --log-level.
+static CONSOLE_LOG_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// The level logged to the --log-file, regardless of the console level.
+const LOG_FILE_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// The --log-file, if one was given.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Timestamps in the log file, matching the console's.
+const LOG_FILE_TIMESTAMP: &[time::format_description::FormatItem] = time::macros::format_description!(
+    "[year]-[month]-[day] [hour]:[minute]:[second],[subsecond digits:3]"
+);
+
+/// Add a line to the log file, if there is one.
+fn write_log_file(level: Level, target: &str, message: &dyn fmt::Display) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if let Some(file) = log_file.as_mut() {
+        let now =
+            time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+        // Failing to log shouldn't fail the conversion.
+        let _ = writeln!(
+            file,
+            "{} {:<5} [{}] {}",
+            now.format(LOG_FILE_TIMESTAMP).unwrap_or_default(),
+            level,
+            target,
+            message
+        );
+    }
 }
 
-impl RGB {
-    fn round(&self) -> RGB {
-        RGB {
-            r: (self.r / 48) * 48,
-            g: (self.g / 48) * 48,
-            b: (self.b / 48) * 48,
+/// Logs to stderr without writing into the middle of the progress bar, and
+/// to the log file at [`LOG_FILE_LEVEL`].
+struct CliLogger(SimpleLogger);
+
+impl Log for CliLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+            || (metadata.level() <= LOG_FILE_LEVEL && LOG_FILE.lock().unwrap().is_some())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.0.enabled(record.metadata()) {
+            without_status_line(|| self.0.log(record));
+        }
+        if record.level() <= LOG_FILE_LEVEL {
+            write_log_file(record.level(), record.target(), record.args());
         }
     }
+
+    fn flush(&self) {
+        self.0.flush();
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Tracks and shows how far through a batch of jobs we are.
+struct Progress {
+    style: ProgressStyle,
+    total: usize,
+    finished: usize,
+    failed: usize,
+}
+
+impl Progress {
+    fn new(args: &CommandArguments) -> Self {
+        let style = args.progress.unwrap_or_else(|| {
+            if args.jobs.len() > 1 && io::stderr().is_terminal() {
+                ProgressStyle::Bar
+            } else {
+                ProgressStyle::None
+            }
+        });
+        Progress {
+            style,
+            total: args.jobs.len(),
+            finished: 0,
+            failed: 0,
+        }
+    }
+
+    fn start(&self, job: &Job) {
+        if self.style != ProgressStyle::Bar {
+            return;
+        }
+        let filled = self.finished * PROGRESS_BAR_WIDTH / self.total.max(1);
+        let line = format!(
+            "[{}{}] {}/{} {}",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            self.finished,
+            self.total,
+            job.input.join(", ")
+        );
+        let mut status_line = STATUS_LINE.lock().unwrap();
+        if let Some(previous) = &*status_line {
+            erase(previous);
+        }
+        eprint!("{}", line);
+        *status_line = Some(line);
+    }
+
+    fn finish(&mut self, job: &Job, succeeded: bool) {
+        self.finished += 1;
+        if !succeeded {
+            self.failed += 1;
+        }
+        if self.style == ProgressStyle::Plain {
+            eprintln!(
+                "[{}/{}] {} {} -> {}",
+                self.finished,
+                self.total,
+                if succeeded { "ok" } else { "failed" },
+                job.input.join(", "),
+                job.output
+            );
+        }
+    }
+
+    fn summary(&self) {
+        if self.style == ProgressStyle::None {
+            return;
+        }
+        if let Some(line) = STATUS_LINE.lock().unwrap().take() {
+            erase(&line);
+        }
+        eprintln!(
+            "{} of {} job(s) succeeded, {} failed",
+            self.finished - self.failed,
+            self.total,
+            self.failed
+        );
+    }
+}
+
+/// Options shared by every subcommand.
+fn global_args() -> Vec<Arg> {
+    vec![
+        Arg::new("verbose")
+            .help("Log more detail. Repeat for trace logging")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .global(true)
+            .help_heading("Global Options"),
+        Arg::new("quiet")
+            .help("Only log warnings. Repeat to hide warnings too, leaving just errors")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::Count)
+            .conflicts_with("verbose")
+            .global(true)
+            .help_heading("Global Options"),
+        Arg::new("log-level")
+            .help("Set the log level directly, overriding -v and -q")
+            .long("log-level")
+            .env("GBTILE_LOG_LEVEL")
+            .value_parser(["off", "error", "warn", "info", "debug", "trace"])
+            .global(true)
+            .help_heading("Global Options"),
+        // Kept from before -v existed.
+        Arg::new("debug")
+            .help("Enable debug logging. Same as -v")
+            .short('d')
+            .action(ArgAction::Count)
+            .hide(true)
+            .global(true),
+        Arg::new("diagnostics")
+            .help("How errors and warnings are reported on stderr. Either 'text' or 'json' (one object per line). Defaults to 'text'")
+            .long("diagnostics")
+            .env("GBTILE_DIAGNOSTICS")
+            .value_parser(["text", "json"])
+            .global(true)
+            .help_heading("Global Options"),
+        Arg::new("log-file")
+            .help("Also write debug logs, errors and warnings to this file, whatever the console log level. Example: 'build/gbtile.log'")
+            .long("log-file")
+            .global(true)
+            .help_heading("Global Options"),
+    ]
+}
+
+/// Images given as positional arguments, for the subcommands that only
+/// read them.
+fn image_args() -> Arg {
+    Arg::new("input")
+        .help("The images to read. Example: 'image.png', a pattern like 'sprites/*.png', or '-' to read from stdin")
+        .num_args(1..)
+}
+
+/// The color number for transparent pixels, for every subcommand that
+/// decodes images.
+fn transparent_color_arg() -> Arg {
+    Arg::new("transparent-color")
+        .help("The color number, 0 to 3, given to fully transparent pixels. Defaults to 0")
+        .long("transparent-color")
+        .env("GBTILE_TRANSPARENT_COLOR")
+        .value_name("COLOR")
+        .value_parser(value_parser!(u8).try_map(ColorNumber::try_from))
+}
+
+/// The sprite color convention, for every subcommand that decodes images.
+fn sprite_arg() -> Arg {
+    Arg::new("sprite")
+        .help("Follow the sprite convention: transparent pixels are color 0, and opaque pixels only use colors 1 to 3, as OBJ palettes do on hardware")
+        .long("sprite")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("transparent-color")
+}
+
+/// Game Boy Color mode, for every subcommand that decodes images.
+fn cgb_arg() -> Arg {
+    Arg::new("cgb")
+        .help("Game Boy Color mode: keep colors exactly as they are instead of rounding them, and write them as a palette of RGB555 words next to the tile data, to load through BCPS/BCPD or OCPS/OCPD. Images with more than four colors share out up to 8 palettes between their tiles, as long as each tile uses at most four. Raw output has no palette")
+        .long("cgb")
+        .action(ArgAction::SetTrue)
+}
+
+/// Super Game Boy mode, for every subcommand that decodes images.
+fn sgb_arg() -> Arg {
+    Arg::new("sgb")
+        .help("Super Game Boy mode: keep colors exactly as they are, and write the SGB's PAL01 and PAL23 palette commands and an attribute file next to the tile data, coloring each tile with one of 4 palettes. Images with more than four colors share out the palettes between their tiles, which all have the image's lightest color, or transparency, as color 0")
+        .long("sgb")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("cgb")
+}
+
+/// The alpha below which pixels are transparent, for every subcommand that
+/// decodes images.
+fn alpha_threshold_arg() -> Arg {
+    Arg::new("alpha-threshold")
+        .help("Pixels with less alpha than this, 0 to 255, are transparent, and the rest are opaque, so semi-transparent anti-aliasing doesn't add colors. Defaults to 1, so only fully transparent pixels are")
+        .long("alpha-threshold")
+        .env("GBTILE_ALPHA_THRESHOLD")
+        .value_name("N")
+        .value_parser(value_parser!(u8))
+}
+
+/// The part of each image to convert, for every subcommand that decodes
+/// images.
+fn rect_arg() -> Arg {
+    Arg::new("rect")
+        .help("Only convert this rectangle of each image, or of each animation frame, given as x,y,width,height in pixels. Widened to the 8x8 tile grid if it isn't on it. Example: '0,64,128,32'")
+        .long("rect")
+        .env("GBTILE_RECT")
+        .value_name("RECT")
+        .value_parser(value_parser!(Rect))
+}
+
+/// Slicing sprite sheets with gutters, for every subcommand that decodes
+/// images.
+fn grid_args() -> Vec<Arg> {
+    vec![
+        Arg::new("grid")
+            .help("Cut the cells of a sprite sheet laid out on a grid of cells this size out of their gutters, and put them side by side before tiling. Cells must be a whole number of 8x8 tiles. Example: '16x16'")
+            .long("grid")
+            .value_name("SIZE")
+            .value_parser(value_parser!(Grid)),
+        Arg::new("margin")
+            .help("With --grid, the pixels around the sheet before the first cell. Defaults to 0")
+            .long("margin")
+            .value_name("PIXELS")
+            .value_parser(value_parser!(u32))
+            .requires("grid"),
+        Arg::new("spacing")
+            .help("With --grid, the pixels between neighbouring cells. Defaults to 0")
+            .long("spacing")
+            .value_name("PIXELS")
+            .value_parser(value_parser!(u32))
+            .requires("grid"),
+        Arg::new("frames")
+            .help("Cut the sheet into animation frames this size, left to right and top to bottom, after --grid, writing a block of tiles per frame with each frame's first tile and the frame count as constants. Frames must be a whole number of 8x8 tiles. Example: '16x16'")
+            .long("frames")
+            .value_name("SIZE")
+            .value_parser(value_parser!(Grid)),
+    ]
+}
+
+/// Padding images to whole tiles, for every subcommand that decodes
+/// images.
+fn pad_arg() -> Arg {
+    Arg::new("pad")
+        .help("Extend images, or animation frames, that aren't a whole number of 8x8 tiles to the next tile boundary with this color number, instead of dropping the edge pixels past the last full tile. The image must use the color, unless it's the transparent color")
+        .long("pad")
+        .value_name("COLOR")
+        .value_parser(value_parser!(u8).range(0..=3))
+}
+
+/// Truncating images to whole tiles, for every subcommand that decodes
+/// images.
+fn truncate_arg() -> Arg {
+    Arg::new("truncate")
+        .help("Drop the edge pixels past the last full 8x8 tile of images that aren't a whole number of tiles, with a warning, instead of failing")
+        .long("truncate")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("pad")
+}
+
+/// The colors to number an image's colors by, for every subcommand that
+/// decodes images.
+fn palette_args() -> Vec<Arg> {
+    vec![
+        Arg::new("palette")
+            .help("Number colors by these four colors, darkest first, from color 3 to color 0, instead of by brightness. Each color takes the number of the nearest of them. Example: '#0f380f,#306230,#8bac0f,#9bbc0f'")
+            .long("palette")
+            .env("GBTILE_PALETTE")
+            .value_name("COLORS")
+            .value_parser(parse_palette),
+        Arg::new("palette-file")
+            .help("Like --palette, but reading the four colors, darkest first, from a GIMP (.gpl) or JASC (.pal) palette file. Example: 'gb.gpl'")
+            .long("palette-file")
+            .env("GBTILE_PALETTE_FILE")
+            .value_name("FILE")
+            .value_parser(|path: &str| {
+                read_palette_file(path).map_err(|err| err.diagnostic().message)
+            })
+            .conflicts_with("palette"),
+        palette_preset_arg().conflicts_with_all(["palette", "palette-file"]),
+        Arg::new("map")
+            .help("Pin source colors to color numbers, 0 to 3, while the rest are still numbered by brightness, for images with two colors that are as bright as each other. Example: 'ff0000=3,00ff00=0'")
+            .long("map")
+            .env("GBTILE_MAP")
+            .value_name("COLORS")
+            .value_parser(parse_color_map)
+            .conflicts_with_all(["palette", "palette-file", "palette-preset"]),
+        Arg::new("strict-palette")
+            .help("Fail, listing the stray colors, if an opaque color isn't exactly one of the palette's, instead of taking the nearest. Needs --palette, --palette-file or --palette-preset")
+            .long("strict-palette")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// A built-in palette, for every subcommand that decodes images and for
+/// `decode`.
+fn palette_preset_arg() -> Arg {
+    Arg::new("palette-preset")
+        .help("Like --palette, but using a built-in palette, which previews are drawn in too: 'dmg-green' for the original Game Boy's screen, 'pocket' for the Game Boy Pocket's, 'grayscale', or 'bgb' for the BGB emulator's")
+        .long("palette-preset")
+        .env("GBTILE_PALETTE_PRESET")
+        .value_name("NAME")
+        .value_parser(["dmg-green", "pocket", "grayscale", "bgb"])
+}
+
+/// The preset given with `--palette-preset`, if any.
+fn palette_preset(matches: &ArgMatches) -> Option<PalettePreset> {
+    matches
+        .get_one::<String>("palette-preset")
+        .map(|preset| preset.parse().unwrap())
+}
+
+/// The palette given with `--palette`, `--palette-file` or
+/// `--palette-preset`, if any.
+fn palette(matches: &ArgMatches) -> Option<[Rgb; GB_MAX_COLOR_COUNT]> {
+    matches
+        .get_one::<[Rgb; GB_MAX_COLOR_COUNT]>("palette")
+        .or(matches.get_one("palette-file"))
+        .copied()
+        .or_else(|| palette_preset(matches).map(PalettePreset::colors))
 }
 
-struct DecodedImage {
-    input_filename: String,
-    info: png::OutputInfo,
-    image_data: Vec<RGB>,
-    color_numbers: HashMap<RGB, u8>,
+/// How colors' brightness is compared, for every subcommand that decodes
+/// images.
+fn luminance_arg() -> Arg {
+    Arg::new("luminance")
+        .help("How colors are ordered from lightest to darkest: 'rgb' compares red, then green, then blue, and 'rec709' weighs the channels by how bright they look. Defaults to 'rgb'")
+        .long("luminance")
+        .env("GBTILE_LUMINANCE")
+        .value_parser(["rgb", "rec709"])
 }
 
-struct EncodedTile {
-    input_filename: String,
-    tile_data: Vec<u8>,
+/// How far apart colors can be and still merge, for every subcommand that
+/// decodes images.
+fn color_tolerance_arg() -> Arg {
+    Arg::new("color-tolerance")
+        .help("How many values of each color channel are rounded together, merging nearby colors before they're counted. Raise it to merge anti-aliased edges, or lower it to keep close shades apart; 1 keeps every color. Defaults to 48")
+        .long("color-tolerance")
+        .env("GBTILE_COLOR_TOLERANCE")
+        .value_name("N")
+        .value_parser(value_parser!(u8).range(1..))
 }
 
-impl DecodedImage {
-    fn lookup_color(&self, pixel: &RGB) -> u8 {
-        *self.color_numbers.get(&pixel).unwrap()
+/// Stretching images' contrast, for every subcommand that decodes images.
+fn auto_contrast_arg() -> Arg {
+    Arg::new("auto-contrast")
+        .help("Stretch the image's contrast before reading its colors, so its darkest channel value becomes 0 and its lightest 255, spreading washed-out images over all four shades instead of collapsing into two. Contrast-stretched images aren't streamed")
+        .long("auto-contrast")
+        .action(ArgAction::SetTrue)
+}
+
+/// Numbering colors by original Game Boy shade, for every subcommand that
+/// decodes images.
+fn dmg_compatible_arg() -> Arg {
+    Arg::new("dmg-compatible")
+        .help("Number each palette's colors by the original Game Boy shade nearest their lightness, instead of just in order, so tiles converted with --cgb also look right in grayscale on the original Game Boy")
+        .long("dmg-compatible")
+        .action(ArgAction::SetTrue)
+}
+
+/// Posterizing images, for every subcommand that decodes images.
+fn posterize_arg() -> Arg {
+    Arg::new("posterize")
+        .help("Round each color channel to this many evenly spaced levels, 2 to 255, before reading colors, after --auto-contrast. Example: 4")
+        .long("posterize")
+        .value_name("LEVELS")
+        .value_parser(value_parser!(u8).range(2..))
+}
+
+/// Reducing images to four colors, for every subcommand that decodes
+/// images.
+fn quantize_arg() -> Arg {
+    Arg::new("quantize")
+        .help("Reduce images with more than four colors to the best four instead of failing, for photos and mockups. Quantized images aren't streamed")
+        .long("quantize")
+        .action(ArgAction::SetTrue)
+}
+
+/// How quantized images are dithered, for every subcommand that decodes
+/// images.
+fn dither_arg() -> Arg {
+    Arg::new("dither")
+        .help("How the four colors of quantized images are spread over their pixels: 'none' gives each pixel the nearest color, and 'floyd-steinberg' spreads the difference onto its neighbors to keep gradients. 'bayer2', 'bayer4' and 'bayer8' nudge pixels by a repeating pattern instead, which deduplicates far better. Defaults to 'none'")
+        .long("dither")
+        .env("GBTILE_DITHER")
+        .value_parser(["none", "floyd-steinberg", "bayer2", "bayer4", "bayer8"])
+}
+
+/// How colors' channels are read, for every subcommand that decodes images.
+fn gamma_arg() -> Arg {
+    Arg::new("gamma")
+        .help("How color channels are read when comparing brightness with --luminance rec709 or matching colors to --palette: 'srgb' uses them as stored, and 'linear' decodes them into linear light first. Defaults to 'srgb'")
+        .long("gamma")
+        .env("GBTILE_GAMMA")
+        .value_parser(["srgb", "linear"])
+}
+
+/// Options picking what to read from Aseprite files, for every subcommand
+/// that decodes images.
+fn aseprite_args() -> Vec<Arg> {
+    vec![
+        Arg::new("layer")
+            .help("Only draw this layer of Aseprite files, even if it's hidden, instead of flattening every visible layer. Example: 'Outline'")
+            .long("layer")
+            .env("GBTILE_LAYER")
+            .value_name("NAME"),
+        Arg::new("tag")
+            .help("Only read the frames of this tag of Aseprite files, instead of every frame. Example: 'walk'")
+            .long("tag")
+            .env("GBTILE_TAG")
+            .value_name("NAME"),
+    ]
+}
+
+/// How images are cut up, and their colors reduced and numbered, for every
+/// subcommand that decodes images.
+fn decode_args() -> Vec<Arg> {
+    let mut args = vec![alpha_threshold_arg(), rect_arg()];
+    args.extend(grid_args());
+    args.extend([pad_arg(), truncate_arg()]);
+    args.extend(palette_args());
+    args.extend([
+        luminance_arg(),
+        gamma_arg(),
+        color_tolerance_arg(),
+        auto_contrast_arg(),
+        posterize_arg(),
+        dmg_compatible_arg(),
+        quantize_arg(),
+        dither_arg(),
+    ]);
+    args.extend(aseprite_args());
+    args
+}
+
+/// The image sources to decode `input` with, reading Aseprite files with
+/// any `--layer` and `--tag` given, and GBMB maps and LDtk projects with
+/// the files next to them.
+fn sources(input: &str, layer: Option<&str>, tag: Option<&str>) -> SourceRegistry {
+    let mut aseprite = AsepriteSource::new();
+    if let Some(layer) = layer {
+        aseprite = aseprite.layer(layer);
+    }
+    if let Some(tag) = tag {
+        aseprite = aseprite.tag(tag);
+    }
+    let mut sources = SourceRegistry::default();
+    sources.register(Box::new(aseprite));
+    let dir = Path::new(input).parent().filter(|_| input != "-");
+    if let Some(dir) = dir {
+        sources.register(Box::new(GbmSource::new().tile_dir(dir)));
+        sources.register(Box::new(LdtkSource::new().project_dir(dir)));
     }
+    sources
 }
 
-#[derive(Debug)]
-enum ImageReadError {
-    Png(png::DecodingError),
-    Io(io::Error),
-    UnsupportedColorType(png::ColorType),
-    TooManyColors,
+fn convert_command() -> Command {
+    Command::new("convert")
+        .about("Generate tiles from images. This is the default when no subcommand is given")
+        .arg(
+            Arg::new("input")
+                .help("The image to generate tiles from. Example: 'image.png', or '-' to read from stdin. Repeat, or use a pattern like 'sprites/*.png', to write several images into one output file")
+                .short('i')
+                .num_args(1..)
+                .action(ArgAction::Append)
+                .required_unless_present_any(["config", "input-dir"]),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output file to generate, or '-' to write to stdout. Usually something like 'tiles.h' for GBDK output, or 'tiles.asm' for RGBDS")
+                .short('o')
+                .required_unless_present_any(["config", "input-dir"]),
+        )
+        .arg(
+            Arg::new("output-type")
+                .help("The output type: 'gbdk', 'rgbds' or '2bpp' for raw tile data. With --config, only for jobs that don't set a type. Defaults to 'gbdk'")
+                .short('t')
+                .env("GBTILE_OUTPUT_TYPE"),
+        )
+        .arg(
+            Arg::new("name")
+                .help("The generated symbol name, for a single input. Defaults to the input file name without its extension")
+                .long("name"),
+        )
+        .arg(
+            Arg::new("regions")
+                .help("A TOML or JSON manifest of named rectangles of a single input, converting each into its own tile block. Example: 'sheet.regions.toml'")
+                .long("regions")
+                .value_name("MANIFEST")
+                .conflicts_with_all(["name", "rect"]),
+        )
+        .arg(
+            Arg::new("palette-mask")
+                .help("With --cgb or --sgb, an image the same size as the inputs whose flat color regions mark the tiles sharing each palette, numbered by where each color first appears. Tiles where it's transparent are shared out as usual. Example: 'level.mask.png'")
+                .long("palette-mask")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("error-image")
+                .help("When an image fails because of its colors or size, write <input>.errors.png with the offending pixels in magenta and the 8x8 tile grid drawn over it")
+                .long("error-image")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Check that every input converts, without writing any output")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("watch"),
+        )
+        .arg(
+            Arg::new("append")
+                .help("Add to the end of existing output files instead of replacing them. Fails if a symbol is already defined")
+                .long("append")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .help("Replace existing output files even if gbtile didn't generate them")
+                .long("force")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("prefix")
+                .help("Prepend this to every generated symbol name. Example: 'gfx_'")
+                .long("prefix")
+                .env("GBTILE_PREFIX"),
+        )
+        .arg(
+            Arg::new("suffix")
+                .help("Append this to every generated symbol name. Example: '_tiles'")
+                .long("suffix")
+                .env("GBTILE_SUFFIX"),
+        )
+        .arg(
+            Arg::new("config")
+                .help("A project config file listing many conversion jobs. Example: 'gbtile.toml'")
+                .short('c')
+                .long("config")
+                .conflicts_with_all(["input", "output", "name", "regions", "palette-mask"]),
+        )
+        .arg(
+            Arg::new("input-dir")
+                .help("Convert every image under this directory, recursively. Requires --output-dir")
+                .long("input-dir")
+                .requires("output-dir")
+                .conflicts_with_all(["input", "output", "config", "name", "regions", "palette-mask"]),
+        )
+        .arg(
+            Arg::new("output-dir")
+                .help("Where --input-dir outputs are written, mirroring the input directory structure and named after each image")
+                .long("output-dir")
+                .requires("input-dir"),
+        )
+        .arg(
+            Arg::new("depfile")
+                .help("Also write a Make/Ninja dependency file listing every output's inputs. Example: 'tiles.d'")
+                .long("depfile"),
+        )
+        .arg(
+            Arg::new("cache")
+                .help("Remember inputs in this file, and skip outputs whose inputs and options haven't changed since. Example: 'build/gbtile.cache'")
+                .long("cache"),
+        )
+        .arg(
+            Arg::new("report")
+                .help("Also write a JSON report of every converted image's size, tile count, colors and symbol, and every output's size. Example: 'build/gbtile.json'")
+                .long("report"),
+        )
+        .arg(transparent_color_arg())
+        .arg(sprite_arg())
+        .arg(cgb_arg())
+        .arg(sgb_arg())
+        .arg(
+            Arg::new("cgb-color-correction")
+                .help("With --cgb, correct the palette's colors for the Game Boy Color's washed-out screen, so they look on hardware like they do in the image. Saturated colors that the screen can't reach are kept as close as they can be")
+                .long("cgb-color-correction")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("obp")
+                .help("Draw sprites made of the tiles with object palette 0 or 1, writing the palette register as OBP0 or OBP1 along with the OAM flags selecting it. Implies --sprite, so only 3 opaque colors are allowed")
+                .long("obp")
+                .value_name("PALETTE")
+                .value_parser(value_parser!(u8).try_map(ObjectPalette::try_from))
+                .conflicts_with("transparent-color"),
+        )
+        .arg(
+            Arg::new("order")
+                .help("The order tiles are written in: 'row' goes left to right, then top to bottom, and 'column' top to bottom, then left to right, as some scrolling engines and VRAM copy routines expect. Maps drawn with the tiles are renumbered to match. Defaults to 'row'")
+                .long("order")
+                .value_name("ORDER")
+                .value_parser(["row", "column"]),
+        )
+        .arg(
+            Arg::new("metatile")
+                .help("Group the tiles into metatiles of this size in pixels, writing each distinct metatile's tile numbers once and a map of which metatile is drawn where. Maps drawn with the tiles are grouped, or the image itself when it has none. Example: '16x16'")
+                .long("metatile")
+                .value_name("SIZE")
+                .value_parser(value_parser!(MetatileSize)),
+        )
+        .arg(
+            Arg::new("base-index")
+                .help("Number the tiles maps and metatiles draw from this VRAM tile instead of 0, for tiles loaded after others, such as a font at tiles 0 to 95. Example: '96'")
+                .long("base-index")
+                .value_name("N")
+                .value_parser(value_parser!(u8)),
+        )
+        .arg(
+            Arg::new("addressing")
+                .help("How the background reads the tile numbers maps and metatiles draw with: '8000' numbers tiles from 0 at $8000, and '8800' numbers them signed, from -128 at $8800 through 0 at $9000, for tiles loaded from $8800. Defaults to '8000'")
+                .long("addressing")
+                .value_name("MODE")
+                .value_parser(["8000", "8800"]),
+        )
+        .arg(
+            Arg::new("bpp")
+                .help("The bits per pixel tiles are written with: 2, or 1 for two-color images like fonts and UI glyphs, writing one byte per tile row, half the size. Pixels that aren't color 0 are set. Defaults to 2")
+                .long("bpp")
+                .value_name("BITS")
+                .value_parser(value_parser!(u8).try_map(Bpp::try_from)),
+        )
+        .arg(
+            Arg::new("font")
+                .help("Convert images as fonts: grids of 8x8 glyphs, read left to right and top to bottom in character order, writing a constant for each character giving the tile that draws it")
+                .long("font")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("order"),
+        )
+        .arg(
+            Arg::new("first-char")
+                .help("With --font, the character code the first glyph draws. Defaults to 32, a space, as in most ASCII font sheets")
+                .long("first-char")
+                .value_name("CODE")
+                .value_parser(value_parser!(u8))
+                .requires("font"),
+        )
+        .arg(
+            Arg::new("trim-blank")
+                .help("Leave out tiles that are entirely color 0, like the padding of sprite sheets made a power of two in size. Maps and metatiles keep one blank tile and are renumbered to match; otherwise only blank tiles at the end are left out, so the rest keep their numbers")
+                .long("trim-blank")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shared-tileset")
+                .help("Write the inputs' tiles as one tileset, each distinct tile once, with a map for each input drawing it from the shared tiles. The tileset is named after the output file, and takes its colors and palettes from the first input")
+                .long("shared-tileset")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("split-banks")
+                .help("Split the tile data into an array or section per bank: 'vram' for as many tiles as --tile-slots, so each part can be loaded at once, or 'rom' for 16 KiB switchable ROM banks. Each part gets a symbol and a tile count")
+                .long("split-banks")
+                .value_name("BANKS")
+                .value_parser(["vram", "rom"]),
+        )
+        .arg(
+            Arg::new("delta-frames")
+                .help("Write animations as their first frame, the tiles that change from each frame to the next, and a list of patches per frame giving the tile to replace and the offset of the tile to copy over it, so only the changes are kept in ROM and copied to VRAM")
+                .long("delta-frames")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("metasprites")
+                .help("Make a metasprite of each animation frame, or of the image, for GBDK-2020's move_metasprite: an 8x8 sprite for each tile that isn't entirely color 0, placed from a pivot at the center of the frame. RGBDS gets the same entries as db lines")
+                .long("metasprites")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("trim-blank"),
+        )
+        .args(decode_args())
+        .arg(
+            Arg::new("tile-slots")
+                .help("The tile slots VRAM usage is measured against: 256 for the background or window, or 384 for all of a VRAM bank's tile data. Defaults to 256")
+                .long("tile-slots")
+                .env("GBTILE_TILE_SLOTS")
+                .value_parser(["256", "384"]),
+        )
+        .arg(
+            Arg::new("warn-vram")
+                .help("Warn when an output uses more than this percentage of the tile slots, or fail with --fail-on-budget. Example: '90'")
+                .long("warn-vram")
+                .env("GBTILE_WARN_VRAM")
+                .value_name("PERCENT")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("fail-on-budget")
+                .help("Exit with status 7 when an output goes over the --warn-vram budget, or has more tiles than the tile slots without --split-banks, instead of only warning. The output is still written")
+                .long("fail-on-budget")
+                .env("GBTILE_FAIL_ON_BUDGET")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("watch")
+                .help("Keep running and regenerate outputs whenever an input changes")
+                .long("watch")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("deterministic")
+                .help("Guarantee byte-identical output for identical inputs and flags")
+                .long("deterministic")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stream")
+                .help("Decode and encode the image 8 pixel rows at a time, to keep memory use low for very large images")
+                .long("stream")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progress")
+                .help("How progress through many jobs is shown on stderr. 'plain' prints a line per job, for CI. Defaults to 'bar' on a terminal when there is more than one job, and 'none' otherwise")
+                .long("progress")
+                .env("GBTILE_PROGRESS")
+                .value_parser(["bar", "plain", "none"]),
+        )
 }
 
-impl From<io::Error> for ImageReadError {
-    fn from(err: io::Error) -> Self {
-        ImageReadError::Io(err)
+fn cli() -> Command {
+    Command::new("gbtile")
+        .version("0.2.0")
+        .author("Blake Smith <blakesmith0@gmail.com>")
+        .about("Generate GBDK or RGBDS Game Boy tiles from PNG, GIF, JPEG, WebP, TGA, Aseprite, GBTD, GBMB, LDtk and TexturePacker files")
+        .args(global_args())
+        .after_help("Exit codes: 0 success, 2 invalid arguments or config, 3 file read or write failed, 4 invalid image, 5 palette overflow, 6 bad dimensions, 7 over a VRAM budget with --fail-on-budget")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(convert_command())
+        .subcommand(
+            Command::new("check")
+                .about("Check that images convert, reporting any errors and warnings without writing output")
+                .arg(image_args().required_unless_present("config"))
+                .arg(transparent_color_arg())
+                .arg(sprite_arg())
+                .arg(cgb_arg())
+                .arg(sgb_arg())
+                .args(decode_args())
+                .arg(
+                    Arg::new("config")
+                        .help("Check every input of a project config file instead. Example: 'gbtile.toml'")
+                        .short('c')
+                        .long("config")
+                        .conflicts_with("input"),
+                ),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Print the size, tile count and color numbers of images")
+                .arg(image_args().required(true))
+                .arg(transparent_color_arg())
+                .arg(sprite_arg())
+                .arg(cgb_arg())
+                .arg(sgb_arg())
+                .args(decode_args()),
+        )
+        .subcommand(
+            Command::new("palette")
+                .about("Print each color of images after rounding, with its pixel count and color number")
+                .arg(image_args().required(true))
+                .arg(transparent_color_arg())
+                .arg(sprite_arg())
+                .arg(cgb_arg())
+                .arg(sgb_arg())
+                .args(decode_args()),
+        )
+        .subcommand(
+            Command::new("preview")
+                .about("Draw the tiles generated from an image in the terminal, in the four Game Boy shades")
+                .arg(image_args().required(true))
+                .arg(transparent_color_arg())
+                .arg(sprite_arg())
+                .arg(cgb_arg())
+                .arg(sgb_arg())
+                .args(decode_args())
+                .arg(
+                    Arg::new("plain")
+                        .help("Draw with ASCII characters instead of colors, even on a terminal")
+                        .long("plain")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("decode")
+                .about("Draw the tiles in a file gbtile generated back into a PNG")
+                .arg(
+                    Arg::new("input")
+                        .help("The generated file to read. Example: 'tiles.h', 'tiles.asm' or 'tiles.2bpp'")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("The PNG file to write")
+                        .short('o')
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("symbol")
+                        .help("Only draw the tiles declared with this name, including an animation's frames. Defaults to every tile in the file")
+                        .long("symbol")
+                        .value_name("NAME"),
+                )
+                .arg(
+                    Arg::new("bpp")
+                        .help("The bits per pixel the tiles are in, 1 or 2. Defaults to 1 for '.1bpp' files and for tiles with a <NAME>_BPP constant of 1, otherwise 2")
+                        .long("bpp")
+                        .value_name("BITS")
+                        .value_parser(value_parser!(u8).range(1..=2)),
+                )
+                .arg(
+                    Arg::new("columns")
+                        .help("The number of tiles drawn in each row. Defaults to 16")
+                        .long("columns")
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    Arg::new("palette")
+                        .help("The four colors to draw with, darkest first, from color 3 to color 0. Defaults to shades of gray. Example: '#0f380f,#306230,#8bac0f,#9bbc0f'")
+                        .long("palette")
+                        .value_name("COLORS")
+                        .value_parser(parse_palette),
+                )
+                .arg(palette_preset_arg().conflicts_with("palette")),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script to stdout")
+                .arg(
+                    Arg::new("shell")
+                        .help("The shell to generate completions for")
+                        .required(true)
+                        .value_parser(value_parser!(Shell)),
+                ),
+        )
+        .subcommand(
+            Command::new("rules")
+                .about("Print Make or Ninja rules converting every image under a directory")
+                .arg(
+                    Arg::new("input-dir")
+                        .help("The directory to scan for images, recursively")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output-dir")
+                        .help("Where generated files go, mirroring the input directory structure")
+                        .long("output-dir")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output-type")
+                        .help("The output type: 'gbdk', 'rgbds' or '2bpp' for raw tile data. Defaults to 'gbdk'")
+                        .short('t')
+                        .env("GBTILE_OUTPUT_TYPE"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("The build system to write rules for. Defaults to 'make'")
+                        .long("format")
+                        .value_parser(["make", "ninja"]),
+                ),
+        )
+}
+
+/// Insert `convert` when the command line doesn't name a subcommand, so
+/// invocations from before subcommands existed, like `gbtile -i a.png -o
+/// a.h`, keep working.
+fn with_default_subcommand(mut args: Vec<OsString>) -> Vec<OsString> {
+    let subcommands: Vec<String> = cli()
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy().into_owned();
+        match arg.as_str() {
+            "help" | "-h" | "--help" | "-V" | "--version" => return args,
+            "--log-level" | "--diagnostics" | "--log-file" => i += 2,
+            "--verbose" | "--quiet" => i += 1,
+            _ if ["--log-level=", "--diagnostics=", "--log-file="]
+                .iter()
+                .any(|flag| arg.starts_with(flag)) =>
+            {
+                i += 1
+            }
+            _ if arg.len() > 1
+                && arg.starts_with('-')
+                && arg[1..].chars().all(|c| "vqd".contains(c)) =>
+            {
+                i += 1
+            }
+            _ if subcommands.contains(&arg) => return args,
+            _ => {
+                args.insert(i, "convert".into());
+                return args;
+            }
+        }
+    }
+    args
+}
+
+fn main() {
+    let args = with_default_subcommand(env::args_os().collect());
+    let matches = cli().try_get_matches_from(args).unwrap_or_else(|err| {
+        let _ = err.print();
+        process::exit(if err.use_stderr() {
+            exit_code::USAGE
+        } else {
+            exit_code::SUCCESS
+        });
+    });
+
+    let diagnostics = match matches.get_one::<String>("diagnostics").map(String::as_str) {
+        Some("json") => DiagnosticsFormat::Json,
+        _ => DiagnosticsFormat::Text,
+    };
+    let log_level = log_level(&matches);
+    CONSOLE_LOG_LEVEL.set(log_level).unwrap();
+    let mut max_level = log_level;
+    if let Some(path) = matches.get_one::<String>("log-file") {
+        if let Err(err) = open_log_file(path) {
+            report_error(diagnostics, &err);
+            process::exit(exit_status(&err));
+        }
+        max_level = max_level.max(LOG_FILE_LEVEL);
     }
+    log::set_max_level(max_level);
+    log::set_boxed_logger(Box::new(CliLogger(
+        SimpleLogger::new().with_level(log_level),
+    )))
+    .unwrap();
+    log::debug!("Command line: {:?}", env::args().collect::<Vec<_>>());
+    let formats = FormatRegistry::default();
+
+    // Every subcommand that reads images takes its defaults from the same
+    // config file, so it's loaded once, and fails the same way for all.
+    let (config, defaults) = match matches.subcommand() {
+        Some(("decode", _)) | Some(("completions", _)) => (None, Defaults::default()),
+        Some((_, matches)) => load_defaults(matches).unwrap_or_else(|err| {
+            report_error(diagnostics, &err);
+            process::exit(exit_status(&err));
+        }),
+        None => unreachable!("a subcommand is required"),
+    };
+    let status = match matches.subcommand() {
+        Some(("convert", matches)) => convert(matches, diagnostics, &formats, config, defaults),
+        Some(("check", matches)) => check(matches, &defaults, diagnostics),
+        Some(("inspect", matches)) => inspect(matches, &defaults, diagnostics),
+        Some(("palette", matches)) => palette_report(matches, &defaults, diagnostics),
+        Some(("preview", matches)) => preview(matches, &defaults, diagnostics),
+        Some(("decode", matches)) => match decode_generated(matches) {
+            Ok(()) => exit_code::SUCCESS,
+            Err(err) => {
+                report_error(diagnostics, &err);
+                exit_status(&err)
+            }
+        },
+        Some(("completions", matches)) => {
+            let shell = *matches.get_one::<Shell>("shell").unwrap();
+            clap_complete::generate(shell, &mut cli(), "gbtile", &mut io::stdout());
+            exit_code::SUCCESS
+        }
+        Some(("rules", matches)) => match build_rules(matches, &defaults, &formats) {
+            Ok(rules) => {
+                print!("{}", rules);
+                exit_code::SUCCESS
+            }
+            Err(err) => {
+                report_error(diagnostics, &err);
+                exit_status(&err)
+            }
+        },
+        _ => unreachable!("a subcommand is required"),
+    };
+    process::exit(status);
 }
 
-impl From<png::DecodingError> for ImageReadError {
-    fn from(err: png::DecodingError) -> Self {
-        ImageReadError::Png(err)
+fn convert(
+    matches: &ArgMatches,
+    diagnostics: DiagnosticsFormat,
+    formats: &FormatRegistry,
+    config: Option<String>,
+    defaults: Defaults,
+) -> i32 {
+    let mut args = match command_arguments(matches, diagnostics, formats, config, defaults) {
+        Ok(args) => args,
+        Err(err) => {
+            report_error(diagnostics, &err);
+            return exit_status(&err);
+        }
+    };
+
+    log::debug!("Arguments are: {:?}", args);
+
+    if matches.get_flag("watch") {
+        if args
+            .jobs
+            .iter()
+            .any(|job| job.input.iter().any(|input| input == "-"))
+        {
+            eprintln!("error: stdin can't be watched for changes");
+            return exit_code::USAGE;
+        }
+        run_jobs(&args, formats);
+        watch(matches, &mut args, formats);
     }
+    run_jobs(&args, formats)
 }
 
-fn rgbs_to_color_number(unique_colors: &BTreeSet<RGB>) -> HashMap<RGB, u8> {
-    let mut color_numbers = HashMap::new();
-    for (i, rgb) in unique_colors.iter().rev().enumerate() {
-        color_numbers.insert(*rgb, i as u8);
+/// The arguments of `convert`, with the `[defaults]` of `config` filling
+/// in the options that aren't given. Palette files are read again each
+/// time, so watching picks up changes to them.
+fn command_arguments(
+    matches: &ArgMatches,
+    diagnostics: DiagnosticsFormat,
+    formats: &FormatRegistry,
+    config: Option<String>,
+    defaults: Defaults,
+) -> Result<CommandArguments, Error> {
+    let value = |id: &str| matches.get_one::<String>(id).cloned();
+    let output_type = value("output-type").or_else(|| defaults.output_type.clone());
+    let jobs = load_jobs(matches, output_type, formats)?;
+    let palette_file = matches
+        .get_raw("palette-file")
+        .and_then(|mut paths| paths.next())
+        .map(|path| path.to_string_lossy().into_owned());
+    let (palette, palette_file) = match (palette_file, palette(matches)) {
+        (Some(path), _) => (Some(read_palette_file(&path)?), Some(path)),
+        (None, Some(palette)) => (Some(palette), None),
+        (None, None) => (defaults.palette, defaults.palette_file),
+    };
+
+    Ok(CommandArguments {
+        jobs,
+        output_type: value("output-type").or(defaults.output_type),
+        deterministic: matches.get_flag("deterministic"),
+        stream: matches.get_flag("stream"),
+        dry_run: matches.get_flag("dry-run"),
+        config,
+        palette_file,
+        depfile: value("depfile"),
+        cache: value("cache"),
+        report: value("report"),
+        tile_slots: value("tile-slots").map_or(256, |slots| slots.parse().unwrap()),
+        warn_vram: matches.get_one::<u32>("warn-vram").copied(),
+        fail_on_budget: matches.get_flag("fail-on-budget"),
+        transparent_color: matches
+            .get_one::<ColorNumber>("transparent-color")
+            .copied()
+            .or(defaults.transparent_color)
+            .unwrap_or_default(),
+        sprite: matches.get_flag("sprite") || defaults.sprite.unwrap_or(false),
+        strict_palette: matches.get_flag("strict-palette")
+            || defaults.strict_palette.unwrap_or(false),
+        cgb: matches.get_flag("cgb") || defaults.cgb.unwrap_or(false),
+        sgb: matches.get_flag("sgb") || defaults.sgb.unwrap_or(false),
+        cgb_color_correction: matches.get_flag("cgb-color-correction")
+            || defaults.cgb_color_correction.unwrap_or(false),
+        obp: matches
+            .get_one::<ObjectPalette>("obp")
+            .copied()
+            .or(defaults.obp),
+        dmg_compatible: matches.get_flag("dmg-compatible")
+            || defaults.dmg_compatible.unwrap_or(false),
+        order: value("order")
+            .map(|order| order.parse().unwrap())
+            .or(defaults.order)
+            .unwrap_or_default(),
+        metatile: matches
+            .get_one::<MetatileSize>("metatile")
+            .copied()
+            .or(defaults.metatile),
+        base_index: matches
+            .get_one::<u8>("base-index")
+            .copied()
+            .or(defaults.base_index)
+            .unwrap_or(0),
+        addressing: value("addressing")
+            .map(|addressing| addressing.parse().unwrap())
+            .or(defaults.addressing)
+            .unwrap_or_default(),
+        bpp: matches
+            .get_one::<Bpp>("bpp")
+            .copied()
+            .or(defaults.bpp)
+            .unwrap_or_default(),
+        first_char: match matches.get_flag("font") || defaults.font.unwrap_or(false) {
+            true => Some(
+                matches
+                    .get_one::<u8>("first-char")
+                    .copied()
+                    .or(defaults.first_char)
+                    .unwrap_or(b' '),
+            ),
+            false => None,
+        },
+        trim_blank: matches.get_flag("trim-blank") || defaults.trim_blank.unwrap_or(false),
+        shared_tileset: matches.get_flag("shared-tileset")
+            || defaults.shared_tileset.unwrap_or(false),
+        split_banks: value("split-banks")
+            .map(|banks| banks.parse().unwrap())
+            .or(defaults.split_banks),
+        delta_frames: matches.get_flag("delta-frames") || defaults.delta_frames.unwrap_or(false),
+        metasprites: matches.get_flag("metasprites") || defaults.metasprites.unwrap_or(false),
+        error_images: matches.get_flag("error-image"),
+        alpha_threshold: matches
+            .get_one::<u8>("alpha-threshold")
+            .copied()
+            .or(defaults.alpha_threshold)
+            .unwrap_or(1),
+        rect: matches.get_one::<Rect>("rect").copied(),
+        grid: grid(matches, defaults.grid, defaults.margin, defaults.spacing),
+        frame_size: matches
+            .get_one::<Grid>("frames")
+            .copied()
+            .or(defaults.frames),
+        pad: matches
+            .get_one::<u8>("pad")
+            .copied()
+            .or(defaults.pad.map(u8::from)),
+        truncate: matches.get_flag("truncate") || defaults.truncate.unwrap_or(false),
+        palette,
+        color_map: matches
+            .get_one::<[Option<Rgb>; GB_MAX_COLOR_COUNT]>("map")
+            .copied()
+            .or(defaults.color_map)
+            .unwrap_or_default(),
+        luminance: value("luminance")
+            .map(|luminance| luminance.parse().unwrap())
+            .or(defaults.luminance)
+            .unwrap_or_default(),
+        gamma: value("gamma")
+            .map(|gamma| gamma.parse().unwrap())
+            .or(defaults.gamma)
+            .unwrap_or_default(),
+        color_tolerance: matches
+            .get_one::<u8>("color-tolerance")
+            .copied()
+            .or(defaults.color_tolerance)
+            .unwrap_or(DEFAULT_COLOR_TOLERANCE),
+        quantize: matches.get_flag("quantize") || defaults.quantize.unwrap_or(false),
+        auto_contrast: matches.get_flag("auto-contrast") || defaults.auto_contrast.unwrap_or(false),
+        posterize: matches
+            .get_one::<u8>("posterize")
+            .copied()
+            .or(defaults.posterize),
+        dither: value("dither")
+            .map(|dither| dither.parse().unwrap())
+            .or(defaults.dither)
+            .unwrap_or_default(),
+        layer: value("layer"),
+        tag: value("tag"),
+        append: matches.get_flag("append"),
+        force: matches.get_flag("force"),
+        prefix: value("prefix").or(defaults.prefix).unwrap_or_default(),
+        suffix: value("suffix").or(defaults.suffix).unwrap_or_default(),
+        diagnostics,
+        progress: match value("progress").as_deref() {
+            Some("bar") => Some(ProgressStyle::Bar),
+            Some("plain") => Some(ProgressStyle::Plain),
+            Some("none") => Some(ProgressStyle::None),
+            _ => None,
+        },
+    })
+}
+
+/// The images named by a subcommand's positional arguments, or every input
+/// of a project config file, with patterns expanded.
+fn image_inputs(matches: &ArgMatches) -> Result<Vec<String>, Error> {
+    let patterns = match matches.try_get_one::<String>("config").ok().flatten() {
+        Some(config) => Config::load(config)?
+            .jobs
+            .into_iter()
+            .flat_map(|job| job.input)
+            .collect(),
+        None => matches
+            .get_many::<String>("input")
+            .unwrap()
+            .cloned()
+            .collect::<Vec<_>>(),
+    };
+    expand_patterns(&patterns)
+}
+
+/// Whether `input` is a TexturePacker JSON atlas, converted to a tile
+/// block per sprite.
+fn is_atlas(input: &str) -> bool {
+    input.to_lowercase().ends_with(".json")
+}
+
+/// The other files converting `input` reads besides it, like a GBMB map's
+/// tile file, an LDtk project's tilesets or an atlas's sheet.
+fn input_references(input: &str, args: &CommandArguments) -> Result<Vec<String>, ImageReadError> {
+    if input == "-" {
+        return Ok(Vec::new());
     }
-    color_numbers
+    let files = if is_atlas(input) {
+        vec![Atlas::parse(&fs::read(input)?)?.sheet_path(input)]
+    } else {
+        let sources = sources(input, args.layer.as_deref(), args.tag.as_deref());
+        referenced_files(&sources, input, File::open(input)?)?
+    };
+    Ok(files
+        .iter()
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Decode an input image, reading stdin for `-`. Atlases decode to an
+/// image per sprite.
+fn decode_input(
+    input: &str,
+    sources: &SourceRegistry,
+    options: &DecodeOptions,
+) -> Result<Vec<DecodedImage>, Error> {
+    let (path, decoded) = if input == "-" {
+        (
+            STDIN_NAME,
+            decode_reader(sources, options, STDIN_NAME, io::stdin().lock())
+                .map(|image| vec![image]),
+        )
+    } else if is_atlas(input) {
+        let sprites = decode_atlas(sources, options, input);
+        (
+            input,
+            sprites.map(|sprites| sprites.into_iter().map(|(_, image)| image).collect()),
+        )
+    } else {
+        (
+            input,
+            decode_image_with(sources, options, input).map(|image| vec![image]),
+        )
+    };
+    decoded.map_err(|source| Error::Read {
+        path: path.to_string(),
+        source,
+    })
 }
 
-fn read_image_data(info: &png::OutputInfo, image_buf: Vec<u8>) -> Result<Vec<RGB>, ImageReadError> {
-    log::debug!("PNG info: {:?}", info);
-    let mut image_data = Vec::new();
-    match info.color_type {
-        png::ColorType::RGB => {
-            for color in image_buf.chunks(3) {
-                let rgb = RGB {
-                    r: color[0],
-                    g: color[1],
-                    b: color[2],
-                };
-                log::debug!("Original RGB is: {:?}", rgb);
-                image_data.push(rgb.round());
+/// Run `f` on every input image, reporting failures and warnings. Returns
+/// the exit status.
+fn for_each_image(
+    matches: &ArgMatches,
+    defaults: &Defaults,
+    diagnostics: DiagnosticsFormat,
+    mut f: impl FnMut(DecodedImage),
+) -> i32 {
+    let (inputs, options) = match image_inputs(matches).and_then(|inputs| {
+        decode_options(matches, defaults, &inputs).map(|options| (inputs, options))
+    }) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            report_error(diagnostics, &err);
+            return exit_status(&err);
+        }
+    };
+    let value = |id: &str| matches.get_one::<String>(id).map(String::as_str);
+    let mut status = exit_code::SUCCESS;
+    for input in &inputs {
+        let sources = sources(input, value("layer"), value("tag"));
+        match decode_input(input, &sources, &options) {
+            Ok(decoded_images) => {
+                for decoded_image in decoded_images {
+                    for warning in &decoded_image.warnings {
+                        report_warning(diagnostics, warning);
+                    }
+                    f(decoded_image);
+                }
+            }
+            Err(err) => {
+                report_error(diagnostics, &err);
+                if status == exit_code::SUCCESS {
+                    status = exit_status(&err);
+                }
             }
         }
-        png::ColorType::RGBA => {
-            for color in image_buf.chunks(4) {
-                let rgb = RGB {
-                    r: color[0],
-                    g: color[1],
-                    b: color[2],
-                };
-                log::debug!("Original RGB is: {:?}", rgb);
-                image_data.push(rgb.round());
+    }
+    status
+}
+
+/// The grid of cells `--grid`, `--margin` and `--spacing` give, each
+/// falling back to its default from the config file.
+fn grid(
+    matches: &ArgMatches,
+    grid: Option<Grid>,
+    margin: Option<u32>,
+    spacing: Option<u32>,
+) -> Option<Grid> {
+    let grid = matches.get_one::<Grid>("grid").copied().or(grid)?;
+    let value = |name: &str, default: Option<u32>| {
+        matches
+            .get_one::<u32>(name)
+            .copied()
+            .or(default)
+            .unwrap_or(0)
+    };
+    Some(Grid {
+        margin: value("margin", margin),
+        spacing: value("spacing", spacing),
+        ..grid
+    })
+}
+
+/// The options for decoding `inputs` given to a subcommand that decodes
+/// images, with `defaults` filling in the ones that aren't given.
+fn decode_options(
+    matches: &ArgMatches,
+    defaults: &Defaults,
+    inputs: &[String],
+) -> Result<DecodeOptions, Error> {
+    let flag = |id: &str, default: Option<bool>| matches.get_flag(id) || default.unwrap_or(false);
+    let mut options = DecodeOptions::default();
+    if let Some(color) = matches
+        .get_one::<ColorNumber>("transparent-color")
+        .copied()
+        .or(defaults.transparent_color)
+    {
+        options.transparent_color = color.into();
+    }
+    options.sprite = flag("sprite", defaults.sprite);
+    options.cgb = flag("cgb", defaults.cgb);
+    options.sgb = flag("sgb", defaults.sgb);
+    if let Some(alpha_threshold) = matches
+        .get_one::<u8>("alpha-threshold")
+        .copied()
+        .or(defaults.alpha_threshold)
+    {
+        options.alpha_threshold = alpha_threshold;
+    }
+    options.rect = matches.get_one::<Rect>("rect").copied();
+    options.grid = grid(matches, defaults.grid, defaults.margin, defaults.spacing);
+    options.frame_size = matches
+        .get_one::<Grid>("frames")
+        .copied()
+        .or(defaults.frames);
+    options.pad = matches
+        .get_one::<u8>("pad")
+        .copied()
+        .or(defaults.pad.map(u8::from));
+    options.truncate = flag("truncate", defaults.truncate);
+    options.palette = palette(matches).or(defaults.palette);
+    if let Some(color_map) = matches
+        .get_one::<[Option<Rgb>; GB_MAX_COLOR_COUNT]>("map")
+        .copied()
+        .or(defaults.color_map)
+    {
+        options.color_map = color_map;
+    }
+    options.strict_palette = flag("strict-palette", defaults.strict_palette);
+    if options.strict_palette && options.palette.is_none() {
+        return Err(strict_palette_error(
+            inputs.first().map_or("", String::as_str),
+        ));
+    }
+    if let Some(luminance) = matches
+        .get_one::<String>("luminance")
+        .map(|luminance| luminance.parse().unwrap())
+        .or(defaults.luminance)
+    {
+        options.luminance = luminance;
+    }
+    if let Some(gamma) = matches
+        .get_one::<String>("gamma")
+        .map(|gamma| gamma.parse().unwrap())
+        .or(defaults.gamma)
+    {
+        options.gamma = gamma;
+    }
+    if let Some(tolerance) = matches
+        .get_one::<u8>("color-tolerance")
+        .copied()
+        .or(defaults.color_tolerance)
+    {
+        options.color_tolerance = tolerance;
+    }
+    options.quantize = flag("quantize", defaults.quantize);
+    options.auto_contrast = flag("auto-contrast", defaults.auto_contrast);
+    options.posterize = matches
+        .get_one::<u8>("posterize")
+        .copied()
+        .or(defaults.posterize);
+    options.dmg_compatible = flag("dmg-compatible", defaults.dmg_compatible);
+    if let Some(dither) = matches
+        .get_one::<String>("dither")
+        .map(|dither| dither.parse().unwrap())
+        .or(defaults.dither)
+    {
+        options.dither = dither;
+    }
+    Ok(options)
+}
+
+fn check(matches: &ArgMatches, defaults: &Defaults, diagnostics: DiagnosticsFormat) -> i32 {
+    for_each_image(matches, defaults, diagnostics, |decoded_image| {
+        let encoded_tile = encode_tile(decoded_image);
+        log::info!(
+            "{}: ok, {} tile(s)",
+            encoded_tile.input_filename,
+            encoded_tile.tile_data.len() / TILE_BYTES
+        );
+    })
+}
+
+fn inspect(matches: &ArgMatches, defaults: &Defaults, diagnostics: DiagnosticsFormat) -> i32 {
+    for_each_image(matches, defaults, diagnostics, |decoded_image| {
+        let (columns, rows) = (decoded_image.width / 8, decoded_image.height / 8);
+        println!("{}", decoded_image.input_filename);
+        println!(
+            "  size:   {}x{} pixels, {}x{} tiles",
+            decoded_image.width, decoded_image.height, columns, rows
+        );
+        println!(
+            "  tiles:  {} ({} bytes)",
+            columns * rows,
+            columns * rows * 16
+        );
+        if decoded_image.frames > 1 {
+            println!(
+                "  frames: {} ({} tiles each)",
+                decoded_image.frames,
+                columns * rows / decoded_image.frames
+            );
+        }
+        for tilemap in &decoded_image.tilemaps {
+            match &tilemap.name {
+                Some(name) => println!(
+                    "  map:    {}x{} tiles ({})",
+                    tilemap.width, tilemap.height, name
+                ),
+                None => println!("  map:    {}x{} tiles", tilemap.width, tilemap.height),
             }
         }
-        png::ColorType::Grayscale => {
-            for color in image_buf {
-                let rgb = RGB {
-                    r: color,
-                    g: color,
-                    b: color,
-                };
-                log::debug!("Original RGB is: {:?}", rgb);
-                image_data.push(rgb.round());
+        // Images sharing out Game Boy Color palettes list each palette's
+        // colors, with transparency after them.
+        let palettes = match &decoded_image.cgb_palettes {
+            Some(cgb_palettes) => cgb_palettes.color_numbers.iter().collect(),
+            None => vec![&decoded_image.color_numbers],
+        };
+        if palettes.len() > 1 {
+            println!("  palettes: {}", palettes.len());
+        }
+        for (palette, color_numbers) in palettes.iter().enumerate() {
+            let mut colors: Vec<_> = color_numbers
+                .iter()
+                .map(|(rgb, color)| (*color, rgb.to_string()))
+                .chain(
+                    decoded_image
+                        .transparent_color
+                        .map(|color| (color, "transparent".to_string())),
+                )
+                .collect();
+            colors.sort();
+            match palettes.len() {
+                1 => println!("  colors: {}", colors.len()),
+                _ => println!("  palette {}: {} colors", palette, colors.len()),
             }
+            for (color, description) in colors {
+                println!("    {}  {}", color, description);
+            }
+        }
+    })
+}
+
+/// Print each color of the inputs as they're read, most used first, with
+/// its pixel count and the color number it takes. Images that can't be
+/// converted, such as ones with too many colors, still list their colors,
+/// with `-` for the color numbers and the error reported after.
+fn palette_report(
+    matches: &ArgMatches,
+    defaults: &Defaults,
+    diagnostics: DiagnosticsFormat,
+) -> i32 {
+    let (inputs, options) = match image_inputs(matches).and_then(|inputs| {
+        decode_options(matches, defaults, &inputs).map(|options| (inputs, options))
+    }) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            report_error(diagnostics, &err);
+            return exit_status(&err);
         }
-        png::ColorType::GrayscaleAlpha => {
-            for color in image_buf.chunks(2) {
-                let rgb = RGB {
-                    r: color[0],
-                    g: color[0],
-                    b: color[0],
-                };
-                log::debug!("Original RGB is: {:?}", rgb);
-                image_data.push(rgb.round());
+    };
+    let value = |id: &str| matches.get_one::<String>(id).map(String::as_str);
+    let mut status = exit_code::SUCCESS;
+    for input in &inputs {
+        let sources = sources(input, value("layer"), value("tag"));
+        let (path, source_image) = if input == "-" {
+            (
+                STDIN_NAME,
+                decode_source(&sources, STDIN_NAME, io::stdin().lock()),
+            )
+        } else {
+            (
+                input.as_str(),
+                File::open(input)
+                    .map_err(ImageReadError::from)
+                    .and_then(|file| decode_source(&sources, input, file)),
+            )
+        };
+        let decoded = source_image.and_then(|source_image| {
+            let counts = count_colors(&source_image, &options);
+            let decoded_image = DecodedImage::from_source(path, source_image, &options);
+            print_color_counts(path, &counts, decoded_image.as_ref().ok());
+            decoded_image
+        });
+        if let Err(source) = decoded {
+            let err = Error::Read {
+                path: path.to_string(),
+                source,
+            };
+            report_error(diagnostics, &err);
+            if status == exit_code::SUCCESS {
+                status = exit_status(&err);
             }
         }
-        color_type => {
-            return Err(ImageReadError::UnsupportedColorType(color_type));
+    }
+    status
+}
+
+/// Print `counts` for the image read from `path`, with the color numbers
+/// `decoded_image` gives each color, or `-` if it couldn't be decoded.
+fn print_color_counts(path: &str, counts: &[ColorCount], decoded_image: Option<&DecodedImage>) {
+    println!("{}", path);
+    println!("  colors: {}", counts.len());
+    let width = counts
+        .iter()
+        .map(|count| count.pixels.to_string().len())
+        .max()
+        .unwrap_or(0);
+    for count in counts {
+        let description = count
+            .color
+            .map_or_else(|| "transparent".to_string(), |rgb| rgb.to_string());
+        let color_numbers = decoded_image.map_or_else(
+            || "-".to_string(),
+            |decoded_image| color_numbers(decoded_image, count.color),
+        );
+        println!(
+            "    {:<11}  {:>width$} pixels  {}",
+            description,
+            count.pixels,
+            color_numbers,
+            width = width
+        );
+    }
+}
+
+/// The color number `color` takes in `decoded_image`, `None` being
+/// transparency. A color in several of the palettes an image shares out
+/// lists its number in each.
+fn color_numbers(decoded_image: &DecodedImage, color: Option<Rgb>) -> String {
+    let Some(rgb) = color else {
+        return decoded_image
+            .transparent_color
+            .map_or_else(|| "-".to_string(), |color| format!("color {}", color));
+    };
+    match &decoded_image.cgb_palettes {
+        Some(cgb_palettes) if cgb_palettes.color_numbers.len() > 1 => {
+            let numbers: Vec<String> = cgb_palettes
+                .color_numbers
+                .iter()
+                .enumerate()
+                .filter_map(|(palette, color_numbers)| {
+                    let color = color_numbers.get(&rgb)?;
+                    Some(format!("palette {} color {}", palette, color))
+                })
+                .collect();
+            numbers.join(", ")
         }
+        _ => decoded_image
+            .color_numbers
+            .get(&rgb)
+            .map_or_else(|| "-".to_string(), |color| format!("color {}", color)),
     }
+}
+
+/// The classic DMG screen's shades, lightest first, indexed by color number,
+/// unless a palette preset is given.
+const PREVIEW_COLORS: [(u8, u8, u8); GB_MAX_COLOR_COUNT] =
+    [(155, 188, 15), (139, 172, 15), (48, 98, 48), (15, 56, 15)];
 
-    Ok(image_data)
+/// Characters standing in for each color number, lightest first, when
+/// colors aren't available.
+const PREVIEW_CHARACTERS: [char; GB_MAX_COLOR_COUNT] = ['.', '+', '*', '#'];
+
+fn preview(matches: &ArgMatches, defaults: &Defaults, diagnostics: DiagnosticsFormat) -> i32 {
+    let color = !matches.get_flag("plain")
+        && io::stdout().is_terminal()
+        && env::var_os("NO_COLOR").is_none();
+    // The config's preset only draws the preview if the command line
+    // doesn't give a palette of its own.
+    let preview_colors = palette_preset(matches)
+        .or_else(|| {
+            defaults
+                .palette_preset
+                .filter(|_| palette(matches).is_none())
+        })
+        .map_or(PREVIEW_COLORS, |preset| {
+            preset.colors().map(|color| (color.r, color.g, color.b))
+        });
+    for_each_image(matches, defaults, diagnostics, |decoded_image| {
+        let columns = (decoded_image.width / 8) as usize;
+        let encoded_tile = encode_tile(decoded_image);
+        // Decode the encoded tiles rather than drawing the image, so the
+        // preview shows exactly what will end up in VRAM.
+        let tile_rows = encoded_tile.tile_data.len() / TILE_BYTES / columns.max(1);
+        let mut pixel_rows = Vec::with_capacity(tile_rows * 8);
+        for tile_row in 0..tile_rows {
+            for y in 0..8 {
+                let mut pixels = Vec::with_capacity(columns * 8);
+                for column in 0..columns {
+                    let offset = (tile_row * columns + column) * TILE_BYTES + y * 2;
+                    let data = &encoded_tile.tile_data[offset..offset + 2];
+                    pixels.extend_from_slice(&unpack_tile_row(data[0], data[1]));
+                }
+                pixel_rows.push(pixels);
+            }
+        }
+
+        println!("{}", encoded_tile.input_filename);
+        if color {
+            // Each character cell draws two pixel rows with a half block.
+            for rows in pixel_rows.chunks(2) {
+                let mut line = String::new();
+                for (top, bottom) in rows[0].iter().zip(&rows[1]) {
+                    let (top, bottom) = (
+                        preview_colors[*top as usize],
+                        preview_colors[*bottom as usize],
+                    );
+                    line.push_str(&format!(
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m\u{2580}",
+                        top.0, top.1, top.2, bottom.0, bottom.1, bottom.2
+                    ));
+                }
+                println!("{}\x1b[0m", line);
+            }
+        } else {
+            for row in &pixel_rows {
+                let line: String = row
+                    .iter()
+                    .map(|color| PREVIEW_CHARACTERS[*color as usize])
+                    .collect();
+                println!("{}", line);
+            }
+        }
+    })
 }
 
-fn decode_image(image_input: &str) -> Result<DecodedImage, ImageReadError> {
-    let file = File::open(image_input)?;
-    let mut unique_colors = BTreeSet::new();
-    let decoder = Decoder::new(file);
-    let (info, mut png_reader) = decoder.read_info()?;
+/// The color numbers' colors when decoding without a palette, white to
+/// black.
+const DECODE_COLORS: [Rgb; GB_MAX_COLOR_COUNT] = [
+    Rgb {
+        r: 0xff,
+        g: 0xff,
+        b: 0xff,
+    },
+    Rgb {
+        r: 0xaa,
+        g: 0xaa,
+        b: 0xaa,
+    },
+    Rgb {
+        r: 0x55,
+        g: 0x55,
+        b: 0x55,
+    },
+    Rgb { r: 0, g: 0, b: 0 },
+];
 
-    let mut image_buf = vec![0; info.buffer_size()];
-    png_reader.next_frame(&mut image_buf)?;
-    let image_data = read_image_data(&info, image_buf)?;
+/// The number of tiles drawn in each row of a decoded image by default.
+const DECODE_COLUMNS: usize = 16;
 
-    log::debug!("Image data size is: {}", image_data.len());
+/// Draw the tiles in a generated file back into an indexed PNG, with the
+/// palette in color number order so converting it again gives the same
+/// tiles.
+fn decode_generated(matches: &ArgMatches) -> Result<(), Error> {
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<String>("output").unwrap();
+    let input_error = |message: String| Error::Input {
+        path: input.clone(),
+        message,
+    };
+    let contents = fs::read(input).map_err(|source| Error::Read {
+        path: input.clone(),
+        source: ImageReadError::Io(source),
+    })?;
+    let extension = Path::new(input).extension().and_then(|ext| ext.to_str());
+    let bpp = matches.get_one::<u8>("bpp").copied();
+    // Each run of tile data with the bits per pixel it's in.
+    let declared_data: Vec<(Vec<u8>, u8)> = match extension {
+        Some("1bpp") => vec![(contents, bpp.unwrap_or(1))],
+        Some("2bpp") | Some("bin") => vec![(contents, bpp.unwrap_or(2))],
+        _ => {
+            let declared = parse_generated(&String::from_utf8_lossy(&contents));
+            let symbol = matches.get_one::<String>("symbol");
+            let declared_data: Vec<(Vec<u8>, u8)> = declared
+                .iter()
+                .filter(|tiles| symbol.is_none_or(|symbol| tiles.is_symbol(symbol)))
+                .map(|tiles| (tiles.tile_data.clone(), bpp.unwrap_or(tiles.bpp)))
+                .collect();
+            match symbol {
+                Some(symbol) if declared_data.is_empty() => {
+                    let names: Vec<&str> =
+                        declared.iter().map(|tiles| tiles.name.as_str()).collect();
+                    return Err(input_error(format!(
+                        "no tiles are declared as '{}'. Declared: {}",
+                        symbol,
+                        names.join(", ")
+                    )));
+                }
+                _ => declared_data,
+            }
+        }
+    };
+    let mut tile_data = Vec::new();
+    for (data, bpp) in &declared_data {
+        let tile_bytes = TILE_BYTES * *bpp as usize / 2;
+        if data.len() % tile_bytes != 0 {
+            return Err(input_error(format!(
+                "found {} bytes of {}bpp tile data, which isn't a whole number of {} byte tiles",
+                data.len(),
+                bpp,
+                tile_bytes
+            )));
+        }
+        if *bpp == 1 {
+            // 1bpp rows draw in color 3 wherever a bit is set.
+            tile_data.extend(data.iter().flat_map(|byte| [*byte, *byte]));
+        } else {
+            tile_data.extend_from_slice(data);
+        }
+    }
+    if tile_data.is_empty() {
+        return Err(input_error("found no tile data".to_string()));
+    }
 
-    for (i, color) in image_data.iter().enumerate() {
-        unique_colors.insert(*color);
-        if unique_colors.len() > GB_MAX_COLOR_COUNT {
-            log::debug!("Unique colors are: {:?}, stopped at: {}", unique_colors, i,);
-            return Err(ImageReadError::TooManyColors);
+    let colors = matches
+        .get_one::<[Rgb; GB_MAX_COLOR_COUNT]>("palette")
+        .copied()
+        .or_else(|| palette_preset(matches).map(PalettePreset::colors))
+        .unwrap_or(DECODE_COLORS);
+    let tile_count = tile_data.len() / TILE_BYTES;
+    let columns = matches
+        .get_one::<u32>("columns")
+        .map_or(DECODE_COLUMNS, |columns| *columns as usize)
+        .min(tile_count);
+    let rows = tile_count.div_ceil(columns);
+    let width = columns * 8;
+    // Cells past the last tile are left in color 0.
+    let mut pixels = vec![0u8; width * rows * 8];
+    for (tile, data) in tile_data.chunks(TILE_BYTES).enumerate() {
+        let (column, row) = (tile % columns, tile / columns);
+        for (y, bytes) in data.chunks(2).enumerate() {
+            let offset = (row * 8 + y) * width + column * 8;
+            pixels[offset..offset + 8].copy_from_slice(&unpack_tile_row(bytes[0], bytes[1]));
         }
     }
-    let color_numbers = rgbs_to_color_number(&unique_colors);
-    log::debug!("Color numbers are: {:?}", color_numbers);
 
-    let decoded = DecodedImage {
-        input_filename: image_input.to_string(),
-        image_data,
-        info,
-        color_numbers,
+    let write_error = |err: png::EncodingError| Error::Write {
+        path: output.clone(),
+        source: io::Error::other(err),
     };
-    Ok(decoded)
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, width as u32, (rows * 8) as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(
+        colors
+            .iter()
+            .flat_map(|color| vec![color.r, color.g, color.b])
+            .collect::<Vec<u8>>(),
+    );
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(write_error)?;
+    write_output(output, &png_data).map_err(|source| Error::Write {
+        path: output.clone(),
+        source,
+    })?;
+    log::info!(
+        "Decoded {} tile(s) from {} into {}",
+        tile_count,
+        input,
+        output
+    );
+    Ok(())
 }
 
-const PIXELS_PER_LINE: u8 = 8;
+/// The error for `--strict-palette` given without a palette to check
+/// `input` against.
+fn strict_palette_error(input: &str) -> Error {
+    Error::Input {
+        path: input.to_string(),
+        message: "--strict-palette needs a palette, give one with --palette, --palette-file or --palette-preset"
+            .to_string(),
+    }
+}
 
-fn encode_tile(decoded_image: DecodedImage) -> EncodedTile {
-    let rows = decoded_image.info.height / 8;
-    let columns = decoded_image.info.width / 8;
-    log::info!(
-        "File: {}, Tile rows: {}, columns: {}, unique colors: {}",
-        decoded_image.input_filename,
-        rows,
-        columns,
-        decoded_image.color_numbers.len()
+/// The exit status for a run that failed with `err`.
+fn exit_status(err: &Error) -> i32 {
+    match err {
+        Error::Read { source, .. } => match source {
+            ImageReadError::Io(_) => exit_code::IO,
+            ImageReadError::Png(_)
+            | ImageReadError::UnsupportedColorType(_)
+            | ImageReadError::Invalid { .. }
+            | ImageReadError::UnknownFormat => exit_code::DECODE,
+            ImageReadError::TooManyColors { .. }
+            | ImageReadError::OffPalette { .. }
+            | ImageReadError::TooManyPalettes { .. }
+            | ImageReadError::PaletteMaskFull { .. }
+            | ImageReadError::PadColor { .. }
+            | ImageReadError::OneBppColors { .. } => exit_code::PALETTE,
+            ImageReadError::ImageTooSmall { .. }
+            | ImageReadError::FrameHeight { .. }
+            | ImageReadError::NotTileAligned { .. }
+            | ImageReadError::NoGridCells { .. }
+            | ImageReadError::RectOutsideImage { .. }
+            | ImageReadError::FontPastLastChar { .. }
+            | ImageReadError::TileNumberPastLast { .. } => exit_code::DIMENSIONS,
+        },
+        Error::Write { .. } => exit_code::IO,
+        Error::Config { .. } | Error::Input { .. } => exit_code::USAGE,
+    }
+}
+
+fn log_level(matches: &ArgMatches) -> LevelFilter {
+    if let Some(level) = matches.get_one::<String>("log-level") {
+        return level.parse().unwrap();
+    }
+    let verbosity = matches.get_count("verbose") + matches.get_count("debug");
+    match (verbosity, matches.get_count("quiet")) {
+        (0, 0) => LevelFilter::Info,
+        (0, 1) => LevelFilter::Warn,
+        (0, _) => LevelFilter::Error,
+        (1, _) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// The jobs described by the command line, either directly or through a
+/// config file or input directory.
+fn load_jobs(
+    matches: &ArgMatches,
+    output_type: Option<String>,
+    formats: &FormatRegistry,
+) -> Result<Vec<Job>, Error> {
+    if let Some(config) = matches.get_one::<String>("config") {
+        let mut jobs = Config::load(config)?.jobs;
+        for job in &mut jobs {
+            if job.output_type.is_none() {
+                job.output_type = output_type.clone();
+            }
+        }
+        Ok(jobs)
+    } else if let Some(input_dir) = matches.get_one::<String>("input-dir") {
+        let output_dir = matches.get_one::<String>("output-dir").unwrap();
+        directory_jobs(input_dir, output_dir, output_type, formats)
+    } else {
+        Ok(vec![Job {
+            input: matches
+                .get_many::<String>("input")
+                .unwrap()
+                .cloned()
+                .collect(),
+            output: matches.get_one::<String>("output").unwrap().clone(),
+            output_type,
+            name: matches.get_one::<String>("name").cloned(),
+            regions: matches.get_one::<String>("regions").cloned(),
+            palette_mask: matches.get_one::<String>("palette-mask").cloned(),
+        }])
+    }
+}
+
+/// The given config file, or the nearest `gbtile.toml` when none is given.
+fn config_path(config: Option<&str>) -> Option<String> {
+    match config {
+        Some(config) => Some(config.to_string()),
+        None => env::current_dir()
+            .ok()
+            .and_then(|dir| Config::discover(&dir))
+            .map(|path| path.to_string_lossy().into_owned()),
+    }
+}
+
+/// The config file a subcommand's `--config` gives, or the nearest
+/// `gbtile.toml`, and its `[defaults]`, if there is one.
+fn load_defaults(matches: &ArgMatches) -> Result<(Option<String>, Defaults), Error> {
+    let config = config_path(
+        matches
+            .try_get_one::<String>("config")
+            .ok()
+            .flatten()
+            .map(String::as_str),
     );
-    let mut tile_data = Vec::new();
-    for row in 0..rows {
-        for column in 0..columns {
-            for tile_row in 0..8 {
-                let mut low_byte = 0;
-                let mut high_byte = 0;
-                for tile_column in 0..8 {
-                    let pixel_index = (column * 8 + tile_column)
-                        + ((decoded_image.info.width * tile_row)
-                            + (row * 8 * decoded_image.info.width));
-                    let pixel = decoded_image.image_data[pixel_index as usize];
-                    let color = decoded_image.lookup_color(&pixel);
-                    low_byte |= (color & 0x01) << (PIXELS_PER_LINE - tile_column as u8 - 1);
-                    high_byte |= ((color >> 1) & 0x01) << (PIXELS_PER_LINE - tile_column as u8 - 1);
-                }
-                tile_data.push(low_byte);
-                tile_data.push(high_byte);
+    let defaults = match &config {
+        Some(path) => {
+            log::debug!("Using defaults from {}", path);
+            Config::load(path)?.defaults
+        }
+        None => Defaults::default(),
+    };
+    Ok((config, defaults))
+}
+
+/// Make or Ninja rules with a build statement per image under the input
+/// directory, and a `gbtile` target depending on all of them.
+fn build_rules(
+    matches: &ArgMatches,
+    defaults: &Defaults,
+    formats: &FormatRegistry,
+) -> Result<String, Error> {
+    let output_type = matches
+        .get_one::<String>("output-type")
+        .or(defaults.output_type.as_ref())
+        .cloned();
+    let jobs = directory_jobs(
+        matches.get_one::<String>("input-dir").unwrap(),
+        matches.get_one::<String>("output-dir").unwrap(),
+        output_type.clone(),
+        formats,
+    )?;
+    let type_flag = match &output_type {
+        Some(output_type) => format!(" -t {}", output_type),
+        None => String::new(),
+    };
+
+    let mut rules = String::new();
+    if matches.get_one::<String>("format").map(String::as_str) == Some("ninja") {
+        let escape = |path: &str| {
+            path.replace('$', "$$")
+                .replace(' ', "$ ")
+                .replace(':', "$:")
+        };
+        rules.push_str("rule gbtile\n");
+        rules.push_str(&format!(
+            "  command = gbtile convert -i \"$in\" -o \"$out\"{} -q\n",
+            type_flag
+        ));
+        rules.push_str("  description = gbtile $in\n");
+        for job in &jobs {
+            rules.push_str(&format!(
+                "\nbuild {}: gbtile {}\n",
+                escape(&job.output),
+                escape(&job.input[0])
+            ));
+        }
+        let outputs: Vec<String> = jobs.iter().map(|job| escape(&job.output)).collect();
+        rules.push_str(&format!("\nbuild gbtile: phony {}\n", outputs.join(" ")));
+    } else {
+        let escape = |path: &str| path.replace('$', "$$").replace(' ', "\\ ");
+        let outputs: Vec<String> = jobs.iter().map(|job| escape(&job.output)).collect();
+        rules.push_str("GBTILE ?= gbtile\n\n");
+        rules.push_str(&format!(".PHONY: gbtile\ngbtile: {}\n", outputs.join(" ")));
+        for job in &jobs {
+            rules.push_str(&format!(
+                "\n{}: {}\n\t$(GBTILE) convert -i \"$<\" -o \"$@\"{} -q\n",
+                escape(&job.output),
+                escape(&job.input[0]),
+                type_flag
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+/// Run every job, reporting failures. Returns the exit status.
+fn run_jobs(args: &CommandArguments, formats: &FormatRegistry) -> i32 {
+    let mut progress = Progress::new(args);
+    let mut cache = args.cache.as_ref().map(Cache::load);
+    let mut report = Vec::new();
+    let mut status = exit_code::SUCCESS;
+    for job in &args.jobs {
+        progress.start(job);
+        let result = expand_inputs(job).and_then(|job| {
+            let report = args.report.as_ref().map(|_| &mut report);
+            run(args, &job, formats, cache.as_mut(), report)
+        });
+        let failed_status = match &result {
+            Ok(Budget::Over) if args.fail_on_budget => Some(exit_code::BUDGET),
+            Ok(_) => None,
+            Err(err) => {
+                report_error(args.diagnostics, err);
+                Some(exit_status(err))
             }
+        };
+        if let (Some(failed_status), exit_code::SUCCESS) = (failed_status, status) {
+            status = failed_status;
+        }
+        progress.finish(job, failed_status.is_none());
+    }
+    progress.summary();
+    if let (Some(cache), false) = (&cache, args.dry_run) {
+        if let Err(source) = cache.save() {
+            report_error(
+                args.diagnostics,
+                &Error::Write {
+                    path: args.cache.clone().unwrap(),
+                    source,
+                },
+            );
+            return exit_code::IO;
+        }
+    }
+    if let Some(depfile) = &args.depfile {
+        if !args.dry_run {
+            if let Err(source) = write_output(depfile, &dependencies(args).into_bytes()) {
+                report_error(
+                    args.diagnostics,
+                    &Error::Write {
+                        path: depfile.clone(),
+                        source,
+                    },
+                );
+                return exit_code::IO;
+            }
+        }
+    }
+    if let (Some(path), false) = (&args.report, args.dry_run) {
+        let contents = serde_json::to_string_pretty(&json!({ "outputs": report })).unwrap() + "\n";
+        if let Err(source) = write_output(path, contents.as_bytes()) {
+            report_error(
+                args.diagnostics,
+                &Error::Write {
+                    path: path.clone(),
+                    source,
+                },
+            );
+            return exit_code::IO;
         }
     }
+    status
+}
+
+/// The --report entry for an output generated from `encoded_tiles`.
+fn report_entry(
+    job: &Job,
+    output_format: &dyn OutputFormat,
+    rendered: &[u8],
+    encoded_tiles: &[EncodedTile],
+) -> Value {
+    let images: Vec<Value> = encoded_tiles
+        .iter()
+        .map(|encoded_tile| {
+            json!({
+                "input": encoded_tile.input_filename,
+                "symbol": variable_name(encoded_tile),
+                "width": encoded_tile.width,
+                "height": encoded_tile.height,
+                "tiles": encoded_tile.tile_data.len() / TILE_BYTES,
+                "tile_bytes": encoded_tile.tile_data.len(),
+                "colors": encoded_tile.colors.len(),
+                "frames": encoded_tile.frames,
+            })
+        })
+        .collect();
+    json!({
+        "output": job.output,
+        "type": output_format.name(),
+        "bytes": rendered.len(),
+        "cached": false,
+        "images": images,
+    })
+}
+
+/// The --report entry for an output skipped as up to date by the cache.
+/// Its images weren't read, so only the output itself is described.
+fn cached_report_entry(job: &Job, output_format: &dyn OutputFormat) -> Value {
+    let bytes = fs::metadata(&job.output)
+        .map(|metadata| metadata.len())
+        .ok();
+    json!({
+        "output": job.output,
+        "type": output_format.name(),
+        "bytes": bytes,
+        "cached": true,
+    })
+}
+
+/// A gcc style dependency file, with a rule per output listing the images,
+/// config file and palette file it was generated from. Like `gcc -MP`,
+/// every input also gets an empty rule so deleting one doesn't break the
+/// build.
+fn dependencies(args: &CommandArguments) -> String {
+    let escape = |path: &str| path.replace(' ', "\\ ");
+    let mut rules = String::new();
+    let mut prerequisites = BTreeSet::new();
+    for job in &args.jobs {
+        if job.output == "-" {
+            continue;
+        }
+        let job = expand_inputs(job).unwrap_or_else(|_| job.clone());
+        let references: Vec<String> = job
+            .input
+            .iter()
+            .flat_map(|input| input_references(input, args).unwrap_or_default())
+            .collect();
+        let inputs = job
+            .input
+            .iter()
+            .filter(|input| *input != "-")
+            .chain(&references)
+            .chain(&job.regions)
+            .chain(&job.palette_mask)
+            .chain(&args.config)
+            .chain(&args.palette_file)
+            .map(|input| escape(input))
+            .collect::<Vec<_>>();
+        rules.push_str(&format!("{}: {}\n", escape(&job.output), inputs.join(" ")));
+        prerequisites.extend(inputs);
+    }
+    for prerequisite in prerequisites {
+        rules.push_str(&format!("\n{}:\n", prerequisite));
+    }
+    rules
+}
 
-    let input_filename = decoded_image.input_filename.clone();
+/// Re-run every job whenever an input changes, until interrupted.
+fn watch(matches: &ArgMatches, args: &mut CommandArguments, formats: &FormatRegistry) -> ! {
+    log::info!("Watching for changes, press Ctrl-C to stop");
+    let mut previous = watched_files(matches, args, formats);
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let mut current = watched_files(matches, args, formats);
+        if current == previous {
+            continue;
+        }
+        // Editors and exporters often save in several steps, so wait until
+        // the inputs stop changing before regenerating.
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let settled = watched_files(matches, args, formats);
+            if settled == current {
+                break;
+            }
+            current = settled;
+        }
+        previous = current;
 
-    EncodedTile {
-        input_filename,
-        tile_data,
+        // The config's defaults and the palette file may have changed too.
+        let reloaded = load_defaults(matches).and_then(|(config, defaults)| {
+            command_arguments(matches, args.diagnostics, formats, config, defaults)
+        });
+        match reloaded {
+            Ok(reloaded) => {
+                *args = reloaded;
+                if run_jobs(args, formats) == exit_code::SUCCESS {
+                    log::info!("Regenerated {} output(s)", args.jobs.len());
+                }
+            }
+            Err(err) => report_error(args.diagnostics, &err),
+        }
     }
 }
 
-fn write_tile_gbdk(variable_name: &str, encoded_tile: &EncodedTile) -> String {
-    let preamble = format!("unsigned char {}[] = {{", variable_name);
-    let mut body = Vec::new();
-    for line in encoded_tile.tile_data.chunks(16) {
-        let mut formatted_bytes = Vec::new();
-        for byte in line {
-            formatted_bytes.push(format!("{:#04X}", byte));
+/// The modification time of every file the jobs read, including the
+/// config file, the palette file and images that only match a pattern or
+/// input directory.
+fn watched_files(
+    matches: &ArgMatches,
+    args: &CommandArguments,
+    formats: &FormatRegistry,
+) -> BTreeMap<String, Option<SystemTime>> {
+    let mut watched: Vec<String> = args
+        .config
+        .iter()
+        .chain(&args.palette_file)
+        .cloned()
+        .collect();
+    for job in load_jobs(matches, args.output_type.clone(), formats).unwrap_or_default() {
+        let job = expand_inputs(&job).unwrap_or(job);
+        for input in &job.input {
+            watched.extend(input_references(input, args).unwrap_or_default());
         }
-        body.push(format!("    {}", formatted_bytes.join(",")));
+        watched.extend(job.input);
+        watched.extend(job.regions);
+        watched.extend(job.palette_mask);
     }
+    watched
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+            (path, modified.ok())
+        })
+        .collect()
+}
 
-    format!("{}\n{}\n}};\n", preamble, body.join(",\n"))
+/// One job per image found under `input_dir`, writing to the same relative
+/// path under `output_dir` with the output format's extension.
+fn directory_jobs(
+    input_dir: &str,
+    output_dir: &str,
+    output_type: Option<String>,
+    formats: &FormatRegistry,
+) -> Result<Vec<Job>, Error> {
+    let extension = output_format(formats, output_type.as_deref()).file_extension();
+    let images = find_images(Path::new(input_dir)).map_err(|err| Error::Read {
+        path: input_dir.to_string(),
+        source: ImageReadError::Io(err),
+    })?;
+    let mut jobs = Vec::new();
+    for image in images {
+        let relative = image.strip_prefix(input_dir).unwrap();
+        let output = Path::new(output_dir)
+            .join(relative)
+            .with_extension(extension);
+        jobs.push(Job {
+            input: vec![image.to_string_lossy().into_owned()],
+            output: output.to_string_lossy().into_owned(),
+            output_type: output_type.clone(),
+            name: None,
+            regions: None,
+            palette_mask: None,
+        });
+    }
+    Ok(jobs)
 }
 
-fn write_tile_rgbds(variable_name: &str, encoded_tile: &EncodedTile) -> String {
-    let end_symbol = format!("{}_end", variable_name);
-    let preamble = format!(
-        "SECTION \"Tiles for '{}'\", ROM0\n\nEXPORT {}, {}\n\n{}:",
-        variable_name, variable_name, end_symbol, variable_name
-    );
-    let mut body = Vec::new();
-    for line in encoded_tile.tile_data.chunks(16) {
-        let mut formatted_bytes = Vec::new();
-        for byte in line {
-            formatted_bytes.push(format!("${:02x}", byte));
+/// The format a job names, falling back to GBDK for unknown names.
+fn output_format<'a>(formats: &'a FormatRegistry, name: Option<&str>) -> &'a dyn OutputFormat {
+    name.and_then(|name| formats.get(name))
+        .unwrap_or_else(|| formats.get("gbdk").unwrap())
+}
+
+/// Decode the palette mask image at `path`.
+fn read_palette_mask(path: &str) -> Result<SourceImage, Error> {
+    let read_error = |source| Error::Read {
+        path: path.to_string(),
+        source,
+    };
+    let file = File::open(path).map_err(|err| read_error(ImageReadError::Io(err)))?;
+    decode_source(&SourceRegistry::default(), path, file).map_err(read_error)
+}
+
+/// Expand glob patterns in a job's inputs ourselves, since not every shell
+/// does. Inputs without any pattern characters are passed through as is.
+fn expand_inputs(job: &Job) -> Result<Job, Error> {
+    Ok(Job {
+        input: expand_patterns(&job.input)?,
+        ..job.clone()
+    })
+}
+
+fn expand_patterns(patterns: &[String]) -> Result<Vec<String>, Error> {
+    let mut inputs = Vec::new();
+    for input in patterns {
+        if glob::Pattern::escape(input) == *input {
+            inputs.push(input.clone());
+            continue;
+        }
+        let input_error = |message: String| Error::Input {
+            path: input.clone(),
+            message,
+        };
+        let paths = glob::glob(input).map_err(|err| input_error(err.to_string()))?;
+        let matches_before = inputs.len();
+        for path in paths {
+            let path = path.map_err(|err| input_error(err.to_string()))?;
+            if path.is_file() {
+                inputs.push(path.to_string_lossy().into_owned());
+            }
+        }
+        if inputs.len() == matches_before {
+            return Err(input_error("no files match this pattern".to_string()));
         }
-        body.push(format!("    db {}", formatted_bytes.join(",")));
     }
+    Ok(inputs)
+}
 
-    format!("{}\n{}\n{}:\n", preamble, body.join(",\n"), end_symbol)
+/// Whether `output` can be replaced without losing hand-written work: it
+/// doesn't exist yet, is empty, or starts with gbtile's generated marker.
+/// Formats without comments can't carry the marker, so are always replaced.
+fn replaceable(output: &str, output_format: &dyn OutputFormat) -> bool {
+    if output_format.comment(GENERATED_MARKER).is_none() {
+        return true;
+    }
+    match fs::read(output) {
+        Ok(contents) => contents.is_empty() || is_generated(&contents),
+        Err(_) => true,
+    }
 }
 
-fn write_tile(
-    encoded_tile: &EncodedTile,
-    out_file: &str,
-    output_type: OutputType,
-) -> Result<(), io::Error> {
-    let variable_name = Path::new(&encoded_tile.input_filename)
-        .file_stem()
-        .map(|stem| stem.to_string_lossy())
-        .expect(&format!(
-            "Invalid file name: {}",
-            encoded_tile.input_filename
-        ));
-    let formatted_result = match output_type {
-        OutputType::Gbdk => write_tile_gbdk(&variable_name, encoded_tile),
-        OutputType::Rgbds => write_tile_rgbds(&variable_name, encoded_tile),
+/// Print a diagnostic compiler style, in color when stderr is a terminal.
+fn print_diagnostic(diagnostic: &Diagnostic) {
+    if !io::stderr().is_terminal() || env::var_os("NO_COLOR").is_some() {
+        without_status_line(|| eprintln!("{}", diagnostic));
+        return;
+    }
+    let severity_color = match diagnostic.severity {
+        Severity::Error => "\x1b[1;31m",
+        Severity::Warning => "\x1b[1;33m",
+    };
+    without_status_line(|| {
+        eprintln!(
+            "{}{}:\x1b[0m \x1b[1m{}:\x1b[0m {}",
+            severity_color,
+            diagnostic.severity.as_str(),
+            diagnostic.position(),
+            diagnostic.message
+        )
+    });
+}
+
+/// Add a diagnostic to the log file, which already shows its severity as
+/// the log level.
+fn log_diagnostic(level: Level, diagnostic: &Diagnostic) {
+    let message = format!("{}: {}", diagnostic.position(), diagnostic.message);
+    write_log_file(level, "gbtile", &message);
+}
+
+/// Create the log file, replacing any log from a previous run.
+fn open_log_file(path: &str) -> Result<(), Error> {
+    let write_error = |source| Error::Write {
+        path: path.to_string(),
+        source,
     };
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(out_file)?;
-    file.write_all(formatted_result.as_bytes())?;
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    *LOG_FILE.lock().unwrap() = Some(File::create(path).map_err(write_error)?);
     Ok(())
 }
 
-fn main() {
-    let matches = App::new("Gameboy Tile Generator")
-        .version("0.2.0")
-        .author("Blake Smith <blakesmith0@gmail.com>")
-        .about("Generate GBDK or RGBDS Game Boy tiles from PNG images")
-        .arg(
-            Arg::with_name("debug")
-                .help("Enable debug logging")
-                .short("d"),
-        )
-        .arg(
-            Arg::with_name("input")
-                .help("The PNG image to generate tiles from. Example: 'image.png'")
-                .short("i")
-                .takes_value(true)
-                .required(true),
-        )
-        .arg(
-            Arg::with_name("output")
-                .help("The output file to generate. Usually something like 'tiles.h' for GBDK output, or 'tiles.asm' for RGBDS")
-                .short("o")
-                .takes_value(true)
-                .required(true),
-        )
-        .arg(
-            Arg::with_name("output-type")
-                .help("The output type. Either 'gbdk' or 'rgbds'. Defaults to 'gbdk'")
-                .takes_value(true)
-                .short("t"),
-        )
-        .get_matches();
+fn report_error(diagnostics: DiagnosticsFormat, err: &Error) {
+    log_diagnostic(Level::Error, &err.diagnostic());
+    match diagnostics {
+        DiagnosticsFormat::Text => print_diagnostic(&err.diagnostic()),
+        DiagnosticsFormat::Json => {
+            without_status_line(|| eprintln!("{}", err.diagnostic().to_json()))
+        }
+    }
+}
 
-    if matches.is_present("debug") {
-        simple_logger::init_with_level(Level::Debug).unwrap();
-    } else {
-        simple_logger::init_with_level(Level::Info).unwrap();
+/// Write `<output>.charmap.inc` next to an RGBDS output holding fonts, with
+/// `CHARMAP` definitions for their characters.
+fn write_charmap(
+    args: &CommandArguments,
+    output: &str,
+    encoded_tiles: &[EncodedTile],
+) -> Result<(), Error> {
+    let Some(charmap) = write_charmap_rgbds(encoded_tiles) else {
+        return Ok(());
+    };
+    let mut charmap = charmap.into_bytes();
+    if args.deterministic {
+        charmap = normalize_line_endings(&charmap);
     }
-    let output_type = match matches.value_of("output-type") {
-        Some("gbdk") => OutputType::Gbdk,
-        Some("rgbds") => OutputType::Rgbds,
-        _ => OutputType::Gbdk,
+    let path = charmap_path(output);
+    let written = if !args.force && !replaceable(&path, &Rgbds) {
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "it wasn't generated by gbtile, pass --force to replace it anyway",
+        ))
+    } else {
+        write_output(&path, &charmap)
     };
+    written.map_err(|source| Error::Write { path, source })
+}
+
+/// The charmap include written next to `output`: `font.asm` gets
+/// `font.charmap.inc`.
+fn charmap_path(output: &str) -> String {
+    Path::new(output)
+        .with_extension("charmap.inc")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The name of the tileset shared by a job's inputs: its output's file
+/// stem, or `tileset` when writing to stdout.
+fn shared_tileset_name(output: &str) -> String {
+    match Path::new(output).file_stem() {
+        Some(stem) if output != "-" => stem.to_string_lossy().into_owned(),
+        _ => "tileset".to_string(),
+    }
+}
 
+/// Write `<input>.errors.png`, showing where an image that failed with
+/// `err` went wrong, if it's about the image's colors or size.
+fn write_error_image(
+    diagnostics: DiagnosticsFormat,
+    converter: &Converter,
+    input: &str,
+    err: &ImageReadError,
+) {
+    if !matches!(
+        err,
+        ImageReadError::TooManyColors { .. }
+            | ImageReadError::OffPalette { .. }
+            | ImageReadError::TooManyPalettes { .. }
+            | ImageReadError::PaletteMaskFull { .. }
+            | ImageReadError::ImageTooSmall { .. }
+            | ImageReadError::FrameHeight { .. }
+            | ImageReadError::NotTileAligned { .. }
+            | ImageReadError::NoGridCells { .. }
+            | ImageReadError::RectOutsideImage { .. }
+    ) {
+        return;
+    }
+    let path = format!("{}.errors.png", input);
+    let written = converter.error_image(input, err).and_then(|png_data| {
+        fs::write(&path, png_data).map_err(|source| Error::Write {
+            path: path.clone(),
+            source,
+        })
+    });
+    match written {
+        Ok(()) => log::info!("Wrote {} showing where {} went wrong", path, input),
+        Err(err) => report_error(diagnostics, &err),
+    }
+}
+
+fn report_warning(diagnostics: DiagnosticsFormat, warning: &Diagnostic) {
+    log_diagnostic(Level::Warn, warning);
+    let console_level = *CONSOLE_LOG_LEVEL.get().unwrap_or(&LevelFilter::Info);
+    match diagnostics {
+        DiagnosticsFormat::Text if console_level >= LevelFilter::Warn => print_diagnostic(warning),
+        DiagnosticsFormat::Text => {}
+        DiagnosticsFormat::Json => without_status_line(|| eprintln!("{}", warning.to_json())),
+    }
+}
+
+/// The arguments that change what a job writes, for its cache key: every
+/// one except those saying where jobs come from, how they run and what
+/// else is written alongside them.
+fn output_options(args: &CommandArguments) -> String {
     let args = CommandArguments {
-        input: matches.value_of("input").unwrap().to_string(),
-        output: matches.value_of("output").unwrap().to_string(),
-        output_type: output_type,
+        jobs: Vec::new(),
+        stream: false,
+        dry_run: false,
+        config: None,
+        palette_file: None,
+        depfile: None,
+        cache: None,
+        report: None,
+        fail_on_budget: false,
+        error_images: false,
+        append: false,
+        force: false,
+        diagnostics: DiagnosticsFormat::Text,
+        progress: None,
+        ..args.clone()
     };
+    format!("{:?}", args)
+}
 
-    let decoded_image = decode_image(&args.input).expect("Could not decode image");
-    let encoded_tile = encode_tile(decoded_image);
-    write_tile(&encoded_tile, &args.output, args.output_type).expect("Could not write out tile");
+fn run(
+    args: &CommandArguments,
+    job: &Job,
+    formats: &FormatRegistry,
+    cache: Option<&mut Cache>,
+    report: Option<&mut Vec<Value>>,
+) -> Result<Budget, Error> {
+    // Appending isn't repeatable and stdin can't be read twice, so neither
+    // can be skipped.
+    let cacheable =
+        !args.append && job.output != "-" && !job.input.iter().any(|input| input == "-");
+    let cache_key = if cacheable && cache.is_some() {
+        // Region manifests and the files inputs read change the output as
+        // much as the images do. Inputs whose references can't be read
+        // fail to convert anyway.
+        let mut cache_inputs: Vec<String> = job
+            .input
+            .iter()
+            .chain(&job.regions)
+            .chain(&job.palette_mask)
+            .cloned()
+            .collect();
+        for input in &job.input {
+            cache_inputs.extend(input_references(input, args).unwrap_or_default());
+        }
+        let options = format!("{:?} {}", job, output_options(args));
+        Cache::key(&cache_inputs, &options).ok()
+    } else {
+        None
+    };
+    let output_format = output_format(formats, job.output_type.as_deref());
+    if let (Some(cache), Some(key)) = (&cache, cache_key) {
+        if cache.is_up_to_date(&job.output, key) {
+            log::info!("{}: up to date", job.output);
+            if let Some(report) = report {
+                report.push(cached_report_entry(job, output_format));
+            }
+            return Ok(Budget::Within);
+        }
+    }
 
-    log::debug!("Arguments are: {:?}", args);
+    let mut converter = Converter::new()
+        .streaming(args.stream)
+        .transparent_color(args.transparent_color)
+        .sprite(args.sprite)
+        .strict_palette(args.strict_palette)
+        .cgb(args.cgb)
+        .sgb(args.sgb)
+        .cgb_color_correction(args.cgb_color_correction)
+        .obp(args.obp)
+        .dmg_compatible(args.dmg_compatible)
+        .order(args.order)
+        .metatile(args.metatile)
+        .base_index(args.base_index)
+        .addressing(args.addressing)
+        .bpp(args.bpp)
+        .font(args.first_char)
+        .trim_blank(args.trim_blank)
+        .split_banks(
+            args.split_banks
+                .map(|banks| banks.bank_tiles(args.tile_slots, args.bpp.into())),
+        )
+        .delta_frames(args.delta_frames)
+        .metasprites(args.metasprites)
+        .alpha_threshold(args.alpha_threshold)
+        .luminance(args.luminance)
+        .gamma(args.gamma)
+        .color_tolerance(args.color_tolerance)
+        .auto_contrast(args.auto_contrast)
+        .posterize(args.posterize)
+        .quantize(args.quantize)
+        .dither(args.dither)
+        .color_map(args.color_map)
+        .prefix(args.prefix.as_str())
+        .suffix(args.suffix.as_str());
+    if let Some(rect) = args.rect {
+        converter = converter.rect(rect);
+    }
+    converter = converter
+        .grid(args.grid)
+        .frame_size(args.frame_size)
+        .pad(args.pad)
+        .truncate(args.truncate);
+    if let Some(palette) = args.palette {
+        converter = converter.palette(palette);
+    } else if args.strict_palette {
+        return Err(strict_palette_error(&job.input[0]));
+    }
+    if let Some(name) = &job.name {
+        if job.input.len() > 1 {
+            return Err(Error::Input {
+                path: job.input[1].clone(),
+                message: "a symbol name can only be given for a single input".to_string(),
+            });
+        }
+        converter = converter.name(name.as_str());
+    }
+    let regions = match &job.regions {
+        Some(manifest) => {
+            if job.input.len() > 1 {
+                return Err(Error::Input {
+                    path: job.input[1].clone(),
+                    message: "regions can only be given for a single input".to_string(),
+                });
+            }
+            if job.name.is_some() {
+                return Err(Error::Input {
+                    path: job.input[0].clone(),
+                    message: "a symbol name can't be given with regions, each region is named in the manifest".to_string(),
+                });
+            }
+            Some(Regions::load(manifest)?)
+        }
+        None => None,
+    };
+    if let Some(palette_mask) = &job.palette_mask {
+        converter = converter.palette_mask(read_palette_mask(palette_mask)?);
+    }
+    let mut budget = Budget::Within;
+    let mut encoded_tiles = Vec::new();
+    for input in &job.input {
+        let converter =
+            converter
+                .clone()
+                .sources(sources(input, args.layer.as_deref(), args.tag.as_deref()));
+        let input_tiles = if let Some(regions) = &regions {
+            if input == "-" {
+                return Err(Error::Input {
+                    path: STDIN_NAME.to_string(),
+                    message: "regions can't be cut from stdin, give the image's path".to_string(),
+                });
+            }
+            converter.encode_regions(input, &regions.regions)?
+        } else if input == "-" {
+            vec![converter.encode_reader(STDIN_NAME, io::stdin().lock())?]
+        } else if is_atlas(input) {
+            if job.name.is_some() {
+                return Err(Error::Input {
+                    path: input.clone(),
+                    message: "a symbol name can't be given for an atlas, its sprites are named after their file names".to_string(),
+                });
+            }
+            converter.encode_atlas(input)?
+        } else {
+            let encoded_tile = converter.encode_file(input);
+            if let (true, false, Err(Error::Read { source, .. })) =
+                (args.error_images, args.dry_run, &encoded_tile)
+            {
+                write_error_image(args.diagnostics, &converter, input, source);
+            }
+            vec![encoded_tile?]
+        };
+        for encoded_tile in input_tiles {
+            for warning in &encoded_tile.warnings {
+                report_warning(args.diagnostics, warning);
+            }
+            let tiles = encoded_tile.tile_data.len() / TILE_BYTES;
+            if tiles > args.tile_slots && args.split_banks.is_none() {
+                budget = Budget::Over;
+                report_warning(
+                    args.diagnostics,
+                    &Diagnostic::warning(
+                        "too-many-tiles",
+                        &encoded_tile.input_filename,
+                        format!(
+                            "has {} tiles, more than the {} tile slots of VRAM, split them with --split-banks",
+                            tiles, args.tile_slots
+                        ),
+                    ),
+                );
+            }
+            encoded_tiles.push(encoded_tile);
+        }
+    }
+    if args.shared_tileset {
+        let name = match &job.name {
+            Some(name) => name.clone(),
+            None => format!(
+                "{}{}{}",
+                args.prefix,
+                shared_tileset_name(&job.output),
+                args.suffix
+            ),
+        };
+        let shared = share_tileset(&name, encoded_tiles).map_err(|message| Error::Input {
+            path: job.output.clone(),
+            message,
+        })?;
+        encoded_tiles = vec![shared];
+    }
+    let mut rendered = output_format.render_all(&encoded_tiles);
+    if args.deterministic {
+        rendered = normalize_line_endings(&rendered);
+    }
+    let tile_bytes = encoded_tiles
+        .iter()
+        .map(|encoded_tile| encoded_tile.tile_data.len())
+        .sum();
+    let usage = VramUsage::new(tile_bytes, args.tile_slots);
+    log::info!("{}: {}", job.output, usage);
+    if let Some(threshold) = args.warn_vram {
+        if usage.slot_percent() > threshold as f64 {
+            budget = Budget::Over;
+            report_warning(
+                args.diagnostics,
+                &Diagnostic::warning(
+                    "vram-budget",
+                    &job.output,
+                    format!(
+                        "uses {:.1}% of the {} tile slots, over the {}% budget",
+                        usage.slot_percent(),
+                        usage.tile_slots,
+                        threshold
+                    ),
+                ),
+            );
+        }
+    }
+    if args.dry_run {
+        log::info!(
+            "{}: ok, {} tile(s) would be written to {}",
+            job.input.join(", "),
+            usage.tiles,
+            job.output
+        );
+        return Ok(budget);
+    }
+    if job.output != "-" {
+        if let Some(output_dir) = Path::new(&job.output).parent() {
+            fs::create_dir_all(output_dir).map_err(|source| Error::Write {
+                path: output_dir.display().to_string(),
+                source,
+            })?;
+        }
+    }
+    let written = if job.output == "-" {
+        io::stdout().write_all(&rendered)
+    } else if args.append {
+        append_output(&job.output, &rendered, output_format)
+    } else if !args.force && !replaceable(&job.output, output_format) {
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "it wasn't generated by gbtile, pass --force to replace it anyway",
+        ))
+    } else {
+        write_output(&job.output, &rendered)
+    };
+    let write_error = |source| Error::Write {
+        path: job.output.clone(),
+        source,
+    };
+    written.map_err(write_error)?;
+    if output_format.name() == "rgbds" && job.output != "-" {
+        write_charmap(args, &job.output, &encoded_tiles)?;
+    }
+    // Outputs over budget are checked again on the next run, so it fails
+    // again with --fail-on-budget.
+    if let (Some(cache), Some(key), Budget::Within) = (cache, cache_key, budget) {
+        cache.insert(&job.output, key).map_err(write_error)?;
+    }
+    if let Some(report) = report {
+        report.push(report_entry(job, output_format, &rendered, &encoded_tiles));
+    }
+    Ok(budget)
 }
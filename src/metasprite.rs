@@ -0,0 +1,110 @@
+use crate::diagnostic::Diagnostic;
+use crate::tile::EncodedTile;
+use crate::vram::TILE_BYTES;
+
+/// The most sprites the Game Boy's OAM holds.
+pub const OAM_SPRITE_COUNT: usize = 40;
+
+/// The largest frame metasprites can be made from, in pixels, so each
+/// sprite's offset from the one before fits a signed byte.
+const MAX_METASPRITE_SIZE: u32 = 128;
+
+/// One 8x8 hardware sprite of a metasprite.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MetaspriteItem {
+    /// The offset in pixels of the sprite's top left corner from the
+    /// metasprite's pivot, the center of its frame.
+    pub y: i16,
+    pub x: i16,
+    /// The number of the tile it draws, counted from the first tile loaded.
+    pub tile: u8,
+    /// The OAM attribute flags it's drawn with.
+    pub props: u8,
+}
+
+/// The pivot metasprites made of `encoded_tile` are placed by: the center
+/// of a frame, in pixels from its top left corner.
+pub fn metasprite_pivot(encoded_tile: &EncodedTile) -> (u32, u32) {
+    let frames = encoded_tile.frames.max(1);
+    (encoded_tile.width / 2, encoded_tile.height / frames / 2)
+}
+
+/// Make a metasprite out of each of `encoded_tile`'s animation frames, or
+/// the whole image for still images: a sprite for each tile that isn't
+/// entirely color 0, which sprites draw transparent, left to right and top
+/// to bottom. Sprites draw with the object palette's OAM flags, as in
+/// [`EncodedTile::oam_flags`], and in Game Boy Color mode, their tile's
+/// palette. Tiles of animations written as changes between frames, as in
+/// [`EncodedTile::frame_deltas`], are numbered from the frame's first tile,
+/// since each frame is copied over the one before. Warnings are added for
+/// frames with more sprites than OAM holds. Fails if the frames are too big
+/// for sprites' offsets, or draw tiles past the 256 sprites can number.
+pub fn build_metasprites(
+    input: &str,
+    encoded_tile: &mut EncodedTile,
+) -> Result<Vec<Vec<MetaspriteItem>>, String> {
+    let frames = encoded_tile.frames.max(1) as usize;
+    let (width, height) = (encoded_tile.width, encoded_tile.height / frames as u32);
+    if width > MAX_METASPRITE_SIZE || height > MAX_METASPRITE_SIZE {
+        return Err(format!(
+            "metasprites can be at most {}x{} pixels, but the frames are {}x{}",
+            MAX_METASPRITE_SIZE, MAX_METASPRITE_SIZE, width, height
+        ));
+    }
+    let (columns, rows) = (width as usize / 8, height as usize / 8);
+    let frame_tiles = columns * rows;
+    let (pivot_x, pivot_y) = metasprite_pivot(encoded_tile);
+    let (pivot_x, pivot_y) = (pivot_x as i16, pivot_y as i16);
+    let oam_flags = encoded_tile.oam_flags().unwrap_or(0);
+    let mut metasprites = Vec::with_capacity(frames);
+    for frame in 0..frames {
+        let mut metasprite = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut tile = encoded_tile.tile_number(column, frame * rows + row);
+                let data = encoded_tile
+                    .tile_data
+                    .get(tile * TILE_BYTES..(tile + 1) * TILE_BYTES)
+                    .unwrap_or(&[]);
+                if data.iter().all(|byte| *byte == 0) {
+                    continue;
+                }
+                let palette = match (encoded_tile.cgb, &encoded_tile.cgb_palettes) {
+                    (true, Some(cgb_palettes)) => {
+                        cgb_palettes.tile_palettes.get(tile).copied().unwrap_or(0)
+                    }
+                    _ => 0,
+                };
+                if encoded_tile.frame_deltas.is_some() {
+                    tile %= frame_tiles.max(1);
+                }
+                if tile > u8::MAX as usize {
+                    return Err(format!(
+                        "frame {} draws tile {}, past the 256 sprites can number",
+                        frame, tile
+                    ));
+                }
+                metasprite.push(MetaspriteItem {
+                    y: (row * 8) as i16 - pivot_y,
+                    x: (column * 8) as i16 - pivot_x,
+                    tile: tile as u8,
+                    props: oam_flags | palette,
+                });
+            }
+        }
+        if metasprite.len() > OAM_SPRITE_COUNT {
+            encoded_tile.warnings.push(Diagnostic::warning(
+                "too-many-sprites",
+                input,
+                format!(
+                    "frame {} is drawn with {} sprites, more than the {} OAM holds",
+                    frame,
+                    metasprite.len(),
+                    OAM_SPRITE_COUNT
+                ),
+            ));
+        }
+        metasprites.push(metasprite);
+    }
+    Ok(metasprites)
+}
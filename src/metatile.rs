@@ -0,0 +1,189 @@
+use crate::tile::{EncodedTile, Tilemap};
+use crate::vram::TILE_BYTES;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The most tiles or metatiles a byte can number.
+const MAX_NUMBERED: usize = 256;
+
+/// The size of the metatiles maps are grouped into, in pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MetatileSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl MetatileSize {
+    /// The size in 8x8 tiles.
+    pub fn tiles(&self) -> (u32, u32) {
+        (self.width / 8, self.height / 8)
+    }
+}
+
+impl FromStr for MetatileSize {
+    type Err = String;
+
+    /// Parses `widthxheight`, e.g. `16x16`.
+    fn from_str(s: &str) -> Result<MetatileSize, String> {
+        let size = s
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        match size {
+            Some((width, height))
+                if width > 0 && height > 0 && width % 8 == 0 && height % 8 == 0 =>
+            {
+                Ok(MetatileSize { width, height })
+            }
+            Some(_) => Err(format!("'{}' isn't a whole number of 8x8 tiles", s)),
+            None => Err(format!("'{}' isn't a size like '16x16'", s)),
+        }
+    }
+}
+
+/// A map grouped into metatiles: blocks of tiles drawn together, as most
+/// engines scroll and collide by. Each distinct block is defined once, and
+/// the map gives the block at each position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metatiles {
+    /// The name of the map they were grouped from, if it has one.
+    pub name: Option<String>,
+    /// The size of each metatile in tiles.
+    pub columns: u32,
+    pub rows: u32,
+    /// The tile numbers of each metatile, left to right and top to bottom,
+    /// one metatile after another.
+    pub definitions: Vec<u8>,
+    /// The Game Boy Color attributes of each tile in `definitions`, as in
+    /// [`EncodedTile::cgb_attributes`].
+    pub attributes: Vec<u8>,
+    /// The size of the map in metatiles.
+    pub width: u32,
+    pub height: u32,
+    /// The number of the metatile at each position, left to right and top
+    /// to bottom.
+    pub map: Vec<u8>,
+}
+
+impl Metatiles {
+    /// How many distinct metatiles there are.
+    pub fn count(&self) -> usize {
+        self.definitions.len() / (self.columns * self.rows).max(1) as usize
+    }
+}
+
+/// Group each map drawn with `encoded_tile`'s tiles into metatiles of
+/// `size`, or the tiles themselves, where they were cut from, for images
+/// that aren't maps. Blocks drawing the same pixels, flips and palettes
+/// are the same metatile, and take the tile numbers of the first of them.
+/// Fails if a map isn't a whole number of metatiles, or there are more
+/// tiles or metatiles than a byte can number.
+pub fn group_metatiles(
+    encoded_tile: &EncodedTile,
+    size: MetatileSize,
+) -> Result<Vec<Metatiles>, String> {
+    if encoded_tile.tilemaps.is_empty() {
+        let tiles = encoded_tile.tile_data.len() / TILE_BYTES;
+        if tiles > MAX_NUMBERED {
+            return Err(format!(
+                "metatiles can only be made of the first {} tiles, but the image has {}",
+                MAX_NUMBERED, tiles
+            ));
+        }
+        let (width, height) = (encoded_tile.width / 8, encoded_tile.height / 8);
+        let image_map = Tilemap {
+            name: None,
+            width,
+            height,
+            tiles: (0..height as usize)
+                .flat_map(|row| {
+                    (0..width as usize)
+                        .map(move |column| encoded_tile.tile_number(column, row) as u8)
+                })
+                .collect(),
+            attributes: vec![0; tiles],
+        };
+        return Ok(vec![group_map(
+            encoded_tile,
+            &image_map,
+            "the image",
+            size,
+        )?]);
+    }
+    encoded_tile
+        .tilemaps
+        .iter()
+        .map(|tilemap| {
+            let map = tilemap.name.as_deref().unwrap_or("the map");
+            group_map(encoded_tile, tilemap, map, size)
+        })
+        .collect()
+}
+
+fn group_map(
+    encoded_tile: &EncodedTile,
+    tilemap: &Tilemap,
+    map: &str,
+    size: MetatileSize,
+) -> Result<Metatiles, String> {
+    let (columns, rows) = size.tiles();
+    if !tilemap.width.is_multiple_of(columns) || !tilemap.height.is_multiple_of(rows) {
+        return Err(format!(
+            "{} is {}x{} tiles, which isn't a whole number of {}x{} metatiles",
+            map, tilemap.width, tilemap.height, size.width, size.height
+        ));
+    }
+    let attributes = match encoded_tile.cgb {
+        true => encoded_tile.cgb_attributes(tilemap),
+        false => vec![0; tilemap.tiles.len()],
+    };
+    let (width, height) = (tilemap.width / columns, tilemap.height / rows);
+    let mut metatiles = Metatiles {
+        name: tilemap.name.clone(),
+        columns,
+        rows,
+        definitions: Vec::new(),
+        attributes: Vec::new(),
+        width,
+        height,
+        map: Vec::with_capacity((width * height) as usize),
+    };
+    // Each metatile's tile data and attributes, to find repeats by.
+    let mut numbers: HashMap<Vec<u8>, usize> = HashMap::new();
+    for y in 0..height {
+        for x in 0..width {
+            let positions: Vec<usize> = (0..rows)
+                .flat_map(|row| {
+                    let start = (y * rows + row) * tilemap.width + x * columns;
+                    (start..start + columns).map(|position| position as usize)
+                })
+                .collect();
+            let mut key = Vec::with_capacity(positions.len() * (TILE_BYTES + 1));
+            for &position in &positions {
+                let tile = tilemap.tiles[position] as usize * TILE_BYTES;
+                key.extend(
+                    encoded_tile
+                        .tile_data
+                        .get(tile..tile + TILE_BYTES)
+                        .unwrap_or(&[0; TILE_BYTES]),
+                );
+                key.push(attributes[position]);
+            }
+            let next = numbers.len();
+            let number = *numbers.entry(key).or_insert(next);
+            if number == next {
+                if number == MAX_NUMBERED {
+                    return Err(format!(
+                        "{} has more than {} different {}x{} metatiles",
+                        map, MAX_NUMBERED, size.width, size.height
+                    ));
+                }
+                for &position in &positions {
+                    metatiles.definitions.push(tilemap.tiles[position]);
+                    metatiles.attributes.push(attributes[position]);
+                }
+            }
+            metatiles.map.push(number as u8);
+        }
+    }
+    Ok(metatiles)
+}
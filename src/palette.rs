@@ -0,0 +1,163 @@
+use crate::image::{Rgb, GB_MAX_COLOR_COUNT};
+use crate::Error;
+use std::convert::TryInto;
+use std::fs;
+use std::str::FromStr;
+
+/// A built-in palette, standing for four colors that are both what an
+/// image is drawn with and what previews are drawn in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PalettePreset {
+    /// The original Game Boy's green screen.
+    DmgGreen,
+    /// The Game Boy Pocket's olive gray screen.
+    Pocket,
+    /// Evenly spaced grays from white to black.
+    Grayscale,
+    /// The BGB emulator's default palette.
+    Bgb,
+}
+
+impl PalettePreset {
+    /// The colors of color numbers 0 to 3, lightest first.
+    pub fn colors(self) -> [Rgb; GB_MAX_COLOR_COUNT] {
+        let hex = match self {
+            PalettePreset::DmgGreen => [0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f],
+            PalettePreset::Pocket => [0xc4cfa1, 0x8b956d, 0x4d533c, 0x1f1f1f],
+            PalettePreset::Grayscale => [0xffffff, 0xaaaaaa, 0x555555, 0x000000],
+            PalettePreset::Bgb => [0xe0f8d0, 0x88c070, 0x346856, 0x081820],
+        };
+        hex.map(|value: u32| Rgb {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        })
+    }
+}
+
+impl FromStr for PalettePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PalettePreset, String> {
+        match s {
+            "dmg-green" => Ok(PalettePreset::DmgGreen),
+            "pocket" => Ok(PalettePreset::Pocket),
+            "grayscale" => Ok(PalettePreset::Grayscale),
+            "bgb" => Ok(PalettePreset::Bgb),
+            _ => Err(format!(
+                "'{}' isn't a palette preset, use 'dmg-green', 'pocket', 'grayscale' or 'bgb'",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse a comma separated list of four hex colors, listed darkest first,
+/// into the colors of color numbers 0 to 3, e.g.
+/// `#0f380f,#306230,#8bac0f,#9bbc0f`.
+pub fn parse_palette(value: &str) -> Result<[Rgb; GB_MAX_COLOR_COUNT], String> {
+    let colors = value
+        .split(',')
+        .map(|color| color.trim().parse())
+        .collect::<Result<Vec<Rgb>, String>>()?;
+    darkest_first(colors)
+}
+
+/// Parse a comma separated list of hex colors pinned to color numbers, e.g.
+/// `ff0000=3,00ff00=0`, into the color pinned to each color number.
+pub fn parse_color_map(value: &str) -> Result<[Option<Rgb>; GB_MAX_COLOR_COUNT], String> {
+    let mut color_map = [None; GB_MAX_COLOR_COUNT];
+    for mapping in value.split(',').map(str::trim) {
+        let (color, number) = mapping
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' isn't a color mapping like 'ff0000=3'", mapping))?;
+        let rgb: Rgb = color.trim().parse()?;
+        let number = number.trim();
+        let color = number
+            .parse::<usize>()
+            .ok()
+            .filter(|color| *color < GB_MAX_COLOR_COUNT)
+            .ok_or_else(|| format!("'{}' isn't a color number, use 0 to 3", number))?;
+        if color_map[color].is_some() {
+            return Err(format!("color {} is mapped to twice", color));
+        }
+        if color_map.contains(&Some(rgb)) {
+            return Err(format!("{} is mapped twice", rgb));
+        }
+        color_map[color] = Some(rgb);
+    }
+    Ok(color_map)
+}
+
+/// Parse a GIMP `.gpl` or JASC `.pal` palette file, as exported by GIMP
+/// or Aseprite, holding four colors. Like [`parse_palette`], the colors are
+/// listed darkest first, from color 3 to color 0.
+pub fn parse_palette_file(contents: &str) -> Result<[Rgb; GB_MAX_COLOR_COUNT], String> {
+    let mut lines = contents.lines().map(str::trim);
+    let colors = match lines.next() {
+        Some("GIMP Palette") => lines
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.starts_with('#')
+                    && !line.starts_with("Name:")
+                    && !line.starts_with("Columns:")
+            })
+            .map(rgb_line)
+            .collect::<Result<Vec<Rgb>, String>>()?,
+        Some("JASC-PAL") => {
+            // A version line and the number of colors come first.
+            let count = lines
+                .nth(1)
+                .and_then(|count| count.parse::<usize>().ok())
+                .ok_or_else(|| "JASC palette is missing its color count".to_string())?;
+            lines
+                .filter(|line| !line.is_empty())
+                .take(count)
+                .map(rgb_line)
+                .collect::<Result<Vec<Rgb>, String>>()?
+        }
+        _ => {
+            return Err("not a GIMP (.gpl) or JASC (.pal) palette file".to_string());
+        }
+    };
+    darkest_first(colors)
+}
+
+/// Read a palette file, as in [`parse_palette_file`].
+pub fn read_palette_file(path: &str) -> Result<[Rgb; GB_MAX_COLOR_COUNT], Error> {
+    let palette_error = |message: String| Error::Config {
+        path: path.to_string(),
+        message,
+    };
+    let contents = fs::read(path).map_err(|err| palette_error(err.to_string()))?;
+    parse_palette_file(&String::from_utf8_lossy(&contents)).map_err(palette_error)
+}
+
+/// A color given as red, green and blue numbers, optionally followed by a
+/// name.
+fn rgb_line(line: &str) -> Result<Rgb, String> {
+    let channels: Vec<u8> = line
+        .split_whitespace()
+        .take(3)
+        .map_while(|channel| channel.parse().ok())
+        .collect();
+    match channels[..] {
+        [r, g, b] => Ok(Rgb { r, g, b }),
+        _ => Err(format!("'{}' isn't a color like '155 188 15'", line)),
+    }
+}
+
+/// The colors of color numbers 0 to 3, from four colors listed darkest
+/// first.
+fn darkest_first(colors: Vec<Rgb>) -> Result<[Rgb; GB_MAX_COLOR_COUNT], String> {
+    let mut palette: [Rgb; GB_MAX_COLOR_COUNT] =
+        colors.try_into().map_err(|colors: Vec<Rgb>| {
+            format!(
+                "expected {} colors, but {} were given",
+                GB_MAX_COLOR_COUNT,
+                colors.len()
+            )
+        })?;
+    palette.reverse();
+    Ok(palette)
+}
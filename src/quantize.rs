@@ -0,0 +1,239 @@
+use crate::image::{nearest_color, DecodeOptions, Dither, Gamma, Rgb, Rgba, GB_MAX_COLOR_COUNT};
+use std::collections::BTreeMap;
+
+/// Replace the opaque colors of `image_data` with the best `GB_MAX_COLOR_COUNT`
+/// colors found by median cut, less one if the image keeps a color for
+/// transparent pixels.
+/// Images that already fit are left alone.
+///
+/// With a dither in `options`, each color is picked from the image's
+/// original `pixels`, `width` pixels per row, instead of `image_data`'s
+/// rounded colors.
+pub(crate) fn quantize(
+    image_data: &mut [Option<Rgb>],
+    pixels: &[Rgba],
+    width: u32,
+    options: &DecodeOptions,
+) {
+    let mut histogram = BTreeMap::new();
+    let mut has_transparent = false;
+    for color in image_data.iter() {
+        match color {
+            Some(color) => *histogram.entry(*color).or_insert(0) += 1,
+            None => has_transparent = true,
+        }
+    }
+    let count = GB_MAX_COLOR_COUNT - options.reserves_transparent(has_transparent) as usize;
+    if histogram.len() <= count {
+        return;
+    }
+    let colors = median_cut(&histogram, count);
+    debug!("Quantized {} colors to: {:?}", histogram.len(), colors);
+    match options.dither {
+        Dither::None => {
+            let nearest: BTreeMap<Rgb, Rgb> = histogram
+                .keys()
+                .map(|rgb| {
+                    let color = nearest_color(&colors, *rgb, options.gamma);
+                    (*rgb, colors[color as usize])
+                })
+                .collect();
+            for color in image_data.iter_mut().flatten() {
+                *color = nearest[color];
+            }
+        }
+        Dither::FloydSteinberg => {
+            floyd_steinberg(image_data, pixels, width as usize, &colors, options.gamma)
+        }
+        Dither::Bayer2 => ordered(
+            image_data,
+            pixels,
+            width as usize,
+            &colors,
+            options.gamma,
+            2,
+        ),
+        Dither::Bayer4 => ordered(
+            image_data,
+            pixels,
+            width as usize,
+            &colors,
+            options.gamma,
+            4,
+        ),
+        Dither::Bayer8 => ordered(
+            image_data,
+            pixels,
+            width as usize,
+            &colors,
+            options.gamma,
+            8,
+        ),
+    }
+}
+
+/// Pick the nearest of `colors` for each opaque pixel after nudging it
+/// lighter or darker by its place in a `size` by `size` Bayer matrix. The
+/// matrix repeats from the image's top left corner, so every 8x8 tile of a
+/// flat area gets the same pattern.
+fn ordered(
+    image_data: &mut [Option<Rgb>],
+    pixels: &[Rgba],
+    width: usize,
+    colors: &[Rgb],
+    gamma: Gamma,
+    size: usize,
+) {
+    // Nudge by up to half the gap between evenly spaced colors either way.
+    let spread = 255 / (colors.len() as i32 - 1).max(1);
+    let cells = (size * size) as i32;
+    for (i, color) in image_data.iter_mut().enumerate() {
+        if color.is_none() {
+            continue;
+        }
+        let threshold = bayer(i % width, i / width, size) as i32;
+        let offset = (2 * threshold + 1) * spread / (2 * cells) - spread / 2;
+        let nudge = |value: u8| (i32::from(value) + offset).clamp(0, 255) as u8;
+        let original = pixels[i];
+        let wanted = Rgb {
+            r: nudge(original.r),
+            g: nudge(original.g),
+            b: nudge(original.b),
+        };
+        *color = Some(colors[nearest_color(colors, wanted, gamma) as usize]);
+    }
+}
+
+/// The entry at (`x`, `y`) of the `size` by `size` Bayer matrix, from 0 to
+/// `size * size - 1`, repeating past its edges. Each bit of the
+/// coordinates picks a cell of the 2x2 matrix, with the lowest bits giving
+/// the entry's highest bits.
+fn bayer(x: usize, y: usize, size: usize) -> usize {
+    let levels = size.trailing_zeros();
+    (0..levels).fold(0, |value, level| {
+        let (x, y) = ((x >> level) & 1, (y >> level) & 1);
+        value | ((((x ^ y) << 1) | y) << (2 * (levels - 1 - level)))
+    })
+}
+
+/// Pick the nearest of `colors` for each opaque pixel, top to bottom and
+/// left to right, spreading the difference onto the pixels right of and
+/// below it that haven't been picked yet.
+fn floyd_steinberg(
+    image_data: &mut [Option<Rgb>],
+    pixels: &[Rgba],
+    width: usize,
+    colors: &[Rgb],
+    gamma: Gamma,
+) {
+    // The error carried onto this row and the next, offset by one so the
+    // pixels either side of the row can be written to.
+    let mut errors = vec![[0i32; 3]; width + 2];
+    let mut next_errors = vec![[0i32; 3]; width + 2];
+    for (i, color) in image_data.iter_mut().enumerate() {
+        let x = i % width;
+        if x == 0 && i > 0 {
+            errors = std::mem::replace(&mut next_errors, vec![[0; 3]; width + 2]);
+        }
+        if color.is_none() {
+            continue;
+        }
+        let original = pixels[i];
+        let wanted: Vec<i32> = [original.r, original.g, original.b]
+            .iter()
+            .zip(&errors[x + 1])
+            .map(|(value, error)| (i32::from(*value) + error).clamp(0, 255))
+            .collect();
+        let wanted_rgb = Rgb {
+            r: wanted[0] as u8,
+            g: wanted[1] as u8,
+            b: wanted[2] as u8,
+        };
+        let picked = colors[nearest_color(colors, wanted_rgb, gamma) as usize];
+        *color = Some(picked);
+        for (channel, value) in [picked.r, picked.g, picked.b].iter().enumerate() {
+            let error = wanted[channel] - i32::from(*value);
+            errors[x + 2][channel] += error * 7 / 16;
+            next_errors[x][channel] += error * 3 / 16;
+            next_errors[x + 1][channel] += error * 5 / 16;
+            next_errors[x + 2][channel] += error / 16;
+        }
+    }
+}
+
+/// Split the colors of `histogram`, weighted by how many pixels use each,
+/// into at most `count` boxes, halving the box with the widest channel at
+/// its median each time. Each box becomes the average of its colors.
+pub(crate) fn median_cut(histogram: &BTreeMap<Rgb, usize>, count: usize) -> Vec<Rgb> {
+    let mut boxes: Vec<Vec<(Rgb, usize)>> = vec![histogram
+        .iter()
+        .map(|(rgb, pixels)| (*rgb, *pixels))
+        .collect()];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .max_by_key(|(_, colors)| widest_channel(colors).1);
+        let index = match widest {
+            Some((index, _)) => index,
+            None => break,
+        };
+        let mut colors = boxes.swap_remove(index);
+        let (channel, _) = widest_channel(&colors);
+        colors.sort_by_key(|(rgb, _)| channel_value(*rgb, channel));
+        let half = colors.iter().map(|(_, pixels)| pixels).sum::<usize>() / 2;
+        let mut seen = 0;
+        let split = colors
+            .iter()
+            .position(|(_, pixels)| {
+                seen += pixels;
+                seen > half
+            })
+            .unwrap()
+            .clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+/// The channel, 0 to 2 for red, green and blue, whose values are spread
+/// the most among `colors`, and how far.
+fn widest_channel(colors: &[(Rgb, usize)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = colors.iter().map(|(rgb, _)| channel_value(*rgb, channel));
+            let min = values.clone().min().unwrap_or(0);
+            let max = values.max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap()
+}
+
+fn channel_value(rgb: Rgb, channel: usize) -> u8 {
+    match channel {
+        0 => rgb.r,
+        1 => rgb.g,
+        _ => rgb.b,
+    }
+}
+
+/// The average of `colors`, weighted by how many pixels use each.
+fn average(colors: &[(Rgb, usize)]) -> Rgb {
+    let pixels = colors.iter().map(|(_, pixels)| *pixels as u64).sum::<u64>();
+    let channel = |channel: usize| {
+        let total = colors
+            .iter()
+            .map(|(rgb, pixels)| u64::from(channel_value(*rgb, channel)) * *pixels as u64)
+            .sum::<u64>();
+        ((total + pixels / 2) / pixels) as u8
+    };
+    Rgb {
+        r: channel(0),
+        g: channel(1),
+        b: channel(2),
+    }
+}
@@ -0,0 +1,110 @@
+//! Region manifests naming rectangles of one image, usually a master sheet,
+//! so each is converted into its own tile block in a single pass:
+//!
+//! ```toml
+//! [[region]]
+//! name = "hud"
+//! x = 0
+//! y = 128
+//! width = 160
+//! height = 16
+//!
+//! [[region]]
+//! name = "font"
+//! x = 0
+//! y = 144
+//! width = 128
+//! height = 48
+//! ```
+//!
+//! Manifests ending in `.json` are read as JSON instead, with the same
+//! fields in a `regions` array:
+//!
+//! ```json
+//! {"regions": [{"name": "hud", "x": 0, "y": 128, "width": 160, "height": 16}]}
+//! ```
+//!
+//! Regions are in pixels, and are widened to the 8x8 tile grid like
+//! [`Converter::rect`](crate::Converter::rect).
+
+use crate::{Error, Rect};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Regions {
+    #[serde(rename = "region", alias = "regions", default)]
+    pub regions: Vec<Region>,
+}
+
+/// A named rectangle of an image.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    /// The generated symbol name for the region's tiles.
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn rect(&self) -> Rect {
+        Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+impl Regions {
+    pub fn load(path: &str) -> Result<Regions, Error> {
+        let manifest_error = |message: String| Error::Config {
+            path: path.to_string(),
+            message,
+        };
+        let contents = fs::read_to_string(path).map_err(|err| manifest_error(err.to_string()))?;
+        let regions = Regions::parse(path, &contents).map_err(manifest_error)?;
+
+        let mut names = BTreeSet::new();
+        for region in &regions.regions {
+            if region.width == 0 || region.height == 0 {
+                return Err(manifest_error(format!(
+                    "region '{}' is empty, its width and height can't be 0",
+                    region.name
+                )));
+            }
+            if !names.insert(region.name.as_str()) {
+                return Err(manifest_error(format!(
+                    "region '{}' is named more than once",
+                    region.name
+                )));
+            }
+        }
+        if regions.regions.is_empty() {
+            return Err(manifest_error(
+                "manifest doesn't list any regions".to_string(),
+            ));
+        }
+        Ok(regions)
+    }
+
+    fn parse(path: &str, contents: &str) -> Result<Regions, String> {
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if !is_json {
+            return toml::from_str(contents).map_err(|err| err.to_string());
+        }
+        #[cfg(feature = "json")]
+        return serde_json::from_str(contents).map_err(|err| err.to_string());
+        #[cfg(not(feature = "json"))]
+        Err("JSON manifests need the 'json' feature".to_string())
+    }
+}
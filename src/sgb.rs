@@ -0,0 +1,110 @@
+/// How many palettes the Super Game Boy colors the screen with.
+pub const SGB_PALETTE_COUNT: usize = 4;
+
+/// The size of the Super Game Boy's screen in tiles, which attribute files
+/// cover.
+pub const SGB_SCREEN_COLUMNS: usize = 20;
+pub const SGB_SCREEN_ROWS: usize = 18;
+
+/// The bytes in each command packet sent to the Super Game Boy.
+pub const SGB_PACKET_BYTES: usize = 16;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const ATTR_BLK: u8 = 0x04;
+
+/// The most data sets an ATTR_BLK command can hold.
+const ATTR_BLK_MAX_SETS: usize = 0x12;
+
+/// The ATTR_BLK control code changing the inside of a block, and with it
+/// the block's border.
+const CHANGE_INSIDE: u8 = 0x01;
+
+/// The first byte of a command: its code, then how many packets it takes.
+fn header(command: u8, packets: usize) -> u8 {
+    (command << 3) | packets as u8
+}
+
+/// The PAL01 and PAL23 packets setting the Super Game Boy's palettes to
+/// `palettes`, four RGB555 words each. Color 0 is shared, so both send the
+/// first palette's.
+pub(crate) fn palette_packets(palettes: &[u16]) -> [[u8; SGB_PACKET_BYTES]; 2] {
+    let color = |palette: usize, color: usize| {
+        palettes
+            .get(palette * 4 + color)
+            .copied()
+            .unwrap_or(0)
+            .to_le_bytes()
+    };
+    [(PAL01, 0), (PAL23, 2)].map(|(command, first)| {
+        let mut packet = [0; SGB_PACKET_BYTES];
+        packet[0] = header(command, 1);
+        let words = std::iter::once(color(0, 0))
+            .chain((1..4).map(|i| color(first, i)))
+            .chain((1..4).map(|i| color(first + 1, i)));
+        for (i, word) in words.enumerate() {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&word);
+        }
+        packet
+    })
+}
+
+/// The ATTR_BLK command coloring the screen with each tile's palette in
+/// `screen`, [`SGB_SCREEN_COLUMNS`] by [`SGB_SCREEN_ROWS`], padded out to
+/// whole packets. The whole screen takes palette 0, then each run of tiles
+/// using another palette becomes a block, joined with the run below when
+/// they line up. `None` if that takes more blocks than one command holds.
+pub(crate) fn attribute_blocks(screen: &[u8]) -> Option<Vec<u8>> {
+    // Each block's palette and its left, top, right and bottom tiles.
+    let mut blocks = vec![(0, [0, 0, SGB_SCREEN_COLUMNS - 1, SGB_SCREEN_ROWS - 1])];
+    let mut above = Vec::new();
+    for (y, row) in screen.chunks(SGB_SCREEN_COLUMNS).enumerate() {
+        let mut open = Vec::new();
+        let mut x = 0;
+        while x < row.len() {
+            let (left, palette) = (x, row[x]);
+            while x < row.len() && row[x] == palette {
+                x += 1;
+            }
+            if palette == 0 {
+                continue;
+            }
+            let joined = above.iter().copied().find(|block: &usize| {
+                let (block_palette, [block_left, _, block_right, _]) = blocks[*block];
+                block_palette == palette && block_left == left && block_right == x - 1
+            });
+            match joined {
+                Some(block) => blocks[block].1[3] = y,
+                None => blocks.push((palette, [left, y, x - 1, y])),
+            }
+            open.push(joined.unwrap_or(blocks.len() - 1));
+        }
+        above = open;
+    }
+    if blocks.len() > ATTR_BLK_MAX_SETS {
+        return None;
+    }
+
+    let packets = (2 + blocks.len() * 6).div_ceil(SGB_PACKET_BYTES);
+    let mut command = vec![header(ATTR_BLK, packets), blocks.len() as u8];
+    for (palette, corners) in blocks {
+        command.extend([CHANGE_INSIDE, palette]);
+        command.extend(corners.map(|corner| corner as u8));
+    }
+    command.resize(packets * SGB_PACKET_BYTES, 0);
+    Some(command)
+}
+
+/// The attribute file for ATTR_TRN giving each tile's palette in `screen`,
+/// [`SGB_SCREEN_COLUMNS`] by [`SGB_SCREEN_ROWS`]: two bits a tile, four
+/// tiles a byte, with the leftmost in the top bits.
+pub(crate) fn attribute_file(screen: &[u8]) -> Vec<u8> {
+    screen
+        .chunks(4)
+        .map(|tiles| {
+            tiles.iter().enumerate().fold(0, |byte, (i, palette)| {
+                byte | (palette & 0x03) << (6 - i * 2)
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,660 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba, GB_MAX_COLOR_COUNT};
+use std::io::Read;
+
+const HEADER_LEN: usize = 128;
+
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+const OLD_PALETTE_CHUNK: u16 = 0x0004;
+
+const LAYER_VISIBLE: u16 = 0x01;
+const LAYER_BACKGROUND: u16 = 0x08;
+/// Set in the header when layer opacity is saved in the file.
+const LAYER_OPACITY_VALID: u32 = 0x01;
+
+const TRANSPARENT: Rgba = Rgba {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0,
+};
+
+/// Aseprite's own `.aseprite` and `.ase` files.
+///
+/// The visible layers are flattened, with every layer drawn in Aseprite's
+/// normal blend mode. A sprite with several frames is read as an
+/// animation, stacked top to bottom. Like a small PNG palette, an indexed
+/// sprite's palette of at most four colors gives the color numbers in its
+/// order.
+#[derive(Clone, Debug, Default)]
+pub struct AsepriteSource {
+    layer: Option<String>,
+    tag: Option<String>,
+}
+
+impl AsepriteSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only draw the layer named `layer`, even if it's hidden. For a group,
+    /// its visible layers are drawn.
+    pub fn layer<S: Into<String>>(mut self, layer: S) -> Self {
+        self.layer = Some(layer.into());
+        self
+    }
+
+    /// Only read the frames of the tag named `tag`, in the order the tag
+    /// plays them.
+    pub fn tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+}
+
+impl ImageSource for AsepriteSource {
+    fn name(&self) -> &'static str {
+        "aseprite"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["aseprite", "ase"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.len() >= 6 && header[4..6] == [0xe0, 0xa5]
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let sprite = Sprite::parse(&data)?;
+        debug!(
+            "Aseprite sprite is {}x{} with {} frame(s), {} layer(s) and {} tag(s)",
+            sprite.width,
+            sprite.height,
+            sprite.frames.len(),
+            sprite.layers.len(),
+            sprite.tags.len()
+        );
+
+        let drawn = sprite.drawn_layers(self.layer.as_deref())?;
+        let frames = sprite.tag_frames(self.tag.as_deref())?;
+        let mut pixels =
+            Vec::with_capacity(sprite.width as usize * sprite.height as usize * frames.len());
+        for &frame in &frames {
+            pixels.extend(sprite.draw_frame(frame, &drawn)?);
+        }
+
+        let palette = Some(&sprite.palette)
+            .filter(|palette| sprite.depth == 8 && palette.len() <= GB_MAX_COLOR_COUNT)
+            .map(|palette| palette.iter().map(Rgba::rgb).collect::<Vec<Rgb>>());
+
+        Ok(SourceImage {
+            width: sprite.width,
+            height: sprite.height * frames.len() as u32,
+            pixels,
+            palette,
+            frames: frames.len() as u32,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "Aseprite",
+        message: message.to_string(),
+    }
+}
+
+/// The parts of an Aseprite file needed to draw its frames.
+struct Sprite {
+    width: u32,
+    height: u32,
+    /// Bits per pixel: 32 for RGBA, 16 for grayscale or 8 for indexed.
+    depth: u16,
+    flags: u32,
+    /// The palette index drawn as transparent in indexed sprites, except on
+    /// the background layer.
+    transparent_index: u8,
+    palette: Vec<Rgba>,
+    layers: Vec<Layer>,
+    /// The cels of each frame.
+    frames: Vec<Vec<Cel>>,
+    tags: Vec<Tag>,
+}
+
+struct Layer {
+    name: String,
+    flags: u16,
+    opacity: u8,
+    /// The group holding this layer, if it's in one.
+    parent: Option<usize>,
+}
+
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    /// Cels are drawn in order of their layer plus this, with ties broken
+    /// by this.
+    z_index: i32,
+    content: CelContent,
+}
+
+enum CelContent {
+    Image {
+        width: u32,
+        height: u32,
+        /// Uncompressed pixels, in the sprite's color depth.
+        data: Vec<u8>,
+    },
+    /// The same layer's cel in another frame.
+    Linked(usize),
+}
+
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    /// 0 forward, 1 reverse, 2 ping-pong or 3 reverse ping-pong.
+    direction: u8,
+}
+
+impl Sprite {
+    fn parse(data: &[u8]) -> Result<Sprite, ImageReadError> {
+        let mut bytes = Bytes { data, position: 0 };
+        let header = bytes.take(HEADER_LEN)?;
+        let mut header = Bytes {
+            data: header,
+            position: 4,
+        };
+        if header.u16()? != 0xa5e0 {
+            return Err(invalid("file has the wrong magic number"));
+        }
+        let frame_count = header.u16()?;
+        let width = u32::from(header.u16()?);
+        let height = u32::from(header.u16()?);
+        let depth = header.u16()?;
+        let flags = header.u32()?;
+        header.take(10)?;
+        let transparent_index = header.u8()?;
+        if ![8, 16, 32].contains(&depth) {
+            return Err(invalid(&format!("{} bit color isn't supported", depth)));
+        }
+
+        let mut sprite = Sprite {
+            width,
+            height,
+            depth,
+            flags,
+            transparent_index,
+            palette: Vec::new(),
+            layers: Vec::new(),
+            frames: Vec::with_capacity(frame_count as usize),
+            tags: Vec::new(),
+        };
+        // The groups enclosing the layer being read, outermost first.
+        let mut groups: Vec<usize> = Vec::new();
+        let mut has_new_palette = false;
+        for _ in 0..frame_count {
+            let frame_start = bytes.position;
+            let frame_len = bytes.u32()? as usize;
+            if bytes.u16()? != 0xf1fa {
+                return Err(invalid("frame has the wrong magic number"));
+            }
+            let old_chunk_count = bytes.u16()?;
+            bytes.take(4)?;
+            let chunk_count = match bytes.u32()? {
+                0 => u32::from(old_chunk_count),
+                count => count,
+            };
+
+            let mut cels = Vec::new();
+            for _ in 0..chunk_count {
+                let chunk_len = bytes.u32()? as usize;
+                let chunk_type = bytes.u16()?;
+                let mut chunk = Bytes {
+                    data: bytes.take(chunk_len.saturating_sub(6))?,
+                    position: 0,
+                };
+                match chunk_type {
+                    LAYER_CHUNK => {
+                        let flags = chunk.u16()?;
+                        let kind = chunk.u16()?;
+                        let level = chunk.u16()? as usize;
+                        chunk.take(6)?;
+                        let opacity = chunk.u8()?;
+                        chunk.take(3)?;
+                        let name = chunk.string()?;
+                        if kind == 2 {
+                            return Err(invalid("tilemap layers aren't supported"));
+                        }
+                        groups.truncate(level);
+                        let index = sprite.layers.len();
+                        sprite.layers.push(Layer {
+                            name,
+                            flags,
+                            opacity,
+                            parent: groups.last().cloned(),
+                        });
+                        groups.push(index);
+                    }
+                    CEL_CHUNK => cels.push(Cel::parse(&mut chunk, depth)?),
+                    PALETTE_CHUNK => {
+                        has_new_palette = true;
+                        let size = chunk.u32()? as usize;
+                        let first = chunk.u32()? as usize;
+                        let last = chunk.u32()? as usize;
+                        chunk.take(8)?;
+                        sprite.palette.resize(size, TRANSPARENT);
+                        for index in first..=last {
+                            let entry_flags = chunk.u16()?;
+                            let rgba = chunk.take(4)?;
+                            if entry_flags & 0x01 != 0 {
+                                chunk.string()?;
+                            }
+                            if let Some(color) = sprite.palette.get_mut(index) {
+                                *color = Rgba {
+                                    r: rgba[0],
+                                    g: rgba[1],
+                                    b: rgba[2],
+                                    a: rgba[3],
+                                };
+                            }
+                        }
+                    }
+                    // Files from older versions of Aseprite only have the
+                    // old palette chunk.
+                    OLD_PALETTE_CHUNK if !has_new_palette => {
+                        let mut index = 0;
+                        for _ in 0..chunk.u16()? {
+                            index += chunk.u8()? as usize;
+                            let count = match chunk.u8()? {
+                                0 => 256,
+                                count => count as usize,
+                            };
+                            if sprite.palette.len() < index + count {
+                                sprite.palette.resize(index + count, TRANSPARENT);
+                            }
+                            for color in &mut sprite.palette[index..index + count] {
+                                let rgb = chunk.take(3)?;
+                                *color = Rgba {
+                                    r: rgb[0],
+                                    g: rgb[1],
+                                    b: rgb[2],
+                                    a: 255,
+                                };
+                            }
+                            index += count;
+                        }
+                    }
+                    TAGS_CHUNK => {
+                        let count = chunk.u16()?;
+                        chunk.take(8)?;
+                        for _ in 0..count {
+                            let from = chunk.u16()? as usize;
+                            let to = chunk.u16()? as usize;
+                            let direction = chunk.u8()?;
+                            chunk.take(12)?;
+                            let name = chunk.string()?;
+                            sprite.tags.push(Tag {
+                                name,
+                                from,
+                                to,
+                                direction,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            sprite.frames.push(cels);
+            bytes.position = frame_start + frame_len;
+        }
+        Ok(sprite)
+    }
+
+    /// Which layers are drawn: every visible layer, or only `name` and its
+    /// visible layers if it's a group.
+    fn drawn_layers(&self, name: Option<&str>) -> Result<Vec<bool>, ImageReadError> {
+        let selected = match name {
+            Some(name) => Some(
+                self.layers
+                    .iter()
+                    .position(|layer| layer.name == name)
+                    .ok_or_else(|| {
+                        invalid(&format!(
+                            "no layer is named '{}', the layers are: {}",
+                            name,
+                            self.names(self.layers.iter().map(|layer| &layer.name))
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        Ok((0..self.layers.len())
+            .map(|mut index| loop {
+                if Some(index) == selected {
+                    break true;
+                }
+                let layer = &self.layers[index];
+                if layer.flags & LAYER_VISIBLE == 0 {
+                    break false;
+                }
+                match layer.parent {
+                    Some(parent) => index = parent,
+                    None => break selected.is_none(),
+                }
+            })
+            .collect())
+    }
+
+    /// The frames to read: every frame, or those of the tag named `name`
+    /// in the order it plays them.
+    fn tag_frames(&self, name: Option<&str>) -> Result<Vec<usize>, ImageReadError> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok((0..self.frames.len()).collect()),
+        };
+        let tag = self
+            .tags
+            .iter()
+            .find(|tag| tag.name == name)
+            .ok_or_else(|| {
+                invalid(&format!(
+                    "no tag is named '{}', the tags are: {}",
+                    name,
+                    self.names(self.tags.iter().map(|tag| &tag.name))
+                ))
+            })?;
+        if tag.from > tag.to || tag.to >= self.frames.len() {
+            return Err(invalid(&format!("tag '{}' has no frames", name)));
+        }
+        let forward: Vec<usize> = (tag.from..=tag.to).collect();
+        let backward: Vec<usize> = forward.iter().rev().cloned().collect();
+        // A ping-pong plays back without repeating the frames at each end.
+        let inner = |frames: &[usize]| frames[1..frames.len().max(2) - 1].to_vec();
+        Ok(match tag.direction {
+            1 => backward,
+            2 => [forward, inner(&backward)].concat(),
+            3 => [backward, inner(&forward)].concat(),
+            _ => forward,
+        })
+    }
+
+    fn names<'a>(&self, names: impl Iterator<Item = &'a String>) -> String {
+        let names: Vec<String> = names.map(|name| format!("'{}'", name)).collect();
+        if names.is_empty() {
+            "(none)".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// Flatten the `drawn` layers of a frame.
+    fn draw_frame(&self, frame: usize, drawn: &[bool]) -> Result<Vec<Rgba>, ImageReadError> {
+        let mut canvas = vec![TRANSPARENT; self.width as usize * self.height as usize];
+        let mut cels: Vec<&Cel> = self.frames[frame]
+            .iter()
+            .filter(|cel| drawn.get(cel.layer).cloned().unwrap_or(false))
+            .collect();
+        cels.sort_by_key(|cel| (cel.layer as i32 + cel.z_index, cel.z_index));
+        for cel in cels {
+            let layer = &self.layers[cel.layer];
+            let (width, height, data) = match cel.content {
+                CelContent::Image {
+                    width,
+                    height,
+                    ref data,
+                } => (width, height, data),
+                CelContent::Linked(linked) => self
+                    .frames
+                    .get(linked)
+                    .and_then(|cels| cels.iter().find(|linked| linked.layer == cel.layer))
+                    .and_then(|linked| match linked.content {
+                        CelContent::Image {
+                            width,
+                            height,
+                            ref data,
+                        } => Some((width, height, data)),
+                        CelContent::Linked(_) => None,
+                    })
+                    .ok_or_else(|| invalid("linked cel refers to a missing cel"))?,
+            };
+            let layer_opacity = if self.flags & LAYER_OPACITY_VALID != 0 {
+                u32::from(layer.opacity)
+            } else {
+                255
+            };
+            let opacity = u32::from(cel.opacity) * layer_opacity / 255;
+            let bytes_per_pixel = (self.depth / 8) as usize;
+            // Pixel data past the cel's size, if any, is left undrawn.
+            let pixels = data
+                .chunks(bytes_per_pixel)
+                .take(width as usize * height as usize);
+            for (i, value) in pixels.enumerate() {
+                let x = cel.x + (i as u32 % width) as i32;
+                let y = cel.y + (i as u32 / width) as i32;
+                if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+                    continue;
+                }
+                let mut pixel = self.pixel(value, layer)?;
+                pixel.a = (u32::from(pixel.a) * opacity / 255) as u8;
+                let below = &mut canvas[(y as u32 * self.width + x as u32) as usize];
+                *below = blend(*below, pixel);
+            }
+        }
+        Ok(canvas)
+    }
+
+    fn pixel(&self, value: &[u8], layer: &Layer) -> Result<Rgba, ImageReadError> {
+        Ok(match *value {
+            [r, g, b, a] => Rgba { r, g, b, a },
+            [gray, a] => Rgba {
+                r: gray,
+                g: gray,
+                b: gray,
+                a,
+            },
+            [index] if index == self.transparent_index && layer.flags & LAYER_BACKGROUND == 0 => {
+                TRANSPARENT
+            }
+            [index] => *self
+                .palette
+                .get(index as usize)
+                .ok_or_else(|| invalid("color index is outside the palette"))?,
+            _ => return Err(invalid("pixel has the wrong size")),
+        })
+    }
+}
+
+impl Cel {
+    fn parse(chunk: &mut Bytes, depth: u16) -> Result<Cel, ImageReadError> {
+        let layer = chunk.u16()? as usize;
+        let x = i32::from(chunk.u16()? as i16);
+        let y = i32::from(chunk.u16()? as i16);
+        let opacity = chunk.u8()?;
+        let kind = chunk.u16()?;
+        let z_index = i32::from(chunk.u16()? as i16);
+        chunk.take(5)?;
+        let content = match kind {
+            0 | 2 => {
+                let width = u32::from(chunk.u16()?);
+                let height = u32::from(chunk.u16()?);
+                if width == 0 || height == 0 {
+                    return Err(invalid("cel has no pixels"));
+                }
+                let rest = chunk.take(chunk.data.len() - chunk.position)?;
+                let data = if kind == 0 {
+                    rest.to_vec()
+                } else {
+                    miniz_oxide::inflate::decompress_to_vec_zlib(rest)
+                        .map_err(|_| invalid("cel's compressed pixels are corrupt"))?
+                };
+                let len = width as usize * height as usize * (depth / 8) as usize;
+                if data.len() < len {
+                    return Err(invalid("cel has too few pixels"));
+                }
+                CelContent::Image {
+                    width,
+                    height,
+                    data,
+                }
+            }
+            1 => CelContent::Linked(chunk.u16()? as usize),
+            _ => return Err(invalid("tilemap cels aren't supported")),
+        };
+        Ok(Cel {
+            layer,
+            x,
+            y,
+            opacity,
+            z_index,
+            content,
+        })
+    }
+}
+
+/// Draw `above` over `below`, mixing their colors by alpha.
+fn blend(below: Rgba, above: Rgba) -> Rgba {
+    if above.a == 255 || below.a == 0 {
+        return above;
+    }
+    let above_alpha = u32::from(above.a);
+    let below_alpha = u32::from(below.a) * (255 - above_alpha) / 255;
+    let alpha = above_alpha + below_alpha;
+    if alpha == 0 {
+        return TRANSPARENT;
+    }
+    let mix = |above: u8, below: u8| {
+        ((u32::from(above) * above_alpha + u32::from(below) * below_alpha) / alpha) as u8
+    };
+    Rgba {
+        r: mix(above.r, below.r),
+        g: mix(above.g, below.g),
+        b: mix(above.b, below.b),
+        a: alpha as u8,
+    }
+}
+
+/// A little-endian cursor over a file's bytes.
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ImageReadError> {
+        let taken = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("file ends early"))?;
+        self.position += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageReadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImageReadError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImageReadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A string prefixed with its length in bytes.
+    fn string(&mut self) -> Result<String, ImageReadError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one-frame, one-layer 32 bit `width`x`height` sprite with a raw cel
+    /// `cel_width`x`cel_height` in size holding `pixels`.
+    fn aseprite(
+        width: u16,
+        height: u16,
+        cel_width: u16,
+        cel_height: u16,
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let mut layer = Vec::new();
+        layer.extend(LAYER_VISIBLE.to_le_bytes());
+        layer.extend([0; 10]);
+        layer.extend([255, 0, 0, 0]);
+        layer.extend(5u16.to_le_bytes());
+        layer.extend(b"Layer");
+        let mut cel = vec![0; 7];
+        cel[6] = 255;
+        cel.extend([0; 9]);
+        cel.extend(cel_width.to_le_bytes());
+        cel.extend(cel_height.to_le_bytes());
+        cel.extend(pixels);
+
+        let mut frame = Vec::new();
+        for (chunk_type, chunk) in [(LAYER_CHUNK, layer), (CEL_CHUNK, cel)] {
+            frame.extend((chunk.len() as u32 + 6).to_le_bytes());
+            frame.extend(chunk_type.to_le_bytes());
+            frame.extend(chunk);
+        }
+        let mut data = vec![0; HEADER_LEN];
+        data[4..6].copy_from_slice(&0xa5e0u16.to_le_bytes());
+        data[6..8].copy_from_slice(&1u16.to_le_bytes());
+        data[8..10].copy_from_slice(&width.to_le_bytes());
+        data[10..12].copy_from_slice(&height.to_le_bytes());
+        data[12..14].copy_from_slice(&32u16.to_le_bytes());
+        data.extend((frame.len() as u32 + 16).to_le_bytes());
+        data.extend(0xf1fau16.to_le_bytes());
+        data.extend([2, 0, 0, 0, 0, 0]);
+        data.extend(2u32.to_le_bytes());
+        data.extend(frame);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        AsepriteSource::new().decode(&mut &data[..])
+    }
+
+    #[test]
+    fn draws_a_cel() {
+        let red = [255, 0, 0, 255];
+        let image = decode(&aseprite(2, 2, 1, 1, &red)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.pixels[0],
+            Rgba {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255
+            }
+        );
+        assert_eq!(image.pixels[1], TRANSPARENT);
+    }
+
+    #[test]
+    fn rejects_zero_sized_cels() {
+        for (width, height) in [(0, 1), (1, 0), (0, 0)] {
+            assert!(matches!(
+                decode(&aseprite(8, 8, width, height, &[0; 8])),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use super::{GbrSource, ImageSource, SourceImage};
+use crate::image::ImageReadError;
+use crate::tile::{Tilemap, CGB_X_FLIP, CGB_Y_FLIP};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+const MAGIC: &[u8] = b"GBO1";
+
+/// Starts every object, followed by its type, id, master id, checksum and
+/// length.
+const OBJECT_MARKER: &[u8] = b"HPJMTL";
+const OBJECT_HEADER_LEN: usize = 20;
+
+/// The object holding the map's size and tile file.
+const MAP: u16 = 0x02;
+/// The object holding the tile at each position of the map.
+const MAP_TILE_DATA: u16 = 0x03;
+
+/// Game Boy Map Builder `.gbm` maps, read with the tiles of the GBTD file
+/// they were drawn with.
+///
+/// The tiles come out as they're stored in the tile file, followed by the
+/// map. Flipped tiles are kept as the map's Game Boy Color attributes,
+/// since a plain Game Boy background can't flip them. Color and Super Game
+/// Boy palettes are left out.
+#[derive(Clone, Debug, Default)]
+pub struct GbmSource {
+    tile_dir: Option<PathBuf>,
+}
+
+impl GbmSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look for each map's tile file in `tile_dir`, usually the directory
+    /// holding the map, instead of at the path saved in the map.
+    pub fn tile_dir<P: Into<PathBuf>>(mut self, tile_dir: P) -> Self {
+        self.tile_dir = Some(tile_dir.into());
+        self
+    }
+
+    /// Where to read the tile file saved in a map as `saved`. It's often
+    /// an absolute Windows path, so the file name alone is tried too.
+    fn tile_path(&self, saved: &str) -> PathBuf {
+        let tile_dir = match &self.tile_dir {
+            Some(tile_dir) => tile_dir,
+            None => return PathBuf::from(saved),
+        };
+        let saved = saved.replace('\\', "/");
+        let file_name = saved.rsplit('/').next().unwrap_or(&saved);
+        let path = tile_dir.join(&saved);
+        if path.is_file() {
+            path
+        } else {
+            tile_dir.join(file_name)
+        }
+    }
+}
+
+impl ImageSource for GbmSource {
+    fn name(&self) -> &'static str {
+        "gbm"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["gbm"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(MAGIC)
+    }
+
+    fn referenced_files(&self, reader: &mut dyn Read) -> Result<Vec<PathBuf>, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let (map, _) = objects(&data)?;
+        Ok(vec![self.tile_path(&saved_tile_file(map))])
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let (map, map_tiles) = objects(&data)?;
+        let u32_at = |i: usize| u32::from_le_bytes([map[i], map[i + 1], map[i + 2], map[i + 3]]);
+        let (width, height) = (u32_at(128), u32_at(132));
+        let saved = saved_tile_file(map);
+        debug!("GBM map is {}x{} tiles of '{}'", width, height, saved);
+
+        let tile_path = self.tile_path(&saved);
+        let mut tile_file = File::open(&tile_path).map_err(|err| {
+            invalid(&format!(
+                "couldn't open the tile file '{}': {}",
+                tile_path.display(),
+                err
+            ))
+        })?;
+        let mut tiles = GbrSource.decode(&mut tile_file)?;
+        if tiles.width != 8 {
+            return Err(invalid("maps can only use 8x8 tiles"));
+        }
+        let tile_count = tiles.height / 8;
+
+        // Each position is 3 bytes, most significant first. The top bits
+        // are the vertical and horizontal flips, then the palettes, and the
+        // low 10 bits the tile number.
+        let len = width as usize * height as usize;
+        let records = map_tiles
+            .get(..len * 3)
+            .ok_or_else(|| invalid("map tiles end early"))?;
+        let mut map_tiles = Vec::with_capacity(len);
+        let mut attributes = Vec::with_capacity(len);
+        for record in records.chunks(3) {
+            attributes.push(
+                ((record[0] >> 7 & 0x01) * CGB_Y_FLIP) | ((record[0] >> 6 & 0x01) * CGB_X_FLIP),
+            );
+            let tile = u32::from(record[1] & 0x03) << 8 | u32::from(record[2]);
+            if tile >= tile_count {
+                return Err(invalid(&format!(
+                    "the map uses tile {}, but the tile file only has {}",
+                    tile, tile_count
+                )));
+            }
+            map_tiles.push(
+                u8::try_from(tile).map_err(|_| invalid("maps can only use the first 256 tiles"))?,
+            );
+        }
+
+        tiles.tilemaps.push(Tilemap {
+            name: None,
+            width,
+            height,
+            tiles: map_tiles,
+            attributes,
+        });
+        Ok(tiles)
+    }
+}
+
+/// A map file's map object, cut to the fields read from it, and its map
+/// tile data object.
+fn objects(data: &[u8]) -> Result<(&[u8], &[u8]), ImageReadError> {
+    if !data.starts_with(MAGIC) {
+        return Err(invalid("file doesn't start with a GBM signature"));
+    }
+
+    let mut map = None;
+    let mut map_tiles = None;
+    let mut position = MAGIC.len();
+    while position < data.len() {
+        let header = data
+            .get(position..position + OBJECT_HEADER_LEN)
+            .filter(|header| header.starts_with(OBJECT_MARKER))
+            .ok_or_else(|| invalid("object header is corrupt"))?;
+        let object_type = u16::from_le_bytes([header[6], header[7]]);
+        let len = u32::from_le_bytes([header[16], header[17], header[18], header[19]]) as usize;
+        let start = position + OBJECT_HEADER_LEN;
+        let object = data
+            .get(start..start + len)
+            .ok_or_else(|| invalid("file ends early"))?;
+        debug!("GBM object {:#04x} is {} bytes", object_type, len);
+        match object_type {
+            MAP if map.is_none() => map = Some(object),
+            MAP_TILE_DATA if map_tiles.is_none() => map_tiles = Some(object),
+            _ => {}
+        }
+        position = start + len;
+    }
+    let map = map.ok_or_else(|| invalid("file has no map"))?;
+    let map_tiles = map_tiles.ok_or_else(|| invalid("file has no map tiles"))?;
+
+    // The map name is 128 bytes, followed by the map size, the number of
+    // properties and the 256 byte tile file name.
+    let map = map
+        .get(..396)
+        .ok_or_else(|| invalid("map object ends early"))?;
+    Ok((map, map_tiles))
+}
+
+/// The tile file name saved in a map object.
+fn saved_tile_file(map: &[u8]) -> String {
+    let saved = map[140..396].split(|&byte| byte == 0).next().unwrap_or(&[]);
+    String::from_utf8_lossy(saved).into_owned()
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "GBM",
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn object(marker: &[u8], object_type: u16, body: &[u8]) -> Vec<u8> {
+        let mut data = marker.to_vec();
+        data.extend(object_type.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend((body.len() as u32).to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    /// A `width`x`height` map drawn with the tiles in `tile_file`, followed
+    /// by its 3 byte `records`.
+    fn gbm(width: u32, height: u32, tile_file: &str, records: &[[u8; 3]]) -> Vec<u8> {
+        let mut map = vec![0; 396];
+        map[128..132].copy_from_slice(&width.to_le_bytes());
+        map[132..136].copy_from_slice(&height.to_le_bytes());
+        map[140..140 + tile_file.len()].copy_from_slice(tile_file.as_bytes());
+        let mut data = MAGIC.to_vec();
+        data.extend(object(OBJECT_MARKER, MAP, &map));
+        data.extend(object(OBJECT_MARKER, MAP_TILE_DATA, &records.concat()));
+        data
+    }
+
+    /// A directory named for `test` holding `tiles.gbr`, with two blank
+    /// 8x8 tiles.
+    fn tile_dir(test: &str) -> PathBuf {
+        let tile_dir =
+            std::env::temp_dir().join(format!("gbtile-gbm-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&tile_dir).unwrap();
+        let mut tiles = vec![0; 30];
+        tiles.extend([8, 0, 8, 0, 2, 0, 0, 0, 0, 0]);
+        tiles.extend([0; 128]);
+        let mut gbr = b"GBO0".to_vec();
+        gbr.extend([2, 0, 1, 0]);
+        gbr.extend((tiles.len() as u32).to_le_bytes());
+        gbr.extend(tiles);
+        fs::write(tile_dir.join("tiles.gbr"), gbr).unwrap();
+        tile_dir
+    }
+
+    fn decode(tile_dir: &Path, data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        GbmSource::new().tile_dir(tile_dir).decode(&mut &data[..])
+    }
+
+    #[test]
+    fn decodes_map_tiles_and_flips() {
+        let tile_dir = tile_dir("decodes");
+        let data = gbm(2, 1, "C:\\art\\tiles.gbr", &[[0x00, 0, 1], [0xc0, 0, 0]]);
+        let image = decode(&tile_dir, &data).unwrap();
+        fs::remove_dir_all(tile_dir).unwrap();
+        assert_eq!((image.width, image.height), (8, 16));
+        let tilemap = &image.tilemaps[0];
+        assert_eq!((tilemap.width, tilemap.height), (2, 1));
+        assert_eq!(tilemap.tiles, vec![1, 0]);
+        assert_eq!(tilemap.attributes, vec![0, CGB_X_FLIP | CGB_Y_FLIP]);
+    }
+
+    #[test]
+    fn references_the_tile_file() {
+        let data = gbm(1, 1, "C:\\art\\tiles.gbr", &[[0, 0, 0]]);
+        let source = GbmSource::new().tile_dir("maps");
+        assert_eq!(
+            source.referenced_files(&mut &data[..]).unwrap(),
+            vec![PathBuf::from("maps").join("tiles.gbr")]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_maps() {
+        let data = gbm(2, 1, "tiles.gbr", &[[0, 0, 0], [0, 0, 1]]);
+        for len in [0, 3, 10, 30, 400, data.len() - 1] {
+            assert!(matches!(
+                GbmSource::new().referenced_files(&mut &data[..len]),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+        let tile_dir = tile_dir("truncated");
+        let result = decode(&tile_dir, &gbm(2, 1, "tiles.gbr", &[[0, 0, 0]]));
+        fs::remove_dir_all(tile_dir).unwrap();
+        assert!(matches!(result, Err(ImageReadError::Invalid { .. })));
+    }
+
+    #[test]
+    fn rejects_tiles_past_the_tile_file() {
+        let tile_dir = tile_dir("past");
+        let result = decode(&tile_dir, &gbm(1, 1, "tiles.gbr", &[[0, 0, 2]]));
+        fs::remove_dir_all(tile_dir).unwrap();
+        assert!(matches!(result, Err(ImageReadError::Invalid { .. })));
+    }
+}
@@ -0,0 +1,177 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba};
+use std::io::Read;
+
+const MAGIC: &[u8] = b"GBO0";
+
+/// The object holding the tiles themselves.
+const TILE_DATA: u16 = 0x02;
+
+/// The shades each color number is drawn in, from white to black.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+/// Game Boy Tile Designer `.gbr` files.
+///
+/// GBTD stores every pixel as its color number already, so tiles come out
+/// exactly as they were drawn. The tiles are stacked top to bottom, each as
+/// wide as the tile size, so tiles larger than 8x8 are split left to right,
+/// then top to bottom.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GbrSource;
+
+impl ImageSource for GbrSource {
+    fn name(&self) -> &'static str {
+        "gbr"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["gbr"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(MAGIC)
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if !data.starts_with(MAGIC) {
+            return Err(invalid("file doesn't start with a GBR signature"));
+        }
+
+        // Each object is a type, an id and a length, followed by that many
+        // bytes. Only the first tile data object is used.
+        let mut position = MAGIC.len();
+        let tiles = loop {
+            let header = data
+                .get(position..position + 8)
+                .ok_or_else(|| invalid("file has no tile data"))?;
+            let object_type = u16::from_le_bytes([header[0], header[1]]);
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let object = data
+                .get(position + 8..position + 8 + len)
+                .ok_or_else(|| invalid("file ends early"))?;
+            debug!("GBR object {:#04x} is {} bytes", object_type, len);
+            if object_type == TILE_DATA {
+                break object;
+            }
+            position += 8 + len;
+        };
+
+        // The tile name is 30 bytes, followed by the tile size, the number
+        // of tiles and four bytes of display colors.
+        let u16_at = |i: usize| -> Result<u32, ImageReadError> {
+            tiles
+                .get(i..i + 2)
+                .map(|bytes| u32::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+                .ok_or_else(|| invalid("tile data ends early"))
+        };
+        let (width, height, count) = (u16_at(30)?, u16_at(32)?, u16_at(34)?);
+        debug!("GBR holds {} {}x{} tile(s)", count, width, height);
+        if width == 0 || height == 0 || count == 0 {
+            return Err(invalid("file has no tiles"));
+        }
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|len| len.checked_mul(count as usize))
+            .ok_or_else(|| invalid("tile data ends early"))?;
+        let pixels = tiles
+            .get(40..40 + len)
+            .ok_or_else(|| invalid("tile data ends early"))?
+            .iter()
+            .map(|&color| {
+                let gray = *SHADES
+                    .get(usize::from(color))
+                    .ok_or_else(|| invalid("color numbers must be 0 to 3"))?;
+                Ok(Rgba {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a: 255,
+                })
+            })
+            .collect::<Result<Vec<Rgba>, ImageReadError>>()?;
+        let palette = SHADES
+            .iter()
+            .map(|&gray| Rgb {
+                r: gray,
+                g: gray,
+                b: gray,
+            })
+            .collect();
+
+        Ok(SourceImage {
+            width,
+            height: height * count,
+            pixels,
+            palette: Some(palette),
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "GBR",
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GBR file with a tile data object holding `count` tiles of
+    /// `width`x`height` pixels, followed by `pixels`.
+    fn gbr(width: u16, height: u16, count: u16, pixels: &[u8]) -> Vec<u8> {
+        let mut tiles = vec![0; 30];
+        tiles.extend(width.to_le_bytes());
+        tiles.extend(height.to_le_bytes());
+        tiles.extend(count.to_le_bytes());
+        tiles.extend([0; 4]);
+        tiles.extend(pixels);
+        let mut data = MAGIC.to_vec();
+        data.extend(TILE_DATA.to_le_bytes());
+        data.extend([1, 0]);
+        data.extend((tiles.len() as u32).to_le_bytes());
+        data.extend(tiles);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        GbrSource.decode(&mut &data[..])
+    }
+
+    #[test]
+    fn decodes_tiles_as_their_color_numbers() {
+        let pixels: Vec<u8> = (0..128).map(|i| (i % 4) as u8).collect();
+        let image = decode(&gbr(8, 8, 2, &pixels)).unwrap();
+        assert_eq!((image.width, image.height), (8, 16));
+        assert_eq!(image.pixels[1].r, SHADES[1]);
+        assert_eq!(image.pixels[3].r, SHADES[3]);
+    }
+
+    #[test]
+    fn rejects_truncated_tiles() {
+        for len in [0, 63, 127] {
+            assert!(matches!(
+                decode(&gbr(8, 8, 2, &vec![0; len])),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+        assert!(matches!(
+            decode(&gbr(u16::MAX, u16::MAX, u16::MAX, &[0; 64])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_color_numbers_past_3() {
+        assert!(matches!(
+            decode(&gbr(8, 8, 1, &[4; 64])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+}
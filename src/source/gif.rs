@@ -0,0 +1,487 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba, GB_MAX_COLOR_COUNT};
+use std::io::Read;
+
+/// The largest code GIF's LZW compression uses, with 12 bit codes.
+const MAX_CODES: usize = 4096;
+
+/// GIF images. The frames of an animated GIF are stacked top to bottom.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GifSource;
+
+impl ImageSource for GifSource {
+    fn name(&self) -> &'static str {
+        "gif"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["gif"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a")
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let gif = Gif::parse(&data)?;
+        debug!(
+            "GIF is {}x{} with {} frame(s)",
+            gif.width,
+            gif.height,
+            gif.frames.len()
+        );
+        if gif.frames.is_empty() {
+            return Err(invalid("no image data"));
+        }
+
+        // Each frame is drawn over what the previous ones left behind, and
+        // the whole canvas is stacked below the frames before it.
+        let transparent = Rgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        let mut canvas = vec![transparent; gif.width as usize * gif.height as usize];
+        let mut pixels = Vec::with_capacity(canvas.len() * gif.frames.len());
+        for frame in &gif.frames {
+            let previous = canvas.clone();
+            frame.draw(&mut canvas, gif.width, gif.height)?;
+            pixels.extend_from_slice(&canvas);
+            match frame.disposal {
+                Disposal::Background => {
+                    frame.clear(&mut canvas, gif.width, gif.height, transparent)
+                }
+                Disposal::Previous => canvas = previous,
+                Disposal::Keep => {}
+            }
+        }
+
+        // The artist picked every color in a small table, so its order
+        // gives the color numbers like a small PNG palette. Every frame has
+        // to share it for the numbers to agree between frames.
+        let color_table = &gif.frames[0].color_table;
+        let palette = Some(color_table.clone()).filter(|color_table| {
+            color_table.len() <= GB_MAX_COLOR_COUNT
+                && gif
+                    .frames
+                    .iter()
+                    .all(|frame| frame.color_table == *color_table)
+        });
+
+        Ok(SourceImage {
+            width: gif.width,
+            height: gif.height * gif.frames.len() as u32,
+            pixels,
+            palette,
+            frames: gif.frames.len() as u32,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "GIF",
+        message: message.to_string(),
+    }
+}
+
+/// The parts of a GIF file needed to draw its frames.
+struct Gif {
+    width: u32,
+    height: u32,
+    frames: Vec<Frame>,
+}
+
+/// One image in a GIF, covering some rectangle of the canvas.
+struct Frame {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    /// The local color table, or the global one if the frame has none.
+    color_table: Vec<Rgb>,
+    transparent_index: Option<u8>,
+    disposal: Disposal,
+    /// Color table indices, top to bottom, already de-interlaced.
+    indices: Vec<u8>,
+}
+
+/// What happens to a frame's rectangle before the next frame is drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Disposal {
+    Keep,
+    /// Cleared to the background, which is drawn as transparent.
+    Background,
+    /// Restored to how it was before the frame was drawn.
+    Previous,
+}
+
+impl Disposal {
+    /// Read the disposal method from a graphic control extension's flags.
+    fn from_flags(flags: u8) -> Disposal {
+        match (flags >> 2) & 0x07 {
+            2 => Disposal::Background,
+            3 => Disposal::Previous,
+            _ => Disposal::Keep,
+        }
+    }
+}
+
+impl Frame {
+    /// Fill the frame's rectangle of the canvas with `pixel`.
+    fn clear(&self, pixels: &mut [Rgba], width: u32, height: u32, pixel: Rgba) {
+        for y in self.top..(self.top + self.height).min(height) {
+            for x in self.left..(self.left + self.width).min(width) {
+                pixels[(y * width + x) as usize] = pixel;
+            }
+        }
+    }
+
+    /// Draw the frame onto a canvas of `pixels`, leaving its transparent
+    /// pixels and anything outside the canvas alone.
+    fn draw(&self, pixels: &mut [Rgba], width: u32, height: u32) -> Result<(), ImageReadError> {
+        for (i, &index) in self.indices.iter().enumerate() {
+            let x = self.left + i as u32 % self.width;
+            let y = self.top + i as u32 / self.width;
+            if Some(index) == self.transparent_index || x >= width || y >= height {
+                continue;
+            }
+            let rgb = self
+                .color_table
+                .get(index as usize)
+                .ok_or_else(|| invalid("color index is outside the color table"))?;
+            pixels[(y * width + x) as usize] = Rgba {
+                r: rgb.r,
+                g: rgb.g,
+                b: rgb.b,
+                a: 255,
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Gif {
+    fn parse(data: &[u8]) -> Result<Gif, ImageReadError> {
+        let mut bytes = Bytes { data, position: 6 };
+        let width = bytes.u16()? as u32;
+        let height = bytes.u16()? as u32;
+        let flags = bytes.u8()?;
+        bytes.take(2)?; // Background color and pixel aspect ratio.
+        let global_color_table = if flags & 0x80 != 0 {
+            Some(bytes.color_table(flags)?)
+        } else {
+            None
+        };
+
+        let mut frames = Vec::new();
+        let mut transparent_index = None;
+        let mut disposal = Disposal::Keep;
+        loop {
+            match bytes.u8()? {
+                // Extension
+                0x21 => {
+                    let label = bytes.u8()?;
+                    let blocks = bytes.sub_blocks()?;
+                    // A graphic control extension applies to the next frame.
+                    if label == 0xf9 && blocks.len() >= 4 {
+                        transparent_index = Some(blocks[3]).filter(|_| blocks[0] & 0x01 != 0);
+                        disposal = Disposal::from_flags(blocks[0]);
+                    }
+                }
+                // Image descriptor
+                0x2c => {
+                    let left = bytes.u16()? as u32;
+                    let top = bytes.u16()? as u32;
+                    let frame_width = bytes.u16()? as u32;
+                    let frame_height = bytes.u16()? as u32;
+                    let flags = bytes.u8()?;
+                    let color_table = if flags & 0x80 != 0 {
+                        bytes.color_table(flags)?
+                    } else {
+                        global_color_table
+                            .clone()
+                            .ok_or_else(|| invalid("frame has no color table"))?
+                    };
+                    let min_code_size = bytes.u8()?;
+                    let pixel_count = frame_width as usize * frame_height as usize;
+                    let mut indices = decode_lzw(min_code_size, &bytes.sub_blocks()?, pixel_count)?;
+                    if flags & 0x40 != 0 {
+                        indices = deinterlace(&indices, frame_width as usize);
+                    }
+                    frames.push(Frame {
+                        left,
+                        top,
+                        width: frame_width,
+                        height: frame_height,
+                        color_table,
+                        transparent_index: transparent_index.take(),
+                        disposal: std::mem::replace(&mut disposal, Disposal::Keep),
+                        indices,
+                    });
+                }
+                // Trailer
+                0x3b => break,
+                _ => return Err(invalid("unknown block")),
+            }
+        }
+        Ok(Gif {
+            width,
+            height,
+            frames,
+        })
+    }
+}
+
+/// A cursor over the bytes of a GIF file.
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ImageReadError> {
+        let taken = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("file ends early"))?;
+        self.position += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageReadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImageReadError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A color table, sized by the low 3 bits of `flags`.
+    fn color_table(&mut self, flags: u8) -> Result<Vec<Rgb>, ImageReadError> {
+        let len = 2 << (flags & 0x07);
+        Ok(self
+            .take(len * 3)?
+            .chunks(3)
+            .map(|color| Rgb {
+                r: color[0],
+                g: color[1],
+                b: color[2],
+            })
+            .collect())
+    }
+
+    /// A run of length-prefixed sub-blocks, joined together.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, ImageReadError> {
+        let mut joined = Vec::new();
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                return Ok(joined);
+            }
+            joined.extend_from_slice(self.take(len)?);
+        }
+    }
+}
+
+/// Decompress GIF's variant of LZW into `pixel_count` color table indices.
+fn decode_lzw(
+    min_code_size: u8,
+    data: &[u8],
+    pixel_count: usize,
+) -> Result<Vec<u8>, ImageReadError> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err(invalid("bad LZW code size"));
+    }
+    let clear_code = 1 << min_code_size;
+    let end_code = clear_code + 1;
+    // Each code's string is its prefix code's string followed by its
+    // suffix. The first byte is kept too, for building the next entry.
+    let mut prefixes = [0u16; MAX_CODES];
+    let mut suffixes = [0u8; MAX_CODES];
+    let mut first_bytes = [0u8; MAX_CODES];
+    for code in 0..clear_code {
+        suffixes[code] = code as u8;
+        first_bytes[code] = code as u8;
+    }
+
+    let mut indices = Vec::with_capacity(pixel_count);
+    let mut string = Vec::new();
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next_code = end_code + 1;
+    let mut previous: Option<usize> = None;
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut data = data.iter();
+    while indices.len() < pixel_count {
+        while bit_count < code_size {
+            let byte = *data
+                .next()
+                .ok_or_else(|| invalid("image data ends early"))?;
+            bits |= (byte as u32) << bit_count;
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear_code {
+            code_size = min_code_size as u32 + 1;
+            next_code = end_code + 1;
+            previous = None;
+            continue;
+        }
+        if code == end_code {
+            break;
+        }
+        let previous_code = match previous {
+            Some(previous_code) => previous_code,
+            None if code < clear_code => {
+                indices.push(code as u8);
+                previous = Some(code);
+                continue;
+            }
+            None => return Err(invalid("bad LZW code")),
+        };
+        let first_byte = match code {
+            code if code < next_code => first_bytes[code],
+            // The code being defined right now: the previous string plus
+            // its own first byte.
+            code if code == next_code => first_bytes[previous_code],
+            _ => return Err(invalid("bad LZW code")),
+        };
+        if next_code < MAX_CODES {
+            prefixes[next_code] = previous_code as u16;
+            suffixes[next_code] = first_byte;
+            first_bytes[next_code] = first_bytes[previous_code];
+            next_code += 1;
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+
+        string.clear();
+        let mut link = code;
+        while link > end_code {
+            string.push(suffixes[link]);
+            link = prefixes[link] as usize;
+        }
+        string.push(suffixes[link]);
+        indices.extend(string.iter().rev());
+        previous = Some(code);
+    }
+    if indices.len() < pixel_count {
+        return Err(invalid("image data ends early"));
+    }
+    indices.truncate(pixel_count);
+    Ok(indices)
+}
+
+/// Put the rows of an interlaced frame back in order. They're stored as
+/// every 8th row from row 0, every 8th from row 4, every 4th from row 2,
+/// then every other row from row 1.
+fn deinterlace(indices: &[u8], width: usize) -> Vec<u8> {
+    let height = indices.len() / width.max(1);
+    let order = (0..height)
+        .step_by(8)
+        .chain((4..height).step_by(8))
+        .chain((2..height).step_by(4))
+        .chain((1..height).step_by(2));
+    let mut deinterlaced = vec![0; indices.len()];
+    for (stored, y) in indices.chunks(width.max(1)).zip(order) {
+        deinterlaced[y * width..(y + 1) * width].copy_from_slice(stored);
+    }
+    deinterlaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack LZW `codes`, each with its size in bits, least significant bit
+    /// first.
+    fn lzw(codes: &[(u32, u32)]) -> Vec<u8> {
+        let (mut data, mut bits, mut bit_count) = (Vec::new(), 0, 0);
+        for &(code, size) in codes {
+            bits |= code << bit_count;
+            bit_count += size;
+            while bit_count >= 8 {
+                data.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        }
+        if bit_count > 0 {
+            data.push(bits as u8);
+        }
+        data
+    }
+
+    /// A `width`x`height` GIF with a four color global table and one frame
+    /// of `image_data`, compressed with a minimum code size of 2.
+    fn gif(width: u16, height: u16, image_data: &[u8]) -> Vec<u8> {
+        let mut data = b"GIF89a".to_vec();
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend([0x81, 0, 0]);
+        data.extend([
+            0xff, 0xff, 0xff, 0xaa, 0xaa, 0xaa, 0x55, 0x55, 0x55, 0, 0, 0,
+        ]);
+        data.extend([0x2c, 0, 0, 0, 0]);
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend([0, 2, image_data.len() as u8]);
+        data.extend(image_data);
+        data.extend([0, 0x3b]);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        GifSource.decode(&mut &data[..])
+    }
+
+    #[test]
+    fn decodes_lzw_codes() {
+        // Clear, 1, then the code being defined (1 1), then 2, after which
+        // codes grow to 4 bits for the end code.
+        let image_data = lzw(&[(4, 3), (1, 3), (6, 3), (2, 3), (5, 4)]);
+        let image = decode(&gif(2, 2, &image_data)).unwrap();
+        let shades: Vec<u8> = image.pixels.iter().map(|pixel| pixel.r).collect();
+        assert_eq!(shades, vec![0xaa, 0xaa, 0xaa, 0x55]);
+        assert_eq!(image.palette.map(|palette| palette.len()), Some(4));
+    }
+
+    #[test]
+    fn rejects_codes_past_the_next_code() {
+        let image_data = lzw(&[(4, 3), (1, 3), (7, 3), (5, 3)]);
+        assert!(matches!(
+            decode(&gif(2, 2, &image_data)),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_image_data() {
+        let image_data = lzw(&[(4, 3), (1, 3), (5, 3)]);
+        assert!(matches!(
+            decode(&gif(2, 2, &image_data)),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = gif(2, 2, &lzw(&[(4, 3), (1, 3), (6, 3), (2, 3), (5, 4)]));
+        for len in [6, 13, 30, data.len() - 1] {
+            assert!(matches!(
+                decode(&data[..len]),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,704 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgba};
+use std::f32::consts::PI;
+use std::io::Read;
+
+/// The most bits a coefficient's value is coded in.
+const MAX_VALUE_BITS: u8 = 16;
+
+/// The position in an 8x8 block of each coefficient, in the order they're
+/// stored.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Baseline JPEG images, decoded to their RGB colors.
+///
+/// Compression smears and speckles the colors of pixel art into many more
+/// than four, so JPEGs need `--quantize`, `--luminance` or `--palette` to
+/// reduce them, like any other image with too many colors.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct JpegSource;
+
+impl ImageSource for JpegSource {
+    fn name(&self) -> &'static str {
+        "jpeg"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["jpg", "jpeg"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(&[0xff, 0xd8, 0xff])
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let jpeg = Jpeg::decode(&data)?;
+        debug!(
+            "JPEG is {}x{} with {} component(s)",
+            jpeg.width,
+            jpeg.height,
+            jpeg.components.len()
+        );
+
+        let pixels = jpeg.pixels();
+
+        Ok(SourceImage {
+            width: jpeg.width,
+            height: jpeg.height,
+            pixels,
+            palette: None,
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "JPEG",
+        message: message.to_string(),
+    }
+}
+
+/// A decoded JPEG, holding each component's samples.
+struct Jpeg {
+    width: u32,
+    height: u32,
+    components: Vec<Component>,
+}
+
+struct Component {
+    id: u8,
+    horizontal_sampling: u32,
+    vertical_sampling: u32,
+    quantization_table: usize,
+    dc_table: usize,
+    ac_table: usize,
+    dc_prediction: i32,
+    /// Blocks across and down, padded out to whole MCUs.
+    blocks_across: u32,
+    blocks_down: u32,
+    /// Decoded samples, `blocks_across * 8` wide.
+    samples: Vec<u8>,
+}
+
+/// The state of decoding a JPEG's segments.
+#[derive(Default)]
+struct Tables {
+    quantization: [Option<[u16; 64]>; 4],
+    dc: [Option<Huffman>; 4],
+    ac: [Option<Huffman>; 4],
+    restart_interval: u32,
+}
+
+impl Jpeg {
+    fn decode(data: &[u8]) -> Result<Jpeg, ImageReadError> {
+        let mut tables = Tables::default();
+        let mut jpeg: Option<Jpeg> = None;
+        let mut scanned = false;
+        let mut position = 2;
+        loop {
+            // Markers may be padded with any number of 0xff bytes.
+            while data.get(position) == Some(&0xff) && data.get(position + 1) == Some(&0xff) {
+                position += 1;
+            }
+            let marker = match data.get(position..position + 2) {
+                Some([0xff, marker]) => *marker,
+                Some(_) => return Err(invalid("expected a marker")),
+                // Some encoders leave off the end marker.
+                None if scanned => break,
+                None => return Err(invalid("file ends early")),
+            };
+            position += 2;
+            match marker {
+                0xd9 => break,
+                0xd0..=0xd8 | 0x01 => continue,
+                _ => {}
+            }
+            let len = match data.get(position..position + 2) {
+                Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return Err(invalid("file ends early")),
+            };
+            let segment = data
+                .get(position + 2..position + len.max(2))
+                .ok_or_else(|| invalid("file ends early"))?;
+            position += len;
+            match marker {
+                0xc0 | 0xc1 => jpeg = Some(Jpeg::parse_frame(segment)?),
+                0xc2 | 0xc6 | 0xca | 0xce => {
+                    return Err(invalid("progressive JPEGs aren't supported"))
+                }
+                0xc3 | 0xc5 | 0xc7 | 0xc9 | 0xcb | 0xcd | 0xcf => {
+                    return Err(invalid(
+                        "only baseline JPEGs are supported, not lossless or arithmetic coded ones",
+                    ))
+                }
+                0xc4 => tables.parse_huffman(segment)?,
+                0xdb => tables.parse_quantization(segment)?,
+                0xdd => {
+                    let interval = segment
+                        .get(..2)
+                        .ok_or_else(|| invalid("restart interval is too short"))?;
+                    tables.restart_interval =
+                        u32::from(u16::from_be_bytes([interval[0], interval[1]]));
+                }
+                0xda => {
+                    let jpeg = jpeg
+                        .as_mut()
+                        .ok_or_else(|| invalid("scan comes before the frame header"))?;
+                    position = jpeg.decode_scan(&tables, segment, data, position)?;
+                    scanned = true;
+                }
+                _ => {}
+            }
+        }
+        match jpeg {
+            Some(jpeg) if scanned => Ok(jpeg),
+            _ => Err(invalid("no image data")),
+        }
+    }
+
+    fn parse_frame(segment: &[u8]) -> Result<Jpeg, ImageReadError> {
+        let header = segment
+            .get(..6)
+            .ok_or_else(|| invalid("frame header is too short"))?;
+        if header[0] != 8 {
+            return Err(invalid("only 8-bit samples are supported"));
+        }
+        let height = u32::from(u16::from_be_bytes([header[1], header[2]]));
+        let width = u32::from(u16::from_be_bytes([header[3], header[4]]));
+        if width == 0 || height == 0 {
+            return Err(invalid("image has no height or width"));
+        }
+        let count = header[5] as usize;
+        if count != 1 && count != 3 {
+            return Err(invalid("only grayscale and YCbCr JPEGs are supported"));
+        }
+        let specs = segment
+            .get(6..6 + count * 3)
+            .ok_or_else(|| invalid("frame header is too short"))?;
+        let mut components: Vec<Component> = specs
+            .chunks(3)
+            .map(|spec| Component {
+                id: spec[0],
+                horizontal_sampling: u32::from(spec[1] >> 4),
+                vertical_sampling: u32::from(spec[1] & 0x0f),
+                quantization_table: (spec[2] & 0x03) as usize,
+                dc_table: 0,
+                ac_table: 0,
+                dc_prediction: 0,
+                blocks_across: 0,
+                blocks_down: 0,
+                samples: Vec::new(),
+            })
+            .collect();
+        // A lone component is always coded a block at a time, whatever
+        // sampling it claims.
+        if count == 1 {
+            components[0].horizontal_sampling = 1;
+            components[0].vertical_sampling = 1;
+        }
+        if components.iter().any(|component| {
+            !(1..=4).contains(&component.horizontal_sampling)
+                || !(1..=4).contains(&component.vertical_sampling)
+        }) {
+            return Err(invalid("sampling factors must be 1 to 4"));
+        }
+
+        let mut jpeg = Jpeg {
+            width,
+            height,
+            components,
+        };
+        let (mcus_across, mcus_down) = jpeg.mcus();
+        for component in &mut jpeg.components {
+            component.blocks_across = mcus_across * component.horizontal_sampling;
+            component.blocks_down = mcus_down * component.vertical_sampling;
+            component.samples =
+                vec![0; component.blocks_across as usize * component.blocks_down as usize * 64];
+        }
+        Ok(jpeg)
+    }
+
+    fn max_sampling(&self) -> (u32, u32) {
+        self.components.iter().fold((1, 1), |(h, v), component| {
+            (
+                h.max(component.horizontal_sampling),
+                v.max(component.vertical_sampling),
+            )
+        })
+    }
+
+    /// The MCUs across and down an interleaved scan.
+    fn mcus(&self) -> (u32, u32) {
+        let (h, v) = self.max_sampling();
+        (self.width.div_ceil(8 * h), self.height.div_ceil(8 * v))
+    }
+
+    /// Decode the entropy coded data following a scan header, returning
+    /// the position of the marker after it.
+    fn decode_scan(
+        &mut self,
+        tables: &Tables,
+        header: &[u8],
+        data: &[u8],
+        position: usize,
+    ) -> Result<usize, ImageReadError> {
+        let count = *header
+            .first()
+            .ok_or_else(|| invalid("scan header is too short"))? as usize;
+        let specs = header
+            .get(1..1 + count * 2)
+            .ok_or_else(|| invalid("scan header is too short"))?;
+        let mut scan_components = Vec::with_capacity(count);
+        for spec in specs.chunks(2) {
+            let index = self
+                .components
+                .iter()
+                .position(|component| component.id == spec[0])
+                .ok_or_else(|| invalid("scan uses an unknown component"))?;
+            let component = &mut self.components[index];
+            component.dc_table = (spec[1] >> 4) as usize & 0x03;
+            component.ac_table = (spec[1] & 0x03) as usize;
+            component.dc_prediction = 0;
+            scan_components.push(index);
+        }
+        if scan_components.is_empty() {
+            return Err(invalid("scan has no components"));
+        }
+
+        let mut reader = BitReader {
+            data,
+            position,
+            bits: 0,
+            count: 0,
+        };
+        let (max_h, max_v) = self.max_sampling();
+        // A scan of one component codes it a block at a time, only covering
+        // the blocks inside the image.
+        let (mcus_across, mcus_down) = if let [index] = scan_components[..] {
+            let component = &self.components[index];
+            let width = (self.width * component.horizontal_sampling).div_ceil(max_h);
+            let height = (self.height * component.vertical_sampling).div_ceil(max_v);
+            (width.div_ceil(8), height.div_ceil(8))
+        } else {
+            self.mcus()
+        };
+
+        let mcu_count = mcus_across * mcus_down;
+        for mcu in 0..mcu_count {
+            if tables.restart_interval > 0 && mcu > 0 && mcu % tables.restart_interval == 0 {
+                reader.restart();
+                for &index in &scan_components {
+                    self.components[index].dc_prediction = 0;
+                }
+            }
+            let (mcu_x, mcu_y) = (mcu % mcus_across, mcu / mcus_across);
+            for &index in &scan_components {
+                let component = &mut self.components[index];
+                let (across, down) = if scan_components.len() == 1 {
+                    (1, 1)
+                } else {
+                    (component.horizontal_sampling, component.vertical_sampling)
+                };
+                for block_y in 0..down {
+                    for block_x in 0..across {
+                        let coefficients = component.decode_block(tables, &mut reader)?;
+                        // Only brightness is kept, but every component's
+                        // blocks have to be read to reach the next one.
+                        if index == 0 {
+                            component.store_block(
+                                mcu_x * across + block_x,
+                                mcu_y * down + block_y,
+                                &idct(&coefficients),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Skip to the next marker that isn't a restart.
+        let mut position = reader.position;
+        while let Some(bytes) = data.get(position..position + 2) {
+            if bytes[0] == 0xff && bytes[1] != 0 && !(0xd0..=0xd7).contains(&bytes[1]) {
+                break;
+            }
+            position += 1;
+        }
+        Ok(position)
+    }
+
+    /// Each pixel's color, top to bottom, converting YCbCr to RGB.
+    fn pixels(&self) -> Vec<Rgba> {
+        let (max_h, max_v) = self.max_sampling();
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let sample = |component: &Component| {
+                    let sample_y = y * component.vertical_sampling / max_v;
+                    let sample_x = x * component.horizontal_sampling / max_h;
+                    let stride = component.blocks_across * 8;
+                    f32::from(component.samples[(sample_y * stride + sample_x) as usize])
+                };
+                let luma = sample(&self.components[0]);
+                let (cb, cr) = match &self.components[..] {
+                    [_, cb, cr] => (sample(cb) - 128.0, sample(cr) - 128.0),
+                    _ => (0.0, 0.0),
+                };
+                let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+                pixels.push(Rgba {
+                    r: channel(luma + 1.402 * cr),
+                    g: channel(luma - 0.344_136 * cb - 0.714_136 * cr),
+                    b: channel(luma + 1.772 * cb),
+                    a: 255,
+                });
+            }
+        }
+        pixels
+    }
+}
+
+impl Component {
+    /// Read one block's coefficients, dequantized, in row order.
+    fn decode_block(
+        &mut self,
+        tables: &Tables,
+        reader: &mut BitReader,
+    ) -> Result<[f32; 64], ImageReadError> {
+        let quantization = tables.quantization[self.quantization_table]
+            .as_ref()
+            .ok_or_else(|| invalid("component uses a missing quantization table"))?;
+        let dc = tables.dc[self.dc_table]
+            .as_ref()
+            .ok_or_else(|| invalid("scan uses a missing Huffman table"))?;
+        let ac = tables.ac[self.ac_table]
+            .as_ref()
+            .ok_or_else(|| invalid("scan uses a missing Huffman table"))?;
+
+        let mut coefficients = [0.0; 64];
+        let size = dc.decode(reader)?;
+        self.dc_prediction = self
+            .dc_prediction
+            .wrapping_add(reader.receive_extend(size)?);
+        coefficients[0] = self.dc_prediction as f32 * f32::from(quantization[0]);
+        let mut k = 1;
+        while k < 64 {
+            let symbol = ac.decode(reader)?;
+            let (run, size) = ((symbol >> 4) as usize, symbol & 0x0f);
+            if size == 0 {
+                if run == 15 {
+                    k += 16;
+                    continue;
+                }
+                break;
+            }
+            k += run;
+            if k > 63 {
+                return Err(invalid("block has too many coefficients"));
+            }
+            coefficients[ZIGZAG[k]] =
+                reader.receive_extend(size)? as f32 * f32::from(quantization[k]);
+            k += 1;
+        }
+        Ok(coefficients)
+    }
+
+    fn store_block(&mut self, block_x: u32, block_y: u32, block: &[u8; 64]) {
+        if block_x >= self.blocks_across || block_y >= self.blocks_down {
+            return;
+        }
+        let stride = self.blocks_across as usize * 8;
+        let origin = block_y as usize * 8 * stride + block_x as usize * 8;
+        for (row, samples) in block.chunks(8).enumerate() {
+            let start = origin + row * stride;
+            self.samples[start..start + 8].copy_from_slice(samples);
+        }
+    }
+}
+
+impl Tables {
+    fn parse_quantization(&mut self, mut segment: &[u8]) -> Result<(), ImageReadError> {
+        while let Some(&spec) = segment.first() {
+            let wide = spec >> 4 != 0;
+            let len = if wide { 128 } else { 64 };
+            let values = segment
+                .get(1..1 + len)
+                .ok_or_else(|| invalid("quantization table is too short"))?;
+            let mut table = [0; 64];
+            for (k, value) in table.iter_mut().enumerate() {
+                *value = if wide {
+                    u16::from_be_bytes([values[k * 2], values[k * 2 + 1]])
+                } else {
+                    u16::from(values[k])
+                };
+            }
+            self.quantization[(spec & 0x03) as usize] = Some(table);
+            segment = &segment[1 + len..];
+        }
+        Ok(())
+    }
+
+    fn parse_huffman(&mut self, mut segment: &[u8]) -> Result<(), ImageReadError> {
+        while let Some(&spec) = segment.first() {
+            let counts = segment
+                .get(1..17)
+                .ok_or_else(|| invalid("Huffman table is too short"))?;
+            let len: usize = counts.iter().map(|&count| count as usize).sum();
+            let values = segment
+                .get(17..17 + len)
+                .ok_or_else(|| invalid("Huffman table is too short"))?;
+            let table = Huffman::new(counts, values)?;
+            let id = (spec & 0x03) as usize;
+            if spec >> 4 == 0 {
+                self.dc[id] = Some(table);
+            } else {
+                self.ac[id] = Some(table);
+            }
+            segment = &segment[17 + len..];
+        }
+        Ok(())
+    }
+}
+
+/// A Huffman table, as the canonical codes of each length.
+struct Huffman {
+    /// The first and last code of each length 1 to 16, and the index of
+    /// the first code's value. Lengths without codes have a `last` of -1.
+    lengths: [(i32, i32, usize); 16],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    /// Fails if the table has no codes, or more codes of a length than
+    /// that many bits can tell apart.
+    fn new(counts: &[u8], values: &[u8]) -> Result<Huffman, ImageReadError> {
+        if values.is_empty() {
+            return Err(invalid("Huffman table has no codes"));
+        }
+        let mut lengths = [(0, -1, 0); 16];
+        let mut code = 0;
+        let mut index = 0;
+        for (length, &count) in counts.iter().enumerate() {
+            let count = i32::from(count);
+            if code + count > 1 << (length + 1) {
+                return Err(invalid(
+                    "Huffman table has more codes than fit their lengths",
+                ));
+            }
+            if count > 0 {
+                lengths[length] = (code, code + count - 1, index);
+            }
+            code = (code + count) << 1;
+            index += count as usize;
+        }
+        Ok(Huffman {
+            lengths,
+            values: values.to_vec(),
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, ImageReadError> {
+        let mut code = 0;
+        for &(first, last, index) in &self.lengths {
+            code = (code << 1) | reader.bit() as i32;
+            if code <= last {
+                return self
+                    .values
+                    .get(index + (code - first) as usize)
+                    .cloned()
+                    .ok_or_else(|| invalid("Huffman code has no value"));
+            }
+        }
+        Err(invalid("unknown Huffman code"))
+    }
+}
+
+/// Reads entropy coded data a bit at a time, most significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> u32 {
+        if self.count == 0 {
+            self.bits = self.next_byte();
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.bits >> self.count) & 1
+    }
+
+    /// The next byte of data. A 0xff byte is followed by a 0 byte, and
+    /// anything else after it is a marker, past which the data reads as
+    /// zeros.
+    fn next_byte(&mut self) -> u32 {
+        match self.data.get(self.position..self.position + 2) {
+            Some([0xff, 0]) => {
+                self.position += 2;
+                0xff
+            }
+            Some([0xff, _]) => 0,
+            _ => match self.data.get(self.position) {
+                Some(&byte) => {
+                    self.position += 1;
+                    u32::from(byte)
+                }
+                None => 0,
+            },
+        }
+    }
+
+    /// Read a `size` bit value, where values with a leading 0 bit are
+    /// negative. Fails if `size`, which comes from a Huffman table, is
+    /// more bits than a coefficient has.
+    fn receive_extend(&mut self, size: u8) -> Result<i32, ImageReadError> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > MAX_VALUE_BITS {
+            return Err(invalid("coefficient is more than 16 bits"));
+        }
+        let mut value = 0;
+        for _ in 0..size {
+            value = (value << 1) | self.bit() as i32;
+        }
+        if value < 1 << (size - 1) {
+            Ok(value - (1 << size) + 1)
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Skip past the restart marker that ends an interval, dropping any
+    /// bits left in the current byte.
+    fn restart(&mut self) {
+        self.count = 0;
+        while let Some(bytes) = self.data.get(self.position..self.position + 2) {
+            self.position += 1;
+            if bytes[0] == 0xff && (0xd0..=0xd7).contains(&bytes[1]) {
+                self.position += 1;
+                break;
+            }
+        }
+    }
+}
+
+/// Turn a block of coefficients back into samples.
+fn idct(coefficients: &[f32; 64]) -> [u8; 64] {
+    let mut cosines = [[0.0; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, cosine) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+            *cosine = scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+        }
+    }
+
+    let mut rows = [0.0; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            rows[y * 8 + x] = (0..8)
+                .map(|u| cosines[x][u] * coefficients[y * 8 + u])
+                .sum();
+        }
+    }
+    let mut samples = [0; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            let value: f32 = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+            samples[y * 8 + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 grayscale baseline JPEG whose DC Huffman table has
+    /// `dc_counts` codes of each length, for `dc_values`, and whose one
+    /// block codes its DC value with the first code and then ends.
+    fn jpeg(dc_counts: [u8; 16], dc_values: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        data.extend([0xff, 0xdb, 0x00, 0x43, 0x00]);
+        data.extend([1; 64]);
+        data.extend([0xff, 0xc0, 0x00, 0x0b, 8, 0, 8, 0, 8, 1, 1, 0x11, 0]);
+        let len = 2 + 1 + 16 + dc_values.len() as u16;
+        data.extend([0xff, 0xc4]);
+        data.extend(len.to_be_bytes());
+        data.push(0x00);
+        data.extend(dc_counts);
+        data.extend(dc_values);
+        // An AC table whose only code, 0, ends the block.
+        data.extend([0xff, 0xc4, 0x00, 0x14, 0x10, 1]);
+        data.extend([0; 15]);
+        data.push(0x00);
+        data.extend([0xff, 0xda, 0x00, 0x08, 1, 1, 0x00, 0, 63, 0]);
+        data.extend([0x00, 0x00]);
+        data.extend([0xff, 0xd9]);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        JpegSource.decode(&mut &data[..])
+    }
+
+    fn one_code() -> [u8; 16] {
+        let mut counts = [0; 16];
+        counts[0] = 1;
+        counts
+    }
+
+    #[test]
+    fn decodes_a_block() {
+        let image = decode(&jpeg(one_code(), &[0])).unwrap();
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.palette, None);
+        let gray = Rgba {
+            r: 128,
+            g: 128,
+            b: 128,
+            a: 255,
+        };
+        assert_eq!(image.pixels, vec![gray; 64]);
+    }
+
+    #[test]
+    fn rejects_values_of_more_than_16_bits() {
+        for size in [17, 32, 200, 255] {
+            assert!(matches!(
+                decode(&jpeg(one_code(), &[size])),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_empty_huffman_tables() {
+        assert!(matches!(
+            decode(&jpeg([0; 16], &[])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_oversubscribed_huffman_tables() {
+        let mut counts = [0; 16];
+        counts[0] = 3;
+        assert!(matches!(
+            decode(&jpeg(counts, &[0, 0, 0])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+}
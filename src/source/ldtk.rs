@@ -0,0 +1,286 @@
+use super::{ImageSource, SourceImage, SourceRegistry};
+use crate::image::{detect_source, ImageReadError};
+use crate::tile::{Tilemap, CGB_X_FLIP, CGB_Y_FLIP};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+/// A map cell's tile, as its column and row in the tileset, counted in 8x8
+/// tiles, and its Game Boy Color flip bits.
+type TilesetCell = (u64, u64, u8);
+
+/// LDtk `.ldtk` projects, read with the tileset image their levels are
+/// drawn with.
+///
+/// The tileset comes out as tiles, followed by a map for each level named
+/// after its identifier. Where tile layers overlap, the top layer's tile
+/// is used, and empty cells use tile 0. Flipped tiles are kept as the map's
+/// Game Boy Color attributes, since a plain Game Boy background can't flip
+/// them.
+#[derive(Clone, Debug, Default)]
+pub struct LdtkSource {
+    project_dir: Option<PathBuf>,
+}
+
+impl LdtkSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the tileset and any external level files relative to
+    /// `project_dir`, the directory holding the project, instead of the
+    /// current directory.
+    pub fn project_dir<P: Into<PathBuf>>(mut self, project_dir: P) -> Self {
+        self.project_dir = Some(project_dir.into());
+        self
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+        match &self.project_dir {
+            Some(project_dir) => project_dir.join(relative),
+            None => PathBuf::from(relative),
+        }
+    }
+
+    /// A level with its layers, read from its own file if the project
+    /// saves levels separately.
+    fn load_level(&self, level: &Value) -> Result<Value, ImageReadError> {
+        let external = match external_level(level) {
+            Some(external) => external,
+            None => return Ok(level.clone()),
+        };
+        let path = self.path(external);
+        let data = fs::read(&path).map_err(|err| {
+            invalid(&format!(
+                "couldn't read the level file '{}': {}",
+                path.display(),
+                err
+            ))
+        })?;
+        serde_json::from_slice(&data)
+            .map_err(|err| invalid(&format!("'{}' isn't valid JSON: {}", path.display(), err)))
+    }
+}
+
+impl ImageSource for LdtkSource {
+    fn name(&self) -> &'static str {
+        "ldtk"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ldtk"]
+    }
+
+    fn matches_magic(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    fn referenced_files(&self, reader: &mut dyn Read) -> Result<Vec<PathBuf>, ImageReadError> {
+        let project = parse_project(reader)?;
+        let mut files = Vec::new();
+        for level in levels(&project) {
+            if let Some(external) = external_level(level) {
+                files.push(self.path(external));
+            }
+            let level = self.load_level(level)?;
+            let layers = level["layerInstances"].as_array().into_iter().flatten();
+            for tileset in layers.filter_map(layer_tileset) {
+                let tileset = self.path(tileset);
+                if !files.contains(&tileset) {
+                    files.push(tileset);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let project = parse_project(reader)?;
+        let mut tileset: Option<String> = None;
+        let mut levels_cells = Vec::new();
+        for level in levels(&project) {
+            let level = self.load_level(level)?;
+            let identifier = level["identifier"]
+                .as_str()
+                .ok_or_else(|| invalid("level has no identifier"))?;
+            let width = level["pxWid"].as_u64().unwrap_or(0) / 8;
+            let height = level["pxHei"].as_u64().unwrap_or(0) / 8;
+            debug!("LDtk level '{}' is {}x{} tiles", identifier, width, height);
+            let mut cells: Vec<Option<TilesetCell>> = vec![None; (width * height) as usize];
+
+            // Layers are listed top first, so draw them in reverse for the
+            // top layer's tiles to win.
+            let layers = level["layerInstances"].as_array().into_iter().flatten();
+            for layer in layers.rev() {
+                let layer_tileset = match layer_tileset(layer) {
+                    Some(layer_tileset) => layer_tileset,
+                    None => continue,
+                };
+                match &tileset {
+                    Some(tileset) if tileset != layer_tileset => {
+                        return Err(invalid(&format!(
+                            "levels can only use one tileset, but both '{}' and '{}' are used",
+                            tileset, layer_tileset
+                        )))
+                    }
+                    Some(_) => {}
+                    None => tileset = Some(layer_tileset.to_string()),
+                }
+                let grid_size = layer["__gridSize"].as_u64().unwrap_or(0);
+                if grid_size == 0 || grid_size % 8 != 0 {
+                    return Err(invalid(&format!(
+                        "layer '{}' has a {} pixel grid, but it must be a multiple of 8",
+                        layer["__identifier"].as_str().unwrap_or_default(),
+                        grid_size
+                    )));
+                }
+                let offset = (
+                    layer["__pxTotalOffsetX"].as_i64().unwrap_or(0),
+                    layer["__pxTotalOffsetY"].as_i64().unwrap_or(0),
+                );
+                let tiles = layer["gridTiles"]
+                    .as_array()
+                    .into_iter()
+                    .chain(layer["autoLayerTiles"].as_array())
+                    .flatten();
+                for tile in tiles {
+                    let (x, y) = coordinates(&tile["px"])?;
+                    let (src_x, src_y) = coordinates(&tile["src"])?;
+                    if src_x % 8 != 0 || src_y % 8 != 0 {
+                        return Err(invalid("tiles must be on an 8 pixel grid in the tileset"));
+                    }
+                    // Bit 0 flips the tile horizontally and bit 1 vertically.
+                    let flips = tile["f"].as_u64().unwrap_or(0);
+                    let (flip_x, flip_y) = (flips & 0x01 != 0, flips & 0x02 != 0);
+                    let attributes = (flip_x as u8 * CGB_X_FLIP) | (flip_y as u8 * CGB_Y_FLIP);
+                    // Larger tiles cover several 8x8 cells, which trade
+                    // places when the tile is flipped.
+                    let cells_across = grid_size as i64 / 8;
+                    let source_cell = |d: i64, flip: bool| match flip {
+                        true => cells_across - 1 - d,
+                        false => d,
+                    };
+                    for dy in 0..cells_across {
+                        for dx in 0..cells_across {
+                            let cell_x = (x + offset.0).div_euclid(8) + dx;
+                            let cell_y = (y + offset.1).div_euclid(8) + dy;
+                            if cell_x < 0
+                                || cell_y < 0
+                                || cell_x as u64 >= width
+                                || cell_y as u64 >= height
+                            {
+                                continue;
+                            }
+                            cells[(cell_y as u64 * width + cell_x as u64) as usize] = Some((
+                                (src_x / 8 + source_cell(dx, flip_x)) as u64,
+                                (src_y / 8 + source_cell(dy, flip_y)) as u64,
+                                attributes,
+                            ));
+                        }
+                    }
+                }
+            }
+            levels_cells.push((identifier.to_string(), width, height, cells));
+        }
+
+        let tileset = tileset.ok_or_else(|| invalid("project has no tile layers"))?;
+        let path = self.path(&tileset);
+        let file = File::open(&path).map_err(|err| {
+            invalid(&format!(
+                "couldn't open the tileset '{}': {}",
+                path.display(),
+                err
+            ))
+        })?;
+        let sources = SourceRegistry::default();
+        let mut reader = BufReader::new(file);
+        let mut image = detect_source(&sources, &tileset, &mut reader)?.decode(&mut reader)?;
+        let columns = u64::from(image.width / 8);
+        let rows = u64::from(image.height / 8);
+
+        for (identifier, width, height, cells) in levels_cells {
+            let mut tiles = Vec::with_capacity(cells.len());
+            let mut attributes = Vec::with_capacity(cells.len());
+            for cell in cells {
+                let (column, row, cell_attributes) = cell.unwrap_or((0, 0, 0));
+                attributes.push(cell_attributes);
+                if column >= columns || row >= rows {
+                    return Err(invalid(&format!(
+                        "level '{}' uses a tile outside the tileset",
+                        identifier
+                    )));
+                }
+                tiles.push(u8::try_from(row * columns + column).map_err(|_| {
+                    invalid(&format!(
+                        "level '{}' uses a tile past the first 256 of the tileset",
+                        identifier
+                    ))
+                })?);
+            }
+            image.tilemaps.push(Tilemap {
+                name: Some(identifier),
+                width: width as u32,
+                height: height as u32,
+                tiles,
+                attributes,
+            });
+        }
+        Ok(image)
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "LDtk",
+        message: message.to_string(),
+    }
+}
+
+fn parse_project(reader: &mut dyn Read) -> Result<Value, ImageReadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    serde_json::from_slice(&data)
+        .map_err(|err| invalid(&format!("project isn't valid JSON: {}", err)))
+}
+
+/// A project's levels. Projects with several worlds keep their levels in
+/// each world.
+fn levels(project: &Value) -> impl Iterator<Item = &Value> {
+    project["levels"]
+        .as_array()
+        .into_iter()
+        .chain(
+            project["worlds"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|world| world["levels"].as_array()),
+        )
+        .flatten()
+}
+
+/// The file a level's layers are saved in, if the project saves them
+/// separately.
+fn external_level(level: &Value) -> Option<&str> {
+    level["externalRelPath"]
+        .as_str()
+        .filter(|_| level["layerInstances"].is_null())
+}
+
+/// The tileset a visible layer is drawn with, if it's a tile layer.
+fn layer_tileset(layer: &Value) -> Option<&str> {
+    layer["__tilesetRelPath"]
+        .as_str()
+        .filter(|_| layer["visible"] != false)
+}
+
+/// An `[x, y]` pair of pixel coordinates.
+fn coordinates(value: &Value) -> Result<(i64, i64), ImageReadError> {
+    match (value[0].as_i64(), value[1].as_i64()) {
+        (Some(x), Some(y)) => Ok((x, y)),
+        _ => Err(invalid("tile has no coordinates")),
+    }
+}
@@ -0,0 +1,349 @@
+use crate::image::{ImageReadError, Rgb, Rgba};
+use crate::tile::Tilemap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+mod aseprite;
+mod gbm;
+mod gbr;
+mod gif;
+mod jpeg;
+#[cfg(feature = "json")]
+mod ldtk;
+mod png;
+mod raw;
+mod tga;
+mod webp;
+
+pub use self::aseprite::AsepriteSource;
+pub use self::gbm::GbmSource;
+pub use self::gbr::GbrSource;
+pub use self::gif::GifSource;
+pub use self::jpeg::JpegSource;
+#[cfg(feature = "json")]
+pub use self::ldtk::LdtkSource;
+pub use self::png::PngSource;
+pub use self::raw::RawSource;
+pub use self::tga::TgaSource;
+pub use self::webp::WebpSource;
+
+/// How many leading bytes of an input are inspected to detect its format.
+pub const MAGIC_LEN: usize = 16;
+
+/// Raw pixels read from an input image, before any color reduction.
+#[derive(Clone, Debug)]
+pub struct SourceImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Rgba>,
+    /// For indexed images with at most four palette entries, the palette,
+    /// or for 2-bit grayscale images, their four shades from white to
+    /// black. Its order gives each color's Game Boy color number, instead
+    /// of them being numbered by color.
+    pub palette: Option<Vec<Rgb>>,
+    /// How many animation frames the image holds, stacked top to bottom
+    /// with each `height / frames` pixels high. 1 for still images.
+    pub frames: u32,
+    /// For map files, the maps drawn with the image's 8x8 tiles, numbered
+    /// left to right and top to bottom.
+    pub tilemaps: Vec<Tilemap>,
+    /// The palette each pixel's tile must take when palettes are shared
+    /// out, as given by [`SourceImage::with_palette_mask`], or `None` for
+    /// pixels whose tile can take any. Every pixel of a tile must agree.
+    pub palette_mask: Option<Vec<Option<u8>>>,
+}
+
+impl SourceImage {
+    /// The pixels of a `width` by `height` rectangle, from (`x`, `y`) in
+    /// this image, as a still image. The rectangle must be inside the
+    /// image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> SourceImage {
+        assert!(
+            x + width <= self.width && y + height <= self.height,
+            "crop is outside the image"
+        );
+        SourceImage {
+            width,
+            height,
+            pixels: crop_rows(&self.pixels, self.width, x, y, width, height),
+            palette: self.palette.clone(),
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: self
+                .palette_mask
+                .as_deref()
+                .map(|palette_mask| crop_rows(palette_mask, self.width, x, y, width, height)),
+        }
+    }
+
+    /// This image with each frame extended right and down to the next 8x8
+    /// tile boundary with `fill` pixels. The new pixels' tiles can take any
+    /// palette.
+    pub fn pad_to_tiles(self, fill: Rgba) -> SourceImage {
+        let frames = self.frames.max(1);
+        let frame_height = self.height / frames;
+        let size = (self.width, frame_height);
+        let padded = (self.width.div_ceil(8) * 8, frame_height.div_ceil(8) * 8);
+        SourceImage {
+            width: padded.0,
+            height: padded.1 * frames,
+            pixels: pad_rows(&self.pixels, size, padded, fill),
+            palette: self.palette,
+            frames: self.frames,
+            tilemaps: self.tilemaps,
+            palette_mask: self
+                .palette_mask
+                .map(|palette_mask| pad_rows(&palette_mask, size, padded, None)),
+        }
+    }
+
+    /// Mark the palette each tile must take when palettes are shared out
+    /// with `mask`, an image the same size as this one whose flat color
+    /// regions cover the tiles sharing each palette. Palettes are numbered
+    /// by where their color first appears, left to right and top to
+    /// bottom. Tiles where `mask` is transparent take any palette.
+    pub fn with_palette_mask(mut self, mask: &SourceImage) -> Result<SourceImage, ImageReadError> {
+        if (mask.width, mask.height) != (self.width, self.height) {
+            return Err(ImageReadError::Invalid {
+                format: "palette mask",
+                message: format!(
+                    "it's {}x{} pixels, but the image is {}x{}",
+                    mask.width, mask.height, self.width, self.height
+                ),
+            });
+        }
+        let mut colors: Vec<Rgb> = Vec::new();
+        let palette_mask = mask
+            .pixels
+            .iter()
+            .map(|pixel| {
+                if pixel.a == 0 {
+                    return None;
+                }
+                let rgb = pixel.rgb();
+                let palette = colors.iter().position(|color| *color == rgb);
+                Some(palette.unwrap_or_else(|| {
+                    colors.push(rgb);
+                    colors.len() - 1
+                }) as u8)
+            })
+            .collect();
+        self.palette_mask = Some(palette_mask);
+        Ok(self)
+    }
+}
+
+/// The values of a `width` by `height` rectangle from (`x`, `y`) of an
+/// image `image_width` values wide.
+fn crop_rows<T: Clone>(
+    values: &[T],
+    image_width: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Vec<T> {
+    values
+        .chunks(image_width as usize)
+        .skip(y as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[x as usize..(x + width) as usize])
+        .cloned()
+        .collect()
+}
+
+/// The values of each frame of an image, with frames `size` values wide
+/// and high, extended to `padded` with `fill`.
+fn pad_rows<T: Clone>(values: &[T], size: (u32, u32), padded: (u32, u32), fill: T) -> Vec<T> {
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let (padded_width, padded_height) = (padded.0 as usize, padded.1 as usize);
+    let frames = values.len() / (width * height).max(1);
+    let mut padded = Vec::with_capacity(frames * padded_width * padded_height);
+    for frame in values.chunks((width * height).max(1)) {
+        for row in frame.chunks(width.max(1)) {
+            padded.extend_from_slice(row);
+            padded.resize(padded.len() + padded_width - width, fill.clone());
+        }
+        padded.resize(
+            padded.len() + (padded_height - height) * padded_width,
+            fill.clone(),
+        );
+    }
+    padded
+}
+
+/// An image file format that can be decoded into a [`SourceImage`].
+///
+/// Implement this to accept a new input format, then add it to a
+/// [`SourceRegistry`] so inputs can be dispatched to it.
+pub trait ImageSource: fmt::Debug + Send + Sync {
+    /// The short name of this format, e.g. `png`.
+    fn name(&self) -> &'static str;
+
+    /// Lowercase file extensions, without the dot, used by this format.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// The extensions of [`extensions`](ImageSource::extensions) that
+    /// images are searched for by in input directories. Formats without a
+    /// signature can leave out extensions other files use too, so those
+    /// are only read when named.
+    fn discovered_extensions(&self) -> &'static [&'static str] {
+        self.extensions()
+    }
+
+    /// Whether the first bytes of an input identify it as this format.
+    fn matches_magic(&self, header: &[u8]) -> bool;
+
+    /// The other files that decoding the input in `reader` reads, such as
+    /// a map's tileset, so builds can depend on them too. None by default.
+    fn referenced_files(&self, _reader: &mut dyn Read) -> Result<Vec<PathBuf>, ImageReadError> {
+        Ok(Vec::new())
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError>;
+
+    /// Decode the image a row at a time into `sink`, top to bottom.
+    ///
+    /// The default decodes the whole image first. Formats that can produce
+    /// rows incrementally should override this, so large images can be
+    /// encoded without holding every pixel in memory.
+    fn decode_rows(
+        &self,
+        reader: &mut dyn Read,
+        sink: &mut dyn RowSink,
+    ) -> Result<(), ImageReadError> {
+        write_rows(&self.decode(reader)?, sink)
+    }
+}
+
+/// Send an already decoded image to `sink`, as [`ImageSource::decode_rows`]
+/// would.
+pub(crate) fn write_rows(
+    source_image: &SourceImage,
+    sink: &mut dyn RowSink,
+) -> Result<(), ImageReadError> {
+    if let Some(palette) = &source_image.palette {
+        sink.palette(palette)?;
+    }
+    if source_image.frames > 1 {
+        sink.frames(source_image.frames)?;
+    }
+    for tilemap in &source_image.tilemaps {
+        sink.tilemap(tilemap)?;
+    }
+    sink.start(source_image.width, source_image.height)?;
+    for row in source_image.pixels.chunks(source_image.width as usize) {
+        sink.row(row)?;
+    }
+    Ok(())
+}
+
+/// Receives an image row by row from [`ImageSource::decode_rows`].
+pub trait RowSink {
+    /// Called once with the image dimensions, before any rows.
+    fn start(&mut self, width: u32, height: u32) -> Result<(), ImageReadError>;
+
+    /// Called before `start` for images whose palette order gives the
+    /// color numbers, as in [`SourceImage::palette`]. Ignored by default.
+    fn palette(&mut self, _palette: &[Rgb]) -> Result<(), ImageReadError> {
+        Ok(())
+    }
+
+    /// Called before `start` for animations, with the number of frames
+    /// stacked in the image, as in [`SourceImage::frames`]. Ignored by
+    /// default.
+    fn frames(&mut self, _frames: u32) -> Result<(), ImageReadError> {
+        Ok(())
+    }
+
+    /// Called before `start` for map files, once for each map drawn with
+    /// the image's tiles, as in [`SourceImage::tilemaps`]. Ignored by
+    /// default.
+    fn tilemap(&mut self, _tilemap: &Tilemap) -> Result<(), ImageReadError> {
+        Ok(())
+    }
+
+    fn row(&mut self, pixels: &[Rgba]) -> Result<(), ImageReadError>;
+}
+
+/// A set of input formats, detected by magic bytes or file extension.
+#[derive(Debug)]
+pub struct SourceRegistry {
+    sources: Vec<Box<dyn ImageSource>>,
+}
+
+impl Default for SourceRegistry {
+    /// A registry holding every built-in source: PNG, GIF, JPEG, WebP, TGA,
+    /// Aseprite, GBTD, GBMB, raw 2bpp and, with the `json` feature, LDtk.
+    fn default() -> Self {
+        let mut registry = SourceRegistry::empty();
+        registry.register(Box::new(PngSource));
+        registry.register(Box::new(GifSource));
+        registry.register(Box::new(JpegSource));
+        registry.register(Box::new(WebpSource));
+        registry.register(Box::new(TgaSource));
+        registry.register(Box::new(AsepriteSource::default()));
+        registry.register(Box::new(GbrSource));
+        registry.register(Box::new(GbmSource::default()));
+        registry.register(Box::new(RawSource));
+        #[cfg(feature = "json")]
+        registry.register(Box::new(LdtkSource::default()));
+        registry
+    }
+}
+
+impl SourceRegistry {
+    pub fn empty() -> Self {
+        SourceRegistry {
+            sources: Vec::new(),
+        }
+    }
+
+    /// Add a source, replacing any existing source with the same name.
+    pub fn register(&mut self, source: Box<dyn ImageSource>) {
+        self.sources
+            .retain(|existing| existing.name() != source.name());
+        self.sources.push(source);
+    }
+
+    /// Whether any source uses the extension of `path`.
+    pub fn handles_extension(&self, path: &Path) -> bool {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => return false,
+        };
+        self.sources
+            .iter()
+            .any(|source| source.extensions().contains(&extension.as_str()))
+    }
+
+    /// Whether any source's images are searched for in input directories by
+    /// the extension of `path`, as in
+    /// [`ImageSource::discovered_extensions`].
+    pub fn discovers_extension(&self, path: &Path) -> bool {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => return false,
+        };
+        self.sources
+            .iter()
+            .any(|source| source.discovered_extensions().contains(&extension.as_str()))
+    }
+
+    /// Find the source for an input, preferring its magic bytes and falling
+    /// back to the file extension of `path`.
+    pub fn detect(&self, path: &Path, header: &[u8]) -> Option<&dyn ImageSource> {
+        let by_magic = self
+            .sources
+            .iter()
+            .find(|source| source.matches_magic(header));
+        let by_extension = || {
+            let extension = path.extension()?.to_string_lossy().to_lowercase();
+            self.sources
+                .iter()
+                .find(|source| source.extensions().contains(&extension.as_str()))
+        };
+        by_magic.or_else(by_extension).map(|source| source.as_ref())
+    }
+}
@@ -0,0 +1,311 @@
+use super::{write_rows, ImageSource, RowSink, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba, GB_MAX_COLOR_COUNT};
+use png::{Decoder, Transformations};
+use std::io::Read;
+
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// PNG images, decoded with the `png` crate.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PngSource;
+
+impl ImageSource for PngSource {
+    fn name(&self) -> &'static str {
+        "png"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["png"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(PNG_SIGNATURE)
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let decoder = decoder(reader);
+        let (info, mut png_reader) = decoder.read_info()?;
+        debug!("PNG info: {:?}", info);
+        read_frames(&info, &mut png_reader)
+    }
+
+    fn decode_rows(
+        &self,
+        reader: &mut dyn Read,
+        sink: &mut dyn RowSink,
+    ) -> Result<(), ImageReadError> {
+        let decoder = decoder(reader);
+        let (info, mut png_reader) = decoder.read_info()?;
+        debug!("PNG info: {:?}", info);
+        if png_reader.info().animation_control().is_some() {
+            // Each frame is drawn over the ones before it, so every frame
+            // has to be decoded before any rows can be encoded.
+            debug!("PNG is animated, decoding every frame before encoding them");
+            return write_rows(&read_frames(&info, &mut png_reader)?, sink);
+        }
+        if let Some(palette) = color_number_palette(png_reader.info()) {
+            sink.palette(&palette)?;
+        }
+        sink.start(info.width, info.height)?;
+
+        if png_reader.info().interlaced {
+            // Interlaced rows arrive one Adam7 pass at a time, so the whole
+            // frame has to be buffered to put them back in order.
+            debug!("PNG is interlaced, decoding the whole image before encoding it");
+            let mut image_buf = vec![0; info.buffer_size()];
+            png_reader.next_frame(&mut image_buf)?;
+            for row in image_buf.chunks(info.line_size) {
+                sink.row(&read_image_data(&info, row)?)?;
+            }
+            return Ok(());
+        }
+        while let Some(row) = png_reader.next_row()? {
+            sink.row(&read_image_data(&info, row)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a PNG's image, or for an animated PNG, every frame of the animation
+/// stacked top to bottom as in [`SourceImage::frames`].
+fn read_frames<R: Read>(
+    info: &png::OutputInfo,
+    png_reader: &mut png::Reader<R>,
+) -> Result<SourceImage, ImageReadError> {
+    let palette = color_number_palette(png_reader.info());
+    let mut image_buf = vec![0; info.buffer_size()];
+    png_reader.next_frame(&mut image_buf)?;
+    let pixels = read_image_data(info, &image_buf)?;
+    let frames = png_reader
+        .info()
+        .animation_control()
+        .map_or(1, |animation_control| animation_control.num_frames);
+    if frames <= 1 {
+        return Ok(SourceImage {
+            width: info.width,
+            height: info.height,
+            pixels,
+            palette,
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        });
+    }
+
+    debug!("PNG is animated with {} frames", frames);
+    // The frame count comes from the header, so it's only trusted as far
+    // as the stacked frames' size can be counted.
+    let height = info
+        .height
+        .checked_mul(frames)
+        .filter(|height| {
+            (*height as usize)
+                .checked_mul(info.width as usize)
+                .is_some_and(|pixels| pixels.checked_mul(4).is_some())
+        })
+        .ok_or_else(|| {
+            invalid(format!(
+                "{} frames of {}x{} pixels are too many to stack",
+                frames, info.width, info.height
+            ))
+        })?;
+    let transparent = Rgba {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+    let mut canvas = vec![transparent; info.width as usize * info.height as usize];
+    let mut stacked = Vec::new();
+    let bytes_per_pixel = info.line_size / info.width as usize;
+    // The default image is only part of the animation when a frame control
+    // chunk comes before it.
+    let mut subframe = png_reader.info().frame_control().map(|_| pixels);
+    for frame in 0..frames {
+        let subframe_pixels = match subframe.take() {
+            Some(subframe_pixels) => subframe_pixels,
+            None => {
+                png_reader.next_frame(&mut image_buf)?;
+                let frame_control = frame_control(png_reader, frame)?;
+                let len =
+                    frame_control.width as usize * frame_control.height as usize * bytes_per_pixel;
+                let subframe_data = image_buf.get(..len).ok_or_else(|| {
+                    invalid(format!(
+                        "frame {} is {}x{} pixels, bigger than the {}x{} image",
+                        frame, frame_control.width, frame_control.height, info.width, info.height
+                    ))
+                })?;
+                read_image_data(info, subframe_data)?
+            }
+        };
+        let frame_control = frame_control(png_reader, frame)?;
+        let previous = canvas.clone();
+        let (left, top) = (frame_control.x_offset, frame_control.y_offset);
+        for (i, pixel) in subframe_pixels.iter().enumerate() {
+            let x = left + i as u32 % frame_control.width;
+            let y = top + i as u32 / frame_control.width;
+            if x >= info.width || y >= info.height {
+                continue;
+            }
+            // Partly transparent pixels are drawn as if they were opaque.
+            if frame_control.blend_op == png::BlendOp::Source || !pixel.is_transparent() {
+                canvas[(y * info.width + x) as usize] = *pixel;
+            }
+        }
+        stacked.extend_from_slice(&canvas);
+
+        match frame_control.dispose_op {
+            png::DisposeOp::Previous if frame > 0 => canvas = previous,
+            png::DisposeOp::Previous | png::DisposeOp::Background => {
+                for y in top..(top + frame_control.height).min(info.height) {
+                    for x in left..(left + frame_control.width).min(info.width) {
+                        canvas[(y * info.width + x) as usize] = transparent;
+                    }
+                }
+            }
+            png::DisposeOp::None => {}
+        }
+    }
+
+    Ok(SourceImage {
+        width: info.width,
+        height,
+        pixels: stacked,
+        palette,
+        frames,
+        tilemaps: Vec::new(),
+        palette_mask: None,
+    })
+}
+
+/// The frame control chunk of the frame `png_reader` just read, the
+/// `frame`th of the animation.
+fn frame_control<R: Read>(
+    png_reader: &png::Reader<R>,
+    frame: u32,
+) -> Result<png::FrameControl, ImageReadError> {
+    png_reader
+        .info()
+        .frame_control()
+        .copied()
+        .ok_or_else(|| invalid(format!("frame {} has no frame control chunk", frame)))
+}
+
+fn invalid(message: String) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "APNG",
+        message,
+    }
+}
+
+/// A decoder that always produces 8-bit samples, so [`read_image_data`]
+/// only has to handle one byte per channel. Indexed, low bit depth and
+/// `tRNS` images are expanded to RGB, grayscale and alpha, and 16-bit
+/// samples, like exports from Krita, are cut down to their high byte.
+fn decoder(reader: &mut dyn Read) -> Decoder<&mut dyn Read> {
+    let mut decoder = Decoder::new(reader);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    decoder
+}
+
+/// The palette whose order gives the Game Boy color numbers, if the image
+/// has one.
+///
+/// For an indexed image, that's its palette if it's small enough. Images
+/// with bigger palettes, like exports from a full 256 color palette, are
+/// numbered by color instead. A 2-bit grayscale image already holds one of
+/// four shades per pixel, so its shades are used from white to black, and
+/// each sample maps directly onto color number 3 minus its value.
+fn color_number_palette(info: &png::Info) -> Option<Vec<Rgb>> {
+    if info.color_type == png::ColorType::Grayscale && info.bit_depth == png::BitDepth::Two {
+        // Samples are expanded to 8 bits by repeating them, so 0b11
+        // becomes 0xff.
+        return Some(
+            (0..GB_MAX_COLOR_COUNT as u8)
+                .rev()
+                .map(|sample| {
+                    let gray = sample * 0x55;
+                    Rgb {
+                        r: gray,
+                        g: gray,
+                        b: gray,
+                    }
+                })
+                .collect(),
+        );
+    }
+    if info.color_type != png::ColorType::Indexed {
+        return None;
+    }
+    let palette = info.palette.as_ref()?;
+    if palette.len() / 3 > GB_MAX_COLOR_COUNT {
+        debug!(
+            "Palette has {} entries, numbering colors by color instead",
+            palette.len() / 3
+        );
+        return None;
+    }
+    Some(
+        palette
+            .chunks(3)
+            .map(|color| Rgb {
+                r: color[0],
+                g: color[1],
+                b: color[2],
+            })
+            .collect(),
+    )
+}
+
+/// Read decoded PNG samples into pixels. The decoder expands indexed and
+/// `tRNS` images, so transparency always arrives as an alpha channel.
+fn read_image_data(info: &png::OutputInfo, image_buf: &[u8]) -> Result<Vec<Rgba>, ImageReadError> {
+    let mut image_data = Vec::new();
+    match info.color_type {
+        png::ColorType::RGB => {
+            for color in image_buf.chunks(3) {
+                image_data.push(Rgba {
+                    r: color[0],
+                    g: color[1],
+                    b: color[2],
+                    a: 255,
+                });
+            }
+        }
+        png::ColorType::RGBA => {
+            for color in image_buf.chunks(4) {
+                image_data.push(Rgba {
+                    r: color[0],
+                    g: color[1],
+                    b: color[2],
+                    a: color[3],
+                });
+            }
+        }
+        png::ColorType::Grayscale => {
+            for &color in image_buf {
+                image_data.push(Rgba {
+                    r: color,
+                    g: color,
+                    b: color,
+                    a: 255,
+                });
+            }
+        }
+        png::ColorType::GrayscaleAlpha => {
+            for color in image_buf.chunks(2) {
+                image_data.push(Rgba {
+                    r: color[0],
+                    g: color[0],
+                    b: color[0],
+                    a: color[1],
+                });
+            }
+        }
+        color_type => {
+            return Err(ImageReadError::UnsupportedColorType(color_type));
+        }
+    }
+
+    Ok(image_data)
+}
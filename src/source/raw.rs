@@ -0,0 +1,85 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba};
+use crate::tile::unpack_tile_row;
+use crate::vram::TILE_BYTES;
+use std::io::Read;
+
+/// The shades each color number is drawn in, from white to black.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+/// Raw 2bpp Game Boy tile data, 16 bytes per tile, as written by gbtile's
+/// `2bpp` output or other tools such as `rgbgfx`.
+///
+/// The tiles are drawn one above the other in a single column, so they're
+/// encoded again in the same order and with the same color numbers. Raw
+/// tile data has no signature, so it's only recognized by its extension,
+/// and input directories are only searched for `.2bpp` files, since `.bin`
+/// is used for all kinds of data.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RawSource;
+
+impl ImageSource for RawSource {
+    fn name(&self) -> &'static str {
+        "2bpp"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["2bpp", "bin"]
+    }
+
+    fn discovered_extensions(&self) -> &'static [&'static str] {
+        &["2bpp"]
+    }
+
+    fn matches_magic(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.is_empty() || data.len() % TILE_BYTES != 0 {
+            return Err(ImageReadError::Invalid {
+                format: "2bpp",
+                message: format!(
+                    "{} bytes isn't a whole number of {} byte tiles",
+                    data.len(),
+                    TILE_BYTES
+                ),
+            });
+        }
+        debug!("2bpp data holds {} tile(s)", data.len() / TILE_BYTES);
+
+        let pixels = data
+            .chunks(2)
+            .flat_map(|bytes| unpack_tile_row(bytes[0], bytes[1]))
+            .map(|color| {
+                let gray = SHADES[color as usize];
+                Rgba {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a: 255,
+                }
+            })
+            .collect();
+        let palette = SHADES
+            .iter()
+            .map(|&gray| Rgb {
+                r: gray,
+                g: gray,
+                b: gray,
+            })
+            .collect();
+
+        Ok(SourceImage {
+            width: 8,
+            height: (data.len() / 2) as u32,
+            pixels,
+            palette: Some(palette),
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
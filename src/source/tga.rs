@@ -0,0 +1,349 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgb, Rgba, GB_MAX_COLOR_COUNT};
+use std::io::Read;
+
+const HEADER_LEN: usize = 18;
+
+/// Uncompressed and RLE compressed TGA images, whether color mapped, true
+/// color or grayscale.
+///
+/// TGA has no magic bytes, so TGAs are only recognized by their extension.
+/// A color map of at most four colors gives the color numbers in its order,
+/// like a small PNG palette.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TgaSource;
+
+impl ImageSource for TgaSource {
+    fn name(&self) -> &'static str {
+        "tga"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["tga"]
+    }
+
+    fn matches_magic(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let header = Header::parse(&data)?;
+        debug!("TGA header: {:?}", header);
+
+        let color_map_start = HEADER_LEN + header.id_len;
+        let color_map_len = header.color_map_len * header.color_map_depth.div_ceil(8);
+        let color_map = match data.get(color_map_start..color_map_start + color_map_len) {
+            Some(color_map) if header.color_mapped() => color_map
+                .chunks(header.color_map_depth.div_ceil(8))
+                .map(|entry| color(entry, header.color_map_depth, header.alpha_bits))
+                .collect::<Result<Vec<Rgba>, ImageReadError>>()?,
+            Some(_) => Vec::new(),
+            None => return Err(invalid("file ends early")),
+        };
+
+        let bytes_per_pixel = header.depth.div_ceil(8);
+        let len = header.width as usize * header.height as usize;
+        let image_data = &data[color_map_start + color_map_len..];
+        let values = if header.is_rle() {
+            decode_rle(image_data, bytes_per_pixel, len)?
+        } else {
+            image_data
+                .get(..len * bytes_per_pixel)
+                .ok_or_else(|| invalid("file ends early"))?
+                .to_vec()
+        };
+
+        let mut pixels = Vec::with_capacity(len);
+        for value in values.chunks(bytes_per_pixel) {
+            pixels.push(if header.color_mapped() {
+                let index = match *value {
+                    [index] => usize::from(index),
+                    [low, high] => usize::from(u16::from_le_bytes([low, high])),
+                    _ => return Err(invalid("color map indices must be 8 or 16 bits")),
+                };
+                *index
+                    .checked_sub(header.color_map_first)
+                    .and_then(|index| color_map.get(index))
+                    .ok_or_else(|| invalid("color index is outside the color map"))?
+            } else if header.grayscale() {
+                let gray = value[0];
+                Rgba {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a: if header.alpha_bits > 0 && bytes_per_pixel > 1 {
+                        value[1]
+                    } else {
+                        255
+                    },
+                }
+            } else {
+                color(value, header.depth, header.alpha_bits)?
+            });
+        }
+
+        // Rows are stored bottom to top unless the descriptor says
+        // otherwise, and may run right to left.
+        let width = header.width as usize;
+        if header.descriptor & 0x10 != 0 {
+            for row in pixels.chunks_mut(width) {
+                row.reverse();
+            }
+        }
+        if header.descriptor & 0x20 == 0 {
+            pixels = pixels.chunks(width).rev().flatten().cloned().collect();
+        }
+
+        let palette = Some(&color_map)
+            .filter(|color_map| header.color_mapped() && color_map.len() <= GB_MAX_COLOR_COUNT)
+            .map(|color_map| color_map.iter().map(Rgba::rgb).collect::<Vec<Rgb>>());
+
+        Ok(SourceImage {
+            width: header.width,
+            height: header.height,
+            pixels,
+            palette,
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "TGA",
+        message: message.to_string(),
+    }
+}
+
+#[derive(Debug)]
+struct Header {
+    id_len: usize,
+    image_type: u8,
+    color_map_first: usize,
+    color_map_len: usize,
+    color_map_depth: usize,
+    width: u32,
+    height: u32,
+    depth: usize,
+    /// Bits 0-3 are the alpha bits per pixel, bit 4 is set for right to
+    /// left rows and bit 5 for top to bottom rows.
+    descriptor: u8,
+    alpha_bits: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header, ImageReadError> {
+        let header = data
+            .get(..HEADER_LEN)
+            .ok_or_else(|| invalid("file ends early"))?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let header = Header {
+            id_len: usize::from(header[0]),
+            image_type: header[2],
+            color_map_first: usize::from(u16_at(3)),
+            color_map_len: usize::from(u16_at(5)),
+            color_map_depth: usize::from(header[7]),
+            width: u32::from(u16_at(12)),
+            height: u32::from(u16_at(14)),
+            depth: usize::from(header[16]),
+            descriptor: header[17],
+            alpha_bits: header[17] & 0x0f,
+        };
+        match header.image_type {
+            1 | 2 | 3 | 9 | 10 | 11 => {}
+            0 => return Err(invalid("file has no image data")),
+            _ => return Err(invalid("only uncompressed and RLE images are supported")),
+        }
+        let depths: &[usize] = if header.color_mapped() || header.grayscale() {
+            &[8, 16]
+        } else {
+            &[15, 16, 24, 32]
+        };
+        if !depths.contains(&header.depth) {
+            return Err(invalid(&format!(
+                "{} bit pixels aren't supported for this image type",
+                header.depth
+            )));
+        }
+        if header.color_mapped() && ![15, 16, 24, 32].contains(&header.color_map_depth) {
+            return Err(invalid(&format!(
+                "{} bit color map entries aren't supported",
+                header.color_map_depth
+            )));
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid("image has no height or width"));
+        }
+        Ok(header)
+    }
+
+    fn color_mapped(&self) -> bool {
+        self.image_type & 0x07 == 1
+    }
+
+    fn grayscale(&self) -> bool {
+        self.image_type & 0x07 == 3
+    }
+
+    fn is_rle(&self) -> bool {
+        self.image_type & 0x08 != 0
+    }
+}
+
+/// A true color pixel or color map entry, stored blue first.
+fn color(value: &[u8], depth: usize, alpha_bits: u8) -> Result<Rgba, ImageReadError> {
+    Ok(match *value {
+        [low, high] => {
+            let value = u16::from_le_bytes([low, high]);
+            let channel = |shift: u16| {
+                let channel = ((value >> shift) & 0x1f) as u8;
+                (channel << 3) | (channel >> 2)
+            };
+            // 15 bit colors have no alpha, and the top bit of 16 bit
+            // colors is only alpha if the descriptor says so.
+            let opaque = depth == 15 || alpha_bits == 0 || value & 0x8000 != 0;
+            Rgba {
+                r: channel(10),
+                g: channel(5),
+                b: channel(0),
+                a: if opaque { 255 } else { 0 },
+            }
+        }
+        [b, g, r] => Rgba { r, g, b, a: 255 },
+        // Without alpha bits in the descriptor, the fourth byte is unused.
+        [b, g, r, a] => Rgba {
+            r,
+            g,
+            b,
+            a: if alpha_bits > 0 { a } else { 255 },
+        },
+        _ => return Err(invalid("color has the wrong size")),
+    })
+}
+
+/// Expand RLE packets into `len` pixels of `bytes_per_pixel` bytes each.
+/// Each packet is a run of one repeated pixel, or a number of raw pixels.
+fn decode_rle(data: &[u8], bytes_per_pixel: usize, len: usize) -> Result<Vec<u8>, ImageReadError> {
+    let mut values = Vec::with_capacity(len * bytes_per_pixel);
+    let mut position = 0;
+    while values.len() < len * bytes_per_pixel {
+        let packet = *data
+            .get(position)
+            .ok_or_else(|| invalid("file ends early"))?;
+        position += 1;
+        let count = usize::from(packet & 0x7f) + 1;
+        let size = if packet & 0x80 != 0 {
+            bytes_per_pixel
+        } else {
+            count * bytes_per_pixel
+        };
+        let packet_data = data
+            .get(position..position + size)
+            .ok_or_else(|| invalid("file ends early"))?;
+        position += size;
+        if packet & 0x80 != 0 {
+            for _ in 0..count {
+                values.extend_from_slice(packet_data);
+            }
+        } else {
+            values.extend_from_slice(packet_data);
+        }
+    }
+    values.truncate(len * bytes_per_pixel);
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A top to bottom `width`x`height` TGA of `image_type` with 24 bit
+    /// pixels, or 8 bit indices into a 24 bit `color_map`, followed by
+    /// `image_data`.
+    fn tga(
+        image_type: u8,
+        width: u16,
+        height: u16,
+        color_map: &[[u8; 3]],
+        image_data: &[u8],
+    ) -> Vec<u8> {
+        let mut data = vec![0, u8::from(!color_map.is_empty()), image_type, 0, 0];
+        data.extend((color_map.len() as u16).to_le_bytes());
+        data.push(if color_map.is_empty() { 0 } else { 24 });
+        data.extend([0; 4]);
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend([if color_map.is_empty() { 24 } else { 8 }, 0x20]);
+        data.extend(color_map.iter().flatten());
+        data.extend(image_data);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        TgaSource.decode(&mut &data[..])
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> Rgba {
+        Rgba { r, g, b, a: 255 }
+    }
+
+    #[test]
+    fn decodes_rle_packets() {
+        // A run of three blue pixels, then one raw red pixel.
+        let image = decode(&tga(10, 4, 1, &[], &[0x82, 0xff, 0, 0, 0x00, 0, 0, 0xff])).unwrap();
+        let blue = rgb(0, 0, 0xff);
+        assert_eq!(image.pixels, vec![blue, blue, blue, rgb(0xff, 0, 0)]);
+        assert_eq!(image.palette, None);
+    }
+
+    #[test]
+    fn numbers_small_color_maps_in_order() {
+        let color_map = [[0xff, 0xff, 0xff], [0, 0, 0]];
+        let image = decode(&tga(9, 2, 1, &color_map, &[0x81, 1])).unwrap();
+        assert_eq!(image.pixels, vec![rgb(0, 0, 0); 2]);
+        assert_eq!(
+            image.palette,
+            Some(vec![
+                Rgb {
+                    r: 0xff,
+                    g: 0xff,
+                    b: 0xff
+                },
+                Rgb { r: 0, g: 0, b: 0 }
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_rle_packets() {
+        for image_data in [&[][..], &[0x82][..], &[0x82, 0xff, 0], &[0x03, 0, 0, 0]] {
+            assert!(matches!(
+                decode(&tga(10, 4, 1, &[], image_data)),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_headers_and_color_maps() {
+        let data = tga(9, 2, 1, &[[0, 0, 0]], &[0x81, 0]);
+        for len in [0, HEADER_LEN - 1, HEADER_LEN + 2] {
+            assert!(matches!(
+                decode(&data[..len]),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_indices_outside_the_color_map() {
+        assert!(matches!(
+            decode(&tga(9, 2, 1, &[[0, 0, 0]], &[0x81, 1])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+}
@@ -0,0 +1,720 @@
+use super::{ImageSource, SourceImage};
+use crate::image::{ImageReadError, Rgba};
+use std::io::Read;
+
+/// The order code lengths of the code length code are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The offsets of the 120 nearest pixels that short distance codes refer
+/// to, as pairs of pixels across then rows down.
+const DISTANCE_OFFSETS: [i32; 240] = [
+    0, 1, 1, 0, 1, 1, -1, 1, 0, 2, 2, 0, 1, 2, -1, 2, 2, 1, -2, 1, 2, 2, -2, 2, 0, 3, 3, 0, 1, 3,
+    -1, 3, 3, 1, -3, 1, 2, 3, -2, 3, 3, 2, -3, 2, 0, 4, 4, 0, 1, 4, -1, 4, 4, 1, -4, 1, 3, 3, -3,
+    3, 2, 4, -2, 4, 4, 2, -4, 2, 0, 5, 3, 4, -3, 4, 4, 3, -4, 3, 5, 0, 1, 5, -1, 5, 5, 1, -5, 1, 2,
+    5, -2, 5, 5, 2, -5, 2, 4, 4, -4, 4, 3, 5, -3, 5, 5, 3, -5, 3, 0, 6, 6, 0, 1, 6, -1, 6, 6, 1,
+    -6, 1, 2, 6, -2, 6, 6, 2, -6, 2, 4, 5, -4, 5, 5, 4, -5, 4, 3, 6, -3, 6, 6, 3, -6, 3, 0, 7, 7,
+    0, 1, 7, -1, 7, 5, 5, -5, 5, 7, 1, -7, 1, 4, 6, -4, 6, 6, 4, -6, 4, 2, 7, -2, 7, 7, 2, -7, 2,
+    3, 7, -3, 7, 7, 3, -7, 3, 5, 6, -5, 6, 6, 5, -6, 5, 8, 0, 4, 7, -4, 7, 7, 4, -7, 4, 8, 1, 8, 2,
+    6, 6, -6, 6, 8, 3, 5, 7, -5, 7, 7, 5, -7, 5, 8, 4, 6, 7, -6, 7, 7, 6, -7, 6, 8, 5, 7, 7, -7, 7,
+    8, 6, 8, 7,
+];
+
+/// Length prefix codes, which follow the 256 green values in the green
+/// alphabet.
+const LENGTH_CODES: usize = 24;
+
+/// Lossless WebP images.
+///
+/// Lossy and animated WebPs aren't supported. Although a lossless WebP may
+/// store a color table, encoders reorder it freely, so unlike a small PNG
+/// palette it doesn't give the color numbers.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WebpSource;
+
+impl ImageSource for WebpSource {
+    fn name(&self) -> &'static str {
+        "webp"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["webp"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.len() >= 12 && header.starts_with(b"RIFF") && &header[8..12] == b"WEBP"
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> Result<SourceImage, ImageReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let lossless = lossless_chunk(&data)?;
+        let (width, height, argb) = decode_lossless(lossless)?;
+        debug!("WebP is {}x{}", width, height);
+        let pixels = argb
+            .iter()
+            .map(|&argb| Rgba {
+                r: (argb >> 16) as u8,
+                g: (argb >> 8) as u8,
+                b: argb as u8,
+                a: (argb >> 24) as u8,
+            })
+            .collect();
+        Ok(SourceImage {
+            width,
+            height,
+            pixels,
+            palette: None,
+            frames: 1,
+            tilemaps: Vec::new(),
+            palette_mask: None,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ImageReadError {
+    ImageReadError::Invalid {
+        format: "WebP",
+        message: message.to_string(),
+    }
+}
+
+/// Find the `VP8L` chunk holding a lossless image in a WebP's RIFF
+/// container.
+fn lossless_chunk(data: &[u8]) -> Result<&[u8], ImageReadError> {
+    let mut position = 12;
+    while let Some(header) = data.get(position..position + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let chunk = data
+            .get(position + 8..position + 8 + len)
+            .ok_or_else(|| invalid("file ends early"))?;
+        match &header[..4] {
+            b"VP8L" => return Ok(chunk),
+            b"VP8 " => return Err(invalid("lossy WebP images aren't supported")),
+            b"ANIM" | b"ANMF" => return Err(invalid("animated WebP images aren't supported")),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        position += 8 + len + len % 2;
+    }
+    Err(invalid("no image data"))
+}
+
+/// Decode a `VP8L` chunk into its width, height and ARGB pixels.
+fn decode_lossless(data: &[u8]) -> Result<(u32, u32, Vec<u32>), ImageReadError> {
+    let mut reader = BitReader::new(data);
+    if reader.bits(8)? != 0x2f {
+        return Err(invalid("lossless image has the wrong signature"));
+    }
+    let width = reader.bits(14)? + 1;
+    let height = reader.bits(14)? + 1;
+    // Whether alpha is used is only a hint, so alpha is decoded regardless.
+    reader.bits(1)?;
+    if reader.bits(3)? != 0 {
+        return Err(invalid("unknown lossless format version"));
+    }
+
+    let mut transforms = Vec::new();
+    let mut coded_width = width;
+    while reader.bits(1)? == 1 {
+        let transform = Transform::read(&mut reader, coded_width, height)?;
+        if let Transform::ColorIndexing {
+            ref color_table, ..
+        } = transform
+        {
+            coded_width = coded_width.div_ceil(1 << index_width_bits(color_table.len()));
+        }
+        transforms.push(transform);
+    }
+
+    let mut pixels = decode_image(&mut reader, coded_width, height, true)?;
+    for transform in transforms.iter().rev() {
+        pixels = transform.invert(pixels, height);
+    }
+    Ok((width, height, pixels))
+}
+
+/// Decode an entropy coded image. Only the main image can use different
+/// prefix codes in different parts of the image.
+fn decode_image(
+    reader: &mut BitReader,
+    width: u32,
+    height: u32,
+    is_main: bool,
+) -> Result<Vec<u32>, ImageReadError> {
+    let cache_bits = if reader.bits(1)? == 1 {
+        let cache_bits = reader.bits(4)?;
+        if !(1..=11).contains(&cache_bits) {
+            return Err(invalid("color cache size is out of range"));
+        }
+        cache_bits
+    } else {
+        0
+    };
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+    let mut prefix_bits = 0;
+    let mut group_image = None;
+    let mut group_count = 1;
+    if is_main && reader.bits(1)? == 1 {
+        prefix_bits = reader.bits(3)? + 2;
+        let groups = decode_image(
+            reader,
+            width.div_ceil(1 << prefix_bits),
+            height.div_ceil(1 << prefix_bits),
+            false,
+        )?;
+        let groups: Vec<usize> = groups
+            .iter()
+            .map(|&pixel| ((pixel >> 8) & 0xffff) as usize)
+            .collect();
+        group_count = groups.iter().max().map_or(1, |group| group + 1);
+        group_image = Some(groups);
+    }
+
+    let mut groups = Vec::with_capacity(group_count);
+    for _ in 0..group_count {
+        groups.push(PrefixCodeGroup {
+            green: PrefixCode::read(reader, 256 + LENGTH_CODES + cache_size)?,
+            red: PrefixCode::read(reader, 256)?,
+            blue: PrefixCode::read(reader, 256)?,
+            alpha: PrefixCode::read(reader, 256)?,
+            distance: PrefixCode::read(reader, 40)?,
+        });
+    }
+
+    let len = width as usize * height as usize;
+    let mut pixels = Vec::with_capacity(len);
+    let mut cache = vec![0; cache_size];
+    let mut cached = 0;
+    while pixels.len() < len {
+        let group = match &group_image {
+            Some(group_image) => {
+                let x = pixels.len() as u32 % width;
+                let y = pixels.len() as u32 / width;
+                let across = width.div_ceil(1 << prefix_bits);
+                group_image[((y >> prefix_bits) * across + (x >> prefix_bits)) as usize]
+            }
+            None => 0,
+        };
+        let group = &groups[group];
+        let symbol = group.green.decode(reader)? as usize;
+        if symbol < 256 {
+            let red = group.red.decode(reader)? as u32;
+            let blue = group.blue.decode(reader)? as u32;
+            let alpha = group.alpha.decode(reader)? as u32;
+            pixels.push((alpha << 24) | (red << 16) | ((symbol as u32) << 8) | blue);
+        } else if symbol < 256 + LENGTH_CODES {
+            let length = prefixed_value(reader, (symbol - 256) as u32)? as usize;
+            let distance_symbol = group.distance.decode(reader)? as u32;
+            let distance_code = prefixed_value(reader, distance_symbol)? as usize;
+            let distance = if distance_code > DISTANCE_OFFSETS.len() / 2 {
+                distance_code - DISTANCE_OFFSETS.len() / 2
+            } else {
+                let across = DISTANCE_OFFSETS[(distance_code - 1) * 2];
+                let down = DISTANCE_OFFSETS[(distance_code - 1) * 2 + 1];
+                (across + down * width as i32).max(1) as usize
+            };
+            if distance > pixels.len() || pixels.len() + length > len {
+                return Err(invalid("back reference is outside the image"));
+            }
+            for _ in 0..length {
+                pixels.push(pixels[pixels.len() - distance]);
+            }
+        } else {
+            let index = symbol - 256 - LENGTH_CODES;
+            pixels.push(cache[index]);
+        }
+        // Every new pixel goes in the cache, including copied ones.
+        if cache_bits > 0 {
+            for &pixel in &pixels[cached..] {
+                cache[(0x1e35_a7bd_u32.wrapping_mul(pixel) >> (32 - cache_bits)) as usize] = pixel;
+            }
+            cached = pixels.len();
+        }
+    }
+    Ok(pixels)
+}
+
+/// Read a length or distance from its prefix code and extra bits.
+fn prefixed_value(reader: &mut BitReader, prefix: u32) -> Result<u32, ImageReadError> {
+    if prefix < 4 {
+        return Ok(prefix + 1);
+    }
+    let extra_bits = (prefix - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    Ok(offset + reader.bits(extra_bits)? + 1)
+}
+
+/// How many bits of a packed pixel's width each color index takes up, as
+/// a shift: 3 packs 8 indices into a pixel, down to 0 for one.
+fn index_width_bits(color_table_size: usize) -> u32 {
+    match color_table_size {
+        0..=2 => 3,
+        3..=4 => 2,
+        5..=16 => 1,
+        _ => 0,
+    }
+}
+
+/// A transform applied to the pixels before they were coded.
+enum Transform {
+    Predictor {
+        width: u32,
+        bits: u32,
+        modes: Vec<u32>,
+    },
+    Color {
+        width: u32,
+        bits: u32,
+        multipliers: Vec<u32>,
+    },
+    SubtractGreen,
+    ColorIndexing {
+        width: u32,
+        color_table: Vec<u32>,
+    },
+}
+
+impl Transform {
+    fn read(reader: &mut BitReader, width: u32, height: u32) -> Result<Transform, ImageReadError> {
+        Ok(match reader.bits(2)? {
+            kind @ 0..=1 => {
+                let bits = reader.bits(3)? + 2;
+                let blocks = decode_image(
+                    reader,
+                    width.div_ceil(1 << bits),
+                    height.div_ceil(1 << bits),
+                    false,
+                )?;
+                if kind == 0 {
+                    Transform::Predictor {
+                        width,
+                        bits,
+                        modes: blocks.iter().map(|pixel| (pixel >> 8) & 0x0f).collect(),
+                    }
+                } else {
+                    Transform::Color {
+                        width,
+                        bits,
+                        multipliers: blocks,
+                    }
+                }
+            }
+            2 => Transform::SubtractGreen,
+            _ => {
+                let size = reader.bits(8)? + 1;
+                let mut color_table = decode_image(reader, size, 1, false)?;
+                // Each color is stored as the difference from the one
+                // before it.
+                for i in 1..color_table.len() {
+                    color_table[i] = add_pixels(color_table[i], color_table[i - 1]);
+                }
+                Transform::ColorIndexing { width, color_table }
+            }
+        })
+    }
+
+    /// Undo the transform, returning the pixels from before it.
+    fn invert(&self, mut pixels: Vec<u32>, height: u32) -> Vec<u32> {
+        match self {
+            Transform::Predictor { width, bits, modes } => {
+                let across = width.div_ceil(1 << bits) as usize;
+                let width = *width as usize;
+                for i in 0..pixels.len() {
+                    let (x, y) = (i % width, i / width);
+                    let prediction = match (x, y) {
+                        (0, 0) => 0xff00_0000,
+                        (_, 0) => pixels[i - 1],
+                        (0, _) => pixels[i - width],
+                        _ => {
+                            let mode = modes[(y >> bits) * across + (x >> bits)];
+                            predict(mode, &pixels, i, width)
+                        }
+                    };
+                    pixels[i] = add_pixels(pixels[i], prediction);
+                }
+                pixels
+            }
+            Transform::Color {
+                width,
+                bits,
+                multipliers,
+            } => {
+                let across = width.div_ceil(1 << bits) as usize;
+                let width = *width as usize;
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let (x, y) = (i % width, i / width);
+                    let multipliers = multipliers[(y >> bits) * across + (x >> bits)];
+                    let green_to_red = multipliers as u8;
+                    let green_to_blue = (multipliers >> 8) as u8;
+                    let red_to_blue = (multipliers >> 16) as u8;
+                    let green = (*pixel >> 8) as u8;
+                    let red = ((*pixel >> 16) as u8).wrapping_add(color_delta(green_to_red, green));
+                    let blue = (*pixel as u8)
+                        .wrapping_add(color_delta(green_to_blue, green))
+                        .wrapping_add(color_delta(red_to_blue, red));
+                    *pixel = (*pixel & 0xff00_ff00) | (u32::from(red) << 16) | u32::from(blue);
+                }
+                pixels
+            }
+            Transform::SubtractGreen => {
+                for pixel in &mut pixels {
+                    let green = (*pixel >> 8) & 0xff;
+                    let red_blue = ((*pixel & 0x00ff_00ff) + ((green << 16) | green)) & 0x00ff_00ff;
+                    *pixel = (*pixel & 0xff00_ff00) | red_blue;
+                }
+                pixels
+            }
+            Transform::ColorIndexing { width, color_table } => {
+                let width_bits = index_width_bits(color_table.len());
+                let packed_width = width.div_ceil(1 << width_bits) as usize;
+                let index_bits = 8 >> width_bits;
+                let mut unpacked = Vec::with_capacity(*width as usize * height as usize);
+                for y in 0..height as usize {
+                    for x in 0..*width as usize {
+                        let packed = (pixels[y * packed_width + (x >> width_bits)] >> 8) & 0xff;
+                        let shift = (x as u32 & ((1 << width_bits) - 1)) * index_bits;
+                        let index = (packed >> shift) & ((1 << index_bits) - 1);
+                        // Indices past the end of the table are transparent
+                        // black.
+                        unpacked.push(color_table.get(index as usize).cloned().unwrap_or(0));
+                    }
+                }
+                unpacked
+            }
+        }
+    }
+}
+
+/// Predict a pixel from its already decoded neighbors.
+fn predict(mode: u32, pixels: &[u32], i: usize, width: usize) -> u32 {
+    let left = pixels[i - 1];
+    let top = pixels[i - width];
+    // The pixel above and right of the last in a row is the first pixel of
+    // the current row.
+    let top_right = pixels[i - width + 1];
+    let top_left = pixels[i - width - 1];
+    match mode {
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => per_channel(left, top, top_left, |l, t, tl| l + t - tl),
+        13 => {
+            let mean = average(left, top);
+            per_channel(mean, top_left, 0, |a, b, _| a + (a - b) / 2)
+        }
+        _ => 0xff00_0000,
+    }
+}
+
+fn channels(pixel: u32) -> [i32; 4] {
+    [
+        (pixel >> 24) as i32,
+        ((pixel >> 16) & 0xff) as i32,
+        ((pixel >> 8) & 0xff) as i32,
+        (pixel & 0xff) as i32,
+    ]
+}
+
+/// Combine pixels a channel at a time, clamping each result to 0 to 255.
+fn per_channel(a: u32, b: u32, c: u32, combine: impl Fn(i32, i32, i32) -> i32) -> u32 {
+    let (a, b, c) = (channels(a), channels(b), channels(c));
+    (0..4).fold(0, |pixel, channel| {
+        let value = combine(a[channel], b[channel], c[channel]).clamp(0, 255) as u32;
+        (pixel << 8) | value
+    })
+}
+
+fn average(a: u32, b: u32) -> u32 {
+    per_channel(a, b, 0, |a, b, _| (a + b) / 2)
+}
+
+/// Pick whichever of `left` and `top` is closer to the gradient through
+/// `top_left`.
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+    let left_distance: i32 = (0..4).map(|c| (t[c] - tl[c]).abs()).sum();
+    let top_distance: i32 = (0..4).map(|c| (l[c] - tl[c]).abs()).sum();
+    if left_distance < top_distance {
+        left
+    } else {
+        top
+    }
+}
+
+/// Add two pixels a channel at a time, wrapping each channel.
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00_ff00).wrapping_add(b & 0xff00_ff00) & 0xff00_ff00;
+    let red_blue = (a & 0x00ff_00ff).wrapping_add(b & 0x00ff_00ff) & 0x00ff_00ff;
+    alpha_green | red_blue
+}
+
+fn color_delta(multiplier: u8, color: u8) -> u8 {
+    ((i32::from(multiplier as i8) * i32::from(color as i8)) >> 5) as u8
+}
+
+/// The five prefix codes used for one part of an image.
+struct PrefixCodeGroup {
+    /// Green values, then length prefixes, then color cache indices.
+    green: PrefixCode,
+    red: PrefixCode,
+    blue: PrefixCode,
+    alpha: PrefixCode,
+    distance: PrefixCode,
+}
+
+/// A canonical prefix code, as the count of codes of each length and the
+/// symbols in code order.
+struct PrefixCode {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl PrefixCode {
+    fn read(reader: &mut BitReader, alphabet_size: usize) -> Result<PrefixCode, ImageReadError> {
+        let mut lengths = vec![0; alphabet_size];
+        if reader.bits(1)? == 1 {
+            // A simple code of one or two symbols.
+            let count = reader.bits(1)? + 1;
+            let first_bits = if reader.bits(1)? == 1 { 8 } else { 1 };
+            let mut symbols = vec![reader.bits(first_bits)? as usize];
+            if count == 2 {
+                symbols.push(reader.bits(8)? as usize);
+            }
+            for symbol in symbols {
+                *lengths
+                    .get_mut(symbol)
+                    .ok_or_else(|| invalid("prefix code symbol is out of range"))? = 1;
+            }
+        } else {
+            let mut code_length_lengths = [0; 19];
+            let count = reader.bits(4)? as usize + 4;
+            for &symbol in &CODE_LENGTH_ORDER[..count] {
+                code_length_lengths[symbol] = reader.bits(3)? as u8;
+            }
+            let code_length_code = PrefixCode::new(&code_length_lengths);
+
+            let mut max_tokens = if reader.bits(1)? == 1 {
+                let length_bits = 2 + 2 * reader.bits(3)?;
+                2 + reader.bits(length_bits)? as usize
+            } else {
+                alphabet_size
+            };
+            let mut previous_length = 8;
+            let mut symbol = 0;
+            while symbol < alphabet_size && max_tokens > 0 {
+                max_tokens -= 1;
+                let token = code_length_code.decode(reader)?;
+                let (length, repeat) = match token {
+                    0..=15 => (token as u8, 1),
+                    16 => (previous_length, 3 + reader.bits(2)? as usize),
+                    17 => (0, 3 + reader.bits(3)? as usize),
+                    _ => (0, 11 + reader.bits(7)? as usize),
+                };
+                if symbol + repeat > alphabet_size {
+                    return Err(invalid("prefix code lengths are out of range"));
+                }
+                lengths[symbol..symbol + repeat]
+                    .iter_mut()
+                    .for_each(|l| *l = length);
+                symbol += repeat;
+                if token < 16 && length != 0 {
+                    previous_length = length;
+                }
+            }
+        }
+        Ok(PrefixCode::new(&lengths))
+    }
+
+    fn new(lengths: &[u8]) -> PrefixCode {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::new();
+        for length in 1..16 {
+            for (symbol, _) in lengths
+                .iter()
+                .enumerate()
+                .filter(|(_, &l)| l as usize == length)
+            {
+                symbols.push(symbol as u16);
+            }
+        }
+        PrefixCode { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, ImageReadError> {
+        // A code with a single symbol takes no bits.
+        if self.symbols.len() == 1 {
+            return Ok(self.symbols[0]);
+        }
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for &count in &self.counts[1..] {
+            let count = i32::from(count);
+            code |= reader.bits(1)? as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("unknown prefix code"))
+    }
+}
+
+/// Reads bits least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, ImageReadError> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| invalid("file ends early"))?;
+            self.buffer |= u64::from(byte) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack `values`, each with its size in bits, least significant bit
+    /// first.
+    fn pack(values: &[(u32, u32)]) -> Vec<u8> {
+        let (mut data, mut bits, mut bit_count) = (Vec::new(), 0u64, 0);
+        for &(value, size) in values {
+            bits |= u64::from(value) << bit_count;
+            bit_count += size;
+            while bit_count >= 8 {
+                data.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        }
+        if bit_count > 0 {
+            data.push(bits as u8);
+        }
+        data
+    }
+
+    /// A lossless `width`x`height` image of one color, coded with simple
+    /// prefix codes of a single symbol each for green, red, blue, alpha and
+    /// distance, so each pixel takes no bits at all.
+    fn lossless(width: u32, height: u32, [green, red, blue, alpha, distance]: [u32; 5]) -> Vec<u8> {
+        let mut values = vec![(0x2f, 8), (width - 1, 14), (height - 1, 14), (0, 1), (0, 3)];
+        // No transforms, color cache or meta prefix codes.
+        values.extend([(0, 1), (0, 1), (0, 1)]);
+        for symbol in [green, red, blue, alpha, distance] {
+            values.extend([(1, 1), (0, 1), (1, 1), (symbol, 8)]);
+        }
+        pack(&values)
+    }
+
+    /// A WebP holding one `chunk`.
+    fn webp(fourcc: &[u8; 4], chunk: &[u8]) -> Vec<u8> {
+        let mut data = b"RIFF".to_vec();
+        data.extend((12 + chunk.len() as u32).to_le_bytes());
+        data.extend(b"WEBP");
+        data.extend(fourcc);
+        data.extend((chunk.len() as u32).to_le_bytes());
+        data.extend(chunk);
+        if chunk.len() % 2 == 1 {
+            data.push(0);
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Result<SourceImage, ImageReadError> {
+        WebpSource.decode(&mut &data[..])
+    }
+
+    #[test]
+    fn decodes_a_lossless_image() {
+        let image = decode(&webp(b"VP8L", &lossless(3, 2, [0x80, 0x40, 0x20, 0xff, 0]))).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        let color = Rgba {
+            r: 0x40,
+            g: 0x80,
+            b: 0x20,
+            a: 0xff,
+        };
+        assert_eq!(image.pixels, vec![color; 6]);
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let chunk = lossless(3, 2, [0x80, 0x40, 0x20, 0xff, 0]);
+        for len in 0..chunk.len() {
+            assert!(matches!(
+                decode(&webp(b"VP8L", &chunk[..len])),
+                Err(ImageReadError::Invalid { .. })
+            ));
+        }
+        let data = webp(b"VP8L", &chunk);
+        assert!(matches!(
+            decode(&data[..data.len() - 2]),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_prefix_code_symbols_out_of_range() {
+        // The distance alphabet only has 40 symbols.
+        assert!(matches!(
+            decode(&webp(b"VP8L", &lossless(1, 1, [0, 0, 0, 0, 200]))),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_lossy_images_and_bad_signatures() {
+        assert!(matches!(
+            decode(&webp(b"VP8 ", &[0; 10])),
+            Err(ImageReadError::Invalid { .. })
+        ));
+        let mut chunk = lossless(1, 1, [0; 5]);
+        chunk[0] = 0;
+        assert!(matches!(
+            decode(&webp(b"VP8L", &chunk)),
+            Err(ImageReadError::Invalid { .. })
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::diagnostic::Diagnostic;
+use crate::filter::posterize;
+use crate::image::{
+    check_dimensions, check_frames, check_tile_aligned, colors_by_number, detect_source,
+    nearest_color, number_colors, palette_color, palette_register, tile_rect, ColorUse,
+    DecodeOptions, ImageReadError, Rect, Rgb, Rgba, GB_MAX_COLOR_COUNT,
+};
+use crate::source::{RowSink, SourceRegistry};
+use crate::tile::{pack_tile_row, unpack_tile_row, Addressing, EncodedTile, TileOrder, Tilemap};
+use std::collections::BTreeSet;
+use std::io::{BufReader, Read};
+
+/// Encodes tiles as image rows arrive, holding only one 8 pixel high band
+/// of the image in memory at a time.
+///
+/// Color numbers depend on every color in the image, so pixels are packed
+/// with provisional indices in order of first appearance, then remapped to
+/// their final color numbers once the last row has been seen.
+pub struct StreamingEncoder {
+    input_filename: String,
+    width: u32,
+    height: u32,
+    /// The rows received from the source, and the rows encoded of them.
+    source_rows: u32,
+    rows_seen: u32,
+    /// The tile-aligned rectangle of each frame being encoded, and the
+    /// height of the source's frames, when only part is converted.
+    rect: Option<(Rect, u32)>,
+    band: Vec<u8>,
+    /// The colors seen so far, in order of first appearance, with `None`
+    /// standing for transparent pixels.
+    colors: Vec<Option<Rgb>>,
+    /// The colors that didn't fit, as they were seen and with where they
+    /// were first used. The image keeps being read to find them all.
+    stray: Vec<(Option<Rgb>, ColorUse)>,
+    /// The colors that aren't in a strict palette, with where they were
+    /// first used.
+    off_palette: Vec<ColorUse>,
+    /// The palette giving the color numbers, for indexed images.
+    palette: Option<Vec<Rgb>>,
+    options: DecodeOptions,
+    frames: u32,
+    tilemaps: Vec<Tilemap>,
+    tile_data: Vec<u8>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl StreamingEncoder {
+    pub fn new(input_filename: &str, options: &DecodeOptions) -> Self {
+        StreamingEncoder {
+            input_filename: input_filename.to_string(),
+            width: 0,
+            height: 0,
+            source_rows: 0,
+            rows_seen: 0,
+            rect: None,
+            band: Vec::new(),
+            colors: Vec::new(),
+            stray: Vec::new(),
+            off_palette: Vec::new(),
+            palette: None,
+            options: *options,
+            frames: 1,
+            tilemaps: Vec::new(),
+            tile_data: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// `x` and `y` are where the pixel is in the source, for errors.
+    fn provisional_index(&mut self, original: Rgba, x: u32, y: u32) -> Result<u8, ImageReadError> {
+        if let Some(strict_colors) = self.options.strict_colors() {
+            let color = original.rgb();
+            if !original.is_below(self.options.alpha_threshold)
+                && !strict_colors.contains(&color)
+                && !self.off_palette.iter().any(|off| off.color == color)
+            {
+                self.off_palette.push(ColorUse { color, x, y });
+            }
+        }
+        let filtered = match self.options.posterize {
+            Some(levels) => posterize(original, levels),
+            None => original,
+        };
+        let rgb = match filtered {
+            filtered if filtered.is_below(self.options.alpha_threshold) => None,
+            filtered
+                if self.palette.is_some()
+                    || self.options.palette.is_some()
+                    || self.options.shares_palettes() =>
+            {
+                Some(palette_color(filtered.rgb(), &self.options))
+            }
+            filtered => Some(filtered.rgb().round_to(self.options.color_tolerance)),
+        };
+        if let Some(color_palette) = &self.options.palette {
+            // Pixels take their palette color's number straight away, and
+            // transparent pixels share the transparent color's.
+            if !self.colors.contains(&rgb) {
+                self.colors.push(rgb);
+            }
+            let first = self.options.sprite as usize;
+            return Ok(match rgb {
+                Some(rgb) => {
+                    nearest_color(&color_palette[first..], rgb, self.options.gamma) + first as u8
+                }
+                None => self.options.transparent_color(true).unwrap(),
+            });
+        }
+        if let Some(index) = self.colors.iter().position(|color| *color == rgb) {
+            return Ok(index as u8);
+        }
+        // Sprites always keep a color for transparency, so adding it never
+        // takes one more.
+        let full = match (rgb, self.options.sprite) {
+            (None, true) => false,
+            (Some(_), true) => self.colors.iter().flatten().count() == GB_MAX_COLOR_COUNT - 1,
+            (_, false) => self.colors.len() == GB_MAX_COLOR_COUNT,
+        };
+        if full {
+            if !self.stray.iter().any(|(color, _)| *color == rgb) {
+                self.stray.push((
+                    rgb,
+                    ColorUse {
+                        color: original.rgb(),
+                        x,
+                        y,
+                    },
+                ));
+            }
+            return Ok(0);
+        }
+        self.colors.push(rgb);
+        Ok((self.colors.len() - 1) as u8)
+    }
+
+    fn encode_band(&mut self) {
+        let width = self.width as usize;
+        for column in 0..width / 8 {
+            for tile_row in 0..8 {
+                let start = tile_row * width + column * 8;
+                let mut colors = [0; 8];
+                colors.copy_from_slice(&self.band[start..start + 8]);
+                let (low_byte, high_byte) = pack_tile_row(colors);
+                self.tile_data.push(low_byte);
+                self.tile_data.push(high_byte);
+            }
+        }
+        self.band.clear();
+    }
+
+    /// Remap the provisional indices to final color numbers and return the
+    /// encoded tiles. Rows past the last full band of tiles are dropped.
+    pub fn finish(self) -> Result<EncodedTile, ImageReadError> {
+        if !self.off_palette.is_empty() {
+            return Err(ImageReadError::off_palette(self.off_palette));
+        }
+        if !self.stray.is_empty() {
+            debug!(
+                "Unique colors are: {:?}, stray: {:?}",
+                self.colors, self.stray
+            );
+            let stray = self.stray.into_iter().map(|(_, stray)| stray).collect();
+            return Err(ImageReadError::too_many_colors(stray, self.options.sprite));
+        }
+        let unique_colors: BTreeSet<Rgb> = self.colors.iter().flatten().cloned().collect();
+        let transparent_color = self.options.transparent_color(self.colors.contains(&None));
+        let color_numbers = number_colors(
+            &unique_colors,
+            self.palette.as_deref(),
+            transparent_color,
+            &self.options,
+        );
+        let mut remap = [0, 1, 2, 3];
+        if self.options.palette.is_none() {
+            for (index, color) in self.colors.iter().enumerate() {
+                remap[index] = match color {
+                    Some(color) => color_numbers[color],
+                    None => transparent_color.unwrap(),
+                };
+            }
+        }
+        debug!(
+            "Color numbers are: {:?}, transparent: {:?}",
+            color_numbers, transparent_color
+        );
+        info!(
+            "File: {}, Tile rows: {}, columns: {}, unique colors: {}",
+            self.input_filename,
+            self.height / 8,
+            self.width / 8,
+            self.colors.len()
+        );
+
+        let mut tile_data = self.tile_data;
+        for bytes in tile_data.chunks_mut(2) {
+            let colors = unpack_tile_row(bytes[0], bytes[1]);
+            let (low_byte, high_byte) = pack_tile_row(colors.map(|index| remap[index as usize]));
+            bytes[0] = low_byte;
+            bytes[1] = high_byte;
+        }
+
+        let mut warnings = self.warnings;
+        warnings.extend(check_tile_aligned(
+            &self.input_filename,
+            self.width,
+            self.height,
+            self.frames,
+            &self.options,
+        )?);
+        Ok(EncodedTile {
+            warnings,
+            input_filename: self.input_filename,
+            name: None,
+            width: self.width,
+            height: self.height,
+            colors: colors_by_number(&color_numbers, transparent_color),
+            transparent_color,
+            palette_register: palette_register(&color_numbers, &self.options),
+            cgb: false,
+            cgb_color_correction: false,
+            sgb: false,
+            obp: None,
+            cgb_palettes: None,
+            frames: self.frames,
+            tile_data,
+            bpp: 2,
+            base_index: 0,
+            addressing: Addressing::Mode8000,
+            first_char: None,
+            order: TileOrder::Row,
+            bank_tiles: None,
+            frame_deltas: None,
+            tilemaps: self.tilemaps,
+            metatiles: Vec::new(),
+            metasprites: Vec::new(),
+        })
+    }
+}
+
+impl RowSink for StreamingEncoder {
+    fn start(&mut self, width: u32, height: u32) -> Result<(), ImageReadError> {
+        check_dimensions(width, height)?;
+        check_frames(height, self.frames)?;
+        let (width, height) = match self.options.rect {
+            Some(rect) => {
+                let frame_height = height / self.frames.max(1);
+                let (rect, warning) = tile_rect(&self.input_filename, rect, width, frame_height)?;
+                self.warnings.extend(warning);
+                self.rect = Some((rect, frame_height));
+                // Maps count the whole image's tiles, so they're left out.
+                self.tilemaps.clear();
+                (rect.width, rect.height * self.frames.max(1))
+            }
+            None => (width, height),
+        };
+        self.width = width;
+        self.height = height;
+        self.band = Vec::with_capacity(width as usize * 8);
+        Ok(())
+    }
+
+    fn palette(&mut self, palette: &[Rgb]) -> Result<(), ImageReadError> {
+        self.palette = Some(palette.to_vec());
+        Ok(())
+    }
+
+    fn frames(&mut self, frames: u32) -> Result<(), ImageReadError> {
+        self.frames = frames;
+        Ok(())
+    }
+
+    fn tilemap(&mut self, tilemap: &Tilemap) -> Result<(), ImageReadError> {
+        self.tilemaps.push(tilemap.clone());
+        Ok(())
+    }
+
+    fn row(&mut self, pixels: &[Rgba]) -> Result<(), ImageReadError> {
+        let y = self.source_rows;
+        self.source_rows += 1;
+        let (pixels, left) = match self.rect {
+            Some((rect, frame_height)) => {
+                let frame_y = y % frame_height;
+                if frame_y < rect.y || frame_y >= rect.y + rect.height {
+                    return Ok(());
+                }
+                (
+                    &pixels[rect.x as usize..(rect.x + rect.width) as usize],
+                    rect.x,
+                )
+            }
+            None => (pixels, 0),
+        };
+        for (x, pixel) in pixels.iter().enumerate() {
+            let index = self.provisional_index(*pixel, left + x as u32, y)?;
+            self.band.push(index);
+        }
+        self.rows_seen += 1;
+        if self.rows_seen.is_multiple_of(8) {
+            self.encode_band();
+        }
+        Ok(())
+    }
+}
+
+/// Decode and encode an image row by row, without holding the whole image
+/// in memory. Produces the same tiles as [`crate::decode_reader`] followed by
+/// [`crate::encode_tile`].
+pub fn encode_streaming<R: Read>(
+    sources: &SourceRegistry,
+    options: &DecodeOptions,
+    image_input: &str,
+    reader: R,
+) -> Result<EncodedTile, ImageReadError> {
+    let mut reader = BufReader::new(reader);
+    let source = detect_source(sources, image_input, &mut reader)?;
+    let mut encoder = StreamingEncoder::new(image_input, options);
+    source.decode_rows(&mut reader, &mut encoder)?;
+    encoder.finish()
+}
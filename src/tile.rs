@@ -0,0 +1,862 @@
+use crate::cgb::CgbPalettes;
+use crate::delta::FrameDeltas;
+use crate::diagnostic::Diagnostic;
+use crate::image::{colors_by_number, DecodedImage, Rgb, GB_MAX_COLOR_COUNT};
+use crate::metasprite::MetaspriteItem;
+use crate::metatile::Metatiles;
+use crate::sgb::{self, SGB_PACKET_BYTES, SGB_SCREEN_COLUMNS, SGB_SCREEN_ROWS};
+use crate::vram::TILE_BYTES;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Game Boy 2bpp tile data, ready to be formatted for an assembler or compiler.
+pub struct EncodedTile {
+    pub input_filename: String,
+    /// The generated symbol name. When unset, it's taken from the input
+    /// file stem.
+    pub name: Option<String>,
+    /// The source image's size in pixels, including any edge pixels that
+    /// didn't fill a whole tile.
+    pub width: u32,
+    pub height: u32,
+    /// The image's colors after rounding, indexed by color number. Only the
+    /// first palette's, when `cgb_palettes` shares out several.
+    pub colors: Vec<Rgb>,
+    /// The color number of transparent pixels, if the image has any.
+    pub transparent_color: Option<u8>,
+    /// The suggested BGP or OBP palette register value, showing each color
+    /// number in the shade of its color, so code loading the tiles doesn't
+    /// have to know what order they were numbered in. `0xE4` unless an
+    /// indexed image's palette numbered its colors out of brightness order.
+    pub palette_register: u8,
+    /// Whether to render [`cgb_palette`](EncodedTile::cgb_palette) with
+    /// the tiles, for the Game Boy Color.
+    pub cgb: bool,
+    /// Whether to correct the Game Boy Color palette's colors for the
+    /// console's screen, as in [`Rgb::cgb_color_corrected`].
+    pub cgb_color_correction: bool,
+    /// Whether to render the Super Game Boy's palette and attribute
+    /// commands with the tiles.
+    pub sgb: bool,
+    /// The object palette, 0 or 1, sprites drawn with the tiles use on the
+    /// original Game Boy, to render `palette_register` as its OBP0 or OBP1
+    /// value along with the OAM flags selecting it.
+    pub obp: Option<u8>,
+    /// The Game Boy Color palettes shared out between the tiles, when the
+    /// image has more colors than one palette holds.
+    pub cgb_palettes: Option<CgbPalettes>,
+    /// How many animation frames the tiles hold. Each frame's tiles follow
+    /// on from the previous frame's, and all frames have the same number.
+    pub frames: u32,
+    pub tile_data: Vec<u8>,
+    /// The bits per pixel the tiles are written with, as in
+    /// [`EncodedTile::rendered_data`]: 2, or 1 for two-color tiles.
+    pub bpp: u8,
+    /// The VRAM tile number the tiles are loaded from, added to each tile
+    /// number maps and metatiles draw with, as in
+    /// [`EncodedTile::map_tiles`].
+    pub base_index: u8,
+    /// How the background reads tile numbers, and so how maps and
+    /// metatiles number the tiles.
+    pub addressing: Addressing,
+    /// In font mode, the character the first tile draws, with each tile
+    /// after it drawing the next character.
+    pub first_char: Option<u8>,
+    /// The order the tiles were cut from the image in, as set by
+    /// [`EncodedTile::reorder`].
+    pub order: TileOrder,
+    /// The most tiles written in each array or section when the tile data
+    /// is split to fit banks, or `None` to write it whole.
+    pub bank_tiles: Option<usize>,
+    /// The animation frames as the tiles that change from each frame to
+    /// the next, when asked for. Only the first frame's tiles are written
+    /// whole then, followed by the changes.
+    pub frame_deltas: Option<FrameDeltas>,
+    /// The maps drawn with the tiles, for inputs that come with them.
+    pub tilemaps: Vec<Tilemap>,
+    /// The maps, or the image itself when it has none, grouped into
+    /// metatiles, when asked for.
+    pub metatiles: Vec<Metatiles>,
+    /// The sprites drawing each animation frame, or the image, as a
+    /// metasprite, when asked for.
+    pub metasprites: Vec<Vec<MetaspriteItem>>,
+    /// Warnings raised while decoding the source image.
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// The order an image's tiles are cut from it and written in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Left to right, then top to bottom.
+    #[default]
+    Row,
+    /// Top to bottom, then left to right, as some scrolling engines and
+    /// VRAM copy routines expect.
+    Column,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileOrder, String> {
+        match s {
+            "row" => Ok(TileOrder::Row),
+            "column" => Ok(TileOrder::Column),
+            _ => Err(format!("'{}' isn't a tile order, use 'row' or 'column'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TileOrder::Row => write!(f, "row"),
+            TileOrder::Column => write!(f, "column"),
+        }
+    }
+}
+
+/// How the background reads tile numbers, as picked by LCDC bit 4.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Addressing {
+    /// Unsigned numbers from 0 at $8000 to 255 at $8FF0, the block sprites
+    /// always use.
+    #[default]
+    Mode8000,
+    /// Signed numbers from -128 at $8800, through 0 at $9000, to 127 at
+    /// $97F0, leaving $8000 to $87FF to sprites.
+    Mode8800,
+}
+
+impl FromStr for Addressing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Addressing, String> {
+        match s {
+            "8000" => Ok(Addressing::Mode8000),
+            "8800" => Ok(Addressing::Mode8800),
+            _ => Err(format!(
+                "'{}' isn't an addressing mode, use '8000' or '8800'",
+                s
+            )),
+        }
+    }
+}
+
+/// The bits per pixel tiles are written with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Bpp {
+    /// One byte per tile row, with a bit set for each pixel that isn't
+    /// color 0, for two-color tiles like fonts.
+    One,
+    /// The two bitplanes the Game Boy draws tiles from.
+    #[default]
+    Two,
+}
+
+impl From<Bpp> for u8 {
+    fn from(bpp: Bpp) -> u8 {
+        match bpp {
+            Bpp::One => 1,
+            Bpp::Two => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for Bpp {
+    type Error = String;
+
+    fn try_from(bits: u8) -> Result<Bpp, String> {
+        match bits {
+            1 => Ok(Bpp::One),
+            2 => Ok(Bpp::Two),
+            _ => Err(format!("'{}' isn't a bit depth, use 1 or 2", bits)),
+        }
+    }
+}
+
+/// One of the original Game Boy's two object palettes, OBP0 and OBP1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectPalette {
+    Obp0,
+    Obp1,
+}
+
+impl From<ObjectPalette> for u8 {
+    fn from(obp: ObjectPalette) -> u8 {
+        match obp {
+            ObjectPalette::Obp0 => 0,
+            ObjectPalette::Obp1 => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for ObjectPalette {
+    type Error = String;
+
+    fn try_from(obp: u8) -> Result<ObjectPalette, String> {
+        match obp {
+            0 => Ok(ObjectPalette::Obp0),
+            1 => Ok(ObjectPalette::Obp1),
+            _ => Err(format!("'{}' isn't an object palette, use 0 or 1", obp)),
+        }
+    }
+}
+
+/// A background map: the number of the tile drawn at each position, left
+/// to right and top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tilemap {
+    /// The map's name, for inputs holding several, e.g. an LDtk level's
+    /// identifier.
+    pub name: Option<String>,
+    /// The map's size in tiles.
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<u8>,
+    /// Each position's Game Boy Color attribute bits given by the source,
+    /// such as [`CGB_X_FLIP`] and [`CGB_Y_FLIP`]. The palette bits are
+    /// left 0.
+    pub attributes: Vec<u8>,
+}
+
+/// The Game Boy Color attribute bit drawing a tile flipped horizontally.
+pub const CGB_X_FLIP: u8 = 0x20;
+
+/// The Game Boy Color attribute bit drawing a tile flipped vertically.
+pub const CGB_Y_FLIP: u8 = 0x40;
+
+/// The OAM attribute bit drawing a sprite with OBP1 instead of OBP0 on the
+/// original Game Boy.
+pub const OAM_OBP1: u8 = 0x10;
+
+impl EncodedTile {
+    /// The OAM attribute flags drawing sprites with `obp`'s palette, if
+    /// the tiles have one.
+    pub fn oam_flags(&self) -> Option<u8> {
+        self.obp.map(|obp| obp * OAM_OBP1)
+    }
+
+    /// The colors of color numbers 0 to 3 as Game Boy Color palettes of
+    /// RGB555 words, four words per palette: one palette, or each of
+    /// `cgb_palettes` in turn. Transparent and unused color numbers are
+    /// black. Colors are corrected for the screen first with
+    /// `cgb_color_correction`.
+    pub fn cgb_palette(&self) -> Vec<u16> {
+        let palettes = match &self.cgb_palettes {
+            Some(cgb_palettes) => cgb_palettes
+                .color_numbers
+                .iter()
+                .map(|color_numbers| colors_by_number(color_numbers, self.transparent_color))
+                .collect(),
+            None => vec![self.colors.clone()],
+        };
+        palettes
+            .iter()
+            .flat_map(|colors| {
+                let mut palette = [0; GB_MAX_COLOR_COUNT];
+                for (word, color) in palette.iter_mut().zip(colors) {
+                    *word = match self.cgb_color_correction {
+                        true => color.cgb_color_corrected().to_rgb555(),
+                        false => color.to_rgb555(),
+                    };
+                }
+                palette
+            })
+            .collect()
+    }
+
+    /// The Game Boy Color attribute map of `tilemap`, as written to VRAM
+    /// bank 1: each position's attributes from the source, with the palette
+    /// bits set to the palette its tile uses. Tiles are read from bank 0
+    /// and don't take priority over sprites.
+    pub fn cgb_attributes(&self, tilemap: &Tilemap) -> Vec<u8> {
+        tilemap
+            .tiles
+            .iter()
+            .zip(&tilemap.attributes)
+            .map(|(tile, attributes)| {
+                let palette = self
+                    .cgb_palettes
+                    .as_ref()
+                    .and_then(|cgb_palettes| cgb_palettes.tile_palettes.get(*tile as usize))
+                    .copied()
+                    .unwrap_or(0);
+                attributes | palette
+            })
+            .collect()
+    }
+
+    /// The VRAM tile numbers drawing `tiles`, the numbers of tiles in
+    /// `tile_data`, once they're loaded from `base_index` tiles into the
+    /// block `addressing` numbers from. In [`Addressing::Mode8800`], tile 0
+    /// at $8800 is -128, written as `0x80`, and numbers count up from there
+    /// through 0 at $9000 to 127.
+    pub fn map_tiles(&self, tiles: &[u8]) -> Vec<u8> {
+        let first = match self.addressing {
+            Addressing::Mode8000 => 0,
+            Addressing::Mode8800 => 0x80,
+        };
+        tiles
+            .iter()
+            .map(|tile| tile.wrapping_add(self.base_index).wrapping_add(first))
+            .collect()
+    }
+
+    /// Each character the tiles draw in font mode, with the VRAM tile number
+    /// drawing it, as in [`EncodedTile::map_tiles`]. Empty outside font
+    /// mode.
+    pub fn char_tiles(&self) -> Vec<(u8, u8)> {
+        let Some(first_char) = self.first_char else {
+            return Vec::new();
+        };
+        let tiles: Vec<u8> = (0..self.tile_data.len() / TILE_BYTES)
+            .map(|tile| tile as u8)
+            .collect();
+        tiles
+            .iter()
+            .map(|tile| first_char.wrapping_add(*tile))
+            .zip(self.map_tiles(&tiles))
+            .collect()
+    }
+
+    /// How many columns of each tile are used, from its left edge up to its
+    /// rightmost pixel that isn't color 0, for variable-width text. Blank
+    /// tiles, like a font's space, measure 0.
+    pub fn glyph_widths(&self) -> Vec<u8> {
+        self.tile_data
+            .chunks(TILE_BYTES)
+            .map(|tile| {
+                let columns = tile.iter().fold(0, |columns, byte| columns | byte);
+                match columns {
+                    0 => 0,
+                    _ => 8 - columns.trailing_zeros() as u8,
+                }
+            })
+            .collect()
+    }
+
+    /// The PAL01 and PAL23 command packets setting the Super Game Boy's
+    /// palettes to [`cgb_palette`](EncodedTile::cgb_palette)'s. Palettes
+    /// the image doesn't use are black, apart from the shared color 0.
+    pub fn sgb_palette_packets(&self) -> [[u8; SGB_PACKET_BYTES]; 2] {
+        sgb::palette_packets(&self.cgb_palette())
+    }
+
+    /// The ATTR_BLK command giving the Super Game Boy screen each tile's
+    /// palette, or `None` if the palettes are too scattered for one.
+    pub fn sgb_attribute_blocks(&self) -> Option<Vec<u8>> {
+        sgb::attribute_blocks(&self.sgb_screen())
+    }
+
+    /// The Super Game Boy attribute file giving each tile's palette, to send
+    /// with ATTR_TRN and pick with ATTR_SET.
+    pub fn sgb_attribute_file(&self) -> Vec<u8> {
+        sgb::attribute_file(&self.sgb_screen())
+    }
+
+    /// The palette of each tile on the Super Game Boy's screen, with the
+    /// image's first frame at the top left. Tiles past the image use
+    /// palette 0, and tiles past the screen are left out.
+    fn sgb_screen(&self) -> Vec<u8> {
+        let columns = self.width as usize / 8;
+        let rows = self.height as usize / 8 / self.frames.max(1) as usize;
+        let mut screen = vec![0; SGB_SCREEN_COLUMNS * SGB_SCREEN_ROWS];
+        if let Some(cgb_palettes) = &self.cgb_palettes {
+            for y in 0..rows.min(SGB_SCREEN_ROWS) {
+                for x in 0..columns.min(SGB_SCREEN_COLUMNS) {
+                    screen[y * SGB_SCREEN_COLUMNS + x] = cgb_palettes
+                        .tile_palettes
+                        .get(self.tile_number(x, y))
+                        .copied()
+                        .unwrap_or(0);
+                }
+            }
+        }
+        screen
+    }
+
+    /// The number of the tile at `column` and `row` of the image, in the
+    /// order the tiles were written in. Each animation frame's tiles follow
+    /// the previous frame's.
+    pub fn tile_number(&self, column: usize, row: usize) -> usize {
+        let columns = self.width as usize / 8;
+        let rows = (self.height as usize / 8 / self.frames.max(1) as usize).max(1);
+        let (frame, row) = (row / rows, row % rows);
+        frame * columns * rows
+            + match self.order {
+                TileOrder::Row => row * columns + column,
+                TileOrder::Column => column * rows + row,
+            }
+    }
+
+    /// Write the tiles of each frame in `order` instead of the order they
+    /// were cut in, along with their palettes, and renumber the maps drawn
+    /// with them to match. Fails if that moves a tile a map draws past the
+    /// 256 a map can number.
+    pub fn reorder(&mut self, order: TileOrder) -> Result<(), String> {
+        if order == self.order {
+            return Ok(());
+        }
+        let tiles = self.tile_data.len() / TILE_BYTES;
+        let moved = self.moved_tiles(order);
+        let mut renumbered = vec![0; tiles];
+        for (number, tile) in moved.iter().enumerate() {
+            renumbered[*tile] = number;
+        }
+        for tilemap in &mut self.tilemaps {
+            for tile in tilemap.tiles.iter_mut() {
+                let number = renumbered
+                    .get(*tile as usize)
+                    .copied()
+                    .unwrap_or(*tile as usize);
+                if number > u8::MAX as usize {
+                    return Err(format!(
+                        "tile {} is drawn in a map, but would be tile {} in {} order, past the 256 a map can number",
+                        tile, number, order
+                    ));
+                }
+                *tile = number as u8;
+            }
+        }
+        self.tile_data = moved
+            .iter()
+            .flat_map(|tile| &self.tile_data[tile * TILE_BYTES..(tile + 1) * TILE_BYTES])
+            .copied()
+            .collect();
+        if let Some(cgb_palettes) = &mut self.cgb_palettes {
+            cgb_palettes.tile_palettes = moved
+                .iter()
+                .map(|tile| cgb_palettes.tile_palettes[*tile])
+                .collect();
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Leave out the tiles that are entirely color 0, returning how many.
+    /// When maps or metatiles draw with the tiles, the first blank tile is
+    /// kept for every blank position they draw, and the other tiles are
+    /// renumbered to match. Otherwise only the blank tiles at the end of
+    /// each animation frame are left out, the same number from each, so the
+    /// tiles before them keep their numbers.
+    pub fn trim_blank(&mut self) -> usize {
+        let blank: Vec<bool> = self
+            .tile_data
+            .chunks(TILE_BYTES)
+            .map(|tile| tile.iter().all(|byte| *byte == 0))
+            .collect();
+        let drawn: Vec<&mut u8> = self
+            .tilemaps
+            .iter_mut()
+            .flat_map(|tilemap| tilemap.tiles.iter_mut())
+            .chain(
+                self.metatiles
+                    .iter_mut()
+                    .flat_map(|metatiles| metatiles.definitions.iter_mut()),
+            )
+            .collect();
+        let kept: Vec<bool> = if drawn.is_empty() || self.frames > 1 {
+            let frames = self.frames.max(1) as usize;
+            let frame_tiles = blank.len() / frames;
+            let trailing = blank
+                .chunks(frame_tiles.max(1))
+                .map(|frame| frame.iter().rev().take_while(|blank| **blank).count())
+                .min()
+                .unwrap_or(0);
+            (0..blank.len())
+                .map(|tile| tile % frame_tiles.max(1) < frame_tiles - trailing)
+                .collect()
+        } else {
+            let first_blank = drawn
+                .iter()
+                .map(|tile| **tile as usize)
+                .filter(|tile| blank.get(*tile) == Some(&true))
+                .min();
+            let kept: Vec<bool> = blank
+                .iter()
+                .enumerate()
+                .map(|(tile, blank)| !blank || Some(tile) == first_blank)
+                .collect();
+            // Each tile's number once the tiles before it are left out.
+            let numbers: Vec<usize> = kept
+                .iter()
+                .scan(0, |next, kept| {
+                    let number = *next;
+                    *next += *kept as usize;
+                    Some(number)
+                })
+                .collect();
+            for tile in drawn {
+                let number = match blank.get(*tile as usize) {
+                    Some(true) => first_blank.map(|first_blank| numbers[first_blank]),
+                    _ => numbers.get(*tile as usize).copied(),
+                };
+                *tile = number.unwrap_or(*tile as usize) as u8;
+            }
+            kept
+        };
+        self.tile_data = self
+            .tile_data
+            .chunks(TILE_BYTES)
+            .zip(&kept)
+            .filter(|(_, kept)| **kept)
+            .flat_map(|(tile, _)| tile.iter().copied())
+            .collect();
+        if let Some(cgb_palettes) = &mut self.cgb_palettes {
+            cgb_palettes.tile_palettes = cgb_palettes
+                .tile_palettes
+                .iter()
+                .zip(&kept)
+                .filter(|(_, kept)| **kept)
+                .map(|(palette, _)| *palette)
+                .collect();
+        }
+        kept.iter().filter(|kept| !**kept).count()
+    }
+
+    /// The tile moving to each position when the tiles are written in
+    /// `order`: each frame's tiles, read in that order instead.
+    fn moved_tiles(&self, order: TileOrder) -> Vec<usize> {
+        let frames = self.frames.max(1) as usize;
+        let frame_tiles = self.tile_data.len() / TILE_BYTES / frames;
+        let positions = self.positions(order);
+        (0..frames)
+            .flat_map(|frame| {
+                positions
+                    .iter()
+                    .map(move |(column, row)| frame * frame_tiles + self.tile_number(*column, *row))
+            })
+            .collect()
+    }
+
+    /// The column and row of each of a frame's tiles, in `order`.
+    fn positions(&self, order: TileOrder) -> Vec<(usize, usize)> {
+        let columns = self.width as usize / 8;
+        let rows = self.height as usize / 8 / self.frames.max(1) as usize;
+        match order {
+            TileOrder::Row => (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect(),
+            TileOrder::Column => (0..columns)
+                .flat_map(|column| (0..rows).map(move |row| (column, row)))
+                .collect(),
+        }
+    }
+
+    /// The tile data as it's written out: `tile_data` itself, or in 1bpp,
+    /// one byte per tile row with a bit set for each pixel that isn't color
+    /// 0, as RGBDS's `INCBIN` of 1bpp fonts and GBDK's
+    /// `set_bkg_1bpp_data` take.
+    pub fn rendered_data(&self) -> Cow<'_, [u8]> {
+        self.rendered(&self.tile_data)
+    }
+
+    /// The changed tiles of [`EncodedTile::frame_deltas`] as they're
+    /// written out, as in [`EncodedTile::rendered_data`].
+    pub fn rendered_delta_data(&self) -> Option<Cow<'_, [u8]>> {
+        self.frame_deltas
+            .as_ref()
+            .map(|frame_deltas| self.rendered(&frame_deltas.tile_data))
+    }
+
+    /// How many bytes each tile takes up as it's written out.
+    pub fn rendered_tile_bytes(&self) -> usize {
+        TILE_BYTES * self.bpp as usize / 2
+    }
+
+    /// How many bytes of [`EncodedTile::rendered_data`] go in each bank when
+    /// the tile data is split, as in [`EncodedTile::bank_tiles`].
+    pub fn bank_bytes(&self) -> Option<usize> {
+        self.bank_tiles
+            .map(|tiles| tiles * self.rendered_tile_bytes())
+    }
+
+    fn rendered<'a>(&self, tile_data: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.bpp {
+            1 => Cow::Owned(tile_data.chunks(2).map(|row| row[0] | row[1]).collect()),
+            _ => Cow::Borrowed(tile_data),
+        }
+    }
+
+    /// The color numbers other than 0 the tiles draw with, in order.
+    pub fn used_colors(&self) -> Vec<u8> {
+        let (low, high, both) =
+            self.tile_data
+                .chunks(2)
+                .fold((0, 0, 0), |(low, high, both), row| {
+                    (
+                        low | (row[0] & !row[1]),
+                        high | (row[1] & !row[0]),
+                        both | (row[0] & row[1]),
+                    )
+                });
+        [(1, low), (2, high), (3, both)]
+            .iter()
+            .filter(|(_, bits)| *bits != 0)
+            .map(|(color, _)| *color)
+            .collect()
+    }
+
+    /// The tile data of each animation frame, in order. Still images have
+    /// a single frame holding all of their tile data.
+    pub fn frame_data(&self) -> impl Iterator<Item = &[u8]> {
+        let frame_len = self.tile_data.len() / self.frames.max(1) as usize;
+        self.tile_data.chunks(frame_len.max(1))
+    }
+}
+
+/// Selects bit 0 of each byte in a `u64`.
+const BIT_0_OF_EACH_BYTE: u64 = 0x0101_0101_0101_0101;
+
+/// Multiplying a `u64` holding one bit per byte by this gathers those bits
+/// into the top byte, with byte 0 landing in the most significant bit. Every
+/// partial product lands on a distinct bit, so nothing carries into the
+/// gathered byte.
+const GATHER_BITS_MSB_FIRST: u64 = 0x8040_2010_0804_0201;
+
+/// For each byte value, a `u64` with byte `i` set to bit `7 - i` of it: the
+/// inverse of the gather above.
+const SPREAD_BITS_MSB_FIRST: [u64; 256] = spread_table();
+
+const fn spread_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut value = 0;
+    while value < 256 {
+        let mut bit = 0;
+        while bit < 8 {
+            table[value] |= (((value >> (7 - bit)) & 0x01) as u64) << (bit * 8);
+            bit += 1;
+        }
+        value += 1;
+    }
+    table
+}
+
+/// Pack one row of 8 color numbers, leftmost first, into its low and high
+/// bitplane bytes.
+///
+/// All 8 pixels are packed at once: the colors are loaded as a single `u64`,
+/// and each bitplane is gathered into a byte with one multiply.
+pub fn pack_tile_row(colors: [u8; 8]) -> (u8, u8) {
+    let pixels = u64::from_le_bytes(colors);
+    let low_byte = (pixels & BIT_0_OF_EACH_BYTE).wrapping_mul(GATHER_BITS_MSB_FIRST) >> 56;
+    let high_byte = ((pixels >> 1) & BIT_0_OF_EACH_BYTE).wrapping_mul(GATHER_BITS_MSB_FIRST) >> 56;
+    (low_byte as u8, high_byte as u8)
+}
+
+/// Unpack a tile row's bitplane bytes back into 8 color numbers.
+pub fn unpack_tile_row(low_byte: u8, high_byte: u8) -> [u8; 8] {
+    let pixels =
+        SPREAD_BITS_MSB_FIRST[low_byte as usize] | (SPREAD_BITS_MSB_FIRST[high_byte as usize] << 1);
+    pixels.to_le_bytes()
+}
+
+/// Compares unequal to the key of every color.
+const TRANSPARENT_KEY: u32 = 1 << 24;
+
+fn pixel_key(pixel: &Option<Rgb>) -> u32 {
+    match pixel {
+        Some(rgb) => (rgb.r as u32) << 16 | (rgb.g as u32) << 8 | rgb.b as u32,
+        None => TRANSPARENT_KEY,
+    }
+}
+
+/// Cut a decoded image into 8x8 tiles, left to right and top to bottom,
+/// and pack each tile row into its low and high bitplane bytes.
+pub fn encode_tile(decoded_image: DecodedImage) -> EncodedTile {
+    let rows = decoded_image.height / 8;
+    let columns = decoded_image.width / 8;
+    info!(
+        "File: {}, Tile rows: {}, columns: {}, unique colors: {}",
+        decoded_image.input_filename,
+        rows,
+        columns,
+        decoded_image.color_numbers.len() + decoded_image.transparent_color.is_some() as usize
+    );
+    // Compare each pixel against all four colors without branching, which
+    // is much cheaper than a map lookup per pixel on busy images. Keys are
+    // stored at the index of their color number, so a match's index is its
+    // color. Images sharing out Game Boy Color palettes get keys for each.
+    let palettes = match &decoded_image.cgb_palettes {
+        Some(cgb_palettes) => cgb_palettes.color_numbers.iter().collect(),
+        None => vec![&decoded_image.color_numbers],
+    };
+    let palette_keys: Vec<[u32; GB_MAX_COLOR_COUNT]> = palettes
+        .iter()
+        .map(|color_numbers| {
+            let mut keys = [u32::MAX; GB_MAX_COLOR_COUNT];
+            for (rgb, color) in color_numbers.iter() {
+                keys[*color as usize] = pixel_key(&Some(*rgb));
+            }
+            if let Some(color) = decoded_image.transparent_color {
+                keys[color as usize] = TRANSPARENT_KEY;
+            }
+            keys
+        })
+        .collect();
+    let lookup_color = |keys: &[u32; GB_MAX_COLOR_COUNT], pixel: &Option<Rgb>| {
+        let key = pixel_key(pixel);
+        keys.iter().enumerate().fold(0, |color, (i, palette_key)| {
+            color | (i as u8 * (key == *palette_key) as u8)
+        })
+    };
+
+    let width = decoded_image.width as usize;
+    let mut tile_data = Vec::with_capacity(rows as usize * columns as usize * 16);
+    for row in 0..rows as usize {
+        for column in 0..columns as usize {
+            let palette = decoded_image
+                .cgb_palettes
+                .as_ref()
+                .map_or(0, |cgb_palettes| {
+                    cgb_palettes.tile_palettes[row * columns as usize + column]
+                });
+            let keys = &palette_keys[palette as usize];
+            for tile_row in 0..8 {
+                let start = (row * 8 + tile_row) * width + column * 8;
+                let pixels = &decoded_image.image_data[start..start + 8];
+                let mut colors = [0; 8];
+                for (color, pixel) in colors.iter_mut().zip(pixels) {
+                    *color = lookup_color(keys, pixel);
+                }
+                let (low_byte, high_byte) = pack_tile_row(colors);
+                tile_data.push(low_byte);
+                tile_data.push(high_byte);
+            }
+        }
+    }
+
+    EncodedTile {
+        input_filename: decoded_image.input_filename,
+        name: None,
+        width: decoded_image.width,
+        height: decoded_image.height,
+        colors: colors_by_number(
+            &decoded_image.color_numbers,
+            decoded_image.transparent_color,
+        ),
+        transparent_color: decoded_image.transparent_color,
+        palette_register: decoded_image.palette_register,
+        cgb: false,
+        cgb_color_correction: false,
+        sgb: false,
+        obp: None,
+        cgb_palettes: decoded_image.cgb_palettes,
+        frames: decoded_image.frames,
+        tile_data,
+        bpp: 2,
+        base_index: 0,
+        addressing: Addressing::Mode8000,
+        first_char: None,
+        order: TileOrder::Row,
+        bank_tiles: None,
+        frame_deltas: None,
+        tilemaps: decoded_image.tilemaps,
+        metatiles: Vec::new(),
+        metasprites: Vec::new(),
+        warnings: decoded_image.warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SHADES: [Rgb; 4] = [
+        Rgb {
+            r: 240,
+            g: 240,
+            b: 240,
+        },
+        Rgb {
+            r: 144,
+            g: 144,
+            b: 144,
+        },
+        Rgb {
+            r: 96,
+            g: 96,
+            b: 96,
+        },
+        Rgb { r: 0, g: 0, b: 0 },
+    ];
+
+    /// An image with a pseudo-random mix of all four shades.
+    fn noise(width: u32, height: u32) -> DecodedImage {
+        let mut state: u32 = 0x1234_5678;
+        let image_data = (0..width * height)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                Some(SHADES[(state % 4) as usize])
+            })
+            .collect();
+        DecodedImage {
+            input_filename: "noise.png".to_string(),
+            width,
+            height,
+            image_data,
+            color_numbers: SHADES
+                .iter()
+                .enumerate()
+                .map(|(i, rgb)| (*rgb, i as u8))
+                .collect(),
+            transparent_color: None,
+            palette_register: 0xE4,
+            cgb_palettes: None,
+            frames: 1,
+            tilemaps: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Encode a pixel at a time, as the encoder did before packing 8 at
+    /// once.
+    fn encode_pixel_at_a_time(decoded_image: &DecodedImage) -> Vec<u8> {
+        let color_numbers: &BTreeMap<Rgb, u8> = &decoded_image.color_numbers;
+        let width = decoded_image.width;
+        let mut tile_data = Vec::new();
+        for row in 0..decoded_image.height / 8 {
+            for column in 0..width / 8 {
+                for tile_row in 0..8 {
+                    let mut low_byte = 0;
+                    let mut high_byte = 0;
+                    for tile_column in 0..8 {
+                        let pixel_index = (column * 8 + tile_column) + (row * 8 + tile_row) * width;
+                        let pixel = decoded_image.image_data[pixel_index as usize];
+                        let color = color_numbers[&pixel.unwrap()];
+                        low_byte |= (color & 0x01) << (7 - tile_column);
+                        high_byte |= ((color >> 1) & 0x01) << (7 - tile_column);
+                    }
+                    tile_data.push(low_byte);
+                    tile_data.push(high_byte);
+                }
+            }
+        }
+        tile_data
+    }
+
+    #[test]
+    fn tile_rows_unpack_to_the_colors_they_pack() {
+        for value in 0..1u32 << 16 {
+            let mut colors = [0; 8];
+            for (i, color) in colors.iter_mut().enumerate() {
+                *color = ((value >> (i * 2)) & 0x03) as u8;
+            }
+            let (low_byte, high_byte) = pack_tile_row(colors);
+            assert_eq!(unpack_tile_row(low_byte, high_byte), colors);
+        }
+    }
+
+    #[test]
+    fn encodes_as_a_pixel_at_a_time() {
+        let decoded_image = noise(64, 48);
+        let expected = encode_pixel_at_a_time(&decoded_image);
+        assert_eq!(encode_tile(decoded_image).tile_data, expected);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::format::variable_name;
+use crate::tile::{EncodedTile, TileOrder, Tilemap};
+use crate::vram::TILE_BYTES;
+use std::collections::HashMap;
+
+/// The most tiles a map can number.
+const MAX_NUMBERED: usize = 256;
+
+/// Merge `encoded_tiles` into one tileset named `name`, holding each
+/// distinct tile once, with a map for each image giving the tiles it draws,
+/// named after the image. Images that are already maps keep their maps,
+/// renumbered and named `<image>_<map>`, and so do their metatiles.
+///
+/// Tiles are the same when they draw the same pixels, and in Game Boy
+/// Color mode, with the same palette. The colors and options of the first
+/// image are used for the whole tileset, so the images should share them.
+/// Fails if there are more distinct tiles than maps can number, the images
+/// have metasprites, or a map draws a tile that was left out as blank.
+pub fn share_tileset(
+    name: &str,
+    mut encoded_tiles: Vec<EncodedTile>,
+) -> Result<EncodedTile, String> {
+    if encoded_tiles.is_empty() {
+        return Err("there are no images to share tiles between".to_string());
+    }
+    if encoded_tiles
+        .iter()
+        .any(|encoded_tile| !encoded_tile.metasprites.is_empty())
+    {
+        return Err(
+            "metasprites are drawn from each image's own tiles, so they can't share a tileset"
+                .to_string(),
+        );
+    }
+    let mut tile_data = Vec::new();
+    let mut tile_palettes = Vec::new();
+    // Each distinct tile's data and palette, and its number in the tileset.
+    let mut numbers: HashMap<(&[u8], u8), usize> = HashMap::new();
+    let mut renumbered = Vec::with_capacity(encoded_tiles.len());
+    for encoded_tile in &encoded_tiles {
+        let image: Vec<usize> = encoded_tile
+            .tile_data
+            .chunks(TILE_BYTES)
+            .enumerate()
+            .map(|(tile, data)| {
+                let palette = encoded_tile
+                    .cgb_palettes
+                    .as_ref()
+                    .and_then(|cgb_palettes| cgb_palettes.tile_palettes.get(tile).copied())
+                    .unwrap_or(0);
+                let next = numbers.len();
+                let number = *numbers.entry((data, palette)).or_insert(next);
+                if number == next {
+                    tile_data.extend_from_slice(data);
+                    tile_palettes.push(palette);
+                }
+                number
+            })
+            .collect();
+        renumbered.push(image);
+    }
+    let tiles = numbers.len();
+    let base_index = encoded_tiles[0].base_index as usize;
+    if tiles + base_index > MAX_NUMBERED {
+        return Err(format!(
+            "the images have {} different tiles, but maps can only number {} from tile {}",
+            tiles,
+            MAX_NUMBERED - base_index,
+            base_index
+        ));
+    }
+    let mut tilemaps = Vec::new();
+    let mut metatiles = Vec::new();
+    let mut warnings = Vec::new();
+    for (encoded_tile, renumbered) in encoded_tiles.iter_mut().zip(renumbered) {
+        let image = variable_name(encoded_tile);
+        // Tile numbers stay whole until they're renumbered, since an image
+        // can have more tiles of its own than a map can number.
+        let shared_number = |tile: usize| {
+            renumbered
+                .get(tile)
+                .map(|number| *number as u8)
+                .ok_or_else(|| {
+                    format!(
+                    "{} draws tile {}, which was left out as blank, so it can't share a tileset",
+                    image, tile
+                )
+                })
+        };
+        let renumber = |tiles: &mut Vec<u8>| -> Result<(), String> {
+            for tile in tiles.iter_mut() {
+                *tile = shared_number(*tile as usize)?;
+            }
+            Ok(())
+        };
+        let map_name = |name: &Option<String>| match name {
+            Some(name) => Some(format!("{}_{}", image, name)),
+            None => Some(image.clone()),
+        };
+        if encoded_tile.tilemaps.is_empty() {
+            let encoded_tile = &*encoded_tile;
+            let (width, height) = (encoded_tile.width / 8, encoded_tile.height / 8);
+            let tiles = (0..height as usize)
+                .flat_map(|row| {
+                    (0..width as usize).map(move |column| encoded_tile.tile_number(column, row))
+                })
+                .map(shared_number)
+                .collect::<Result<Vec<u8>, String>>()?;
+            tilemaps.push(Tilemap {
+                name: Some(image.clone()),
+                width,
+                height,
+                attributes: vec![0; tiles.len()],
+                tiles,
+            });
+        }
+        for mut tilemap in encoded_tile.tilemaps.drain(..) {
+            renumber(&mut tilemap.tiles)?;
+            tilemap.name = map_name(&tilemap.name);
+            tilemaps.push(tilemap);
+        }
+        for mut image_metatiles in encoded_tile.metatiles.drain(..) {
+            renumber(&mut image_metatiles.definitions)?;
+            image_metatiles.name = map_name(&image_metatiles.name);
+            metatiles.push(image_metatiles);
+        }
+        warnings.append(&mut encoded_tile.warnings);
+    }
+    let mut shared = encoded_tiles.swap_remove(0);
+    shared.name = Some(name.to_string());
+    shared.width = 8;
+    shared.height = (tiles * 8) as u32;
+    shared.frames = 1;
+    shared.order = TileOrder::Row;
+    shared.first_char = None;
+    shared.frame_deltas = None;
+    shared.tile_data = tile_data;
+    if let Some(cgb_palettes) = &mut shared.cgb_palettes {
+        cgb_palettes.tile_palettes = tile_palettes;
+    }
+    shared.tilemaps = tilemaps;
+    shared.metatiles = metatiles;
+    shared.warnings = warnings;
+    Ok(shared)
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The bytes of one encoded 8x8 tile.
+pub const TILE_BYTES: usize = 16;
+
+/// The size of one bank of Game Boy VRAM.
+pub const VRAM_BANK_BYTES: usize = 8 * 1024;
+
+/// The size of one switchable bank of Game Boy ROM.
+pub const ROM_BANK_BYTES: usize = 16 * 1024;
+
+/// The banks tile data is split to fit, with
+/// [`Converter::split_banks`](crate::Converter::split_banks).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SplitBanks {
+    /// The tile slots of VRAM, so each part can be loaded at once.
+    Vram,
+    /// Switchable 16 KiB banks of ROM.
+    Rom,
+}
+
+impl SplitBanks {
+    /// The most tiles that fit in a bank, measuring VRAM against
+    /// `tile_slots` and ROM in tiles of `bpp` bits per pixel.
+    pub fn bank_tiles(&self, tile_slots: usize, bpp: u8) -> usize {
+        match self {
+            SplitBanks::Vram => tile_slots,
+            SplitBanks::Rom => ROM_BANK_BYTES / (TILE_BYTES * bpp.max(1) as usize / 2),
+        }
+    }
+}
+
+impl FromStr for SplitBanks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SplitBanks, String> {
+        match s {
+            "vram" => Ok(SplitBanks::Vram),
+            "rom" => Ok(SplitBanks::Rom),
+            _ => Err(format!("'{}' isn't a kind of bank, use 'vram' or 'rom'", s)),
+        }
+    }
+}
+
+/// How much of VRAM some encoded tile data would take up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VramUsage {
+    pub tiles: usize,
+    pub bytes: usize,
+    /// The tile slots available: 256 for the background or window using one
+    /// addressing mode, or 384 for all of a bank's tile data.
+    pub tile_slots: usize,
+}
+
+impl VramUsage {
+    /// The usage of `bytes` of encoded tile data.
+    pub fn new(bytes: usize, tile_slots: usize) -> Self {
+        VramUsage {
+            tiles: bytes / TILE_BYTES,
+            bytes,
+            tile_slots,
+        }
+    }
+
+    /// The percentage of the tile slots used, which is over 100 when the
+    /// tiles don't fit.
+    pub fn slot_percent(&self) -> f64 {
+        self.tiles as f64 * 100.0 / self.tile_slots as f64
+    }
+
+    /// The percentage of a VRAM bank used.
+    pub fn bank_percent(&self) -> f64 {
+        self.bytes as f64 * 100.0 / VRAM_BANK_BYTES as f64
+    }
+}
+
+impl fmt::Display for VramUsage {
+    /// Formats like `64 of 256 tile slots (25.0%), 1024 bytes (12.5% of an
+    /// 8 KiB VRAM bank)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} tile slots ({:.1}%), {} bytes ({:.1}% of an 8 KiB VRAM bank)",
+            self.tiles,
+            self.tile_slots,
+            self.slot_percent(),
+            self.bytes,
+            self.bank_percent()
+        )
+    }
+}
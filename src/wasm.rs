@@ -0,0 +1,26 @@
+//! JavaScript bindings for running gbtile in the browser.
+//!
+//! Build with `wasm-pack build --target web -- --features wasm`.
+
+use crate::{Converter, FormatRegistry};
+use wasm_bindgen::prelude::*;
+
+/// Convert image bytes (e.g. the contents of a dropped PNG file) into
+/// generated source text. `name` becomes the generated symbol, and
+/// `output_type` is either `gbdk` or `rgbds`.
+#[wasm_bindgen(js_name = convertImage)]
+pub fn convert_image(name: &str, image: &[u8], output_type: &str) -> Result<String, JsError> {
+    let formats = FormatRegistry::default();
+    let output_format = formats
+        .get(output_type)
+        .ok_or_else(|| JsError::new(&format!("unknown output type: {}", output_type)))?;
+    let encoded_tile = Converter::new().encode_bytes(name, image)?;
+    Ok(String::from_utf8_lossy(&output_format.render(&encoded_tile)).into_owned())
+}
+
+/// Convert image bytes into raw 2bpp tile data, 16 bytes per tile.
+#[wasm_bindgen(js_name = encodeImage)]
+pub fn encode_image(name: &str, image: &[u8]) -> Result<Vec<u8>, JsError> {
+    let encoded_tile = Converter::new().encode_bytes(name, image)?;
+    Ok(encoded_tile.tile_data)
+}